@@ -0,0 +1,87 @@
+//! Compares the speed of the maps using the global collector and a dedicated one.
+//!
+//! The maps with their own collector need to pin it through handles they keep for the purpose,
+//! which is slower than pinning the global one. This measures by how much, for the lookups and
+//! the modifications, with one and with several threads.
+
+use std::collections::hash_map::RandomState;
+use std::time::{Duration, Instant};
+
+use contrie::ConMap;
+use crossbeam_epoch::Collector;
+use crossbeam_utils::thread;
+
+const ELEMENTS: usize = 100_000;
+const ROUNDS: usize = 5;
+
+/// Runs the operation on the map from the given number of threads, taking the best time.
+///
+/// The map is filled with all the elements before each round.
+fn measure<F>(map: &ConMap<usize, usize>, threads: usize, op: F) -> Duration
+where
+    F: Fn(&ConMap<usize, usize>, usize) + Sync,
+{
+    (0..ROUNDS)
+        .map(|_| {
+            for i in 0..ELEMENTS {
+                map.insert(i, i);
+            }
+            let start = Instant::now();
+            thread::scope(|s| {
+                for t in 0..threads {
+                    let op = &op;
+                    s.spawn(move |_| {
+                        for i in (t..ELEMENTS).step_by(threads) {
+                            op(map, i);
+                        }
+                    });
+                }
+            })
+            .unwrap();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn per_op(time: Duration) -> f64 {
+    time.as_nanos() as f64 / ELEMENTS as f64
+}
+
+fn main() {
+    println!(
+        "{:>8} {:>8} {:>12} {:>12} {:>12}",
+        "threads", "op", "global", "dedicated", "scoped"
+    );
+    for &threads in &[1, 4] {
+        let maps = [
+            ConMap::new(),
+            ConMap::with_hasher_and_collector(RandomState::new(), Collector::new()),
+            ConMap::new_scoped(),
+        ];
+        let mut insert = Vec::new();
+        let mut get = Vec::new();
+        let mut remove = Vec::new();
+        for map in &maps {
+            insert.push(measure(map, threads, |map, i| {
+                map.insert(i, i + 1);
+            }));
+            get.push(measure(map, threads, |map, i| {
+                assert!(map.get(&i).is_some());
+            }));
+            remove.push(measure(map, threads, |map, i| {
+                map.remove(&i);
+            }));
+        }
+        for (name, times) in &[("insert", insert), ("get", get), ("remove", remove)] {
+            println!(
+                "{:>8} {:>8} {:>9.1} ns {:>9.1} ns {:>9.1} ns",
+                threads,
+                name,
+                per_op(times[0]),
+                per_op(times[1]),
+                per_op(times[2])
+            );
+        }
+    }
+}
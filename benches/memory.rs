@@ -0,0 +1,72 @@
+//! Compares the memory footprint of the fixed and compressed inner nodes.
+//!
+//! This is not a timing benchmark, it just fills tries of several sizes and prints how much heap
+//! they take (as seen by a counting global allocator).
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::hash_map::RandomState;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use contrie::raw::config::{CompressedNodes, Config, Trivial};
+use contrie::raw::Raw;
+
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// Returns the number of bytes the trie with `cnt` elements takes.
+fn measure<C: Config<Payload = usize>>(cnt: usize) -> usize {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    // A private collector, so we can make sure the garbage is gone before measuring.
+    let map = Raw::<C, _>::with_hasher_scoped(RandomState::new());
+    map.with_pin(|pin| {
+        for i in 0..cnt {
+            map.insert(i, pin);
+        }
+    });
+    // Each flush collects only a bounded amount of garbage, so keep going until it's all gone.
+    loop {
+        let last = ALLOCATED.load(Ordering::Relaxed);
+        map.flush_garbage();
+        if ALLOCATED.load(Ordering::Relaxed) == last {
+            break;
+        }
+    }
+    let used = ALLOCATED.load(Ordering::Relaxed) - before;
+    drop(map);
+    used
+}
+
+fn main() {
+    println!(
+        "{:>10} {:>14} {:>10} {:>14} {:>10}",
+        "elements", "fixed", "per elem", "compressed", "per elem"
+    );
+    for &cnt in &[100, 10_000, 100_000, 1_000_000] {
+        let fixed = measure::<Trivial<usize>>(cnt);
+        let compressed = measure::<CompressedNodes<Trivial<usize>>>(cnt);
+        println!(
+            "{:>10} {:>14} {:>10.1} {:>14} {:>10.1}",
+            cnt,
+            fixed,
+            fixed as f64 / cnt as f64,
+            compressed,
+            compressed as f64 / cnt as f64
+        );
+    }
+}
@@ -1,23 +1,37 @@
-//! The [`CloneConMap`][crate::CloneConMap] type and its helpers.
+//! The [`CloneConMap`] type and its helpers.
 
 use std::borrow::Borrow;
 use std::collections::hash_map::RandomState;
 use std::fmt::{Debug, Formatter, Result as FmtResult};
+#[cfg(feature = "async")]
+use std::future::Future;
 use std::hash::{BuildHasher, Hash};
 use std::iter::FromIterator;
 use std::marker::PhantomData;
-
+use std::ops::RangeBounds;
+#[cfg(feature = "stream")]
+use std::pin::Pin;
+#[cfg(feature = "stream")]
+use std::task::{Context, Poll};
+
+use crossbeam_epoch::Collector;
+#[cfg(feature = "stream")]
+use futures_core::Stream;
+#[cfg(feature = "rayon")]
+use rayon::iter::plumbing::UnindexedConsumer;
 #[cfg(feature = "rayon")]
 use rayon::iter::{FromParallelIterator, IntoParallelIterator, ParallelExtend, ParallelIterator};
 
 use crate::existing_or_new::ExistingOrNew;
 use crate::raw::config::Config;
-use crate::raw::{self, Raw};
+use crate::raw::cursor::Cursor;
+use crate::raw::{self, MapGuard, Raw};
 
 #[derive(Clone)]
 struct CloneMapPayload<K, V>((K, V));
 
 impl<K, V> Borrow<K> for CloneMapPayload<K, V> {
+    #[allow(clippy::needless_borrowed_reference)]
     fn borrow(&self) -> &K {
         let &(ref k, _) = &self.0;
         k
@@ -28,11 +42,12 @@ struct CloneMapConfig<K, V>(PhantomData<(K, V)>);
 
 impl<K, V> Config for CloneMapConfig<K, V>
 where
-    K: Clone + Hash + Eq + 'static,
-    V: Clone + 'static,
+    K: Clone + Hash + Eq,
+    V: Clone,
 {
     type Payload = CloneMapPayload<K, V>;
     type Key = K;
+    type Hash = u64;
 }
 
 /// The iterator of the [`CloneConMap`].
@@ -40,20 +55,573 @@ where
 /// See the [`iter`][CloneConMap::iter] method for details.
 pub struct Iter<'a, K, V, S>
 where
-    K: Clone + Hash + Eq + 'static,
-    V: Clone + 'static,
+    K: Clone + Hash + Eq,
+    V: Clone,
 {
     inner: raw::iterator::Iter<'a, CloneMapConfig<K, V>, S>,
 }
 
 impl<'a, K, V, S> Iterator for Iter<'a, K, V, S>
 where
-    K: Clone + Hash + Eq + 'static,
-    V: Clone + 'static,
+    K: Clone + Hash + Eq,
+    V: Clone,
+{
+    type Item = (K, V);
+    fn next(&mut self) -> Option<(K, V)> {
+        self.inner.next().map(|p| (p.0).clone())
+    }
+}
+
+/// The iterator of the [`CloneConMap`] starting at a [`Cursor`].
+///
+/// See the [`iter_from`][CloneConMap::iter_from] and
+/// [`iter_hash_range`][CloneConMap::iter_hash_range] methods for details.
+pub struct CursorIter<'a, K, V, S>
+where
+    K: Clone + Hash + Eq,
+    V: Clone,
+{
+    inner: raw::cursor::CursorIter<'a, CloneMapConfig<K, V>, S>,
+}
+
+impl<K, V, S> CursorIter<'_, K, V, S>
+where
+    K: Clone + Hash + Eq,
+    V: Clone,
+    S: BuildHasher,
+{
+    /// The position right after the last returned element.
+    ///
+    /// Passing it to [`iter_from`][CloneConMap::iter_from] resumes the iteration. Returns `None` if
+    /// there are no more elements in the range.
+    pub fn cursor(&self) -> Option<Cursor<u64>> {
+        self.inner.cursor()
+    }
+
+    /// Makes the iterator release its epoch pin and take a new one after each `n` elements.
+    ///
+    /// This bounds the time the iterator holds back releasing memory, which is useful for long
+    /// walks through large maps. See [`raw::cursor::CursorIter::repin_every`] for details.
+    ///
+    /// # Panics
+    ///
+    /// If `n` is 0.
+    pub fn repin_every(self, n: usize) -> Self {
+        CursorIter {
+            inner: self.inner.repin_every(n),
+        }
+    }
+}
+
+impl<K, V, S> Iterator for CursorIter<'_, K, V, S>
+where
+    K: Clone + Hash + Eq,
+    V: Clone,
+    S: BuildHasher,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        self.inner.next().map(|p| (p.0).clone())
+    }
+}
+
+/// The iterator of the [`CloneConMap`] holding no epoch pin.
+///
+/// See the [`send_iter`][CloneConMap::send_iter] method for details.
+pub struct SendIter<'a, K, V, S>
+where
+    K: Clone + Hash + Eq,
+    V: Clone,
+{
+    inner: raw::cursor::SendIter<'a, CloneMapConfig<K, V>, S, ExtractElement<K, V>>,
+}
+
+impl<'a, K, V, S> SendIter<'a, K, V, S>
+where
+    K: Clone + Hash + Eq,
+    V: Clone,
+{
+    /// The position right after the last returned element.
+    ///
+    /// See [`CursorIter::cursor`].
+    pub fn cursor(&self) -> Option<Cursor<u64>> {
+        self.inner.cursor()
+    }
+
+    /// Turns the iterator into a futures `Stream`.
+    ///
+    /// Available with the `stream` feature.
+    #[cfg(feature = "stream")]
+    pub fn into_stream(self) -> SendStream<'a, K, V, S> {
+        SendStream {
+            inner: self.inner.into_stream(),
+        }
+    }
+}
+
+impl<K, V, S> Iterator for SendIter<'_, K, V, S>
+where
+    K: Clone + Hash + Eq,
+    V: Clone,
+    S: BuildHasher,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        self.inner.next()
+    }
+}
+
+/// The [`SendIter`] as a futures `Stream`.
+///
+/// Created by [`SendIter::into_stream`], available with the `stream` feature.
+#[cfg(feature = "stream")]
+pub struct SendStream<'a, K, V, S>
+where
+    K: Clone + Hash + Eq,
+    V: Clone,
+{
+    inner: raw::cursor::SendStream<'a, CloneMapConfig<K, V>, S, ExtractElement<K, V>>,
+}
+
+#[cfg(feature = "stream")]
+impl<K, V, S> SendStream<'_, K, V, S>
+where
+    K: Clone + Hash + Eq,
+    V: Clone,
+{
+    /// The position right after the last returned element.
+    ///
+    /// See [`CursorIter::cursor`].
+    pub fn cursor(&self) -> Option<Cursor<u64>> {
+        self.inner.cursor()
+    }
+}
+
+#[cfg(feature = "stream")]
+impl<K, V, S> Stream for SendStream<'_, K, V, S>
+where
+    K: Clone + Hash + Eq,
+    V: Clone,
+    S: BuildHasher,
+{
+    type Item = (K, V);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<(K, V)>> {
+        Pin::new(&mut self.inner).poll_next(cx)
+    }
+}
+
+/// Turns the payload into an element returned by the parallel and `Send` iterators.
+type ExtractElement<K, V> = fn(&CloneMapPayload<K, V>) -> (K, V);
+
+/// The parallel iterator of the [`CloneConMap`].
+///
+/// See the [`par_iter`][CloneConMap::par_iter] method for details. Available with the `rayon`
+/// feature.
+#[cfg(feature = "rayon")]
+pub struct ParIter<'a, K, V, S>
+where
+    K: Clone + Hash + Eq,
+    V: Clone,
+{
+    inner: raw::parallel::ParIter<'a, CloneMapConfig<K, V>, S, ExtractElement<K, V>>,
+}
+
+#[cfg(feature = "rayon")]
+impl<K, V, S> ParallelIterator for ParIter<'_, K, V, S>
+where
+    K: Clone + Hash + Eq + Send + Sync,
+    V: Clone + Send + Sync,
+    S: Sync,
+{
+    type Item = (K, V);
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        self.inner.drive_unindexed(consumer)
+    }
+}
+
+/// A reference to an element of the [`CloneConMap`].
+///
+/// See the [`get_ref`][CloneConMap::get_ref] method for details.
+pub type Ref<'a, K, V> = raw::reference::Ref<'a, (K, V)>;
+
+/// A read handle to the [`CloneConMap`], holding an epoch pin.
+///
+/// See the [`pin`][CloneConMap::pin] method for details.
+pub struct Pinned<'a, K, V, S>
+where
+    K: Clone + Hash + Eq,
+    V: Clone,
+{
+    map: &'a CloneConMap<K, V, S>,
+    pin: MapGuard<'a>,
+}
+
+impl<K, V, S> Pinned<'_, K, V, S>
+where
+    K: Clone + Hash + Eq,
+    V: Clone,
+    S: BuildHasher,
+{
+    /// Looks up the value of an element.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        Q: ?Sized + Eq + Hash,
+        K: Borrow<Q>,
+    {
+        self.get_key_value(key).map(|(_, v)| v)
+    }
+
+    /// Looks up an element, providing both its key and value.
+    pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        Q: ?Sized + Eq + Hash,
+        K: Borrow<Q>,
+    {
+        self.map.raw.get(key, &self.pin).map(|p| {
+            let (k, v) = &p.0;
+            (k, v)
+        })
+    }
+
+    /// Checks if an element with the given key is present.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: ?Sized + Eq + Hash,
+        K: Borrow<Q>,
+    {
+        self.map.raw.get(key, &self.pin).is_some()
+    }
+}
+
+/// A consistent read-only view of the [`CloneConMap`] at a given moment.
+///
+/// See the [`snapshot`][CloneConMap::snapshot] method for details.
+pub struct Snapshot<'a, K, V, S>
+where
+    K: Clone + Hash + Eq,
+    V: Clone,
+{
+    inner: raw::snapshot::Snapshot<'a, CloneMapConfig<K, V>, S>,
+}
+
+impl<K, V, S> Snapshot<'_, K, V, S>
+where
+    K: Clone + Hash + Eq,
+    V: Clone,
+    S: BuildHasher,
+{
+    /// Looks up the value of an element.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        Q: ?Sized + Eq + Hash,
+        K: Borrow<Q>,
+    {
+        self.get_key_value(key).map(|(_, v)| v)
+    }
+
+    /// Looks up an element, providing both its key and value.
+    pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        Q: ?Sized + Eq + Hash,
+        K: Borrow<Q>,
+    {
+        self.inner.get(key).map(|p| {
+            let (k, v) = &p.0;
+            (k, v)
+        })
+    }
+
+    /// Checks if an element with the given key is present.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: ?Sized + Eq + Hash,
+        K: Borrow<Q>,
+    {
+        self.inner.get(key).is_some()
+    }
+
+    /// Checks if the snapshot is empty.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Returns an iterator through the elements in the snapshot.
+    pub fn iter(&self) -> Iter<'_, K, V, S> {
+        Iter {
+            inner: self.inner.iter(),
+        }
+    }
+}
+
+type RawEntry<'a, K, V, S> = raw::entry::Entry<'a, CloneMapConfig<K, V>, S>;
+
+/// A view into a single key of the [`CloneConMap`].
+///
+/// See the [`entry`][CloneConMap::entry] method for details.
+pub enum Entry<'a, K, V, S>
+where
+    K: Clone + Hash + Eq,
+    V: Clone,
+{
+    /// There's an element with the key in the map.
+    Occupied(OccupiedEntry<'a, K, V, S>),
+    /// The key is missing from the map.
+    Vacant(VacantEntry<'a, K, V, S>),
+}
+
+impl<K, V, S> Entry<'_, K, V, S>
+where
+    K: Clone + Hash + Eq,
+    V: Clone,
+{
+    /// The key of this entry.
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+}
+
+impl<K, V, S> Debug for Entry<'_, K, V, S>
+where
+    K: Clone + Debug + Hash + Eq,
+    V: Clone + Debug,
+{
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self {
+            Entry::Occupied(entry) => fmt.debug_tuple("Entry").field(entry).finish(),
+            Entry::Vacant(entry) => fmt.debug_tuple("Entry").field(entry).finish(),
+        }
+    }
+}
+
+impl<'a, K, V, S> Entry<'a, K, V, S>
+where
+    K: Clone + Hash + Eq,
+    V: Clone,
+    S: BuildHasher,
+{
+    fn from_raw(raw: RawEntry<'a, K, V, S>, key: K) -> Self {
+        if raw.get().is_some() {
+            Entry::Occupied(OccupiedEntry { raw, key })
+        } else {
+            Entry::Vacant(VacantEntry { raw, key })
+        }
+    }
+
+    // Tries to put the new value in place of whatever the entry is pinned to (None removes it).
+    // Returns if it succeeded together with the entry re-pinned to the current state.
+    fn commit(mut raw: RawEntry<'a, K, V, S>, key: K, value: Option<V>) -> (bool, Self) {
+        let new = value.map(|value| CloneMapPayload((key.clone(), value)));
+        let success = raw.replace(&key, new);
+        (success, Self::from_raw(raw, key))
+    }
+
+    /// Returns the present element or inserts the provided value.
+    pub fn or_insert(self, value: V) -> (K, V) {
+        self.or_insert_with(|| value)
+    }
+
+    /// Returns the present element or inserts a value created by the closure.
+    ///
+    /// If another thread inserts an element concurrently, that one is returned and the created
+    /// value is thrown away.
+    pub fn or_insert_with<F>(self, create: F) -> (K, V)
+    where
+        F: FnOnce() -> V,
+    {
+        match self {
+            Entry::Occupied(entry) => entry.into_element(),
+            Entry::Vacant(entry) => match entry.insert(create()) {
+                Ok(element) => element,
+                Err(Entry::Occupied(entry)) => entry.into_element(),
+                Err(Entry::Vacant(_)) => unreachable!("Only a concurrent insert can make it fail"),
+            },
+        }
+    }
+
+    /// Returns the present element or inserts a default value.
+    pub fn or_default(self) -> (K, V)
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    /// Modifies the present element, if any.
+    ///
+    /// The closure gets the current value and returns a new one to be put in its place. If the
+    /// element changes before the new value is stored, the closure is called again with the fresh
+    /// one, so it may be called multiple times. If the element is removed in the meantime, nothing
+    /// is stored.
+    ///
+    /// Returns the entry pinned to the resulting state.
+    pub fn and_modify<F>(self, mut f: F) -> Self
+    where
+        F: FnMut(&V) -> V,
+    {
+        let mut entry = self;
+        loop {
+            match entry {
+                Entry::Occupied(occupied) => {
+                    let value = f(occupied.get());
+                    let (success, updated) = Self::commit(occupied.raw, occupied.key, Some(value));
+                    if success {
+                        return updated;
+                    }
+                    entry = updated;
+                }
+                vacant => return vacant,
+            }
+        }
+    }
+}
+
+/// An [`Entry`] with an element present in the map.
+pub struct OccupiedEntry<'a, K, V, S>
+where
+    K: Clone + Hash + Eq,
+    V: Clone,
+{
+    raw: RawEntry<'a, K, V, S>,
+    key: K,
+}
+
+impl<K, V, S> OccupiedEntry<'_, K, V, S>
+where
+    K: Clone + Hash + Eq,
+    V: Clone,
+{
+    /// The key of this entry.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// The value of the element this entry is pinned to.
+    pub fn get(&self) -> &V {
+        let payload = self
+            .raw
+            .get()
+            .expect("Occupied entry pinned to a missing element");
+        &(payload.0).1
+    }
+
+    /// Turns the entry into (a copy of) the element it is pinned to.
+    pub fn into_element(self) -> (K, V) {
+        (self.key.clone(), self.get().clone())
+    }
+}
+
+impl<K, V, S> Debug for OccupiedEntry<'_, K, V, S>
+where
+    K: Clone + Debug + Hash + Eq,
+    V: Clone + Debug,
+{
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        fmt.debug_struct("OccupiedEntry")
+            .field("key", self.key())
+            .field("value", self.get())
+            .finish()
+    }
+}
+
+impl<'a, K, V, S> OccupiedEntry<'a, K, V, S>
+where
+    K: Clone + Hash + Eq,
+    V: Clone,
+    S: BuildHasher,
+{
+    /// Replaces the element with a new value.
+    ///
+    /// This succeeds only if the element is still the one the entry is pinned to. In such case,
+    /// the replaced element is returned. Otherwise nothing is modified and the entry pinned to the
+    /// current state is returned as the error.
+    ///
+    /// Note that the elements are compared by identity, not by value. If another thread stores an
+    /// equal value in the meantime, this still fails.
+    pub fn insert(self, value: V) -> Result<(K, V), Entry<'a, K, V, S>> {
+        let previous = (self.key.clone(), self.get().clone());
+        match Entry::commit(self.raw, self.key, Some(value)) {
+            (true, _) => Ok(previous),
+            (false, entry) => Err(entry),
+        }
+    }
+
+    /// Removes the element.
+    ///
+    /// This succeeds only if the element is still the one the entry is pinned to. In such case,
+    /// the removed element is returned. Otherwise nothing is modified and the entry pinned to the
+    /// current state is returned as the error.
+    pub fn remove(self) -> Result<(K, V), Entry<'a, K, V, S>> {
+        let previous = (self.key.clone(), self.get().clone());
+        match Entry::commit(self.raw, self.key, None) {
+            (true, _) => Ok(previous),
+            (false, entry) => Err(entry),
+        }
+    }
+}
+
+/// An [`Entry`] with the key missing from the map.
+pub struct VacantEntry<'a, K, V, S>
+where
+    K: Clone + Hash + Eq,
+    V: Clone,
+{
+    raw: RawEntry<'a, K, V, S>,
+    key: K,
+}
+
+impl<K, V, S> VacantEntry<'_, K, V, S>
+where
+    K: Clone + Hash + Eq,
+    V: Clone,
+{
+    /// The key of this entry.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Turns the entry into the key.
+    pub fn into_key(self) -> K {
+        self.key
+    }
+}
+
+impl<K, V, S> Debug for VacantEntry<'_, K, V, S>
+where
+    K: Clone + Debug + Hash + Eq,
+    V: Clone,
+{
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        fmt.debug_tuple("VacantEntry").field(self.key()).finish()
+    }
+}
+
+impl<'a, K, V, S> VacantEntry<'a, K, V, S>
+where
+    K: Clone + Hash + Eq,
+    V: Clone,
+    S: BuildHasher,
 {
-    type Item = (K, V);
-    fn next(&mut self) -> Option<(K, V)> {
-        self.inner.next().map(|p| (p.0).clone())
+    /// Inserts a new element.
+    ///
+    /// This succeeds only if the key is still missing. In such case, the inserted element is
+    /// returned. Otherwise nothing is modified and the entry pinned to the element inserted in the
+    /// meantime is returned as the error.
+    pub fn insert(self, value: V) -> Result<(K, V), Entry<'a, K, V, S>> {
+        match Entry::commit(self.raw, self.key, Some(value)) {
+            (true, Entry::Occupied(entry)) => Ok(entry.into_element()),
+            (true, Entry::Vacant(_)) => unreachable!("Inserted element is missing"),
+            (false, entry) => Err(entry),
+        }
     }
 }
 
@@ -109,39 +677,118 @@ where
 /// ```
 pub struct CloneConMap<K, V, S = RandomState>
 where
-    K: Clone + Hash + Eq + 'static,
-    V: Clone + 'static,
+    K: Clone + Hash + Eq,
+    V: Clone,
 {
     raw: Raw<CloneMapConfig<K, V>, S>,
 }
 
 impl<K, V> CloneConMap<K, V>
 where
-    K: Clone + Hash + Eq + 'static,
-    V: Clone + 'static,
+    K: Clone + Hash + Eq,
+    V: Clone,
 {
     /// Creates a new empty map.
-    pub fn new() -> Self {
+    pub fn new() -> Self
+    where
+        K: 'static,
+        V: 'static,
+    {
         Self::with_hasher(RandomState::default())
     }
+
+    /// Creates a new empty map, with its own private epoch collector.
+    ///
+    /// See [`with_hasher_scoped`][CloneConMap::with_hasher_scoped] for details.
+    pub fn new_scoped() -> Self {
+        Self::with_hasher_scoped(RandomState::default())
+    }
 }
 
 impl<K, V, S> CloneConMap<K, V, S>
 where
-    K: Clone + Hash + Eq + 'static,
-    V: Clone + 'static,
+    K: Clone + Hash + Eq,
+    V: Clone,
     S: BuildHasher,
 {
     /// Inserts a new element as a tuple `(key, value)`.
     ///
     /// Any previous element with the same key is replaced and returned.
     pub fn insert(&self, key: K, value: V) -> Option<(K, V)> {
-        let pin = crossbeam_epoch::pin();
+        let pin = self.raw.guard();
         self.raw
             .insert(CloneMapPayload((key, value)), &pin)
             .map(|p| p.0.clone())
     }
 
+    /// Inserts multiple elements at once.
+    ///
+    /// This is like calling [insert][CloneConMap::insert] for each of them, but it pins the epoch
+    /// only once and walks the trie in an order that lets the neighbouring keys share the work.
+    /// The replaced elements are returned in the same order as the inserted ones. If the same key
+    /// is present multiple times, the later element wins.
+    ///
+    /// The batch is not atomic, other threads may observe only some of the elements inserted.
+    pub fn insert_many<I>(&self, elements: I) -> Vec<Option<(K, V)>>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let pin = self.raw.guard();
+        self.raw
+            .insert_many(elements.into_iter().map(CloneMapPayload), &pin)
+            .into_iter()
+            .map(|p| p.map(|p| p.0.clone()))
+            .collect()
+    }
+
+    /// Replaces an element, but only if there's already one with the same key.
+    ///
+    /// The previous element is returned. If there's no element with the key, nothing is inserted
+    /// and `None` is returned.
+    pub fn replace(&self, key: K, value: V) -> Option<(K, V)> {
+        let pin = self.raw.guard();
+        self.raw
+            .replace(CloneMapPayload((key, value)), &pin)
+            .map(|p| p.0.clone())
+    }
+
+    /// Replaces the value of an element, but only if the current value is equal to the provided
+    /// one.
+    ///
+    /// This allows optimistic read-modify-write updates without losing concurrent modifications
+    /// from other threads.
+    ///
+    /// On success, the previous element is returned. Otherwise, the element currently present in
+    /// the map (if any) is returned as the error and the new value is not inserted.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use contrie::CloneConMap;
+    ///
+    /// let map = CloneConMap::new();
+    /// map.insert("hello", 1);
+    ///
+    /// assert_eq!(Ok(("hello", 1)), map.compare_and_swap_value("hello", &1, 2));
+    /// assert_eq!(Err(Some(("hello", 2))), map.compare_and_swap_value("hello", &1, 3));
+    /// assert_eq!(Err(None), map.compare_and_swap_value("world", &1, 3));
+    /// ```
+    pub fn compare_and_swap_value(
+        &self,
+        key: K,
+        current: &V,
+        value: V,
+    ) -> Result<(K, V), Option<(K, V)>>
+    where
+        V: PartialEq,
+    {
+        let pin = self.raw.guard();
+        self.raw
+            .replace_if(CloneMapPayload((key, value)), |p| (p.0).1 == *current, &pin)
+            .map(|p| p.0.clone())
+            .map_err(|p| p.map(|p| p.0.clone()))
+    }
+
     /// Looks up or inserts an element as a tuple `(key, value)`.
     ///
     /// It looks up an element. If it isn't present, the provided one is
@@ -163,11 +810,14 @@ where
     /// existing element is returned. This should be relatively rare
     /// (another thread must insert the new element between this method
     /// observes an empty slot and manages to insert the new element).
+    /// If the closure is expensive or has side effects, use
+    /// [get_or_insert_with_once][CloneConMap::get_or_insert_with_once]
+    /// instead.
     pub fn get_or_insert_with<F>(&self, key: K, create: F) -> ExistingOrNew<(K, V)>
     where
         F: FnOnce() -> V,
     {
-        let pin = crossbeam_epoch::pin();
+        let pin = self.raw.guard();
 
         self.raw
             .get_or_insert_with(
@@ -181,6 +831,87 @@ where
             .map(|payload| (payload.0).clone())
     }
 
+    /// Looks up or inserts a newly created element, calling the
+    /// closure at most once.
+    ///
+    /// This is like [get_or_insert_with][CloneConMap::get_or_insert_with],
+    /// but if multiple threads look for the same missing key at the
+    /// same time, only one of them calls its closure. The others block
+    /// until the element is inserted and return it as an existing one.
+    /// If the closure panics, one of the waiting threads calls its own
+    /// closure instead.
+    pub fn get_or_insert_with_once<F>(&self, key: K, create: F) -> ExistingOrNew<(K, V)>
+    where
+        F: FnOnce() -> V,
+    {
+        self.raw
+            .get_or_insert_with_once(key, |key| {
+                let value: V = create();
+                CloneMapPayload((key, value))
+            })
+            .map(|payload| (payload.0).clone())
+    }
+
+    /// Looks up or inserts an element created by a future, driving at
+    /// most one such future per key at a time.
+    ///
+    /// This is the async counterpart of
+    /// [get_or_insert_with_once][CloneConMap::get_or_insert_with_once].
+    /// The first caller to miss the key drives the future returned by
+    /// the closure. Concurrent callers for the same key await the
+    /// element it creates instead of creating their own. If the driving
+    /// future is dropped (or panics) before finishing, one of the
+    /// waiting callers calls its own closure instead.
+    ///
+    /// No epoch pin is held across the `.await` points, so the future
+    /// can be sent between threads and kept pending for a long time
+    /// without blocking reclamation of memory.
+    ///
+    /// Available with the `async` feature.
+    #[cfg(feature = "async")]
+    pub async fn get_or_insert_with_async<F, R>(&self, key: K, create: F) -> ExistingOrNew<(K, V)>
+    where
+        F: FnOnce() -> R,
+        R: Future<Output = V>,
+    {
+        self.raw
+            .get_or_insert_with_async(key, |key| async move {
+                let value: V = create().await;
+                CloneMapPayload((key, value))
+            })
+            .await
+            .map(|payload| (payload.0).clone())
+    }
+
+    /// Looks up or inserts a newly created element, if the creation
+    /// succeeds.
+    ///
+    /// This is like [get_or_insert_with][CloneConMap::get_or_insert_with],
+    /// but the closure may fail. In such case the error is returned
+    /// together with the key and the map is left unmodified.
+    ///
+    /// # Quirks
+    ///
+    /// The closure is called whenever the key is found missing. If
+    /// another thread inserts an element with the same key before the
+    /// new one is put in place, the new one is thrown away and the
+    /// existing one is returned.
+    pub fn get_or_try_insert_with<F, E>(
+        &self,
+        key: K,
+        create: F,
+    ) -> Result<ExistingOrNew<(K, V)>, (K, E)>
+    where
+        F: FnOnce(&K) -> Result<V, E>,
+    {
+        self.raw
+            .get_or_try_insert_with(key, |key| match create(&key) {
+                Ok(value) => Ok(CloneMapPayload((key, value))),
+                Err(e) => Err((key, e)),
+            })
+            .map(|found| found.map(|payload| (payload.0).clone()))
+    }
+
     /// Looks up or inserts a default value of an element.
     ///
     /// This is like [get_or_insert_with][CloneConMap::get_or_insert_with],
@@ -192,6 +923,144 @@ where
     {
         self.get_or_insert_with(key, V::default)
     }
+
+    /// Atomically computes a new value for the key.
+    ///
+    /// The closure gets the current value (if any) and returns the value that should be stored
+    /// instead. Returning `None` removes the element (or leaves the key missing). The new value is
+    /// put in place only if the element hasn't changed since the closure has seen it. Otherwise,
+    /// the closure is called again with the fresh value, until it succeeds. Therefore, no
+    /// concurrent update gets lost, but the closure may be called multiple times and should not
+    /// have side effects.
+    ///
+    /// Returns the newly stored element, if any.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use contrie::CloneConMap;
+    ///
+    /// let map = CloneConMap::new();
+    /// assert_eq!(Some(("hello", 1)), map.compute("hello", |v| Some(v.unwrap_or(&0) + 1)));
+    /// assert_eq!(Some(("hello", 2)), map.compute("hello", |v| Some(v.unwrap_or(&0) + 1)));
+    /// assert_eq!(None, map.compute("hello", |_| None));
+    /// assert!(map.is_empty());
+    /// ```
+    pub fn compute<F>(&self, key: K, mut f: F) -> Option<(K, V)>
+    where
+        F: FnMut(Option<&V>) -> Option<V>,
+    {
+        let pin = self.raw.guard();
+        self.raw
+            .compute(
+                &key,
+                |current| {
+                    f(current.map(|p| &(p.0).1)).map(|value| CloneMapPayload((key.clone(), value)))
+                },
+                &pin,
+            )
+            .map(|p| p.0.clone())
+    }
+
+    /// Atomically computes a new value for the key, but only if it is already present.
+    ///
+    /// This is like [compute][CloneConMap::compute], but the closure is called only if there's an
+    /// element to compute from. Returning `None` from the closure removes the element.
+    ///
+    /// Returns the newly stored element, if any.
+    pub fn compute_if_present<Q, F>(&self, key: &Q, mut f: F) -> Option<(K, V)>
+    where
+        Q: ?Sized + Eq + Hash,
+        K: Borrow<Q>,
+        F: FnMut(&V) -> Option<V>,
+    {
+        let pin = self.raw.guard();
+        self.raw
+            .compute(
+                key,
+                |current| {
+                    let (key, value) = &current?.0;
+                    let value = f(value)?;
+                    Some(CloneMapPayload((key.clone(), value)))
+                },
+                &pin,
+            )
+            .map(|p| p.0.clone())
+    }
+
+    /// Atomically updates the value of an existing element.
+    ///
+    /// This is like [compute_if_present][CloneConMap::compute_if_present], but the element is
+    /// never removed. If there's no element with the key, nothing happens.
+    ///
+    /// Returns the updated element, if any.
+    pub fn update<Q, F>(&self, key: &Q, mut f: F) -> Option<(K, V)>
+    where
+        Q: ?Sized + Eq + Hash,
+        K: Borrow<Q>,
+        F: FnMut(&V) -> V,
+    {
+        self.compute_if_present(key, |current| Some(f(current)))
+    }
+
+    /// Atomically merges a value into the map.
+    ///
+    /// If there's no element with the key, the value is inserted as it is. Otherwise, the closure
+    /// is called with the current and the provided value and its result is stored instead
+    /// (returning `None` removes the element). Just like with [compute][CloneConMap::compute], no
+    /// concurrent update is lost, but the closure may be called multiple times.
+    ///
+    /// Returns the newly stored element, if any.
+    pub fn merge<F>(&self, key: K, value: V, mut f: F) -> Option<(K, V)>
+    where
+        F: FnMut(&V, &V) -> Option<V>,
+    {
+        let pin = self.raw.guard();
+        self.raw
+            .compute(
+                &key,
+                |current| match current {
+                    None => Some(CloneMapPayload((key.clone(), value.clone()))),
+                    Some(current) => {
+                        f(&(current.0).1, &value).map(|value| CloneMapPayload((key.clone(), value)))
+                    }
+                },
+                &pin,
+            )
+            .map(|p| p.0.clone())
+    }
+
+    /// Gets the entry for the given key, for in-place inspection and manipulation.
+    ///
+    /// The entry is pinned to the element present at the time of the call (or to its absence).
+    /// Modifications done through it are compare-and-swap operations ‒ they fail cleanly if the
+    /// element was changed by someone else in the meantime, returning a fresh entry. The
+    /// convenience methods, like [`and_modify`][Entry::and_modify], retry on their own.
+    ///
+    /// The entry holds an epoch pin (see the [`raw`] module), therefore it should not
+    /// be kept around for long.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use contrie::clonemap::{CloneConMap, Entry};
+    ///
+    /// let map = CloneConMap::new();
+    ///
+    /// for word in "hello world hello".split_whitespace() {
+    ///     map.entry(word).and_modify(|cnt| cnt + 1).or_insert(1);
+    /// }
+    /// assert_eq!(2, map.get("hello").unwrap().1);
+    /// assert_eq!(1, map.get("world").unwrap().1);
+    ///
+    /// if let Entry::Occupied(entry) = map.entry("world") {
+    ///     assert_eq!(("world", 1), entry.remove().unwrap());
+    /// }
+    /// assert!(map.get("world").is_none());
+    /// ```
+    pub fn entry(&self, key: K) -> Entry<'_, K, V, S> {
+        Entry::from_raw(raw::entry::Entry::new(&self.raw, &key), key)
+    }
 }
 
 impl<K, V, S> CloneConMap<K, V, S>
@@ -201,30 +1070,318 @@ where
     S: BuildHasher,
 {
     /// Creates a new empty map, but with the provided hasher implementation.
-    pub fn with_hasher(hasher: S) -> Self {
+    pub fn with_hasher(hasher: S) -> Self
+    where
+        K: 'static,
+        V: 'static,
+    {
         Self {
             raw: Raw::with_hasher(hasher),
         }
     }
 
+    /// Creates a new empty map with the provided hasher and a dedicated epoch collector.
+    ///
+    /// The removed elements are destroyed through the given collector instead of the global
+    /// default one. If the map holds the last reference to the collector, all the elements are
+    /// guaranteed to be destroyed by the time the drop of the map returns.
+    ///
+    /// Note that operations on such map are somewhat slower.
+    pub fn with_hasher_and_collector(hasher: S, collector: Collector) -> Self
+    where
+        K: 'static,
+        V: 'static,
+    {
+        Self {
+            raw: Raw::with_hasher_and_collector(hasher, collector),
+        }
+    }
+
+    /// Creates a new empty map with the provided hasher, that may borrow its keys and values.
+    ///
+    /// The map uses its own epoch collector, which is never shared. This makes sure all the
+    /// removed elements are destroyed before the map goes away, so the keys and values don't have
+    /// to be `'static`.
+    ///
+    /// Note that operations on such map are somewhat slower.
+    pub fn with_hasher_scoped(hasher: S) -> Self {
+        Self {
+            raw: Raw::with_hasher_scoped(hasher),
+        }
+    }
+
+    /// Turns on the [snapshots][CloneConMap::snapshot] for this map.
+    ///
+    /// This is meant to be chained right after one of the constructors. Modifications of such map
+    /// are considerably slower, because each one copies the whole path from the root of the trie
+    /// (see [`Config::SNAPSHOTS`]). Lookups and iteration stay the same.
+    pub fn with_snapshots(self) -> Self {
+        Self {
+            raw: self.raw.with_snapshots(),
+        }
+    }
+
     /// Looks up an element.
     pub fn get<Q>(&self, key: &Q) -> Option<(K, V)>
     where
-        Q: ?Sized + Eq + Hash,
-        K: Borrow<Q>,
+        Q: ?Sized + Eq + Hash,
+        K: Borrow<Q>,
+    {
+        let pin = self.raw.guard();
+        self.raw.get(key, &pin).map(|r| (r.0).clone())
+    }
+
+    /// Looks up multiple elements at once.
+    ///
+    /// The results are in the same order as the keys. This is cheaper than calling
+    /// [get][CloneConMap::get] for each of them, as the epoch is pinned only once and the lookups
+    /// of keys close to each other in the trie share part of the walk.
+    pub fn get_many<Q>(&self, keys: &[&Q]) -> Vec<Option<(K, V)>>
+    where
+        Q: ?Sized + Eq + Hash,
+        K: Borrow<Q>,
+    {
+        let pin = self.raw.guard();
+        self.raw
+            .get_many(keys, &pin)
+            .into_iter()
+            .map(|r| r.map(|r| (r.0).clone()))
+            .collect()
+    }
+
+    /// Looks up an element and returns a reference to it, instead of a copy.
+    ///
+    /// The reference holds an epoch pin, so it should not be kept around for extended periods of
+    /// time.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use contrie::CloneConMap;
+    ///
+    /// let map = CloneConMap::new();
+    /// map.insert("hello", vec![1, 2, 3]);
+    /// let found = map.get_ref("hello").unwrap();
+    /// assert_eq!(3, found.1.len());
+    /// ```
+    pub fn get_ref<Q>(&self, key: &Q) -> Option<Ref<'_, K, V>>
+    where
+        Q: ?Sized + Eq + Hash,
+        K: Borrow<Q>,
+    {
+        self.raw
+            .get_ref(key)
+            .map(|r| raw::reference::Ref::map(r, |p| &p.0))
+    }
+
+    /// Pins the epoch and returns a read handle to the map.
+    ///
+    /// The lookups through the handle return references to the keys and values instead of
+    /// copies, bound to the lifetime of the handle. It also saves the cost of pinning for each
+    /// lookup. However, as the handle holds the pin, it should not be kept around for extended
+    /// periods of time.
+    pub fn pin(&self) -> Pinned<'_, K, V, S> {
+        Pinned {
+            map: self,
+            pin: self.raw.guard(),
+        }
+    }
+
+    /// Takes a consistent snapshot of the current content.
+    ///
+    /// Taking the snapshot is cheap and it is not affected by any later modifications of the map.
+    /// Like the [`pin`][CloneConMap::pin], it holds an epoch pin for its whole lifetime.
+    ///
+    /// # Panics
+    ///
+    /// If the map was not created [with the snapshots][CloneConMap::with_snapshots].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use contrie::CloneConMap;
+    ///
+    /// let map = CloneConMap::new().with_snapshots();
+    /// map.insert("a", 1);
+    /// map.insert("b", 2);
+    ///
+    /// let snapshot = map.snapshot();
+    /// map.insert("a", 3);
+    /// map.remove("b");
+    ///
+    /// assert_eq!(Some(&1), snapshot.get("a"));
+    /// assert!(snapshot.contains_key("b"));
+    /// assert_eq!(2, snapshot.iter().count());
+    /// ```
+    pub fn snapshot(&self) -> Snapshot<'_, K, V, S> {
+        Snapshot {
+            inner: self.raw.snapshot(),
+        }
+    }
+
+    /// Removes an element identified by the given key, returning it.
+    pub fn remove<Q>(&self, key: &Q) -> Option<(K, V)>
+    where
+        Q: ?Sized + Eq + Hash,
+        K: Borrow<Q>,
+    {
+        let pin = self.raw.guard();
+        self.raw.remove(key, &pin).map(|r| (r.0).clone())
+    }
+
+    /// Removes multiple elements at once, returning them.
+    ///
+    /// The removed elements are in the same order as the keys. Like
+    /// [insert_many][CloneConMap::insert_many], the batch is done under a single pin, but it is
+    /// not atomic.
+    pub fn remove_many<Q>(&self, keys: &[&Q]) -> Vec<Option<(K, V)>>
+    where
+        Q: ?Sized + Eq + Hash,
+        K: Borrow<Q>,
+    {
+        let pin = self.raw.guard();
+        self.raw
+            .remove_many(keys, &pin)
+            .into_iter()
+            .map(|r| r.map(|r| (r.0).clone()))
+            .collect()
+    }
+
+    /// Removes an element identified by the given key, but only if the predicate accepts it.
+    ///
+    /// The check and the removal are done atomically. If the element gets replaced in between,
+    /// the predicate is consulted again about the new one, so it may be called multiple times.
+    ///
+    /// Returns the removed element, if any.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use contrie::CloneConMap;
+    ///
+    /// let map = CloneConMap::new();
+    /// map.insert("hello", 1);
+    ///
+    /// assert_eq!(None, map.remove_if("hello", |_, v| *v == 2));
+    /// assert_eq!(Some(("hello", 1)), map.remove_if("hello", |_, v| *v == 1));
+    /// assert!(map.is_empty());
+    /// ```
+    pub fn remove_if<Q, F>(&self, key: &Q, mut predicate: F) -> Option<(K, V)>
+    where
+        Q: ?Sized + Eq + Hash,
+        K: Borrow<Q>,
+        F: FnMut(&K, &V) -> bool,
+    {
+        let pin = self.raw.guard();
+        self.raw
+            .remove_if(key, |p| predicate(&(p.0).0, &(p.0).1), &pin)
+            .map(|r| (r.0).clone())
+    }
+
+    /// Removes all the elements the predicate rejects.
+    ///
+    /// This walks the whole map and removes the rejected elements in place, which is cheaper than
+    /// collecting the keys and removing them one by one. Emptied branches are pruned on the way.
+    ///
+    /// This is not an atomic operation. Elements inserted or modified concurrently may or may not
+    /// be visited and the predicate may be called multiple times for the same element.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use contrie::CloneConMap;
+    ///
+    /// let map: CloneConMap<usize, usize> = (0..10).map(|i| (i, i * 10)).collect();
+    /// map.retain(|k, _| k % 2 == 0);
+    ///
+    /// assert_eq!(Some((4, 40)), map.get(&4));
+    /// assert!(map.get(&5).is_none());
+    /// ```
+    pub fn retain<F>(&self, mut predicate: F)
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        let pin = self.raw.guard();
+        self.raw.retain(|p| predicate(&(p.0).0, &(p.0).1), &pin);
+    }
+
+    /// Removes all the elements the predicate rejects, in parallel.
+    ///
+    /// This is the parallel version of [`retain`][CloneConMap::retain], with the same quirks. The
+    /// top levels of the trie are split between the workers, each of them owns a whole subtree.
+    ///
+    /// Available with the `rayon` feature.
+    #[cfg(feature = "rayon")]
+    pub fn par_retain<F>(&self, predicate: F)
+    where
+        K: Send + Sync,
+        V: Send + Sync,
+        S: Sync,
+        F: Fn(&K, &V) -> bool + Sync + Send,
+    {
+        self.raw.par_retain(|p| predicate(&(p.0).0, &(p.0).1));
+    }
+
+    /// Calls the closure on each element, in parallel.
+    ///
+    /// Unlike going through [`par_iter`][CloneConMap::par_iter], this doesn't clone the
+    /// elements.
+    ///
+    /// Available with the `rayon` feature.
+    #[cfg(feature = "rayon")]
+    pub fn par_for_each<F>(&self, f: F)
+    where
+        K: Send + Sync,
+        V: Send + Sync,
+        S: Sync,
+        F: Fn(&K, &V) + Sync + Send,
+    {
+        self.raw.par_for_each(|p| f(&(p.0).0, &(p.0).1));
+    }
+
+    /// Creates a new map with the same keys and the values converted by the closure, in
+    /// parallel.
+    ///
+    /// The new map uses a copy of the hasher, so the elements land in the same places of its trie
+    /// and each worker fills its own subtree.
+    ///
+    /// Available with the `rayon` feature.
+    #[cfg(feature = "rayon")]
+    pub fn par_map_values<U, F>(&self, f: F) -> CloneConMap<K, U, S>
+    where
+        K: Send + Sync + 'static,
+        V: Send + Sync,
+        U: Clone + Send + Sync + 'static,
+        S: Clone + Sync,
+        F: Fn(&K, &V) -> U + Sync + Send,
     {
-        let pin = crossbeam_epoch::pin();
-        self.raw.get(key, &pin).map(|r| (r.0).clone())
+        let new = CloneConMap::with_hasher(self.raw.hasher().clone());
+        new.raw.par_extend(raw::parallel::ParIter::new(
+            &self.raw,
+            |p: &CloneMapPayload<K, V>| {
+                let (key, value) = &p.0;
+                CloneMapPayload((key.clone(), f(key, value)))
+            },
+        ));
+        new
     }
 
-    /// Removes an element identified by the given key, returning it.
-    pub fn remove<Q>(&self, key: &Q) -> Option<(K, V)>
+    /// Creates a copy of the map, in parallel.
+    ///
+    /// This is the parallel version of [`clone`][Clone::clone]. As the copy uses the same hasher,
+    /// each worker fills its own subtree.
+    ///
+    /// Available with the `rayon` feature.
+    #[cfg(feature = "rayon")]
+    pub fn par_clone(&self) -> Self
     where
-        Q: ?Sized + Eq + Hash,
-        K: Borrow<Q>,
+        K: Send + Sync,
+        V: Send + Sync,
+        S: Clone + Sync,
     {
-        let pin = crossbeam_epoch::pin();
-        self.raw.remove(key, &pin).map(|r| (r.0).clone())
+        Self {
+            raw: self.raw.par_clone(),
+        }
     }
 }
 
@@ -242,18 +1399,152 @@ where
         self.raw.is_empty()
     }
 
+    /// Returns the number of elements.
+    ///
+    /// Similar to [is_empty][CloneConMap::is_empty], this is inherently racy if other threads
+    /// modify the map at the same time. The returned number is exact if there are no concurrent
+    /// modifications. Otherwise it is only an estimate.
+    pub fn len(&self) -> usize {
+        self.raw.len()
+    }
+
+    /// Returns an approximate number of elements.
+    ///
+    /// This is cheaper than [len][CloneConMap::len] and is meant for things like statistics. If
+    /// other threads modify the map at the same time, the number might not match any actual state
+    /// of the map.
+    pub fn approx_len(&self) -> usize {
+        self.raw.approx_len()
+    }
+
+    /// Removes all the elements.
+    ///
+    /// This detaches the whole content of the map at once, instead of removing the elements one
+    /// by one. Elements inserted concurrently with the clear may or may not survive.
+    pub fn clear(&self) {
+        let pin = self.raw.guard();
+        self.raw.clear(&pin);
+    }
+
+    /// Removes all the elements and returns them as a new map.
+    ///
+    /// This is like [`clear`][CloneConMap::clear], but the old content is moved into the returned
+    /// map instead of being destroyed. This can be used to atomically rotate the content.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use contrie::CloneConMap;
+    ///
+    /// let window = CloneConMap::new();
+    /// window.insert("requests", 42);
+    ///
+    /// let previous = window.clear_and_take();
+    /// assert!(window.is_empty());
+    /// assert_eq!(Some(("requests", 42)), previous.get("requests"));
+    /// ```
+    pub fn clear_and_take(&self) -> Self
+    where
+        S: Clone,
+    {
+        let pin = self.raw.guard();
+        Self {
+            raw: self.raw.clear_and_take(&pin),
+        }
+    }
+
+    /// Tries to destroy the already removed elements right away.
+    ///
+    /// Removed elements are normally destroyed at some later point, once no thread can be looking
+    /// at them. This forces the collection at a known point. Elements that might still be
+    /// accessed by some other thread (eg. through an iterator) are not destroyed.
+    ///
+    /// This is most useful together with a dedicated collector (see
+    /// [`with_hasher_and_collector`][CloneConMap::with_hasher_and_collector]), as the global one is
+    /// shared with the rest of the program.
+    pub fn flush_garbage(&self) {
+        self.raw.flush_garbage();
+    }
+
     /// Returns an iterator through the elements of the map.
+    #[allow(mismatched_lifetime_syntaxes)]
     pub fn iter(&self) -> Iter<K, V, S> {
         Iter {
             inner: raw::iterator::Iter::new(&self.raw),
         }
     }
+
+    /// Returns an iterator through the elements of the map, starting at the cursor.
+    ///
+    /// Together with [`CursorIter::cursor`], this allows pausing the iteration (and releasing the
+    /// epoch pin it holds) and resuming it later. See [`Cursor`] for the order of the elements.
+    pub fn iter_from(&self, cursor: Cursor<u64>) -> CursorIter<'_, K, V, S>
+    where
+        S: BuildHasher,
+    {
+        CursorIter {
+            inner: self.raw.iter_from(cursor),
+        }
+    }
+
+    /// Returns an iterator through the elements with hashes in the given range.
+    ///
+    /// The range is in the order of the walk through the trie, not the numerical one. In
+    /// particular, the ranges `k..k + 1` for `k` below `1 <<`
+    /// [`LEVEL_BITS`][crate::raw::config::Config::LEVEL_BITS] (with the last one left open) split
+    /// the map into disjoint parts that can be walked by different threads.
+    pub fn iter_hash_range<R>(&self, range: R) -> CursorIter<'_, K, V, S>
+    where
+        S: BuildHasher,
+        R: RangeBounds<u64>,
+    {
+        CursorIter {
+            inner: self.raw.iter_hash_range(range),
+        }
+    }
+
+    /// Returns an iterator through the elements of the map that holds no epoch pin.
+    ///
+    /// The iterator pins only for the duration of each `next` call and remembers just a
+    /// [`Cursor`] in between. Therefore it is `Send` (if the map is `Sync`), so it can be
+    /// held across `.await` points in a multi-threaded runtime or moved to another thread. With
+    /// the `stream` feature, it can be turned into a futures `Stream`. The price is a walk from
+    /// the root of the trie for each element.
+    pub fn send_iter(&self) -> SendIter<'_, K, V, S> {
+        self.send_iter_from(Cursor::default())
+    }
+
+    /// Like [`send_iter`][CloneConMap::send_iter], but starting at the cursor.
+    pub fn send_iter_from(&self, cursor: Cursor<u64>) -> SendIter<'_, K, V, S> {
+        SendIter {
+            inner: raw::cursor::SendIter::new(&self.raw, cursor, |p| (p.0).clone()),
+        }
+    }
+
+    /// Returns a parallel iterator through the elements of the map.
+    ///
+    /// The work is split along the branches of the trie, each part being walked under its own
+    /// epoch pin. However, the thread driving the iterator holds a pin until all the work is
+    /// done, so no memory is reclaimed in the meantime.
+    ///
+    /// Available with the `rayon` feature.
+    #[cfg(feature = "rayon")]
+    pub fn par_iter(&self) -> ParIter<'_, K, V, S>
+    where
+        K: Send + Sync,
+        V: Send + Sync,
+        S: Sync,
+    {
+        ParIter {
+            inner: raw::parallel::ParIter::new(&self.raw, |p| (p.0).clone()),
+        }
+    }
 }
 
 impl<K, V> Default for CloneConMap<K, V>
 where
-    K: Clone + Hash + Eq,
-    V: Clone,
+    K: Clone + Hash + Eq + 'static,
+    V: Clone + 'static,
 {
     fn default() -> Self {
         Self::new()
@@ -277,8 +1568,9 @@ where
     S: Clone + BuildHasher,
 {
     fn clone(&self) -> Self {
-        let builder = self.raw.hash_builder().clone();
-        let mut new = Self::with_hasher(builder);
+        let mut new = Self {
+            raw: self.raw.empty_like(),
+        };
         new.extend(self);
         new
     }
@@ -296,6 +1588,21 @@ where
     }
 }
 
+#[cfg(feature = "rayon")]
+impl<'a, K, V, S> IntoParallelIterator for &'a CloneConMap<K, V, S>
+where
+    K: Clone + Hash + Eq + Send + Sync,
+    V: Clone + Send + Sync,
+    S: Sync,
+{
+    type Item = (K, V);
+    type Iter = ParIter<'a, K, V, S>;
+    fn into_par_iter(self) -> Self::Iter {
+        self.par_iter()
+    }
+}
+
+#[allow(clippy::needless_lifetimes)]
 impl<'a, K, V, S> Extend<(K, V)> for &'a CloneConMap<K, V, S>
 where
     K: Clone + Hash + Eq,
@@ -306,9 +1613,7 @@ where
     where
         T: IntoIterator<Item = (K, V)>,
     {
-        for (k, v) in iter {
-            self.insert(k, v);
-        }
+        self.raw.extend(iter.into_iter().map(CloneMapPayload));
     }
 }
 
@@ -329,8 +1634,8 @@ where
 
 impl<K, V> FromIterator<(K, V)> for CloneConMap<K, V>
 where
-    K: Clone + Hash + Eq,
-    V: Clone,
+    K: Clone + Hash + Eq + 'static,
+    V: Clone + 'static,
 {
     fn from_iter<T>(iter: T) -> Self
     where
@@ -353,13 +1658,13 @@ where
     where
         T: IntoParallelIterator<Item = (K, V)>,
     {
-        par_iter.into_par_iter().for_each(|(k, v)| {
-            self.insert(k.clone(), v.clone());
-        });
+        let mut me: &CloneConMap<_, _, _> = self;
+        me.par_extend(par_iter);
     }
 }
 
 #[cfg(feature = "rayon")]
+#[allow(clippy::needless_lifetimes)]
 impl<'a, K, V, S> ParallelExtend<(K, V)> for &'a CloneConMap<K, V, S>
 where
     K: Clone + Hash + Eq + Send + Sync,
@@ -370,17 +1675,16 @@ where
     where
         T: IntoParallelIterator<Item = (K, V)>,
     {
-        par_iter.into_par_iter().for_each(|(k, v)| {
-            self.insert(k.clone(), v.clone());
-        });
+        self.raw
+            .par_extend(par_iter.into_par_iter().map(CloneMapPayload));
     }
 }
 
 #[cfg(feature = "rayon")]
 impl<K, V> FromParallelIterator<(K, V)> for CloneConMap<K, V>
 where
-    K: Clone + Hash + Eq + Send + Sync,
-    V: Clone + Send + Sync,
+    K: Clone + Hash + Eq + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
 {
     fn from_par_iter<T>(par_iter: T) -> Self
     where
@@ -394,6 +1698,8 @@ where
 
 #[cfg(test)]
 mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
     use crossbeam_utils::thread;
     use std::rc::Rc;
 
@@ -402,7 +1708,7 @@ mod tests {
 
     use super::*;
     use crate::raw::tests::NoHasher;
-    use crate::raw::LEVEL_CELLS;
+    use crate::raw::tests::LEVEL_CELLS;
 
     const TEST_THREADS: usize = 4;
     const TEST_BATCH: usize = 10000;
@@ -415,6 +1721,24 @@ mod tests {
         drop(map);
     }
 
+    #[test]
+    fn pinned_reads() {
+        let map = CloneConMap::new();
+        map.insert("hello".to_owned(), vec![1, 2, 3]);
+        let found = map.get_ref("hello").unwrap();
+        let pinned = map.pin();
+        assert!(map.remove("hello").is_some());
+        assert!(!pinned.contains_key("hello"));
+        assert!(pinned.get("hello").is_none());
+        assert_eq!("hello", found.0);
+        assert_eq!(vec![1, 2, 3], found.1);
+        map.insert("world".to_owned(), vec![]);
+        assert_eq!(
+            Some((&"world".to_owned(), &vec![])),
+            map.pin().get_key_value("world")
+        );
+    }
+
     #[test]
     fn debug_formatting() {
         let map: CloneConMap<&str, &str> = CloneConMap::new();
@@ -583,6 +1907,127 @@ mod tests {
         get_or_insert_many_inner(CloneConMap::with_hasher(NoHasher), TEST_BATCH_SMALL);
     }
 
+    fn get_or_insert_once_inner<H: BuildHasher + Sync>(
+        map: CloneConMap<usize, usize, H>,
+        len: usize,
+    ) {
+        let calls = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for _ in 0..TEST_THREADS {
+                s.spawn(|_| {
+                    for i in 0..len {
+                        let val = map.get_or_insert_with_once(i, || {
+                            calls.fetch_add(1, Ordering::Relaxed);
+                            i
+                        });
+                        assert_eq!(i, val.1);
+                    }
+                });
+            }
+        })
+        .unwrap();
+        // Each value got created exactly once.
+        assert_eq!(len, calls.load(Ordering::Relaxed));
+        assert_eq!(len, map.len());
+    }
+
+    #[test]
+    fn get_or_insert_once() {
+        get_or_insert_once_inner(CloneConMap::new(), TEST_BATCH_SMALL);
+    }
+
+    #[test]
+    fn get_or_insert_once_collision() {
+        get_or_insert_once_inner(CloneConMap::with_hasher(NoHasher), TEST_BATCH_SMALL);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn get_or_insert_async() {
+        use futures::executor::block_on;
+
+        fn check_send<T: Send>(t: T) -> T {
+            t
+        }
+
+        let map = CloneConMap::new();
+        let val = block_on(check_send(map.get_or_insert_with_async(1, || async { 42 })));
+        assert!(val.is_new());
+        assert_eq!(42, val.1);
+        let val = block_on(map.get_or_insert_with_async(1, || async { 0 }));
+        assert!(!val.is_new());
+        assert_eq!(42, val.1);
+    }
+
+    #[test]
+    fn get_or_try_insert_err() {
+        let map = CloneConMap::<_, usize>::new();
+        let (key, e) = map
+            .get_or_try_insert_with("hello", |k| Err(format!("No value for {}", k)))
+            .unwrap_err();
+        assert_eq!("hello", key);
+        assert_eq!("No value for hello", e);
+        assert!(map.is_empty());
+        assert!(map.get("hello").is_none());
+    }
+
+    #[test]
+    fn get_or_try_insert_ok() {
+        let map = CloneConMap::new();
+        let val = map
+            .get_or_try_insert_with("hello", |_| "42".parse::<usize>())
+            .unwrap();
+        assert!(val.is_new());
+        assert_eq!(42, val.1);
+
+        // The closure is not called for an existing element, so the failure doesn't matter.
+        let val = map
+            .get_or_try_insert_with("hello", |_| "nope".parse::<usize>())
+            .unwrap();
+        assert!(!val.is_new());
+        assert_eq!(42, val.1);
+        assert_eq!(1, map.len());
+    }
+
+    #[test]
+    fn batch() {
+        let map = CloneConMap::new();
+        assert!(map.insert(1, "one").is_none());
+        let replaced = map.insert_many(vec![(0, "zero"), (1, "uno"), (2, "two")]);
+        assert_eq!(vec![None, Some((1, "one")), None], replaced);
+        let found = map.get_many(&[&2, &3, &1]);
+        assert_eq!(vec![Some((2, "two")), None, Some((1, "uno"))], found);
+        let removed = map.remove_many(&[&0, &3, &0]);
+        assert_eq!(vec![Some((0, "zero")), None, None], removed);
+        assert_eq!(2, map.len());
+    }
+
+    #[test]
+    fn cursor() {
+        let map: CloneConMap<usize, usize> = (0..100).map(|i| (i, i)).collect();
+        let mut page = map.iter_from(Cursor::default());
+        let mut found = page.by_ref().take(50).collect::<Vec<_>>();
+        let cursor = page.cursor().unwrap();
+        drop(page);
+        found.extend(map.iter_from(cursor));
+        found.sort();
+        assert_eq!((0..100).map(|i| (i, i)).collect::<Vec<_>>(), found);
+    }
+
+    #[test]
+    fn send_iter() {
+        let map: CloneConMap<usize, usize> = (0..100).map(|i| (i, i)).collect();
+        let mut iter = map.send_iter();
+        let first = iter.next().unwrap();
+        let mut found = map
+            .send_iter_from(iter.cursor().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(99, found.len());
+        found.push(first);
+        found.sort();
+        assert_eq!((0..100).map(|i| (i, i)).collect::<Vec<_>>(), found);
+    }
+
     #[test]
     fn simple_remove() {
         let map = CloneConMap::new();
@@ -663,6 +2108,188 @@ mod tests {
         assert!(map.is_empty());
     }
 
+    #[test]
+    fn replace_only_present() {
+        let map = CloneConMap::new();
+        assert!(map.replace("hello", 1).is_none());
+        assert!(map.is_empty());
+        assert!(map.insert("hello", 1).is_none());
+        assert_eq!(Some(("hello", 1)), map.replace("hello", 2));
+        assert_eq!(Some(("hello", 2)), map.get("hello"));
+    }
+
+    #[test]
+    fn compare_and_swap_collision() {
+        let map = CloneConMap::with_hasher(NoHasher);
+        map.insert(1, 1);
+        map.insert(2, 2);
+        assert_eq!(Err(Some((1, 1))), map.compare_and_swap_value(1, &2, 3));
+        assert_eq!(Ok((1, 1)), map.compare_and_swap_value(1, &1, 3));
+        assert_eq!(Err(None), map.compare_and_swap_value(3, &1, 3));
+        assert_eq!(Some((1, 3)), map.get(&1));
+        assert_eq!(Some((2, 2)), map.get(&2));
+        assert_eq!(None, map.get(&3));
+    }
+
+    #[test]
+    fn remove_conditional() {
+        let mut map = CloneConMap::with_hasher(NoHasher);
+        map.insert(1, 1);
+        map.insert(2, 2);
+        assert_eq!(None, map.remove_if(&1, |_, v| *v == 2));
+        assert_eq!(Some((1, 1)), map.remove_if(&1, |_, v| *v == 1));
+        map.raw.assert_pruned();
+        assert_eq!(Some((2, 2)), map.remove_if(&2, |k, _| *k == 2));
+        map.raw.assert_pruned();
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn compute_family() {
+        let mut map = CloneConMap::with_hasher(NoHasher);
+        map.insert(2, 2);
+        assert_eq!(None, map.update(&1, |v| v + 1));
+        assert_eq!(Some((1, 1)), map.merge(1, 1, |a, b| Some(a + b)));
+        assert_eq!(Some((1, 3)), map.merge(1, 2, |a, b| Some(a + b)));
+        assert_eq!(Some((1, 4)), map.update(&1, |v| v + 1));
+        assert_eq!(Some((1, 5)), map.compute(1, |v| v.map(|v| v + 1)));
+        assert_eq!(None, map.compute_if_present(&1, |_| None));
+        assert_eq!(None, map.get(&1));
+        assert_eq!(Some((2, 2)), map.get(&2));
+        map.raw.assert_pruned();
+    }
+
+    #[test]
+    fn par_update() {
+        let map = CloneConMap::new();
+        map.insert("counter", 0);
+        thread::scope(|s| {
+            for _ in 0..TEST_THREADS {
+                s.spawn(|_| {
+                    for _ in 0..TEST_BATCH_SMALL {
+                        map.update("counter", |v| v + 1);
+                    }
+                });
+            }
+        })
+        .unwrap();
+        assert_eq!(
+            Some(("counter", TEST_THREADS * TEST_BATCH_SMALL)),
+            map.get("counter")
+        );
+    }
+
+    #[test]
+    fn entry_conflict() {
+        let map = CloneConMap::new();
+        map.insert("hello", 1);
+        let occupied = map.entry("hello");
+        // Equal value, but a different element, so the entry is outdated
+        map.insert("hello", 1);
+        let fresh = match occupied {
+            Entry::Occupied(entry) => entry.insert(2).unwrap_err(),
+            Entry::Vacant(_) => panic!("Entry should be occupied"),
+        };
+        assert_eq!(Some(("hello", 1)), map.get("hello"));
+        match fresh {
+            Entry::Occupied(entry) => assert_eq!(("hello", 1), entry.insert(3).unwrap()),
+            Entry::Vacant(_) => panic!("Entry should be occupied"),
+        }
+        assert_eq!(Some(("hello", 3)), map.get("hello"));
+
+        let occupied = map.entry("hello");
+        map.remove("hello");
+        match occupied {
+            Entry::Occupied(entry) => match entry.remove().unwrap_err() {
+                Entry::Vacant(entry) => assert_eq!(("hello", 4), entry.insert(4).unwrap()),
+                Entry::Occupied(_) => panic!("Entry should be vacant"),
+            },
+            Entry::Vacant(_) => panic!("Entry should be occupied"),
+        }
+        assert_eq!(Some(("hello", 4)), map.get("hello"));
+    }
+
+    #[test]
+    fn par_entry() {
+        let map = CloneConMap::with_hasher(NoHasher);
+        thread::scope(|s| {
+            for _ in 0..TEST_THREADS {
+                s.spawn(|_| {
+                    for i in 0..TEST_BATCH_SMALL {
+                        map.entry(i % 2).and_modify(|v| v + 1).or_insert(1);
+                    }
+                });
+            }
+        })
+        .unwrap();
+        let total = TEST_THREADS * TEST_BATCH_SMALL / 2;
+        assert_eq!(Some((0, total)), map.get(&0));
+        assert_eq!(Some((1, total)), map.get(&1));
+    }
+
+    #[test]
+    fn par_retain_insert() {
+        let mut map = CloneConMap::new();
+        thread::scope(|s| {
+            s.spawn(|_| {
+                for i in 0..TEST_BATCH {
+                    map.insert(i, i);
+                }
+            });
+            s.spawn(|_| {
+                for _ in 0..TEST_BATCH_SMALL {
+                    map.retain(|_, v| v % 2 == 0);
+                }
+            });
+        })
+        .unwrap();
+        map.retain(|_, v| v % 2 == 0);
+        map.raw.assert_pruned();
+        for i in 0..TEST_BATCH {
+            assert_eq!(i % 2 == 0, map.get(&i).is_some());
+        }
+    }
+
+    /// The snapshot keeps seeing the content from the time it was taken, while other threads
+    /// modify the map.
+    #[test]
+    fn snapshot_isolated() {
+        let map = CloneConMap::with_hasher(NoHasher).with_snapshots();
+        for i in 0..TEST_BATCH_SMALL {
+            map.insert(i, i);
+        }
+        let snapshot = map.snapshot();
+        thread::scope(|s| {
+            for t in 0..TEST_THREADS {
+                let map = &map;
+                s.spawn(move |_| {
+                    for i in 0..TEST_BATCH_SMALL {
+                        if i % TEST_THREADS == t {
+                            map.insert(i, i + 1);
+                        }
+                    }
+                });
+            }
+        })
+        .unwrap();
+        assert_eq!(Some((0, 1)), map.get(&0));
+        let mut content = snapshot.iter().collect::<Vec<_>>();
+        content.sort();
+        assert_eq!(
+            (0..TEST_BATCH_SMALL).map(|i| (i, i)).collect::<Vec<_>>(),
+            content
+        );
+        for i in 0..TEST_BATCH_SMALL {
+            assert_eq!(Some(&i), snapshot.get(&i));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Snapshots are not turned on")]
+    fn snapshot_not_turned_on() {
+        CloneConMap::<usize, usize>::new().snapshot();
+    }
+
     fn iter_test_inner<S: BuildHasher>(map: CloneConMap<usize, usize, S>) {
         for i in 0..TEST_BATCH_SMALL {
             assert!(map.insert(i, i).is_none());
@@ -768,4 +2395,52 @@ mod tests {
         let expected = (0..TEST_BATCH_SMALL).collect::<Vec<_>>();
         assert_eq!(expected, extracted);
     }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn rayon_par_iter() {
+        let map = CloneConMap::new();
+        map.insert_many((0..TEST_BATCH_SMALL).map(|i| (i, i)));
+
+        let mut extracted = map
+            .par_iter()
+            .map(|n| {
+                assert_eq!(n.0, n.1);
+                n.1
+            })
+            .collect::<Vec<_>>();
+        extracted.par_sort();
+
+        let expected = (0..TEST_BATCH_SMALL).collect::<Vec<_>>();
+        assert_eq!(expected, extracted);
+        assert_eq!(TEST_BATCH_SMALL, (&map).into_par_iter().count());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn rayon_par_bulk() {
+        let map = CloneConMap::new();
+        map.insert_many((0..TEST_BATCH_SMALL).map(|i| (i, i)));
+
+        let copy = map.par_clone();
+        let doubled = map.par_map_values(|k, v| k + v);
+        map.par_retain(|k, _| k % 2 == 0);
+        let sum = AtomicUsize::new(0);
+        map.par_for_each(|_, v| {
+            sum.fetch_add(*v, Ordering::Relaxed);
+        });
+
+        let expected = (0..TEST_BATCH_SMALL).filter(|i| i % 2 == 0).sum::<usize>();
+        assert_eq!(expected, sum.into_inner());
+        assert_eq!(
+            (0..TEST_BATCH_SMALL).filter(|i| i % 2 == 0).count(),
+            map.len()
+        );
+        assert_eq!(TEST_BATCH_SMALL, copy.len());
+        assert_eq!(TEST_BATCH_SMALL, doubled.len());
+        for i in 0..TEST_BATCH_SMALL {
+            assert_eq!(i, copy.get(&i).unwrap().1);
+            assert_eq!(2 * i, doubled.get(&i).unwrap().1);
+        }
+    }
 }
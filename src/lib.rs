@@ -5,6 +5,8 @@
 // Note: we can't use forbid(unsafe_code). We do allow unsafe code in the raw submodule (but not
 // outside of it).
 #![deny(missing_docs, warnings, unsafe_code)]
+// Some lints allowed on the older items are unknown to older compilers.
+#![allow(unknown_lints, renamed_and_removed_lints)]
 
 //! A concurrent trie.
 //!
@@ -24,7 +26,9 @@
 //!   removed elements are not deleted at precisely known moment. While the [crossbeam-epoch] is
 //!   usually reasonably fast in collecting garbage, this might be unsuitable for object with
 //!   observable side effects in their destructors (like, containing open files that need to be
-//!   flushed and closed).
+//!   flushed and closed). A map can be given its own [`Collector`][crossbeam_epoch::Collector]
+//!   to destroy the elements before the map's destructor returns, and the collection can be forced
+//!   by `flush_garbage`.
 //! * As even after removing an element this element might be still being accessed by another
 //!   thread, there's no way to get an owned access to the original element once it is inserted.
 //!   Depending on the flavour, the data structure either clones the data or returns [`Arc`]s to
@@ -35,16 +39,22 @@
 //! * The order of modifications as seen by different threads may be different (if one thread
 //!   inserts elements `a` and `b`, another thread may see `b` already inserted while `a` still not
 //!   being present). In other words, the existence of values of different keys is considered
-//!   independent on each other. This limitation might be lifted in the future by letting the user
-//!   configure the desired sequentially consistent behaviour at the cost of further slowdown.
+//!   independent on each other. On the [`raw::Raw`] level, the config can ask for
+//!   [sequentially consistent][raw::config::Config::SEQUENTIALLY_CONSISTENT] behaviour at the
+//!   cost of further slowdown.
 //! * Iteration doesn't take a snapshot at a given time. In other words, if the data structure is
 //!   modified during the iteration (even if by the same thread that iterates), the changes may or
-//!   may not be reflected in the list of iterated elements.
+//!   may not be reflected in the list of iterated elements. Consistent snapshots are available
+//!   for maps created [`with_snapshots`][ConMap::with_snapshots] (at the cost of slower
+//!   modifications).
 //! * Iteration pins an epoch for the whole time it iterates, possibly delaying releasing some
 //!   memory. Therefore, it is advised not to hold onto iterators for extended periods of time.
+//!   Long walks can use [`ConMap::iter_from`] with
+//!   [`repin_every`][map::CursorIter::repin_every], which takes a fresh pin from time to time.
 //! * Because the garbage collection of [crossbeam-epoch] can postpone destroying values for
-//!   arbitrary time, the values and keys stored inside need to be owned (eg. `'static`). This
-//!   limitation will likely be lifted eventually.
+//!   arbitrary time, the values and keys stored inside need to be owned (eg. `'static`). The
+//!   exception are the maps and sets created by the `new_scoped` or `with_hasher_scoped`
+//!   constructors. These use their own private collector, which allows them to hold borrowed data.
 //!
 //! # The gist of the data structure
 //!
@@ -86,7 +96,17 @@
 //! # Features
 //!
 //! If compiled with the `rayon` feature, some parallel traits will be implemented for
-//! the types provided by this crate.
+//! the types provided by this crate. Their contents can also be walked in parallel (`par_iter`).
+//!
+//! The `async` feature adds `get_or_insert_with_async` to the maps, creating missing values by
+//! futures. Concurrent callers for the same key await the one future instead of creating their
+//! own values. No epoch pin is held across the `.await` points.
+//!
+//! The `stream` feature allows turning the `send_iter` iterators, which don't hold an epoch pin
+//! between the elements, into futures `Stream`s.
+//!
+//! The `serde` feature makes the [cursors][raw::cursor::Cursor] of the resumable iteration
+//! serializable.
 //!
 //! [wait-free]: https://en.wikipedia.org/wiki/Non-blocking_algorithm#Wait-freedom
 //! [lock-free]: https://en.wikipedia.org/wiki/Non-blocking_algorithm#Lock-freedom
@@ -107,6 +127,11 @@ pub mod set;
 // Interface is tested through doctests anyway.
 #[cfg(test)]
 mod tests;
+// The compile fail tests are doc tests, so they need to be visible to rustdoc, not to the test
+// build.
+#[cfg(doctest)]
+#[path = "tests/compile_fail.rs"]
+mod compile_fail;
 
 pub use self::clonemap::CloneConMap;
 pub use self::existing_or_new::ExistingOrNew;
@@ -1,19 +1,32 @@
-//! The [`ConMap`][crate::ConMap] type and its helpers.
+//! The [`ConMap`] type and its helpers.
 
 use std::borrow::Borrow;
 use std::collections::hash_map::RandomState;
 use std::fmt::{Debug, Formatter, Result as FmtResult};
+#[cfg(feature = "async")]
+use std::future::Future;
 use std::hash::{BuildHasher, Hash};
 use std::iter::FromIterator;
 use std::marker::PhantomData;
+use std::ops::RangeBounds;
+#[cfg(feature = "stream")]
+use std::pin::Pin;
 use std::sync::Arc;
+#[cfg(feature = "stream")]
+use std::task::{Context, Poll};
 
+use crossbeam_epoch::Collector;
+#[cfg(feature = "stream")]
+use futures_core::Stream;
+#[cfg(feature = "rayon")]
+use rayon::iter::plumbing::UnindexedConsumer;
 #[cfg(feature = "rayon")]
 use rayon::iter::{FromParallelIterator, IntoParallelIterator, ParallelExtend, ParallelIterator};
 
 use crate::existing_or_new::ExistingOrNew;
 use crate::raw::config::Config;
-use crate::raw::{self, Raw};
+use crate::raw::cursor::Cursor;
+use crate::raw::{self, MapGuard, Raw};
 
 // :-( It would be nice if we could provide deref to (K, V). But that is incompatible with unsized
 // values.
@@ -45,6 +58,19 @@ impl<K, V: ?Sized> Element<K, V> {
     }
 }
 
+/// The result of [compare_and_swap_element][ConMap::compare_and_swap_element] and similar
+/// methods.
+///
+/// On success, it holds the replaced element. On failure, it holds the element currently present
+/// in the map, if any.
+pub type SwapResult<K, V> = Result<Arc<Element<K, V>>, Option<Arc<Element<K, V>>>>;
+
+/// The result of [get_or_try_insert_with][ConMap::get_or_try_insert_with].
+///
+/// On success, it holds the found or inserted element. On failure, it holds the key together with
+/// the error of the constructor.
+pub type TryInsertResult<K, V, E> = Result<ExistingOrNew<Arc<Element<K, V>>>, (K, E)>;
+
 struct MapPayload<K, V: ?Sized>(Arc<Element<K, V>>);
 
 impl<K, V: ?Sized> Clone for MapPayload<K, V> {
@@ -63,11 +89,16 @@ struct MapConfig<K, V: ?Sized>(PhantomData<(K, V)>);
 
 impl<K, V> Config for MapConfig<K, V>
 where
-    V: ?Sized + 'static,
-    K: Hash + Eq + 'static,
+    V: ?Sized,
+    K: Hash + Eq,
 {
     type Payload = MapPayload<K, V>;
     type Key = K;
+    type Hash = u64;
+
+    fn same(a: &Self::Payload, b: &Self::Payload) -> bool {
+        Arc::ptr_eq(&a.0, &b.0)
+    }
 }
 
 /// The iterator of the [`ConMap`].
@@ -76,16 +107,16 @@ where
 pub struct Iter<'a, K, V, S>
 where
     // TODO: It would be great if the bounds wouldn't have to be on the struct, only on the impls
-    K: Hash + Eq + 'static,
-    V: ?Sized + 'static,
+    K: Hash + Eq,
+    V: ?Sized,
 {
     inner: raw::iterator::Iter<'a, MapConfig<K, V>, S>,
 }
 
 impl<'a, K, V, S> Iterator for Iter<'a, K, V, S>
 where
-    K: Hash + Eq + 'static,
-    V: ?Sized + 'static,
+    K: Hash + Eq,
+    V: ?Sized,
 {
     type Item = Arc<Element<K, V>>;
     fn next(&mut self) -> Option<Arc<Element<K, V>>> {
@@ -93,737 +124,2886 @@ where
     }
 }
 
-// TODO: Bunch of derives? Which ones? And which one do we need to implement?
-/// A concurrent map.
-///
-/// This flavour stores the data as [`Arc<Element<K, V>>`][Element]. This allows returning handles
-/// to the held values cheaply even if the data is larger or impossible to clone. This has several
-/// consequences:
-///
-/// * It is sometimes less convenient to use.
-/// * It allows the values to be `?Sized` ‒ you can store trait objects or slices as the values
-///   (not the keys).
-/// * Entries can be shared between multiple maps.
-/// * Cloning of the map doesn't clone the data, it will point to the same objects.
-/// * There's another indirection in play.
-///
-/// Iteration returns (cloned) handles to the elements. The [`FromIterator`] and [`Extend`] traits
-/// accept both tuples and element handles. Furthermore, the [`Extend`] is also implemented for
-/// shared references (to allow extending the same map concurrently from multiple threads).
-///
-/// TODO: Support for rayon iterators/extend.
-///
-/// If this is not suitable, the `CloneConMap` can be used instead (TODO: Implement it).
-///
-/// # Examples
-///
-/// ```rust
-/// use contrie::ConMap;
-/// use crossbeam_utils::thread;
-///
-/// let map = ConMap::new();
-///
-/// thread::scope(|s| {
-///     s.spawn(|_| {
-///         map.insert("hello", 1);
-///     });
-///     s.spawn(|_| {
-///         map.insert("world", 2);
-///     });
-/// }).unwrap();
-/// assert_eq!(1, *map.get("hello").unwrap().value());
-/// assert_eq!(2, *map.get("world").unwrap().value());
-/// ```
-///
-/// ```rust
-/// use std::sync::Arc;
-/// use contrie::map::{ConMap, Element};
-/// let map_1: ConMap<usize, [usize]> = ConMap::new();
-///
-/// map_1.insert_element(Arc::new(Element::new(42, [1, 2, 3])));
-/// map_1.insert_element(Arc::new(Element::new(43, [1, 2, 3, 4])));
-///
-/// assert_eq!(3, map_1.get(&42).unwrap().value().len());
+/// The iterator of the [`ConMap`] starting at a [`Cursor`].
 ///
-/// let map_2 = ConMap::new();
-/// map_2.insert_element(map_1.get(&43).unwrap());
-/// ```
-pub struct ConMap<K, V, S = RandomState>
-where
-    // TODO: It would be great if the bounds wouldn't have to be on the struct, only on the impls
-    K: Hash + Eq + 'static,
-    V: ?Sized + 'static,
-{
-    raw: Raw<MapConfig<K, V>, S>,
-}
-
-impl<K, V> ConMap<K, V>
+/// See the [`iter_from`][ConMap::iter_from] and [`iter_hash_range`][ConMap::iter_hash_range]
+/// methods for details.
+pub struct CursorIter<'a, K, V, S>
 where
-    K: Hash + Eq + 'static,
-    V: ?Sized + 'static,
+    K: Hash + Eq,
+    V: ?Sized,
 {
-    /// Creates a new empty map.
-    pub fn new() -> Self {
-        Self::with_hasher(RandomState::default())
-    }
+    inner: raw::cursor::CursorIter<'a, MapConfig<K, V>, S>,
 }
 
-// TODO: Once we have the unsized locals, this should be possible to move into the V: ?Sized block
-impl<K, V, S> ConMap<K, V, S>
+impl<K, V, S> CursorIter<'_, K, V, S>
 where
-    K: Hash + Eq + 'static,
-    V: 'static,
+    K: Hash + Eq,
+    V: ?Sized,
     S: BuildHasher,
 {
-    /// Inserts a new element.
-    ///
-    /// Any previous element with the same key is replaced and returned.
-    pub fn insert(&self, key: K, value: V) -> Option<Arc<Element<K, V>>> {
-        self.insert_element(Arc::new(Element::new(key, value)))
-    }
-
-    /// Looks up or inserts an element.
+    /// The position right after the last returned element.
     ///
-    /// It looks up an element. If it isn't present, the provided one is inserted instead. Either
-    /// way, an element is returned.
-    pub fn get_or_insert(&self, key: K, value: V) -> ExistingOrNew<Arc<Element<K, V>>> {
-        self.get_or_insert_with(key, || value)
+    /// Passing it to [`iter_from`][ConMap::iter_from] resumes the iteration. Returns `None` if
+    /// there are no more elements in the range.
+    pub fn cursor(&self) -> Option<Cursor<u64>> {
+        self.inner.cursor()
     }
 
-    /// Looks up or inserts a newly created element.
-    ///
-    /// It looks up an element. If it isn't present, the provided closure is used to create a new
-    /// one insert it. Either way, an element is returned.
+    /// Makes the iterator release its epoch pin and take a new one after each `n` elements.
     ///
-    /// # Quirks
+    /// This bounds the time the iterator holds back releasing memory, which is useful for long
+    /// walks through large maps. See [`raw::cursor::CursorIter::repin_every`] for details.
     ///
-    /// Due to races in case of concurrent accesses, the closure may be called even if the value is
-    /// not subsequently inserted and an existing element is returned. This should be relatively
-    /// rare (another thread must insert the new element between this method observes an empty slot
-    /// and manages to insert the new element).
-    pub fn get_or_insert_with<F>(&self, key: K, create: F) -> ExistingOrNew<Arc<Element<K, V>>>
-    where
-        F: FnOnce() -> V,
-    {
-        self.get_or_insert_with_element(key, |key| {
-            let value = create();
-            Arc::new(Element::new(key, value))
-        })
-    }
-
-    /// Looks up or inserts a default value of an element.
+    /// # Panics
     ///
-    /// This is like [get_or_insert_with][ConMap::get_or_insert_with], but a default value is used
-    /// instead of manually providing a closure.
-    pub fn get_or_insert_default(&self, key: K) -> ExistingOrNew<Arc<Element<K, V>>>
-    where
-        V: Default,
-    {
-        self.get_or_insert_with(key, V::default)
+    /// If `n` is 0.
+    pub fn repin_every(self, n: usize) -> Self {
+        CursorIter {
+            inner: self.inner.repin_every(n),
+        }
     }
 }
 
-impl<K, V, S> ConMap<K, V, S>
+impl<K, V, S> Iterator for CursorIter<'_, K, V, S>
 where
     K: Hash + Eq,
     V: ?Sized,
     S: BuildHasher,
 {
-    /// Creates a new empty map, but with the provided hasher implementation.
-    pub fn with_hasher(hasher: S) -> Self {
-        Self {
-            raw: Raw::with_hasher(hasher),
-        }
-    }
-
-    /// Inserts a new element.
-    ///
-    /// This acts the same as [insert][ConMap::insert], but takes the already created element. It
-    /// can be used when:
-    ///
-    /// * `V: ?Sized`.
-    /// * You want to insert the same element into multiple maps.
-    pub fn insert_element(&self, element: Arc<Element<K, V>>) -> Option<Arc<Element<K, V>>> {
-        let pin = crossbeam_epoch::pin();
-        self.raw
-            .insert(MapPayload(element), &pin)
-            .map(|p| Arc::clone(&p.0))
-    }
-
-    /// Looks up or inserts a new element.
-    ///
-    /// This is the same as [get_or_insert_with][ConMap::get_or_insert_with], but the closure
-    /// returns a pre-created element. This can be used when:
-    ///
-    /// * `V: ?Sized`.
-    /// * You want to insert the same element into multiple maps.
-    pub fn get_or_insert_with_element<F>(
-        &self,
-        key: K,
-        create: F,
-    ) -> ExistingOrNew<Arc<Element<K, V>>>
-    where
-        F: FnOnce(K) -> Arc<Element<K, V>>,
-    {
-        let pin = crossbeam_epoch::pin();
-        self.raw
-            .get_or_insert_with(key, |key| MapPayload(create(key)), &pin)
-            .map(|payload| Arc::clone(&payload.0))
-    }
+    type Item = Arc<Element<K, V>>;
 
-    /// Looks up an element.
-    pub fn get<Q>(&self, key: &Q) -> Option<Arc<Element<K, V>>>
-    where
-        Q: ?Sized + Eq + Hash,
-        K: Borrow<Q>,
-    {
-        let pin = crossbeam_epoch::pin();
-        self.raw.get(key, &pin).map(|r| Arc::clone(&r.0))
+    fn next(&mut self) -> Option<Arc<Element<K, V>>> {
+        self.inner.next().map(|p| Arc::clone(&p.0))
     }
+}
 
-    /// Removes an element identified by the given key, returning it.
-    pub fn remove<Q>(&self, key: &Q) -> Option<Arc<Element<K, V>>>
-    where
-        Q: ?Sized + Eq + Hash,
-        K: Borrow<Q>,
-    {
-        let pin = crossbeam_epoch::pin();
-        self.raw.remove(key, &pin).map(|r| Arc::clone(&r.0))
-    }
+/// The iterator of the [`ConMap`] holding no epoch pin.
+///
+/// See the [`send_iter`][ConMap::send_iter] method for details.
+pub struct SendIter<'a, K, V, S>
+where
+    K: Hash + Eq,
+    V: ?Sized,
+{
+    inner: raw::cursor::SendIter<'a, MapConfig<K, V>, S, ExtractElement<K, V>>,
 }
 
-impl<K, V, S> ConMap<K, V, S>
+impl<'a, K, V, S> SendIter<'a, K, V, S>
 where
     K: Hash + Eq,
     V: ?Sized,
 {
-    /// Checks if the map is currently empty.
+    /// The position right after the last returned element.
     ///
-    /// Note that due to the nature of concurrent map, this is inherently racy ‒ another thread may
-    /// add or remove elements between you call this method and act based on the result.
-    pub fn is_empty(&self) -> bool {
-        self.raw.is_empty()
+    /// See [`CursorIter::cursor`].
+    pub fn cursor(&self) -> Option<Cursor<u64>> {
+        self.inner.cursor()
     }
 
-    /// Returns an iterator through the elements of the map.
-    pub fn iter(&self) -> Iter<K, V, S> {
-        Iter {
-            inner: raw::iterator::Iter::new(&self.raw),
+    /// Turns the iterator into a futures `Stream`.
+    ///
+    /// Available with the `stream` feature.
+    #[cfg(feature = "stream")]
+    pub fn into_stream(self) -> SendStream<'a, K, V, S> {
+        SendStream {
+            inner: self.inner.into_stream(),
         }
     }
 }
 
-impl<K, V> Default for ConMap<K, V>
+impl<K, V, S> Iterator for SendIter<'_, K, V, S>
 where
     K: Hash + Eq,
     V: ?Sized,
+    S: BuildHasher,
 {
-    fn default() -> Self {
-        Self::new()
+    type Item = Arc<Element<K, V>>;
+
+    fn next(&mut self) -> Option<Arc<Element<K, V>>> {
+        self.inner.next()
     }
 }
 
-impl<K, V, S> Debug for ConMap<K, V, S>
+/// The [`SendIter`] as a futures `Stream`.
+///
+/// Created by [`SendIter::into_stream`], available with the `stream` feature.
+#[cfg(feature = "stream")]
+pub struct SendStream<'a, K, V, S>
 where
-    K: Debug + Hash + Eq,
-    V: Debug + ?Sized,
+    K: Hash + Eq,
+    V: ?Sized,
 {
-    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
-        let mut d = fmt.debug_map();
-        // TODO: As we return Arcs, it seem we can't use the iterator approach with .map :-(
-        // This might hint to need for better iteration API?
-        for n in self {
-            // Hack: As of 1.37.0 the parameters to entry need to be &Sized. By using a double-ref,
-            // we satisfy the compiler's pickiness in that regard.
-            let val: &&V = &n.value();
-            d.entry(n.key() as &dyn Debug, val);
-        }
-        d.finish()
-    }
+    inner: raw::cursor::SendStream<'a, MapConfig<K, V>, S, ExtractElement<K, V>>,
 }
 
-impl<K, V, S> Clone for ConMap<K, V, S>
+#[cfg(feature = "stream")]
+impl<K, V, S> SendStream<'_, K, V, S>
 where
     K: Hash + Eq,
     V: ?Sized,
-    S: Clone + BuildHasher,
 {
-    fn clone(&self) -> Self {
-        let builder = self.raw.hash_builder().clone();
-        let mut new = Self::with_hasher(builder);
-        new.extend(self);
-        new
+    /// The position right after the last returned element.
+    ///
+    /// See [`CursorIter::cursor`].
+    pub fn cursor(&self) -> Option<Cursor<u64>> {
+        self.inner.cursor()
     }
 }
 
-impl<'a, K, V, S> IntoIterator for &'a ConMap<K, V, S>
+#[cfg(feature = "stream")]
+impl<K, V, S> Stream for SendStream<'_, K, V, S>
 where
     K: Hash + Eq,
     V: ?Sized,
+    S: BuildHasher,
 {
     type Item = Arc<Element<K, V>>;
-    type IntoIter = Iter<'a, K, V, S>;
-    fn into_iter(self) -> Self::IntoIter {
-        self.iter()
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Arc<Element<K, V>>>> {
+        Pin::new(&mut self.inner).poll_next(cx)
     }
 }
 
-impl<'a, K, V, S> Extend<Arc<Element<K, V>>> for &'a ConMap<K, V, S>
+/// Turns the payload into an element returned by the parallel and `Send` iterators.
+type ExtractElement<K, V> = fn(&MapPayload<K, V>) -> Arc<Element<K, V>>;
+
+/// The parallel iterator of the [`ConMap`].
+///
+/// See the [`par_iter`][ConMap::par_iter] method for details. Available with the `rayon` feature.
+#[cfg(feature = "rayon")]
+pub struct ParIter<'a, K, V, S>
 where
     K: Hash + Eq,
     V: ?Sized,
-    S: BuildHasher,
 {
-    fn extend<T>(&mut self, iter: T)
-    where
-        T: IntoIterator<Item = Arc<Element<K, V>>>,
-    {
-        for n in iter {
-            self.insert_element(n);
-        }
-    }
+    inner: raw::parallel::ParIter<'a, MapConfig<K, V>, S, ExtractElement<K, V>>,
 }
 
-impl<'a, K, V, S> Extend<(K, V)> for &'a ConMap<K, V, S>
+#[cfg(feature = "rayon")]
+impl<K, V, S> ParallelIterator for ParIter<'_, K, V, S>
 where
-    K: Hash + Eq,
-    S: BuildHasher,
+    K: Hash + Eq + Send + Sync,
+    V: ?Sized + Send + Sync,
+    S: Sync,
 {
-    fn extend<T>(&mut self, iter: T)
+    type Item = Arc<Element<K, V>>;
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
     where
-        T: IntoIterator<Item = (K, V)>,
+        C: UnindexedConsumer<Self::Item>,
     {
-        self.extend(iter.into_iter().map(|(k, v)| Arc::new(Element::new(k, v))));
+        self.inner.drive_unindexed(consumer)
     }
 }
 
-impl<K, V, S> Extend<Arc<Element<K, V>>> for ConMap<K, V, S>
+/// A reference to an element of the [`ConMap`].
+///
+/// See the [`get_ref`][ConMap::get_ref] method for details.
+pub type Ref<'a, K, V> = raw::reference::Ref<'a, Element<K, V>>;
+
+/// A read handle to the [`ConMap`], holding an epoch pin.
+///
+/// See the [`pin`][ConMap::pin] method for details.
+pub struct Pinned<'a, K, V, S>
 where
     K: Hash + Eq,
     V: ?Sized,
-    S: BuildHasher,
 {
-    fn extend<T>(&mut self, iter: T)
-    where
-        T: IntoIterator<Item = Arc<Element<K, V>>>,
-    {
-        let mut me: &ConMap<_, _, _> = self;
-        me.extend(iter);
-    }
+    map: &'a ConMap<K, V, S>,
+    pin: MapGuard<'a>,
 }
 
-impl<K, V, S> Extend<(K, V)> for ConMap<K, V, S>
+impl<K, V, S> Pinned<'_, K, V, S>
 where
     K: Hash + Eq,
+    V: ?Sized,
     S: BuildHasher,
 {
-    fn extend<T>(&mut self, iter: T)
+    /// Looks up the value of an element.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
     where
-        T: IntoIterator<Item = (K, V)>,
+        Q: ?Sized + Eq + Hash,
+        K: Borrow<Q>,
     {
-        let mut me: &ConMap<_, _, _> = self;
-        me.extend(iter);
+        self.get_key_value(key).map(|(_, v)| v)
+    }
+
+    /// Looks up an element, providing both its key and value.
+    pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        Q: ?Sized + Eq + Hash,
+        K: Borrow<Q>,
+    {
+        self.map
+            .raw
+            .get(key, &self.pin)
+            .map(|p| (p.0.key(), p.0.value()))
+    }
+
+    /// Checks if an element with the given key is present.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: ?Sized + Eq + Hash,
+        K: Borrow<Q>,
+    {
+        self.map.raw.get(key, &self.pin).is_some()
     }
 }
 
-impl<K, V> FromIterator<Arc<Element<K, V>>> for ConMap<K, V>
+/// A consistent read-only view of the [`ConMap`] at a given moment.
+///
+/// See the [`snapshot`][ConMap::snapshot] method for details.
+pub struct Snapshot<'a, K, V, S>
 where
     K: Hash + Eq,
     V: ?Sized,
 {
-    fn from_iter<T>(iter: T) -> Self
-    where
-        T: IntoIterator<Item = Arc<Element<K, V>>>,
-    {
-        let mut me = ConMap::new();
-        me.extend(iter);
-        me
-    }
+    inner: raw::snapshot::Snapshot<'a, MapConfig<K, V>, S>,
 }
 
-impl<K, V> FromIterator<(K, V)> for ConMap<K, V>
+impl<K, V, S> Snapshot<'_, K, V, S>
 where
     K: Hash + Eq,
+    V: ?Sized,
+    S: BuildHasher,
 {
-    fn from_iter<T>(iter: T) -> Self
+    /// Looks up the value of an element.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
     where
-        T: IntoIterator<Item = (K, V)>,
+        Q: ?Sized + Eq + Hash,
+        K: Borrow<Q>,
     {
-        let mut me = ConMap::new();
-        me.extend(iter);
-        me
+        self.get_key_value(key).map(|(_, v)| v)
     }
-}
 
-#[cfg(feature = "rayon")]
-impl<'a, K, V, S> ParallelExtend<Arc<Element<K, V>>> for &'a ConMap<K, V, S>
-where
-    K: Hash + Eq + Send + Sync,
-    V: ?Sized + Send + Sync,
-    S: BuildHasher + Sync,
-{
-    fn par_extend<T>(&mut self, par_iter: T)
+    /// Looks up an element, providing both its key and value.
+    pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
     where
-        T: IntoParallelIterator<Item = Arc<Element<K, V>>>,
+        Q: ?Sized + Eq + Hash,
+        K: Borrow<Q>,
     {
-        par_iter.into_par_iter().for_each(|n| {
-            self.insert_element(n);
-        });
+        self.inner.get(key).map(|p| (p.0.key(), p.0.value()))
+    }
+
+    /// Checks if an element with the given key is present.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: ?Sized + Eq + Hash,
+        K: Borrow<Q>,
+    {
+        self.inner.get(key).is_some()
+    }
+
+    /// Checks if the snapshot is empty.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Returns an iterator through the elements in the snapshot.
+    pub fn iter(&self) -> Iter<'_, K, V, S> {
+        Iter {
+            inner: self.inner.iter(),
+        }
     }
 }
 
-#[cfg(feature = "rayon")]
-impl<K, V, S> ParallelExtend<(K, V)> for ConMap<K, V, S>
+type RawEntry<'a, K, V, S> = raw::entry::Entry<'a, MapConfig<K, V>, S>;
+
+/// A view into a single key of the [`ConMap`].
+///
+/// See the [`entry`][ConMap::entry] method for details.
+pub enum Entry<'a, K, V, S>
 where
-    K: Hash + Eq + Send + Sync,
-    S: BuildHasher + Sync,
-    V: Send + Sync,
+    K: Hash + Eq,
 {
-    fn par_extend<T>(&mut self, par_iter: T)
-    where
-        T: IntoParallelIterator<Item = (K, V)>,
-    {
-        self.par_extend(
-            par_iter
-                .into_par_iter()
-                .map(|(k, v)| Arc::new(Element::new(k, v))),
-        );
+    /// There's an element with the key in the map.
+    Occupied(OccupiedEntry<'a, K, V, S>),
+    /// The key is missing from the map.
+    Vacant(VacantEntry<'a, K, V, S>),
+}
+
+impl<K, V, S> Entry<'_, K, V, S>
+where
+    K: Hash + Eq,
+{
+    /// The key of this entry.
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
     }
 }
 
-#[cfg(feature = "rayon")]
-impl<K, V, S> ParallelExtend<Arc<Element<K, V>>> for ConMap<K, V, S>
+impl<K, V, S> Debug for Entry<'_, K, V, S>
 where
-    K: Hash + Eq + Send + Sync,
-    V: ?Sized + Send + Sync,
-    S: BuildHasher + Sync,
+    K: Debug + Hash + Eq,
+    V: Debug,
 {
-    fn par_extend<T>(&mut self, par_iter: T)
-    where
-        T: IntoParallelIterator<Item = Arc<Element<K, V>>>,
-    {
-        let mut me: &ConMap<_, _, _> = self;
-        me.par_extend(par_iter);
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self {
+            Entry::Occupied(entry) => fmt.debug_tuple("Entry").field(entry).finish(),
+            Entry::Vacant(entry) => fmt.debug_tuple("Entry").field(entry).finish(),
+        }
     }
 }
 
-#[cfg(feature = "rayon")]
-impl<'a, K, V, S> ParallelExtend<(K, V)> for &'a ConMap<K, V, S>
+impl<'a, K, V, S> Entry<'a, K, V, S>
 where
-    K: Hash + Eq + Send + Sync,
-    S: BuildHasher + Sync,
-    V: Send + Sync,
+    K: Hash + Eq + Clone,
+    S: BuildHasher,
 {
-    fn par_extend<T>(&mut self, par_iter: T)
+    fn from_raw(raw: RawEntry<'a, K, V, S>, key: K) -> Self {
+        if raw.get().is_some() {
+            Entry::Occupied(OccupiedEntry { raw, key })
+        } else {
+            Entry::Vacant(VacantEntry { raw, key })
+        }
+    }
+
+    // Tries to put the new value in place of whatever the entry is pinned to (None removes it).
+    // Returns if it succeeded together with the entry re-pinned to the current state.
+    fn commit(mut raw: RawEntry<'a, K, V, S>, key: K, value: Option<V>) -> (bool, Self) {
+        let new = value.map(|value| MapPayload(Arc::new(Element::new(key.clone(), value))));
+        let success = raw.replace(&key, new);
+        (success, Self::from_raw(raw, key))
+    }
+
+    /// Returns the present element or inserts the provided value.
+    pub fn or_insert(self, value: V) -> Arc<Element<K, V>> {
+        self.or_insert_with(|| value)
+    }
+
+    /// Returns the present element or inserts a value created by the closure.
+    ///
+    /// If another thread inserts an element concurrently, that one is returned and the created
+    /// value is thrown away.
+    pub fn or_insert_with<F>(self, create: F) -> Arc<Element<K, V>>
     where
-        T: IntoParallelIterator<Item = (K, V)>,
+        F: FnOnce() -> V,
     {
-        self.par_extend(
-            par_iter
-                .into_par_iter()
-                .map(|(k, v)| Arc::new(Element::new(k, v))),
-        );
+        match self {
+            Entry::Occupied(entry) => entry.into_element(),
+            Entry::Vacant(entry) => match entry.insert(create()) {
+                Ok(element) => element,
+                Err(Entry::Occupied(entry)) => entry.into_element(),
+                Err(Entry::Vacant(_)) => unreachable!("Only a concurrent insert can make it fail"),
+            },
+        }
+    }
+
+    /// Returns the present element or inserts a default value.
+    pub fn or_default(self) -> Arc<Element<K, V>>
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    /// Modifies the present element, if any.
+    ///
+    /// The closure gets the current value and returns a new one to be put in its place. If the
+    /// element changes before the new value is stored, the closure is called again with the fresh
+    /// one, so it may be called multiple times. If the element is removed in the meantime, nothing
+    /// is stored.
+    ///
+    /// Returns the entry pinned to the resulting state.
+    pub fn and_modify<F>(self, mut f: F) -> Self
+    where
+        F: FnMut(&V) -> V,
+    {
+        let mut entry = self;
+        loop {
+            match entry {
+                Entry::Occupied(occupied) => {
+                    let value = f(occupied.get());
+                    let (success, updated) = Self::commit(occupied.raw, occupied.key, Some(value));
+                    if success {
+                        return updated;
+                    }
+                    entry = updated;
+                }
+                vacant => return vacant,
+            }
+        }
     }
 }
 
-#[cfg(feature = "rayon")]
-impl<K, V> FromParallelIterator<Arc<Element<K, V>>> for ConMap<K, V>
+/// An [`Entry`] with an element present in the map.
+pub struct OccupiedEntry<'a, K, V, S>
 where
-    K: Hash + Eq + Send + Sync,
-    V: ?Sized + Send + Sync,
+    K: Hash + Eq,
 {
-    fn from_par_iter<T>(par_iter: T) -> Self
-    where
-        T: IntoParallelIterator<Item = Arc<Element<K, V>>>,
-    {
-        let mut me = ConMap::new();
-        me.par_extend(par_iter);
-        me
+    raw: RawEntry<'a, K, V, S>,
+    key: K,
+}
+
+impl<K, V, S> OccupiedEntry<'_, K, V, S>
+where
+    K: Hash + Eq,
+{
+    /// The key of this entry.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// The element this entry is pinned to.
+    pub fn element(&self) -> &Arc<Element<K, V>> {
+        &self
+            .raw
+            .get()
+            .expect("Occupied entry pinned to a missing element")
+            .0
+    }
+
+    /// The value of the element this entry is pinned to.
+    pub fn get(&self) -> &V {
+        self.element().value()
+    }
+
+    /// Turns the entry into the element it is pinned to.
+    pub fn into_element(self) -> Arc<Element<K, V>> {
+        Arc::clone(self.element())
     }
 }
 
-#[cfg(feature = "rayon")]
-impl<K, V> FromParallelIterator<(K, V)> for ConMap<K, V>
+impl<K, V, S> Debug for OccupiedEntry<'_, K, V, S>
 where
-    K: Hash + Eq + Send + Sync,
-    V: Send + Sync,
+    K: Debug + Hash + Eq,
+    V: Debug,
 {
-    fn from_par_iter<T>(par_iter: T) -> Self
-    where
-        T: IntoParallelIterator<Item = (K, V)>,
-    {
-        let mut me = ConMap::new();
-        me.par_extend(par_iter);
-        me
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        fmt.debug_struct("OccupiedEntry")
+            .field("key", self.key())
+            .field("value", self.get())
+            .finish()
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use crossbeam_utils::thread;
+impl<'a, K, V, S> OccupiedEntry<'a, K, V, S>
+where
+    K: Hash + Eq + Clone,
+    S: BuildHasher,
+{
+    /// Replaces the element with a new value.
+    ///
+    /// This succeeds only if the element is still the one the entry is pinned to. In such case,
+    /// the replaced element is returned. Otherwise nothing is modified and the entry pinned to the
+    /// current state is returned as the error.
+    pub fn insert(self, value: V) -> Result<Arc<Element<K, V>>, Entry<'a, K, V, S>> {
+        let previous = Arc::clone(self.element());
+        match Entry::commit(self.raw, self.key, Some(value)) {
+            (true, _) => Ok(previous),
+            (false, entry) => Err(entry),
+        }
+    }
 
-    #[cfg(feature = "rayon")]
-    use rayon::prelude::*;
+    /// Removes the element.
+    ///
+    /// This succeeds only if the element is still the one the entry is pinned to. In such case,
+    /// the removed element is returned. Otherwise nothing is modified and the entry pinned to the
+    /// current state is returned as the error.
+    pub fn remove(self) -> Result<Arc<Element<K, V>>, Entry<'a, K, V, S>> {
+        let previous = Arc::clone(self.element());
+        match Entry::commit(self.raw, self.key, None) {
+            (true, _) => Ok(previous),
+            (false, entry) => Err(entry),
+        }
+    }
+}
 
-    use super::*;
-    use crate::raw::tests::NoHasher;
-    use crate::raw::LEVEL_CELLS;
+/// An [`Entry`] with the key missing from the map.
+pub struct VacantEntry<'a, K, V, S>
+where
+    K: Hash + Eq,
+{
+    raw: RawEntry<'a, K, V, S>,
+    key: K,
+}
 
-    const TEST_THREADS: usize = 4;
-    const TEST_BATCH: usize = 10000;
-    const TEST_BATCH_SMALL: usize = 100;
-    const TEST_REP: usize = 20;
+impl<K, V, S> VacantEntry<'_, K, V, S>
+where
+    K: Hash + Eq,
+{
+    /// The key of this entry.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
 
-    #[test]
-    fn create_destroy() {
-        let map: ConMap<String, usize> = ConMap::new();
-        drop(map);
+    /// Turns the entry into the key.
+    pub fn into_key(self) -> K {
+        self.key
     }
+}
 
-    #[test]
-    fn lookup_empty() {
-        let map: ConMap<String, usize> = ConMap::new();
-        assert!(map.get("hello").is_none());
+impl<K, V, S> Debug for VacantEntry<'_, K, V, S>
+where
+    K: Debug + Hash + Eq,
+{
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        fmt.debug_tuple("VacantEntry").field(self.key()).finish()
     }
+}
 
-    #[test]
-    fn insert_lookup() {
-        let map = ConMap::new();
-        assert!(map.insert("hello", "world").is_none());
-        assert!(map.get("world").is_none());
-        let found = map.get("hello").unwrap();
-        assert_eq!(Element::new("hello", "world"), *found);
+impl<'a, K, V, S> VacantEntry<'a, K, V, S>
+where
+    K: Hash + Eq + Clone,
+    S: BuildHasher,
+{
+    /// Inserts a new element.
+    ///
+    /// This succeeds only if the key is still missing. In such case, the inserted element is
+    /// returned. Otherwise nothing is modified and the entry pinned to the element inserted in the
+    /// meantime is returned as the error.
+    pub fn insert(self, value: V) -> Result<Arc<Element<K, V>>, Entry<'a, K, V, S>> {
+        match Entry::commit(self.raw, self.key, Some(value)) {
+            (true, Entry::Occupied(entry)) => Ok(entry.into_element()),
+            (true, Entry::Vacant(_)) => unreachable!("Inserted element is missing"),
+            (false, entry) => Err(entry),
+        }
+    }
+}
+
+// TODO: Bunch of derives? Which ones? And which one do we need to implement?
+/// A concurrent map.
+///
+/// This flavour stores the data as [`Arc<Element<K, V>>`][Element]. This allows returning handles
+/// to the held values cheaply even if the data is larger or impossible to clone. This has several
+/// consequences:
+///
+/// * It is sometimes less convenient to use.
+/// * It allows the values to be `?Sized` ‒ you can store trait objects or slices as the values
+///   (not the keys).
+/// * Entries can be shared between multiple maps.
+/// * Cloning of the map doesn't clone the data, it will point to the same objects.
+/// * There's another indirection in play.
+///
+/// Iteration returns (cloned) handles to the elements. The [`FromIterator`] and [`Extend`] traits
+/// accept both tuples and element handles. Furthermore, the [`Extend`] is also implemented for
+/// shared references (to allow extending the same map concurrently from multiple threads).
+///
+/// With the `rayon` feature, the same goes for the parallel iterators (`par_iter`,
+/// `ParallelExtend` and others).
+///
+/// If this is not suitable, the `CloneConMap` can be used instead.
+///
+/// # Examples
+///
+/// ```rust
+/// use contrie::ConMap;
+/// use crossbeam_utils::thread;
+///
+/// let map = ConMap::new();
+///
+/// thread::scope(|s| {
+///     s.spawn(|_| {
+///         map.insert("hello", 1);
+///     });
+///     s.spawn(|_| {
+///         map.insert("world", 2);
+///     });
+/// }).unwrap();
+/// assert_eq!(1, *map.get("hello").unwrap().value());
+/// assert_eq!(2, *map.get("world").unwrap().value());
+/// ```
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use contrie::map::{ConMap, Element};
+/// let map_1: ConMap<usize, [usize]> = ConMap::new();
+///
+/// map_1.insert_element(Arc::new(Element::new(42, [1, 2, 3])));
+/// map_1.insert_element(Arc::new(Element::new(43, [1, 2, 3, 4])));
+///
+/// assert_eq!(3, map_1.get(&42).unwrap().value().len());
+///
+/// let map_2 = ConMap::new();
+/// map_2.insert_element(map_1.get(&43).unwrap());
+/// ```
+pub struct ConMap<K, V, S = RandomState>
+where
+    // TODO: It would be great if the bounds wouldn't have to be on the struct, only on the impls
+    K: Hash + Eq,
+    V: ?Sized,
+{
+    raw: Raw<MapConfig<K, V>, S>,
+}
+
+impl<K, V> ConMap<K, V>
+where
+    K: Hash + Eq,
+    V: ?Sized,
+{
+    /// Creates a new empty map.
+    pub fn new() -> Self
+    where
+        K: 'static,
+        V: 'static,
+    {
+        Self::with_hasher(RandomState::default())
+    }
+
+    /// Creates a new empty map, with its own private epoch collector.
+    ///
+    /// See [`with_hasher_scoped`][ConMap::with_hasher_scoped] for details.
+    pub fn new_scoped() -> Self {
+        Self::with_hasher_scoped(RandomState::default())
+    }
+}
+
+// TODO: Once we have the unsized locals, this should be possible to move into the V: ?Sized block
+impl<K, V, S> ConMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    /// Inserts a new element.
+    ///
+    /// Any previous element with the same key is replaced and returned.
+    pub fn insert(&self, key: K, value: V) -> Option<Arc<Element<K, V>>> {
+        self.insert_element(Arc::new(Element::new(key, value)))
+    }
+
+    /// Inserts multiple elements at once.
+    ///
+    /// This is like calling [insert][ConMap::insert] for each of them, but it pins the epoch only
+    /// once and walks the trie in an order that lets the neighbouring keys share the work. The
+    /// replaced elements are returned in the same order as the inserted ones. If the same key is
+    /// present multiple times, the later element wins.
+    ///
+    /// The batch is not atomic, other threads may observe only some of the elements inserted.
+    pub fn insert_many<I>(&self, elements: I) -> Vec<Option<Arc<Element<K, V>>>>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        self.insert_elements(
+            elements
+                .into_iter()
+                .map(|(key, value)| Arc::new(Element::new(key, value))),
+        )
+    }
+
+    /// Replaces an element, but only if there's already one with the same key.
+    ///
+    /// The previous element is returned. If there's no element with the key, nothing is inserted
+    /// and `None` is returned.
+    pub fn replace(&self, key: K, value: V) -> Option<Arc<Element<K, V>>> {
+        self.replace_element(Arc::new(Element::new(key, value)))
+    }
+
+    /// Replaces the value of an element, but only if the current value is equal to the provided
+    /// one.
+    ///
+    /// This is like [compare_and_swap_element][ConMap::compare_and_swap_element], but the current
+    /// value is compared by [`PartialEq`] instead of by identity.
+    ///
+    /// On success, the previous element is returned. Otherwise, the element currently present in
+    /// the map (if any) is returned as the error.
+    pub fn compare_and_swap_value(&self, key: K, current: &V, value: V) -> SwapResult<K, V>
+    where
+        V: PartialEq,
+    {
+        let pin = self.raw.guard();
+        self.raw
+            .replace_if(
+                MapPayload(Arc::new(Element::new(key, value))),
+                |p| p.0.value() == current,
+                &pin,
+            )
+            .map(|p| Arc::clone(&p.0))
+            .map_err(|p| p.map(|p| Arc::clone(&p.0)))
+    }
+
+    /// Looks up or inserts an element.
+    ///
+    /// It looks up an element. If it isn't present, the provided one is inserted instead. Either
+    /// way, an element is returned.
+    pub fn get_or_insert(&self, key: K, value: V) -> ExistingOrNew<Arc<Element<K, V>>> {
+        self.get_or_insert_with(key, || value)
+    }
+
+    /// Looks up or inserts a newly created element.
+    ///
+    /// It looks up an element. If it isn't present, the provided closure is used to create a new
+    /// one insert it. Either way, an element is returned.
+    ///
+    /// # Quirks
+    ///
+    /// Due to races in case of concurrent accesses, the closure may be called even if the value is
+    /// not subsequently inserted and an existing element is returned. This should be relatively
+    /// rare (another thread must insert the new element between this method observes an empty slot
+    /// and manages to insert the new element). If the closure is expensive or has side effects,
+    /// use [get_or_insert_with_once][ConMap::get_or_insert_with_once] instead.
+    pub fn get_or_insert_with<F>(&self, key: K, create: F) -> ExistingOrNew<Arc<Element<K, V>>>
+    where
+        F: FnOnce() -> V,
+    {
+        self.get_or_insert_with_element(key, |key| {
+            let value = create();
+            Arc::new(Element::new(key, value))
+        })
+    }
+
+    /// Looks up or inserts a newly created element, calling the closure at most once.
+    ///
+    /// This is like [get_or_insert_with][ConMap::get_or_insert_with], but if multiple threads
+    /// look for the same missing key at the same time, only one of them calls its closure. The
+    /// others block until the element is inserted and return it as an existing one. If the
+    /// closure panics, one of the waiting threads calls its own closure instead.
+    pub fn get_or_insert_with_once<F>(&self, key: K, create: F) -> ExistingOrNew<Arc<Element<K, V>>>
+    where
+        F: FnOnce() -> V,
+    {
+        self.raw
+            .get_or_insert_with_once(key, |key| {
+                let value = create();
+                MapPayload(Arc::new(Element::new(key, value)))
+            })
+            .map(|payload| Arc::clone(&payload.0))
+    }
+
+    /// Looks up or inserts an element created by a future, driving at most one such future per
+    /// key at a time.
+    ///
+    /// This is the async counterpart of [get_or_insert_with_once][ConMap::get_or_insert_with_once].
+    /// The first caller to miss the key drives the future returned by the closure. Concurrent
+    /// callers for the same key await the element it creates instead of creating their own. If
+    /// the driving future is dropped (or panics) before finishing, one of the waiting callers
+    /// calls its own closure instead.
+    ///
+    /// No epoch pin is held across the `.await` points, so the future can be sent between threads
+    /// and kept pending for a long time without blocking reclamation of memory.
+    ///
+    /// Available with the `async` feature.
+    #[cfg(feature = "async")]
+    pub async fn get_or_insert_with_async<F, R>(
+        &self,
+        key: K,
+        create: F,
+    ) -> ExistingOrNew<Arc<Element<K, V>>>
+    where
+        F: FnOnce() -> R,
+        R: Future<Output = V>,
+    {
+        self.raw
+            .get_or_insert_with_async(key, |key| async move {
+                let value: V = create().await;
+                MapPayload(Arc::new(Element::new(key, value)))
+            })
+            .await
+            .map(|payload| Arc::clone(&payload.0))
+    }
+
+    /// Looks up or inserts a newly created element, if the creation succeeds.
+    ///
+    /// This is like [get_or_insert_with][ConMap::get_or_insert_with], but the closure may fail.
+    /// In such case the error is returned together with the key and the map is left unmodified.
+    ///
+    /// # Quirks
+    ///
+    /// The closure is called whenever the key is found missing. If another thread inserts an
+    /// element with the same key before the new one is put in place, the new one is thrown away
+    /// and the existing one is returned.
+    pub fn get_or_try_insert_with<F, E>(&self, key: K, create: F) -> TryInsertResult<K, V, E>
+    where
+        F: FnOnce(&K) -> Result<V, E>,
+    {
+        self.raw
+            .get_or_try_insert_with(key, |key| match create(&key) {
+                Ok(value) => Ok(MapPayload(Arc::new(Element::new(key, value)))),
+                Err(e) => Err((key, e)),
+            })
+            .map(|found| found.map(|payload| Arc::clone(&payload.0)))
+    }
+
+    /// Looks up or inserts a default value of an element.
+    ///
+    /// This is like [get_or_insert_with][ConMap::get_or_insert_with], but a default value is used
+    /// instead of manually providing a closure.
+    pub fn get_or_insert_default(&self, key: K) -> ExistingOrNew<Arc<Element<K, V>>>
+    where
+        V: Default,
+    {
+        self.get_or_insert_with(key, V::default)
+    }
+
+    /// Atomically computes a new value for the key.
+    ///
+    /// The closure gets the current value (if any) and returns the value that should be stored
+    /// instead. Returning `None` removes the element (or leaves the key missing). The new value is
+    /// put in place only if the element hasn't changed since the closure has seen it. Otherwise,
+    /// the closure is called again with the fresh value, until it succeeds. Therefore, no
+    /// concurrent update gets lost, but the closure may be called multiple times and should not
+    /// have side effects.
+    ///
+    /// Returns the newly stored element, if any.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use contrie::ConMap;
+    /// use crossbeam_utils::thread;
+    ///
+    /// let map = ConMap::new();
+    ///
+    /// thread::scope(|s| {
+    ///     for _ in 0..4 {
+    ///         s.spawn(|_| {
+    ///             for _ in 0..100 {
+    ///                 map.compute("counter", |cnt| Some(cnt.unwrap_or(&0) + 1));
+    ///             }
+    ///         });
+    ///     }
+    /// }).unwrap();
+    ///
+    /// assert_eq!(400, *map.get("counter").unwrap().value());
+    /// ```
+    pub fn compute<F>(&self, key: K, mut f: F) -> Option<Arc<Element<K, V>>>
+    where
+        K: Clone,
+        F: FnMut(Option<&V>) -> Option<V>,
+    {
+        let pin = self.raw.guard();
+        self.raw
+            .compute(
+                &key,
+                |current| {
+                    f(current.map(|p| p.0.value()))
+                        .map(|value| MapPayload(Arc::new(Element::new(key.clone(), value))))
+                },
+                &pin,
+            )
+            .map(|p| Arc::clone(&p.0))
+    }
+
+    /// Atomically computes a new value for the key, but only if it is already present.
+    ///
+    /// This is like [compute][ConMap::compute], but the closure is called only if there's an
+    /// element to compute from. Returning `None` from the closure removes the element.
+    ///
+    /// Returns the newly stored element, if any.
+    pub fn compute_if_present<Q, F>(&self, key: &Q, mut f: F) -> Option<Arc<Element<K, V>>>
+    where
+        Q: ?Sized + Eq + Hash,
+        K: Borrow<Q> + Clone,
+        F: FnMut(&V) -> Option<V>,
+    {
+        let pin = self.raw.guard();
+        self.raw
+            .compute(
+                key,
+                |current| {
+                    let current = &current?.0;
+                    let value = f(current.value())?;
+                    Some(MapPayload(Arc::new(Element::new(
+                        current.key().clone(),
+                        value,
+                    ))))
+                },
+                &pin,
+            )
+            .map(|p| Arc::clone(&p.0))
+    }
+
+    /// Atomically updates the value of an existing element.
+    ///
+    /// This is like [compute_if_present][ConMap::compute_if_present], but the element is never
+    /// removed. If there's no element with the key, nothing happens.
+    ///
+    /// Returns the updated element, if any.
+    pub fn update<Q, F>(&self, key: &Q, mut f: F) -> Option<Arc<Element<K, V>>>
+    where
+        Q: ?Sized + Eq + Hash,
+        K: Borrow<Q> + Clone,
+        F: FnMut(&V) -> V,
+    {
+        self.compute_if_present(key, |current| Some(f(current)))
+    }
+
+    /// Atomically merges a value into the map.
+    ///
+    /// If there's no element with the key, the value is inserted as it is. Otherwise, the closure
+    /// is called with the current and the provided value and its result is stored instead
+    /// (returning `None` removes the element). Just like with [compute][ConMap::compute], no
+    /// concurrent update is lost, but the closure may be called multiple times.
+    ///
+    /// Returns the newly stored element, if any.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use contrie::ConMap;
+    ///
+    /// let map = ConMap::new();
+    /// for word in "a b a c a b".split_whitespace() {
+    ///     map.merge(word, 1, |a, b| Some(a + b));
+    /// }
+    /// assert_eq!(3, *map.get("a").unwrap().value());
+    /// assert_eq!(2, *map.get("b").unwrap().value());
+    /// assert_eq!(1, *map.get("c").unwrap().value());
+    /// ```
+    pub fn merge<F>(&self, key: K, value: V, mut f: F) -> Option<Arc<Element<K, V>>>
+    where
+        K: Clone,
+        F: FnMut(&V, &V) -> Option<V>,
+    {
+        let pin = self.raw.guard();
+        // Created just once, so we can insert it again on retries.
+        let given = Arc::new(Element::new(key, value));
+        self.raw
+            .compute(
+                given.key(),
+                |current| match current {
+                    None => Some(MapPayload(Arc::clone(&given))),
+                    Some(current) => f(current.0.value(), given.value()).map(|value| {
+                        MapPayload(Arc::new(Element::new(given.key().clone(), value)))
+                    }),
+                },
+                &pin,
+            )
+            .map(|p| Arc::clone(&p.0))
+    }
+
+    /// Gets the entry for the given key, for in-place inspection and manipulation.
+    ///
+    /// The entry is pinned to the element present at the time of the call (or to its absence).
+    /// Modifications done through it are compare-and-swap operations ‒ they fail cleanly if the
+    /// element was changed by someone else in the meantime, returning a fresh entry. The
+    /// convenience methods, like [`and_modify`][Entry::and_modify], retry on their own.
+    ///
+    /// The entry holds an epoch pin (see the [`raw`] module), therefore it should not
+    /// be kept around for long.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use contrie::map::{ConMap, Entry};
+    ///
+    /// let map = ConMap::new();
+    ///
+    /// map.entry("hello").and_modify(|v| v + 1).or_insert(1);
+    /// map.entry("hello").and_modify(|v| v + 1).or_insert(1);
+    /// assert_eq!(2, *map.get("hello").unwrap().value());
+    ///
+    /// if let Entry::Occupied(entry) = map.entry("hello") {
+    ///     // Someone else modifies the element in the meantime
+    ///     map.insert("hello", 42);
+    ///     // So the entry is outdated and the removal fails
+    ///     let fresh = entry.remove().err().unwrap();
+    ///     match fresh {
+    ///         Entry::Occupied(entry) => assert_eq!(42, *entry.get()),
+    ///         Entry::Vacant(_) => unreachable!(),
+    ///     }
+    /// };
+    /// ```
+    pub fn entry(&self, key: K) -> Entry<'_, K, V, S>
+    where
+        K: Clone,
+    {
+        Entry::from_raw(raw::entry::Entry::new(&self.raw, &key), key)
+    }
+}
+
+impl<K, V, S> ConMap<K, V, S>
+where
+    K: Hash + Eq,
+    V: ?Sized,
+    S: BuildHasher,
+{
+    /// Creates a new empty map, but with the provided hasher implementation.
+    pub fn with_hasher(hasher: S) -> Self
+    where
+        K: 'static,
+        V: 'static,
+    {
+        Self {
+            raw: Raw::with_hasher(hasher),
+        }
+    }
+
+    /// Creates a new empty map with the provided hasher and a dedicated epoch collector.
+    ///
+    /// The removed elements are destroyed through the given collector instead of the global
+    /// default one. If the map holds the last reference to the collector, all the elements are
+    /// guaranteed to be destroyed by the time the drop of the map returns.
+    ///
+    /// Note that operations on such map are somewhat slower.
+    pub fn with_hasher_and_collector(hasher: S, collector: Collector) -> Self
+    where
+        K: 'static,
+        V: 'static,
+    {
+        Self {
+            raw: Raw::with_hasher_and_collector(hasher, collector),
+        }
+    }
+
+    /// Creates a new empty map with the provided hasher, that may borrow its keys and values.
+    ///
+    /// The map uses its own epoch collector, which is never shared. This makes sure all the
+    /// removed elements are destroyed before the map goes away, so the keys and values don't have
+    /// to be `'static`.
+    ///
+    /// Note that operations on such map are somewhat slower.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use contrie::ConMap;
+    ///
+    /// let input = String::from("a=1 b=2 c=3");
+    /// let map = ConMap::new_scoped();
+    /// for pair in input.split_whitespace() {
+    ///     let mut parts = pair.split('=');
+    ///     map.insert(parts.next().unwrap(), parts.next().unwrap());
+    /// }
+    /// assert_eq!("2", *map.get("b").unwrap().value());
+    /// ```
+    pub fn with_hasher_scoped(hasher: S) -> Self {
+        Self {
+            raw: Raw::with_hasher_scoped(hasher),
+        }
+    }
+
+    /// Turns on the [snapshots][ConMap::snapshot] for this map.
+    ///
+    /// This is meant to be chained right after one of the constructors. Modifications of such map
+    /// are considerably slower, because each one copies the whole path from the root of the trie
+    /// (see [`Config::SNAPSHOTS`]). Lookups and iteration stay the same.
+    pub fn with_snapshots(self) -> Self {
+        Self {
+            raw: self.raw.with_snapshots(),
+        }
+    }
+
+    /// Inserts a new element.
+    ///
+    /// This acts the same as [insert][ConMap::insert], but takes the already created element. It
+    /// can be used when:
+    ///
+    /// * `V: ?Sized`.
+    /// * You want to insert the same element into multiple maps.
+    pub fn insert_element(&self, element: Arc<Element<K, V>>) -> Option<Arc<Element<K, V>>> {
+        let pin = self.raw.guard();
+        self.raw
+            .insert(MapPayload(element), &pin)
+            .map(|p| Arc::clone(&p.0))
+    }
+
+    /// Inserts multiple already created elements at once.
+    ///
+    /// This acts the same as [insert_many][ConMap::insert_many], but takes the already created
+    /// elements.
+    pub fn insert_elements<I>(&self, elements: I) -> Vec<Option<Arc<Element<K, V>>>>
+    where
+        I: IntoIterator<Item = Arc<Element<K, V>>>,
+    {
+        let pin = self.raw.guard();
+        self.raw
+            .insert_many(elements.into_iter().map(MapPayload), &pin)
+            .into_iter()
+            .map(|p| p.map(|p| Arc::clone(&p.0)))
+            .collect()
+    }
+
+    /// Replaces an element, but only if there's already one with the same key.
+    ///
+    /// This acts the same as [replace][ConMap::replace], but takes the already created element.
+    pub fn replace_element(&self, element: Arc<Element<K, V>>) -> Option<Arc<Element<K, V>>> {
+        let pin = self.raw.guard();
+        self.raw
+            .replace(MapPayload(element), &pin)
+            .map(|p| Arc::clone(&p.0))
+    }
+
+    /// Replaces an element, but only if the current one is the provided `current` element.
+    ///
+    /// The elements are compared by identity (eg. [`Arc::ptr_eq`]), so this succeeds only if
+    /// nobody replaced the element since it was read out of the map, even if with an equal value.
+    /// The key of the `new` element is used to identify the slot.
+    ///
+    /// This allows optimistic read-modify-write updates without losing concurrent modifications
+    /// from other threads.
+    ///
+    /// On success, the replaced element is returned. Otherwise, the element currently present in
+    /// the map (if any) is returned as the error and `new` is not inserted.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::sync::Arc;
+    /// use contrie::map::{ConMap, Element};
+    ///
+    /// let map = ConMap::new();
+    /// map.insert("hello", 1);
+    ///
+    /// let current = map.get("hello").unwrap();
+    /// let new = Arc::new(Element::new("hello", *current.value() + 1));
+    /// assert!(map.compare_and_swap_element(&current, new).is_ok());
+    ///
+    /// // The current one is now outdated, so this fails.
+    /// let new = Arc::new(Element::new("hello", 42));
+    /// let actual = map.compare_and_swap_element(&current, new).unwrap_err().unwrap();
+    /// assert_eq!(2, *actual.value());
+    /// ```
+    pub fn compare_and_swap_element(
+        &self,
+        current: &Arc<Element<K, V>>,
+        new: Arc<Element<K, V>>,
+    ) -> SwapResult<K, V> {
+        let pin = self.raw.guard();
+        self.raw
+            .replace_if(MapPayload(new), |p| Arc::ptr_eq(&p.0, current), &pin)
+            .map(|p| Arc::clone(&p.0))
+            .map_err(|p| p.map(|p| Arc::clone(&p.0)))
+    }
+
+    /// Looks up or inserts a new element.
+    ///
+    /// This is the same as [get_or_insert_with][ConMap::get_or_insert_with], but the closure
+    /// returns a pre-created element. This can be used when:
+    ///
+    /// * `V: ?Sized`.
+    /// * You want to insert the same element into multiple maps.
+    pub fn get_or_insert_with_element<F>(
+        &self,
+        key: K,
+        create: F,
+    ) -> ExistingOrNew<Arc<Element<K, V>>>
+    where
+        F: FnOnce(K) -> Arc<Element<K, V>>,
+    {
+        let pin = self.raw.guard();
+        self.raw
+            .get_or_insert_with(key, |key| MapPayload(create(key)), &pin)
+            .map(|payload| Arc::clone(&payload.0))
+    }
+
+    /// Looks up an element.
+    pub fn get<Q>(&self, key: &Q) -> Option<Arc<Element<K, V>>>
+    where
+        Q: ?Sized + Eq + Hash,
+        K: Borrow<Q>,
+    {
+        let pin = self.raw.guard();
+        self.raw.get(key, &pin).map(|r| Arc::clone(&r.0))
+    }
+
+    /// Looks up multiple elements at once.
+    ///
+    /// The results are in the same order as the keys. This is cheaper than calling
+    /// [get][ConMap::get] for each of them, as the epoch is pinned only once and the lookups of
+    /// keys close to each other in the trie share part of the walk.
+    pub fn get_many<Q>(&self, keys: &[&Q]) -> Vec<Option<Arc<Element<K, V>>>>
+    where
+        Q: ?Sized + Eq + Hash,
+        K: Borrow<Q>,
+    {
+        let pin = self.raw.guard();
+        self.raw
+            .get_many(keys, &pin)
+            .into_iter()
+            .map(|p| p.map(|p| Arc::clone(&p.0)))
+            .collect()
+    }
+
+    /// Looks up an element and returns a reference to it.
+    ///
+    /// Unlike [`get`][ConMap::get], this doesn't touch the reference count of the element, which
+    /// may be a point of contention if many threads read the same element. The reference holds an
+    /// epoch pin, so it should not be kept around for extended periods of time.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use contrie::ConMap;
+    ///
+    /// let map = ConMap::new();
+    /// map.insert("hello", 42);
+    /// let found = map.get_ref("hello").unwrap();
+    /// assert_eq!(42, *found.value());
+    /// ```
+    pub fn get_ref<Q>(&self, key: &Q) -> Option<Ref<'_, K, V>>
+    where
+        Q: ?Sized + Eq + Hash,
+        K: Borrow<Q>,
+    {
+        self.raw
+            .get_ref(key)
+            .map(|r| raw::reference::Ref::map(r, |p| &*p.0))
+    }
+
+    /// Pins the epoch and returns a read handle to the map.
+    ///
+    /// The lookups through the handle return plain references to the keys and values, bound to
+    /// the lifetime of the handle. This saves both the cost of pinning for each lookup and
+    /// touching the reference counts of the elements. However, as the handle holds the pin, it
+    /// should not be kept around for extended periods of time.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use contrie::ConMap;
+    ///
+    /// let map = ConMap::new();
+    /// map.insert("hello", 1);
+    /// map.insert("world", 2);
+    ///
+    /// let pinned = map.pin();
+    /// let sum: usize = ["hello", "world", "missing"]
+    ///     .iter()
+    ///     .filter_map(|key| pinned.get(key))
+    ///     .sum();
+    /// assert_eq!(3, sum);
+    /// ```
+    pub fn pin(&self) -> Pinned<'_, K, V, S> {
+        Pinned {
+            map: self,
+            pin: self.raw.guard(),
+        }
+    }
+
+    /// Takes a consistent snapshot of the current content.
+    ///
+    /// Taking the snapshot is cheap and it is not affected by any later modifications of the map.
+    /// Like the [`pin`][ConMap::pin], it holds an epoch pin for its whole lifetime.
+    ///
+    /// # Panics
+    ///
+    /// If the map was not created [with the snapshots][ConMap::with_snapshots].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use contrie::ConMap;
+    ///
+    /// let map = ConMap::new().with_snapshots();
+    /// map.insert("a", 1);
+    /// map.insert("b", 2);
+    ///
+    /// let snapshot = map.snapshot();
+    /// map.insert("a", 3);
+    /// map.remove("b");
+    ///
+    /// assert_eq!(Some(&1), snapshot.get("a"));
+    /// assert!(snapshot.contains_key("b"));
+    /// assert_eq!(2, snapshot.iter().count());
+    /// ```
+    pub fn snapshot(&self) -> Snapshot<'_, K, V, S> {
+        Snapshot {
+            inner: self.raw.snapshot(),
+        }
+    }
+
+    /// Removes an element identified by the given key, returning it.
+    pub fn remove<Q>(&self, key: &Q) -> Option<Arc<Element<K, V>>>
+    where
+        Q: ?Sized + Eq + Hash,
+        K: Borrow<Q>,
+    {
+        let pin = self.raw.guard();
+        self.raw.remove(key, &pin).map(|r| Arc::clone(&r.0))
+    }
+
+    /// Removes multiple elements at once, returning them.
+    ///
+    /// The removed elements are in the same order as the keys. Like
+    /// [insert_many][ConMap::insert_many], the batch is done under a single pin, but it is not
+    /// atomic.
+    pub fn remove_many<Q>(&self, keys: &[&Q]) -> Vec<Option<Arc<Element<K, V>>>>
+    where
+        Q: ?Sized + Eq + Hash,
+        K: Borrow<Q>,
+    {
+        let pin = self.raw.guard();
+        self.raw
+            .remove_many(keys, &pin)
+            .into_iter()
+            .map(|p| p.map(|p| Arc::clone(&p.0)))
+            .collect()
+    }
+
+    /// Removes an element identified by the given key, but only if the predicate accepts it.
+    ///
+    /// The check and the removal are done atomically. If the element gets replaced in between,
+    /// the predicate is consulted again about the new one, so it may be called multiple times.
+    ///
+    /// Returns the removed element, if any.
+    pub fn remove_if<Q, F>(&self, key: &Q, mut predicate: F) -> Option<Arc<Element<K, V>>>
+    where
+        Q: ?Sized + Eq + Hash,
+        K: Borrow<Q>,
+        F: FnMut(&Element<K, V>) -> bool,
+    {
+        let pin = self.raw.guard();
+        self.raw
+            .remove_if(key, |p| predicate(&p.0), &pin)
+            .map(|r| Arc::clone(&r.0))
+    }
+
+    /// Removes the given element, but only if it is still the one stored in the map.
+    ///
+    /// The elements are compared by identity (see
+    /// [compare_and_swap_element][ConMap::compare_and_swap_element]). Returns if the element was
+    /// removed.
+    pub fn remove_element(&self, element: &Arc<Element<K, V>>) -> bool {
+        let pin = self.raw.guard();
+        self.raw
+            .remove_if(element.key(), |p| Arc::ptr_eq(&p.0, element), &pin)
+            .is_some()
+    }
+
+    /// Removes all the elements the predicate rejects.
+    ///
+    /// This walks the whole map and removes the rejected elements in place, which is cheaper than
+    /// collecting the keys and removing them one by one. Emptied branches are pruned on the way.
+    ///
+    /// This is not an atomic operation. Elements inserted or modified concurrently may or may not
+    /// be visited and the predicate may be called multiple times for the same element.
+    pub fn retain<F>(&self, mut predicate: F)
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        let pin = self.raw.guard();
+        self.raw.retain(|p| predicate(p.0.key(), p.0.value()), &pin);
+    }
+
+    /// Removes all the elements the predicate rejects, in parallel.
+    ///
+    /// This is the parallel version of [`retain`][ConMap::retain], with the same quirks. The top
+    /// levels of the trie are split between the workers, each of them owns a whole subtree.
+    ///
+    /// Available with the `rayon` feature.
+    #[cfg(feature = "rayon")]
+    pub fn par_retain<F>(&self, predicate: F)
+    where
+        K: Send + Sync,
+        V: Send + Sync,
+        S: Sync,
+        F: Fn(&K, &V) -> bool + Sync + Send,
+    {
+        self.raw.par_retain(|p| predicate(p.0.key(), p.0.value()));
+    }
+
+    /// Calls the closure on each element, in parallel.
+    ///
+    /// Unlike going through [`par_iter`][ConMap::par_iter], this doesn't touch the reference
+    /// counts of the elements.
+    ///
+    /// Available with the `rayon` feature.
+    #[cfg(feature = "rayon")]
+    pub fn par_for_each<F>(&self, f: F)
+    where
+        K: Send + Sync,
+        V: Send + Sync,
+        S: Sync,
+        F: Fn(&K, &V) + Sync + Send,
+    {
+        self.raw.par_for_each(|p| f(p.0.key(), p.0.value()));
+    }
+
+    /// Creates a new map with the same keys and the values converted by the closure, in
+    /// parallel.
+    ///
+    /// The new map uses a copy of the hasher, so the elements land in the same places of its trie
+    /// and each worker fills its own subtree.
+    ///
+    /// Available with the `rayon` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[cfg(feature = "rayon")] {
+    /// use contrie::ConMap;
+    ///
+    /// let map: ConMap<usize, usize> = (0..100).map(|i| (i, i)).collect();
+    /// let doubled = map.par_map_values(|_, v| v * 2);
+    /// assert_eq!(84, *doubled.get(&42).unwrap().value());
+    /// # }
+    /// ```
+    #[cfg(feature = "rayon")]
+    pub fn par_map_values<U, F>(&self, f: F) -> ConMap<K, U, S>
+    where
+        K: Clone + Send + Sync + 'static,
+        V: Send + Sync,
+        U: Send + Sync + 'static,
+        S: Clone + Sync,
+        F: Fn(&K, &V) -> U + Sync + Send,
+    {
+        let new = ConMap::with_hasher(self.raw.hasher().clone());
+        new.raw.par_extend(raw::parallel::ParIter::new(
+            &self.raw,
+            |p: &MapPayload<K, V>| {
+                let (key, value) = (p.0.key(), p.0.value());
+                MapPayload(Arc::new(Element::new(key.clone(), f(key, value))))
+            },
+        ));
+        new
+    }
+
+    /// Creates a copy of the map, in parallel.
+    ///
+    /// This is the parallel version of [`clone`][Clone::clone]. The elements are shared with the
+    /// original map, not copied. As the copy uses the same hasher, each worker fills its own
+    /// subtree.
+    ///
+    /// Available with the `rayon` feature.
+    #[cfg(feature = "rayon")]
+    pub fn par_clone(&self) -> Self
+    where
+        K: Send + Sync,
+        V: Send + Sync,
+        S: Clone + Sync,
+    {
+        Self {
+            raw: self.raw.par_clone(),
+        }
+    }
+}
+
+impl<K, V, S> ConMap<K, V, S>
+where
+    K: Hash + Eq,
+    V: ?Sized,
+{
+    /// Checks if the map is currently empty.
+    ///
+    /// Note that due to the nature of concurrent map, this is inherently racy ‒ another thread may
+    /// add or remove elements between you call this method and act based on the result.
+    pub fn is_empty(&self) -> bool {
+        self.raw.is_empty()
+    }
+
+    /// Returns the number of elements.
+    ///
+    /// Similar to [is_empty][ConMap::is_empty], this is inherently racy if other threads modify
+    /// the map at the same time. The returned number is exact if there are no concurrent
+    /// modifications. Otherwise it is only an estimate.
+    pub fn len(&self) -> usize {
+        self.raw.len()
+    }
+
+    /// Returns an approximate number of elements.
+    ///
+    /// This is cheaper than [len][ConMap::len] and is meant for things like statistics. If other
+    /// threads modify the map at the same time, the number might not match any actual state of
+    /// the map.
+    pub fn approx_len(&self) -> usize {
+        self.raw.approx_len()
+    }
+
+    /// Removes all the elements.
+    ///
+    /// This detaches the whole content of the map at once, instead of removing the elements one
+    /// by one. Elements inserted concurrently with the clear may or may not survive.
+    pub fn clear(&self) {
+        let pin = self.raw.guard();
+        self.raw.clear(&pin);
+    }
+
+    /// Removes all the elements and returns them as a new map.
+    ///
+    /// This is like [`clear`][ConMap::clear], but the old content is moved into the returned map
+    /// instead of being destroyed. This can be used to atomically rotate the content.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use contrie::ConMap;
+    ///
+    /// let window = ConMap::new();
+    /// window.insert("requests", 42);
+    ///
+    /// let previous = window.clear_and_take();
+    /// assert!(window.is_empty());
+    /// assert_eq!(42, *previous.get("requests").unwrap().value());
+    /// ```
+    pub fn clear_and_take(&self) -> Self
+    where
+        S: Clone,
+    {
+        let pin = self.raw.guard();
+        Self {
+            raw: self.raw.clear_and_take(&pin),
+        }
+    }
+
+    /// Tries to destroy the already removed elements right away.
+    ///
+    /// Removed elements are normally destroyed at some later point, once no thread can be looking
+    /// at them. This forces the collection at a known point. Elements that might still be
+    /// accessed by some other thread (eg. through an iterator) are not destroyed.
+    ///
+    /// This is most useful together with a dedicated collector (see
+    /// [`with_hasher_and_collector`][ConMap::with_hasher_and_collector]), as the global one is
+    /// shared with the rest of the program.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::collections::hash_map::RandomState;
+    /// use std::sync::Arc;
+    ///
+    /// use contrie::ConMap;
+    /// use crossbeam_epoch::Collector;
+    ///
+    /// let handle = Arc::new(());
+    /// let map = ConMap::with_hasher_and_collector(RandomState::new(), Collector::new());
+    /// map.insert("file", Arc::clone(&handle));
+    ///
+    /// map.remove("file");
+    /// map.flush_garbage();
+    /// assert_eq!(1, Arc::strong_count(&handle));
+    /// ```
+    pub fn flush_garbage(&self) {
+        self.raw.flush_garbage();
+    }
+
+    /// Returns an iterator through the elements of the map.
+    #[allow(mismatched_lifetime_syntaxes)]
+    pub fn iter(&self) -> Iter<K, V, S> {
+        Iter {
+            inner: raw::iterator::Iter::new(&self.raw),
+        }
+    }
+
+    /// Returns an iterator through the elements of the map, starting at the cursor.
+    ///
+    /// Together with [`CursorIter::cursor`], this allows pausing the iteration (and releasing the
+    /// epoch pin it holds) and resuming it later. See [`Cursor`] for the order of the elements.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use contrie::raw::cursor::Cursor;
+    /// use contrie::ConMap;
+    ///
+    /// let map: ConMap<usize, usize> = (0..100).map(|i| (i, i)).collect();
+    /// let mut cursor = Some(Cursor::default());
+    /// let mut listed = 0;
+    /// while let Some(start) = cursor {
+    ///     // Each page holds its epoch pin only while it is being listed.
+    ///     let mut page = map.iter_from(start);
+    ///     listed += page.by_ref().take(10).count();
+    ///     cursor = page.cursor();
+    /// }
+    /// assert_eq!(100, listed);
+    /// ```
+    pub fn iter_from(&self, cursor: Cursor<u64>) -> CursorIter<'_, K, V, S>
+    where
+        S: BuildHasher,
+    {
+        CursorIter {
+            inner: self.raw.iter_from(cursor),
+        }
+    }
+
+    /// Returns an iterator through the elements with hashes in the given range.
+    ///
+    /// The range is in the order of the walk through the trie, not the numerical one. In
+    /// particular, the ranges `k..k + 1` for `k` below `1 <<`
+    /// [`LEVEL_BITS`][crate::raw::config::Config::LEVEL_BITS] (with the last one left open) split
+    /// the map into disjoint parts that can be walked by different threads.
+    pub fn iter_hash_range<R>(&self, range: R) -> CursorIter<'_, K, V, S>
+    where
+        S: BuildHasher,
+        R: RangeBounds<u64>,
+    {
+        CursorIter {
+            inner: self.raw.iter_hash_range(range),
+        }
+    }
+
+    /// Returns an iterator through the elements of the map that holds no epoch pin.
+    ///
+    /// The iterator pins only for the duration of each `next` call and remembers just a
+    /// [`Cursor`] in between. Therefore it is `Send` (if the map is `Sync`), so it can be
+    /// held across `.await` points in a multi-threaded runtime or moved to another thread. With
+    /// the `stream` feature, it can be turned into a futures `Stream`. The price is a walk from
+    /// the root of the trie for each element.
+    pub fn send_iter(&self) -> SendIter<'_, K, V, S> {
+        self.send_iter_from(Cursor::default())
+    }
+
+    /// Like [`send_iter`][ConMap::send_iter], but starting at the cursor.
+    pub fn send_iter_from(&self, cursor: Cursor<u64>) -> SendIter<'_, K, V, S> {
+        SendIter {
+            inner: raw::cursor::SendIter::new(&self.raw, cursor, |p| Arc::clone(&p.0)),
+        }
+    }
+
+    /// Returns a parallel iterator through the elements of the map.
+    ///
+    /// The work is split along the branches of the trie, each part being walked under its own
+    /// epoch pin. However, the thread driving the iterator holds a pin until all the work is
+    /// done, so no memory is reclaimed in the meantime.
+    ///
+    /// Available with the `rayon` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[cfg(feature = "rayon")] {
+    /// use contrie::ConMap;
+    /// use rayon::prelude::*;
+    ///
+    /// let map: ConMap<usize, usize> = (0..1000).map(|i| (i, i)).collect();
+    /// let sum: usize = map.par_iter().map(|e| *e.value()).sum();
+    /// assert_eq!(499_500, sum);
+    /// # }
+    /// ```
+    #[cfg(feature = "rayon")]
+    pub fn par_iter(&self) -> ParIter<'_, K, V, S>
+    where
+        K: Send + Sync,
+        V: Send + Sync,
+        S: Sync,
+    {
+        ParIter {
+            inner: raw::parallel::ParIter::new(&self.raw, |p| Arc::clone(&p.0)),
+        }
+    }
+}
+
+impl<K, V> Default for ConMap<K, V>
+where
+    K: Hash + Eq + 'static,
+    V: ?Sized + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, S> Debug for ConMap<K, V, S>
+where
+    K: Debug + Hash + Eq,
+    V: Debug + ?Sized,
+{
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        let mut d = fmt.debug_map();
+        // TODO: As we return Arcs, it seem we can't use the iterator approach with .map :-(
+        // This might hint to need for better iteration API?
+        for n in self {
+            // Hack: As of 1.37.0 the parameters to entry need to be &Sized. By using a double-ref,
+            // we satisfy the compiler's pickiness in that regard.
+            let val: &&V = &n.value();
+            d.entry(n.key() as &dyn Debug, val);
+        }
+        d.finish()
+    }
+}
+
+impl<K, V, S> Clone for ConMap<K, V, S>
+where
+    K: Hash + Eq,
+    V: ?Sized,
+    S: Clone + BuildHasher,
+{
+    fn clone(&self) -> Self {
+        let mut new = Self {
+            raw: self.raw.empty_like(),
+        };
+        new.extend(self);
+        new
+    }
+}
+
+impl<'a, K, V, S> IntoIterator for &'a ConMap<K, V, S>
+where
+    K: Hash + Eq,
+    V: ?Sized,
+{
+    type Item = Arc<Element<K, V>>;
+    type IntoIter = Iter<'a, K, V, S>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<'a, K, V, S> IntoParallelIterator for &'a ConMap<K, V, S>
+where
+    K: Hash + Eq + Send + Sync,
+    V: ?Sized + Send + Sync,
+    S: Sync,
+{
+    type Item = Arc<Element<K, V>>;
+    type Iter = ParIter<'a, K, V, S>;
+    fn into_par_iter(self) -> Self::Iter {
+        self.par_iter()
+    }
+}
+
+#[allow(clippy::needless_lifetimes)]
+impl<'a, K, V, S> Extend<Arc<Element<K, V>>> for &'a ConMap<K, V, S>
+where
+    K: Hash + Eq,
+    V: ?Sized,
+    S: BuildHasher,
+{
+    fn extend<T>(&mut self, iter: T)
+    where
+        T: IntoIterator<Item = Arc<Element<K, V>>>,
+    {
+        self.raw.extend(iter.into_iter().map(MapPayload));
+    }
+}
+
+#[allow(clippy::needless_lifetimes)]
+impl<'a, K, V, S> Extend<(K, V)> for &'a ConMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    fn extend<T>(&mut self, iter: T)
+    where
+        T: IntoIterator<Item = (K, V)>,
+    {
+        self.extend(iter.into_iter().map(|(k, v)| Arc::new(Element::new(k, v))));
+    }
+}
+
+impl<K, V, S> Extend<Arc<Element<K, V>>> for ConMap<K, V, S>
+where
+    K: Hash + Eq,
+    V: ?Sized,
+    S: BuildHasher,
+{
+    fn extend<T>(&mut self, iter: T)
+    where
+        T: IntoIterator<Item = Arc<Element<K, V>>>,
+    {
+        let mut me: &ConMap<_, _, _> = self;
+        me.extend(iter);
+    }
+}
+
+impl<K, V, S> Extend<(K, V)> for ConMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    fn extend<T>(&mut self, iter: T)
+    where
+        T: IntoIterator<Item = (K, V)>,
+    {
+        let mut me: &ConMap<_, _, _> = self;
+        me.extend(iter);
+    }
+}
+
+impl<K, V> FromIterator<Arc<Element<K, V>>> for ConMap<K, V>
+where
+    K: Hash + Eq + 'static,
+    V: ?Sized + 'static,
+{
+    fn from_iter<T>(iter: T) -> Self
+    where
+        T: IntoIterator<Item = Arc<Element<K, V>>>,
+    {
+        let mut me = ConMap::new();
+        me.extend(iter);
+        me
+    }
+}
+
+impl<K, V> FromIterator<(K, V)> for ConMap<K, V>
+where
+    K: Hash + Eq + 'static,
+    V: 'static,
+{
+    fn from_iter<T>(iter: T) -> Self
+    where
+        T: IntoIterator<Item = (K, V)>,
+    {
+        let mut me = ConMap::new();
+        me.extend(iter);
+        me
+    }
+}
+
+#[cfg(feature = "rayon")]
+#[allow(clippy::needless_lifetimes)]
+impl<'a, K, V, S> ParallelExtend<Arc<Element<K, V>>> for &'a ConMap<K, V, S>
+where
+    K: Hash + Eq + Send + Sync,
+    V: ?Sized + Send + Sync,
+    S: BuildHasher + Sync,
+{
+    fn par_extend<T>(&mut self, par_iter: T)
+    where
+        T: IntoParallelIterator<Item = Arc<Element<K, V>>>,
+    {
+        self.raw
+            .par_extend(par_iter.into_par_iter().map(MapPayload));
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<K, V, S> ParallelExtend<(K, V)> for ConMap<K, V, S>
+where
+    K: Hash + Eq + Send + Sync,
+    S: BuildHasher + Sync,
+    V: Send + Sync,
+{
+    fn par_extend<T>(&mut self, par_iter: T)
+    where
+        T: IntoParallelIterator<Item = (K, V)>,
+    {
+        self.par_extend(
+            par_iter
+                .into_par_iter()
+                .map(|(k, v)| Arc::new(Element::new(k, v))),
+        );
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<K, V, S> ParallelExtend<Arc<Element<K, V>>> for ConMap<K, V, S>
+where
+    K: Hash + Eq + Send + Sync,
+    V: ?Sized + Send + Sync,
+    S: BuildHasher + Sync,
+{
+    fn par_extend<T>(&mut self, par_iter: T)
+    where
+        T: IntoParallelIterator<Item = Arc<Element<K, V>>>,
+    {
+        let mut me: &ConMap<_, _, _> = self;
+        me.par_extend(par_iter);
+    }
+}
+
+#[cfg(feature = "rayon")]
+#[allow(clippy::needless_lifetimes)]
+impl<'a, K, V, S> ParallelExtend<(K, V)> for &'a ConMap<K, V, S>
+where
+    K: Hash + Eq + Send + Sync,
+    S: BuildHasher + Sync,
+    V: Send + Sync,
+{
+    fn par_extend<T>(&mut self, par_iter: T)
+    where
+        T: IntoParallelIterator<Item = (K, V)>,
+    {
+        self.par_extend(
+            par_iter
+                .into_par_iter()
+                .map(|(k, v)| Arc::new(Element::new(k, v))),
+        );
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<K, V> FromParallelIterator<Arc<Element<K, V>>> for ConMap<K, V>
+where
+    K: Hash + Eq + Send + Sync + 'static,
+    V: ?Sized + Send + Sync + 'static,
+{
+    fn from_par_iter<T>(par_iter: T) -> Self
+    where
+        T: IntoParallelIterator<Item = Arc<Element<K, V>>>,
+    {
+        let mut me = ConMap::new();
+        me.par_extend(par_iter);
+        me
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<K, V> FromParallelIterator<(K, V)> for ConMap<K, V>
+where
+    K: Hash + Eq + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+{
+    fn from_par_iter<T>(par_iter: T) -> Self
+    where
+        T: IntoParallelIterator<Item = (K, V)>,
+    {
+        let mut me = ConMap::new();
+        me.par_extend(par_iter);
+        me
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crossbeam_utils::thread;
+
+    #[cfg(feature = "rayon")]
+    use rayon::prelude::*;
+
+    use super::*;
+    use crate::raw::tests::NoHasher;
+    use crate::raw::tests::LEVEL_CELLS;
+
+    const TEST_THREADS: usize = 4;
+    const TEST_BATCH: usize = 10000;
+    const TEST_BATCH_SMALL: usize = 100;
+    const TEST_REP: usize = 20;
+
+    #[test]
+    fn create_destroy() {
+        let map: ConMap<String, usize> = ConMap::new();
+        drop(map);
+    }
+
+    #[test]
+    fn lookup_empty() {
+        let map: ConMap<String, usize> = ConMap::new();
+        assert!(map.get("hello").is_none());
+    }
+
+    #[test]
+    fn insert_lookup() {
+        let map = ConMap::new();
+        assert!(map.insert("hello", "world").is_none());
+        assert!(map.get("world").is_none());
+        let found = map.get("hello").unwrap();
+        assert_eq!(Element::new("hello", "world"), *found);
+    }
+
+    #[test]
+    fn insert_overwrite_lookup() {
+        let map = ConMap::new();
+        assert!(map.insert("hello", "world").is_none());
+        let old = map.insert("hello", "universe").unwrap();
+        assert_eq!(Element::new("hello", "world"), *old);
+        let found = map.get("hello").unwrap();
+        assert_eq!(Element::new("hello", "universe"), *found);
+    }
+
+    // Insert a lot of things, to make sure we have multiple levels.
+    #[test]
+    fn insert_many() {
+        let map = ConMap::new();
+        for i in 0..TEST_BATCH * LEVEL_CELLS {
+            assert!(map.insert(i, i).is_none());
+        }
+
+        for i in 0..TEST_BATCH * LEVEL_CELLS {
+            assert_eq!(i, *map.get(&i).unwrap().value());
+        }
+    }
+
+    #[test]
+    fn par_insert_many() {
+        for _ in 0..TEST_REP {
+            let map: ConMap<usize, usize> = ConMap::new();
+            thread::scope(|s| {
+                for t in 0..TEST_THREADS {
+                    let map = &map;
+                    s.spawn(move |_| {
+                        for i in 0..TEST_BATCH {
+                            let num = t * TEST_BATCH + i;
+                            assert!(map.insert(num, num).is_none());
+                        }
+                    });
+                }
+            })
+            .unwrap();
+
+            for i in 0..TEST_BATCH * TEST_THREADS {
+                assert_eq!(*map.get(&i).unwrap().value(), i);
+            }
+        }
+    }
+
+    #[test]
+    fn par_get_many() {
+        for _ in 0..TEST_REP {
+            let map = ConMap::new();
+            for i in 0..TEST_BATCH * TEST_THREADS {
+                assert!(map.insert(i, i).is_none());
+            }
+            thread::scope(|s| {
+                for t in 0..TEST_THREADS {
+                    let map = &map;
+                    s.spawn(move |_| {
+                        for i in 0..TEST_BATCH {
+                            let num = t * TEST_BATCH + i;
+                            assert_eq!(*map.get(&num).unwrap().value(), num);
+                        }
+                    });
+                }
+            })
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn collisions() {
+        let map = ConMap::with_hasher(NoHasher);
+        // While their hash is the same under the hasher, they don't kick each other out.
+        for i in 0..TEST_BATCH_SMALL {
+            assert!(map.insert(i, i).is_none());
+        }
+        // And all are present.
+        for i in 0..TEST_BATCH_SMALL {
+            assert_eq!(i, *map.get(&i).unwrap().value());
+        }
+        // But reusing the key kicks the other one out.
+        for i in 0..TEST_BATCH_SMALL {
+            assert_eq!(i, *map.insert(i, i + 1).unwrap().value());
+            assert_eq!(i + 1, *map.get(&i).unwrap().value());
+        }
+    }
+
+    #[test]
+    fn get_or_insert_empty() {
+        let map = ConMap::new();
+        let val = map.get_or_insert("hello", 42);
+        assert_eq!(42, *val.value());
+        assert_eq!("hello", *val.key());
+        assert!(val.is_new());
+    }
+
+    #[test]
+    fn get_or_insert_existing() {
+        let map = ConMap::new();
+        assert!(map.insert("hello", 42).is_none());
+        let val = map.get_or_insert("hello", 0);
+        // We still have the original
+        assert_eq!(42, *val.value());
+        assert_eq!("hello", *val.key());
+        assert!(!val.is_new());
+    }
+
+    fn get_or_insert_many_inner<H: BuildHasher>(map: ConMap<usize, usize, H>, len: usize) {
+        for i in 0..len {
+            let val = map.get_or_insert(i, i);
+            assert_eq!(i, *val.key());
+            assert_eq!(i, *val.value());
+            assert!(val.is_new());
+        }
+
+        for i in 0..len {
+            let val = map.get_or_insert(i, 0);
+            assert_eq!(i, *val.key());
+            assert_eq!(i, *val.value());
+            assert!(!val.is_new());
+        }
+    }
+
+    #[test]
+    fn get_or_insert_many() {
+        get_or_insert_many_inner(ConMap::new(), TEST_BATCH);
+    }
+
+    #[test]
+    fn get_or_insert_collision() {
+        get_or_insert_many_inner(ConMap::with_hasher(NoHasher), TEST_BATCH_SMALL);
+    }
+
+    fn get_or_insert_once_inner<H: BuildHasher + Sync>(map: ConMap<usize, usize, H>, len: usize) {
+        let calls = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for _ in 0..TEST_THREADS {
+                s.spawn(|_| {
+                    for i in 0..len {
+                        let val = map.get_or_insert_with_once(i, || {
+                            calls.fetch_add(1, Ordering::Relaxed);
+                            i
+                        });
+                        assert_eq!(i, *val.value());
+                    }
+                });
+            }
+        })
+        .unwrap();
+        // Each value got created exactly once.
+        assert_eq!(len, calls.load(Ordering::Relaxed));
+        assert_eq!(len, map.len());
+    }
+
+    #[test]
+    fn get_or_insert_once() {
+        get_or_insert_once_inner(ConMap::new(), TEST_BATCH_SMALL);
+    }
+
+    #[test]
+    fn get_or_insert_once_collision() {
+        get_or_insert_once_inner(ConMap::with_hasher(NoHasher), TEST_BATCH_SMALL);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn get_or_insert_async() {
+        use futures::executor::block_on;
+
+        fn check_send<T: Send>(t: T) -> T {
+            t
+        }
+
+        let map = ConMap::new();
+        let val = block_on(check_send(map.get_or_insert_with_async(1, || async { 42 })));
+        assert!(val.is_new());
+        assert_eq!(42, *val.value());
+        let val = block_on(map.get_or_insert_with_async(1, || async { 0 }));
+        assert!(!val.is_new());
+        assert_eq!(42, *val.value());
+    }
+
+    #[test]
+    fn get_or_try_insert_err() {
+        let map = ConMap::<_, usize>::new();
+        let (key, e) = map
+            .get_or_try_insert_with("hello", |k| Err(format!("No value for {}", k)))
+            .unwrap_err();
+        assert_eq!("hello", key);
+        assert_eq!("No value for hello", e);
+        assert!(map.is_empty());
+        assert!(map.get("hello").is_none());
+    }
+
+    #[test]
+    fn get_or_try_insert_ok() {
+        let map = ConMap::new();
+        let val = map
+            .get_or_try_insert_with("hello", |_| "42".parse::<usize>())
+            .unwrap();
+        assert!(val.is_new());
+        assert_eq!(42, *val.value());
+
+        // The closure is not called for an existing element, so the failure doesn't matter.
+        let val = map
+            .get_or_try_insert_with("hello", |_| "nope".parse::<usize>())
+            .unwrap();
+        assert!(!val.is_new());
+        assert_eq!(42, *val.value());
+        assert_eq!(1, map.len());
+    }
+
+    #[test]
+    fn batch() {
+        let map = ConMap::new();
+        assert!(map.insert(1, "one").is_none());
+        let replaced = map.insert_many(vec![(0, "zero"), (1, "uno"), (2, "two")]);
+        let replaced = replaced
+            .iter()
+            .map(|e| e.as_ref().map(|e| *e.value()))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![None, Some("one"), None], replaced);
+
+        let found = map.get_many(&[&2, &3, &1]);
+        let found = found
+            .iter()
+            .map(|e| e.as_ref().map(|e| *e.value()))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![Some("two"), None, Some("uno")], found);
+
+        let removed = map.remove_many(&[&0, &3, &0]);
+        let removed = removed
+            .iter()
+            .map(|e| e.as_ref().map(|e| *e.value()))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![Some("zero"), None, None], removed);
+        assert_eq!(2, map.len());
+    }
+
+    #[test]
+    fn cursor_pages() {
+        let map: ConMap<usize, usize> = (0..TEST_BATCH_SMALL).map(|i| (i, i)).collect();
+        let mut found = Vec::new();
+        let mut cursor = Some(Cursor::default());
+        while let Some(start) = cursor {
+            let mut page = map.iter_from(start);
+            found.extend(page.by_ref().take(7).map(|e| *e.key()));
+            cursor = page.cursor();
+        }
+        found.sort();
+        assert_eq!((0..TEST_BATCH_SMALL).collect::<Vec<_>>(), found);
+    }
+
+    /// Removed values get destroyed while a repinning iterator is still alive.
+    #[test]
+    fn cursor_repin() {
+        let handle = Arc::new(());
+        let map = ConMap::with_hasher_and_collector(RandomState::new(), Collector::new());
+        for i in 0..10 {
+            map.insert(i, Arc::clone(&handle));
+        }
+        let mut iter = map.iter_from(Cursor::default()).repin_every(1);
+        let first = *iter.next().unwrap().key();
+        map.remove(&((first + 1) % 10));
+        // Each step takes a fresh pin, allowing the epoch to advance a bit further.
+        for _ in 0..4 {
+            map.flush_garbage();
+            assert!(iter.next().is_some());
+        }
+        map.flush_garbage();
+        assert_eq!(10, Arc::strong_count(&handle));
+        assert_eq!(4, iter.count());
+    }
+
+    #[test]
+    fn send_iter() {
+        fn assert_send<T: Send>(t: T) -> T {
+            t
+        }
+
+        let map: ConMap<usize, usize> = (0..TEST_BATCH_SMALL).map(|i| (i, i)).collect();
+        let iter = assert_send(map.send_iter());
+        let mut found = thread::scope(|s| {
+            s.spawn(move |_| iter.map(|e| *e.key()).collect::<Vec<_>>())
+                .join()
+                .unwrap()
+        })
+        .unwrap();
+        found.sort();
+        assert_eq!((0..TEST_BATCH_SMALL).collect::<Vec<_>>(), found);
+
+        // Can be held across an await point of a future that needs to be Send.
+        let pending = assert_send(async {
+            let mut iter = map.send_iter();
+            let first = iter.next();
+            futures::future::ready(()).await;
+            first.is_some() && iter.count() == TEST_BATCH_SMALL - 1
+        });
+        assert!(futures::executor::block_on(pending));
+    }
+
+    #[cfg(feature = "stream")]
+    #[test]
+    fn send_stream() {
+        use futures::stream::StreamExt;
+
+        let map: ConMap<usize, usize> = (0..TEST_BATCH_SMALL).map(|i| (i, i)).collect();
+        let stream = map.send_iter().into_stream().map(|e| *e.value());
+        let mut found = futures::executor::block_on(stream.collect::<Vec<_>>());
+        found.sort();
+        assert_eq!((0..TEST_BATCH_SMALL).collect::<Vec<_>>(), found);
+    }
+
+    #[test]
+    fn cursor_shards() {
+        let map: ConMap<usize, usize> = (0..TEST_BATCH_SMALL).map(|i| (i, i)).collect();
+        let mut found = thread::scope(|s| {
+            let handles = (0..LEVEL_CELLS as u64)
+                .map(|shard| {
+                    let map = &map;
+                    s.spawn(move |_| {
+                        let iter = if shard + 1 == LEVEL_CELLS as u64 {
+                            map.iter_hash_range(shard..)
+                        } else {
+                            map.iter_hash_range(shard..shard + 1)
+                        };
+                        iter.map(|e| *e.key()).collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect::<Vec<_>>()
+        })
+        .unwrap();
+        found.sort();
+        assert_eq!((0..TEST_BATCH_SMALL).collect::<Vec<_>>(), found);
+    }
+
+    #[test]
+    fn simple_remove() {
+        let map = ConMap::new();
+        assert!(map.remove(&42).is_none());
+        assert!(map.insert(42, "hello").is_none());
+        assert_eq!("hello", *map.get(&42).unwrap().value());
+        assert_eq!("hello", *map.remove(&42).unwrap().value());
+        assert!(map.get(&42).is_none());
+        assert!(map.is_empty());
+        assert!(map.remove(&42).is_none());
+        assert!(map.is_empty());
+    }
+
+    fn remove_many_inner<H: BuildHasher>(mut map: ConMap<usize, usize, H>, len: usize) {
+        for i in 0..len {
+            assert!(map.insert(i, i).is_none());
+        }
+        for i in 0..len {
+            assert_eq!(i, *map.get(&i).unwrap().value());
+            assert_eq!(i, *map.remove(&i).unwrap().value());
+            assert!(map.get(&i).is_none());
+            map.raw.assert_pruned();
+        }
+
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn remove_many() {
+        remove_many_inner(ConMap::new(), TEST_BATCH);
+    }
+
+    #[test]
+    fn remove_many_collision() {
+        remove_many_inner(ConMap::with_hasher(NoHasher), TEST_BATCH_SMALL);
+    }
+
+    #[test]
+    fn collision_remove_one_left() {
+        let mut map = ConMap::with_hasher(NoHasher);
+        map.insert(1, 1);
+        map.insert(2, 2);
+
+        map.raw.assert_pruned();
+
+        assert!(map.remove(&2).is_some());
+        map.raw.assert_pruned();
+
+        assert!(map.remove(&1).is_some());
+
+        map.raw.assert_pruned();
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn remove_par() {
+        let mut map = ConMap::new();
+        for i in 0..TEST_THREADS * TEST_BATCH {
+            map.insert(i, i);
+        }
+
+        thread::scope(|s| {
+            for t in 0..TEST_THREADS {
+                let map = &map;
+                s.spawn(move |_| {
+                    for i in 0..TEST_BATCH {
+                        let num = t * TEST_BATCH + i;
+                        let val = map.remove(&num).unwrap();
+                        assert_eq!(num, *val.value());
+                        assert_eq!(num, *val.key());
+                    }
+                });
+            }
+        })
+        .unwrap();
+
+        map.raw.assert_pruned();
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn unsized_values() {
+        let map: ConMap<usize, [usize]> = ConMap::new();
+        assert!(map
+            .insert_element(Arc::new(Element::new(42, [1, 2, 3])))
+            .is_none());
+        let found = map.get(&42).unwrap();
+        assert_eq!(&[1, 2, 3], found.value());
+        let inserted = map.get_or_insert_with_element(0, |k| {
+            assert_eq!(0, k);
+            Arc::new(Element::new(k, []))
+        });
+        assert_eq!(0, *inserted.key());
+        assert!(inserted.value().is_empty());
+        assert!(inserted.is_new());
+        let removed = map.remove(&0).unwrap();
+        assert_eq!(inserted.into_inner(), removed);
+    }
+
+    #[test]
+    fn replace_only_present() {
+        let map = ConMap::new();
+        assert!(map.replace("hello", 1).is_none());
+        assert!(map.get("hello").is_none());
+        assert!(map.is_empty());
+        assert!(map.insert("hello", 1).is_none());
+        assert_eq!(1, *map.replace("hello", 2).unwrap().value());
+        assert_eq!(2, *map.get("hello").unwrap().value());
+    }
+
+    #[test]
+    fn compare_and_swap_identity() {
+        let map = ConMap::with_hasher(NoHasher);
+        map.insert(1, 1);
+        map.insert(2, 2);
+        let current = map.get(&1).unwrap();
+        // Equal value, but a different element.
+        let other = Arc::new(Element::new(1, 1));
+        let found = map
+            .compare_and_swap_element(&other, Arc::new(Element::new(1, 10)))
+            .unwrap_err()
+            .unwrap();
+        assert!(Arc::ptr_eq(&current, &found));
+        let old = map
+            .compare_and_swap_element(&current, Arc::new(Element::new(1, 10)))
+            .unwrap();
+        assert!(Arc::ptr_eq(&current, &old));
+        assert_eq!(10, *map.get(&1).unwrap().value());
+        assert_eq!(2, *map.get(&2).unwrap().value());
+        assert!(map
+            .compare_and_swap_element(&current, Arc::new(Element::new(3, 3)))
+            .unwrap_err()
+            .is_none());
+        assert!(map.get(&3).is_none());
+    }
+
+    #[test]
+    fn compare_and_swap_value() {
+        let map = ConMap::new();
+        assert!(map.compare_and_swap_value(1, &1, 2).unwrap_err().is_none());
+        map.insert(1, 1);
+        assert_eq!(1, *map.compare_and_swap_value(1, &1, 2).unwrap().value());
+        let actual = map.compare_and_swap_value(1, &1, 3).unwrap_err().unwrap();
+        assert_eq!(2, *actual.value());
+    }
+
+    #[test]
+    fn remove_conditional() {
+        let mut map = ConMap::with_hasher(NoHasher);
+        map.insert(1, 1);
+        map.insert(2, 2);
+        let current = map.get(&1).unwrap();
+        assert!(map.remove_if(&1, |e| *e.value() == 2).is_none());
+        map.insert(1, 1);
+        // Replaced by an equal one, but not the same.
+        assert!(!map.remove_element(&current));
+        assert!(map.get(&1).is_some());
+        assert_eq!(1, *map.remove_if(&1, |e| *e.value() == 1).unwrap().value());
+        let current = map.get(&2).unwrap();
+        assert!(map.remove_element(&current));
+        map.raw.assert_pruned();
+        assert!(map.is_empty());
+    }
+
+    /// Counting through optimistic read-modify-write doesn't lose any updates.
+    #[test]
+    fn par_compare_and_swap_counter() {
+        let map = ConMap::new();
+        map.insert("counter", 0);
+        thread::scope(|s| {
+            for _ in 0..TEST_THREADS {
+                s.spawn(|_| {
+                    for _ in 0..TEST_BATCH_SMALL {
+                        let mut current = map.get("counter").unwrap();
+                        loop {
+                            let new = Arc::new(Element::new("counter", current.value() + 1));
+                            match map.compare_and_swap_element(&current, new) {
+                                Ok(_) => break,
+                                Err(actual) => current = actual.unwrap(),
+                            }
+                        }
+                    }
+                });
+            }
+        })
+        .unwrap();
+        assert_eq!(
+            TEST_THREADS * TEST_BATCH_SMALL,
+            *map.get("counter").unwrap().value()
+        );
+    }
+
+    #[test]
+    fn compute_insert_update_remove() {
+        let mut map = ConMap::with_hasher(NoHasher);
+        map.insert(2, 2);
+        assert!(map.compute(1, |v| v.map(|v| v + 1)).is_none());
+        assert!(map.get(&1).is_none());
+        let new = map
+            .compute(1, |v| Some(v.map(|v| v + 1).unwrap_or(10)))
+            .unwrap();
+        assert_eq!(10, *new.value());
+        let new = map
+            .compute(1, |v| Some(v.map(|v| v + 1).unwrap_or(10)))
+            .unwrap();
+        assert_eq!(11, *new.value());
+        assert!(map.compute(1, |_| None).is_none());
+        assert!(map.get(&1).is_none());
+        assert_eq!(2, *map.get(&2).unwrap().value());
+        map.raw.assert_pruned();
+    }
+
+    #[test]
+    fn compute_if_present_and_update() {
+        let mut map = ConMap::new();
+        assert!(map.update(&1, |v| v + 1).is_none());
+        assert!(map.compute_if_present(&1, |v| Some(v + 1)).is_none());
+        assert!(map.is_empty());
+        map.insert(1, 1);
+        assert_eq!(2, *map.update(&1, |v| v + 1).unwrap().value());
+        assert_eq!(
+            3,
+            *map.compute_if_present(&1, |v| Some(v + 1)).unwrap().value()
+        );
+        assert!(map.compute_if_present(&1, |_| None).is_none());
+        map.raw.assert_pruned();
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn merge() {
+        let mut map = ConMap::new();
+        assert_eq!(1, *map.merge(1, 1, |_, _| unreachable!()).unwrap().value());
+        assert_eq!(3, *map.merge(1, 2, |a, b| Some(a + b)).unwrap().value());
+        assert!(map
+            .merge(1, 3, |a, b| if a == b { None } else { Some(0) })
+            .is_none());
+        map.raw.assert_pruned();
+        assert!(map.is_empty());
+    }
+
+    /// Concurrent updates through compute don't get lost, even in collision nodes where other keys
+    /// are changing too.
+    #[test]
+    fn par_compute() {
+        let map = ConMap::with_hasher(NoHasher);
+        thread::scope(|s| {
+            for t in 0..TEST_THREADS {
+                let map = &map;
+                s.spawn(move |_| {
+                    for i in 0..TEST_BATCH_SMALL {
+                        map.compute(i % 2, |v| Some(v.unwrap_or(&0) + 1));
+                        map.merge(t + 2, 1, |a, b| Some(a + b));
+                    }
+                });
+            }
+        })
+        .unwrap();
+        let total = TEST_THREADS * TEST_BATCH_SMALL / 2;
+        assert_eq!(total, *map.get(&0).unwrap().value());
+        assert_eq!(total, *map.get(&1).unwrap().value());
+        for t in 0..TEST_THREADS {
+            assert_eq!(TEST_BATCH_SMALL, *map.get(&(t + 2)).unwrap().value());
+        }
+    }
+
+    #[test]
+    fn entry_insert_modify_remove() {
+        let map = ConMap::new();
+        assert_eq!(1, *map.entry("hello").or_insert(1).value());
+        assert_eq!(1, *map.entry("hello").or_insert(2).value());
+        let entry = map.entry("hello").and_modify(|v| v + 10);
+        assert_eq!(11, *map.get("hello").unwrap().value());
+        match entry {
+            Entry::Occupied(entry) => {
+                assert_eq!(11, *entry.get());
+                assert_eq!(11, *entry.remove().unwrap().value());
+            }
+            Entry::Vacant(_) => panic!("Entry should be occupied"),
+        }
+        assert!(map.get("hello").is_none());
+        match map.entry("hello").and_modify(|v| v + 10) {
+            Entry::Vacant(entry) => assert_eq!(2, *entry.insert(2).unwrap().value()),
+            Entry::Occupied(_) => panic!("Entry should be vacant"),
+        }
+        assert_eq!(2, *map.get("hello").unwrap().value());
+    }
+
+    #[test]
+    fn entry_conflict() {
+        let map = ConMap::new();
+        let vacant = map.entry("hello");
+        map.insert("hello", 1);
+        match vacant {
+            Entry::Vacant(entry) => match entry.insert(2).err().unwrap() {
+                Entry::Occupied(entry) => assert_eq!(1, *entry.get()),
+                Entry::Vacant(_) => panic!("Entry should be occupied"),
+            },
+            Entry::Occupied(_) => panic!("Entry should be vacant"),
+        }
+
+        let occupied = map.entry("hello");
+        map.remove("hello");
+        match occupied {
+            Entry::Occupied(entry) => match entry.insert(3).err().unwrap() {
+                Entry::Vacant(entry) => assert_eq!("hello", entry.into_key()),
+                Entry::Occupied(_) => panic!("Entry should be vacant"),
+            },
+            Entry::Vacant(_) => panic!("Entry should be occupied"),
+        }
+        assert!(map.get("hello").is_none());
+
+        map.insert("hello", 4);
+        let occupied = map.entry("hello");
+        // The same value, but a different element
+        map.insert("hello", 4);
+        match occupied {
+            Entry::Occupied(entry) => assert!(entry.remove().is_err()),
+            Entry::Vacant(_) => panic!("Entry should be occupied"),
+        }
+        assert_eq!(4, *map.get("hello").unwrap().value());
     }
 
+    /// Modifying another key in the same collision node rebuilds the node, but the entry still
+    /// recognizes its element.
     #[test]
-    fn insert_overwrite_lookup() {
-        let map = ConMap::new();
-        assert!(map.insert("hello", "world").is_none());
-        let old = map.insert("hello", "universe").unwrap();
-        assert_eq!(Element::new("hello", "world"), *old);
-        let found = map.get("hello").unwrap();
-        assert_eq!(Element::new("hello", "universe"), *found);
+    fn entry_collision_neighbour() {
+        let map = ConMap::with_hasher(NoHasher);
+        map.insert(1, 1);
+        map.insert(2, 2);
+        let occupied = map.entry(1);
+        map.insert(2, 3);
+        let old = match occupied {
+            Entry::Occupied(entry) => entry.insert(10).unwrap(),
+            Entry::Vacant(_) => panic!("Entry should be occupied"),
+        };
+        assert_eq!(1, *old.value());
+
+        let occupied = map.entry(1);
+        map.remove(&2);
+        match occupied {
+            Entry::Occupied(entry) => assert_eq!(10, *entry.remove().unwrap().value()),
+            Entry::Vacant(_) => panic!("Entry should be occupied"),
+        }
+        assert!(map.get(&1).is_none());
     }
 
-    // Insert a lot of things, to make sure we have multiple levels.
     #[test]
-    fn insert_many() {
-        let map = ConMap::new();
-        for i in 0..TEST_BATCH * LEVEL_CELLS {
-            assert!(map.insert(i, i).is_none());
-        }
+    fn par_entry() {
+        let map = ConMap::with_hasher(NoHasher);
+        thread::scope(|s| {
+            for _ in 0..TEST_THREADS {
+                let map = &map;
+                s.spawn(move |_| {
+                    for i in 0..TEST_BATCH_SMALL {
+                        map.entry(i % 2).and_modify(|v| v + 1).or_insert(1);
+                    }
+                });
+            }
+        })
+        .unwrap();
+        let total = TEST_THREADS * TEST_BATCH_SMALL / 2;
+        assert_eq!(total, *map.get(&0).unwrap().value());
+        assert_eq!(total, *map.get(&1).unwrap().value());
+    }
 
-        for i in 0..TEST_BATCH * LEVEL_CELLS {
-            assert_eq!(i, *map.get(&i).unwrap().value());
+    #[test]
+    fn retain() {
+        let mut map: ConMap<usize, usize> = (0..TEST_BATCH).map(|i| (i, i)).collect();
+        map.retain(|k, v| k % 3 != 0 && *v != 1);
+        map.raw.assert_pruned();
+        for i in 0..TEST_BATCH {
+            assert_eq!(i % 3 != 0 && i != 1, map.get(&i).is_some());
         }
+        map.retain(|_, _| false);
+        map.raw.assert_pruned();
+        assert!(map.is_empty());
     }
 
     #[test]
-    fn par_insert_many() {
+    fn par_retain() {
         for _ in 0..TEST_REP {
-            let map: ConMap<usize, usize> = ConMap::new();
+            let mut map: ConMap<usize, usize> =
+                (0..TEST_BATCH * TEST_THREADS).map(|i| (i, i)).collect();
             thread::scope(|s| {
                 for t in 0..TEST_THREADS {
                     let map = &map;
-                    s.spawn(move |_| {
-                        for i in 0..TEST_BATCH {
-                            let num = t * TEST_BATCH + i;
-                            assert!(map.insert(num, num).is_none());
-                        }
-                    });
+                    s.spawn(move |_| map.retain(|k, _| k % TEST_THREADS != t));
                 }
             })
             .unwrap();
-
-            for i in 0..TEST_BATCH * TEST_THREADS {
-                assert_eq!(*map.get(&i).unwrap().value(), i);
-            }
+            map.raw.assert_pruned();
+            assert!(map.is_empty());
         }
     }
 
     #[test]
-    fn par_get_many() {
+    fn clear() {
+        let map: ConMap<usize, usize> = (0..TEST_BATCH).map(|i| (i, i)).collect();
+        map.clear();
+        assert!(map.is_empty());
+        assert!(map.get(&0).is_none());
+        map.insert(0, 1);
+        assert_eq!(1, *map.get(&0).unwrap().value());
+    }
+
+    /// Taking the content while other threads insert doesn't lose nor duplicate anything.
+    #[test]
+    fn par_clear_and_take() {
         for _ in 0..TEST_REP {
-            let map = ConMap::new();
-            for i in 0..TEST_BATCH * TEST_THREADS {
-                assert!(map.insert(i, i).is_none());
-            }
-            thread::scope(|s| {
+            let map: ConMap<usize, usize> = ConMap::new();
+            let taken = thread::scope(|s| {
                 for t in 0..TEST_THREADS {
                     let map = &map;
                     s.spawn(move |_| {
                         for i in 0..TEST_BATCH {
                             let num = t * TEST_BATCH + i;
-                            assert_eq!(*map.get(&num).unwrap().value(), num);
+                            assert!(map.insert(num, num).is_none());
                         }
                     });
                 }
+                let taker = s.spawn(|_| {
+                    (0..TEST_BATCH_SMALL)
+                        .map(|_| map.clear_and_take())
+                        .collect::<Vec<_>>()
+                });
+                taker.join().unwrap()
             })
             .unwrap();
+
+            let mut found = taken
+                .iter()
+                .chain(Some(&map))
+                .flat_map(|m| m.iter().map(|e| *e.key()))
+                .collect::<Vec<_>>();
+            found.sort();
+            let expected = (0..TEST_BATCH * TEST_THREADS).collect::<Vec<_>>();
+            assert_eq!(expected, found);
         }
     }
 
     #[test]
-    fn collisions() {
-        let map = ConMap::with_hasher(NoHasher);
-        // While their hash is the same under the hasher, they don't kick each other out.
-        for i in 0..TEST_BATCH_SMALL {
-            assert!(map.insert(i, i).is_none());
-        }
-        // And all are present.
-        for i in 0..TEST_BATCH_SMALL {
-            assert_eq!(i, *map.get(&i).unwrap().value());
-        }
-        // But reusing the key kicks the other one out.
+    fn len() {
+        let map = ConMap::new();
+        assert_eq!(0, map.len());
         for i in 0..TEST_BATCH_SMALL {
-            assert_eq!(i, *map.insert(i, i + 1).unwrap().value());
-            assert_eq!(i + 1, *map.get(&i).unwrap().value());
+            map.insert(i, i);
         }
+        map.insert(0, 1);
+        map.replace(1, 2);
+        map.compute(TEST_BATCH_SMALL, |_| Some(0));
+        assert_eq!(TEST_BATCH_SMALL + 1, map.len());
+        map.remove(&0);
+        map.remove(&0);
+        map.retain(|k, _| k % 2 == 0);
+        assert_eq!(TEST_BATCH_SMALL / 2, map.len());
+        assert_eq!(TEST_BATCH_SMALL / 2, map.approx_len());
+        assert_eq!((0, None), map.iter().size_hint());
+        let taken = map.clear_and_take();
+        assert_eq!(0, map.len());
+        assert_eq!(TEST_BATCH_SMALL / 2, taken.len());
     }
 
     #[test]
-    fn get_or_insert_empty() {
+    fn par_len() {
         let map = ConMap::new();
-        let val = map.get_or_insert("hello", 42);
-        assert_eq!(42, *val.value());
-        assert_eq!("hello", *val.key());
-        assert!(val.is_new());
+        thread::scope(|s| {
+            for t in 0..TEST_THREADS {
+                let map = &map;
+                s.spawn(move |_| {
+                    for i in 0..TEST_BATCH {
+                        let num = t * TEST_BATCH + i;
+                        map.insert(num, num);
+                        if i % 2 == 0 {
+                            map.remove(&num);
+                        }
+                    }
+                });
+            }
+            s.spawn(|_| {
+                for _ in 0..TEST_BATCH {
+                    assert!(map.len() <= TEST_BATCH * TEST_THREADS);
+                }
+            });
+        })
+        .unwrap();
+        assert_eq!(TEST_BATCH * TEST_THREADS / 2, map.len());
     }
 
+    /// The references and pinned lookups stay valid while other threads remove the elements.
     #[test]
-    fn get_or_insert_existing() {
+    fn pinned_reads() {
         let map = ConMap::new();
-        assert!(map.insert("hello", 42).is_none());
-        let val = map.get_or_insert("hello", 0);
-        // We still have the original
-        assert_eq!(42, *val.value());
-        assert_eq!("hello", *val.key());
-        assert!(!val.is_new());
-    }
-
-    fn get_or_insert_many_inner<H: BuildHasher>(map: ConMap<usize, usize, H>, len: usize) {
-        for i in 0..len {
-            let val = map.get_or_insert(i, i);
-            assert_eq!(i, *val.key());
-            assert_eq!(i, *val.value());
-            assert!(val.is_new());
-        }
-
-        for i in 0..len {
-            let val = map.get_or_insert(i, 0);
-            assert_eq!(i, *val.key());
-            assert_eq!(i, *val.value());
-            assert!(!val.is_new());
+        for i in 0..TEST_BATCH_SMALL {
+            map.insert(i, i.to_string());
         }
-    }
-
-    #[test]
-    fn get_or_insert_many() {
-        get_or_insert_many_inner(ConMap::new(), TEST_BATCH);
-    }
-
-    #[test]
-    fn get_or_insert_collision() {
-        get_or_insert_many_inner(ConMap::with_hasher(NoHasher), TEST_BATCH_SMALL);
-    }
-
-    #[test]
-    fn simple_remove() {
-        let map = ConMap::new();
-        assert!(map.remove(&42).is_none());
-        assert!(map.insert(42, "hello").is_none());
-        assert_eq!("hello", *map.get(&42).unwrap().value());
-        assert_eq!("hello", *map.remove(&42).unwrap().value());
-        assert!(map.get(&42).is_none());
-        assert!(map.is_empty());
-        assert!(map.remove(&42).is_none());
+        let pinned = map.pin();
+        let refs = (0..TEST_BATCH_SMALL)
+            .map(|i| map.get_ref(&i).unwrap())
+            .collect::<Vec<_>>();
+        let values = (0..TEST_BATCH_SMALL)
+            .map(|i| pinned.get_key_value(&i).unwrap())
+            .collect::<Vec<_>>();
+        thread::scope(|s| {
+            s.spawn(|_| {
+                for i in 0..TEST_BATCH_SMALL {
+                    map.remove(&i);
+                }
+                map.flush_garbage();
+            });
+        })
+        .unwrap();
         assert!(map.is_empty());
-    }
-
-    fn remove_many_inner<H: BuildHasher>(mut map: ConMap<usize, usize, H>, len: usize) {
-        for i in 0..len {
-            assert!(map.insert(i, i).is_none());
-        }
-        for i in 0..len {
-            assert_eq!(i, *map.get(&i).unwrap().value());
-            assert_eq!(i, *map.remove(&i).unwrap().value());
-            assert!(map.get(&i).is_none());
-            map.raw.assert_pruned();
+        assert!(!pinned.contains_key(&0));
+        for (i, (r, (k, v))) in refs.iter().zip(values).enumerate() {
+            assert_eq!(i, *r.key());
+            assert_eq!(i, *k);
+            assert_eq!(i.to_string(), *r.value());
+            assert_eq!(i.to_string(), *v);
         }
-
-        assert!(map.is_empty());
-    }
-
-    #[test]
-    fn remove_many() {
-        remove_many_inner(ConMap::new(), TEST_BATCH);
-    }
-
-    #[test]
-    fn remove_many_collision() {
-        remove_many_inner(ConMap::with_hasher(NoHasher), TEST_BATCH_SMALL);
     }
 
+    /// A scoped map can hold keys borrowed from a local buffer, even across threads.
     #[test]
-    fn collision_remove_one_left() {
-        let mut map = ConMap::with_hasher(NoHasher);
-        map.insert(1, 1);
-        map.insert(2, 2);
-
-        map.raw.assert_pruned();
-
-        assert!(map.remove(&2).is_some());
-        map.raw.assert_pruned();
-
-        assert!(map.remove(&1).is_some());
-
-        map.raw.assert_pruned();
-        assert!(map.is_empty());
+    fn scoped_borrowed_keys() {
+        let input = (0..TEST_BATCH_SMALL)
+            .map(|i| format!("key{}", i))
+            .collect::<Vec<_>>();
+        let map = ConMap::new_scoped();
+        thread::scope(|s| {
+            for t in 0..TEST_THREADS {
+                let map = &map;
+                let input = &input;
+                s.spawn(move |_| {
+                    for (i, key) in input.iter().enumerate() {
+                        map.insert(key.as_str(), t);
+                        if i % 2 == 0 {
+                            map.remove(key.as_str());
+                        }
+                    }
+                });
+            }
+        })
+        .unwrap();
+        assert_eq!(TEST_BATCH_SMALL / 2, map.len());
+        assert!(map.get("key1").is_some());
+        let copy = map.clone();
+        drop(map);
+        assert!(copy.get("key1").is_some());
     }
 
+    /// The snapshot keeps seeing the content from the time it was taken, while other threads
+    /// modify the map.
     #[test]
-    fn remove_par() {
-        let mut map = ConMap::new();
-        for i in 0..TEST_THREADS * TEST_BATCH {
+    fn snapshot_isolated() {
+        let map = ConMap::with_hasher(NoHasher).with_snapshots();
+        for i in 0..TEST_BATCH_SMALL {
             map.insert(i, i);
         }
-
+        let snapshot = map.snapshot();
         thread::scope(|s| {
             for t in 0..TEST_THREADS {
                 let map = &map;
                 s.spawn(move |_| {
-                    for i in 0..TEST_BATCH {
-                        let num = t * TEST_BATCH + i;
-                        let val = map.remove(&num).unwrap();
-                        assert_eq!(num, *val.value());
-                        assert_eq!(num, *val.key());
+                    for i in 0..TEST_BATCH_SMALL {
+                        if i % TEST_THREADS == t {
+                            map.remove(&i);
+                            map.insert(i + TEST_BATCH_SMALL, i);
+                        }
                     }
                 });
             }
         })
         .unwrap();
+        assert!(map.get(&0).is_none());
+        let mut content = snapshot.iter().map(|e| *e.key()).collect::<Vec<_>>();
+        content.sort();
+        assert_eq!((0..TEST_BATCH_SMALL).collect::<Vec<_>>(), content);
+        for i in 0..TEST_BATCH_SMALL {
+            assert_eq!(Some((&i, &i)), snapshot.get_key_value(&i));
+            assert!(!snapshot.contains_key(&(i + TEST_BATCH_SMALL)));
+        }
+    }
 
-        map.raw.assert_pruned();
-        assert!(map.is_empty());
+    /// The maps made out of a map with snapshots have them too.
+    #[test]
+    fn snapshot_copies() {
+        let map = ConMap::new().with_snapshots();
+        map.insert(1, 1);
+        assert!(map.clone().snapshot().contains_key(&1));
+        assert!(map.clear_and_take().snapshot().contains_key(&1));
+        assert!(map.snapshot().is_empty());
     }
 
     #[test]
-    fn unsized_values() {
-        let map: ConMap<usize, [usize]> = ConMap::new();
-        assert!(map
-            .insert_element(Arc::new(Element::new(42, [1, 2, 3])))
-            .is_none());
-        let found = map.get(&42).unwrap();
-        assert_eq!(&[1, 2, 3], found.value());
-        let inserted = map.get_or_insert_with_element(0, |k| {
-            assert_eq!(0, k);
-            Arc::new(Element::new(k, []))
-        });
-        assert_eq!(0, *inserted.key());
-        assert!(inserted.value().is_empty());
-        assert!(inserted.is_new());
-        let removed = map.remove(&0).unwrap();
-        assert_eq!(inserted.into_inner(), removed);
+    #[should_panic(expected = "Snapshots are not turned on")]
+    fn snapshot_not_turned_on() {
+        ConMap::<usize, usize>::new().snapshot();
     }
 
     fn iter_test_inner<S: BuildHasher>(map: ConMap<usize, usize, S>) {
@@ -931,4 +3111,52 @@ mod tests {
         let expected = (0..TEST_BATCH_SMALL).collect::<Vec<_>>();
         assert_eq!(expected, extracted);
     }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn rayon_par_iter() {
+        let map = ConMap::new();
+        map.insert_many((0..TEST_BATCH_SMALL).map(|i| (i, i)));
+
+        let mut extracted = map
+            .par_iter()
+            .map(|n| {
+                assert_eq!(n.key(), n.value());
+                *n.value()
+            })
+            .collect::<Vec<_>>();
+        extracted.par_sort();
+
+        let expected = (0..TEST_BATCH_SMALL).collect::<Vec<_>>();
+        assert_eq!(expected, extracted);
+        assert_eq!(TEST_BATCH_SMALL, (&map).into_par_iter().count());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn rayon_par_bulk() {
+        let map = ConMap::new();
+        map.insert_many((0..TEST_BATCH_SMALL).map(|i| (i, i)));
+
+        let copy = map.par_clone();
+        let doubled = map.par_map_values(|k, v| k + v);
+        map.par_retain(|k, _| k % 2 == 0);
+        let sum = AtomicUsize::new(0);
+        map.par_for_each(|_, v| {
+            sum.fetch_add(*v, Ordering::Relaxed);
+        });
+
+        let expected = (0..TEST_BATCH_SMALL).filter(|i| i % 2 == 0).sum::<usize>();
+        assert_eq!(expected, sum.into_inner());
+        assert_eq!(
+            (0..TEST_BATCH_SMALL).filter(|i| i % 2 == 0).count(),
+            map.len()
+        );
+        assert_eq!(TEST_BATCH_SMALL, copy.len());
+        assert_eq!(TEST_BATCH_SMALL, doubled.len());
+        for i in 0..TEST_BATCH_SMALL {
+            assert_eq!(i, *copy.get(&i).unwrap().value());
+            assert_eq!(2 * i, *doubled.get(&i).unwrap().value());
+        }
+    }
 }
@@ -0,0 +1,279 @@
+//! Batch operations on the [`Raw`] trie.
+//!
+//! Each batch takes a single pin and hashes all the keys up front. The keys are then handled in
+//! the order of their paths through the trie (that is, sorted by the bits of the hash the levels
+//! use), so consecutive keys tend to share a prefix of their paths. The lookups take advantage of
+//! that and resume from the deepest inner node shared with the previous key instead of starting at
+//! the top again. The modifications walk from the top each time, as the inner nodes seen by the
+//! previous one might have been pruned (or copied, in the snapshot mode) since; they still profit
+//! from the warm caches.
+//!
+//! The results are returned in the order of the input. A batch is not atomic ‒ it is the same as
+//! doing the operations one by one, only in a different order. The order of equal keys is
+//! preserved, though.
+
+use std::borrow::Borrow;
+use std::hash::{BuildHasher, Hash};
+
+use arrayvec::ArrayVec;
+use crossbeam_epoch::{Guard, Shared};
+#[cfg(feature = "rayon")]
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use super::config::{Config, HashValue};
+use super::{
+    load_data, load_inner, load_ptr, nf, path_order, shared_levels, Inner, Levels, NodeFlags,
+    Outcome, Raw, TraverseMode, TraverseState, PATH_CAPACITY,
+};
+
+/// How many values [`extend`][Raw::extend] inserts under a single pin.
+const EXTEND_BATCH: usize = 1024;
+
+/// The indices of the hashes, sorted by their paths through the trie.
+///
+/// The sort is stable, so equal keys stay in the original order.
+fn by_path<C: Config>(hashes: &[C::Hash]) -> Vec<usize> {
+    let mut order = (0..hashes.len()).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| path_order::<C>(hashes[a], hashes[b]));
+    order
+}
+
+impl<C, S> Raw<C, S>
+where
+    C: Config,
+    S: BuildHasher,
+{
+    /// Looks up multiple values at once.
+    ///
+    /// All the keys are hashed up front and looked up in the order of their paths through the
+    /// trie, under the single pin. Each lookup resumes from the deepest inner node shared with the
+    /// previous one. The results are in the same order as the keys.
+    pub fn get_many<'r, 's, 'p, Q>(
+        &'s self,
+        keys: &[&Q],
+        pin: &'p Guard,
+    ) -> Vec<Option<&'r C::Payload>>
+    where
+        's: 'r,
+        'p: 's,
+        Q: ?Sized + Eq + Hash,
+        C::Key: Borrow<Q>,
+    {
+        let hashes = keys.iter().map(|key| self.hash(*key)).collect::<Vec<_>>();
+        let mut result = vec![None; keys.len()];
+        // The inner nodes on the path of the previous key, the one at the top first.
+        let mut path: ArrayVec<[Shared<'p, Inner>; PATH_CAPACITY]> = ArrayVec::new();
+        let mut previous = None;
+        for idx in by_path::<C>(&hashes) {
+            let (hash, key) = (hashes[idx], keys[idx]);
+            // The inner node on the level after the last shared one is still on our path.
+            let keep = previous.map_or(0, |previous| shared_levels::<C>(previous, hash) + 1);
+            path.truncate(keep);
+            previous = Some(hash);
+
+            let mut shift = path.len() * C::LEVEL_BITS;
+            let mut node = match path.last() {
+                Some(&inner) => {
+                    let bits = hash.bits(shift - C::LEVEL_BITS, C::LEVEL_MASK);
+                    match unsafe { load_inner::<C>(inner) }.slot(bits) {
+                        Some(slot) => load_ptr::<C, _>(slot, pin),
+                        None => Shared::null(),
+                    }
+                }
+                None => load_ptr::<C, _>(&self.root(pin).trie, pin),
+            };
+            result[idx] = loop {
+                if node.is_null() {
+                    break None;
+                } else if nf(node).contains(NodeFlags::DATA) {
+                    break unsafe { load_data::<C>(node) }
+                        .iter()
+                        .find(|l| C::eq((*l).borrow(), key));
+                } else {
+                    path.push(node);
+                    let inner = unsafe { load_inner::<C>(node) };
+                    let bits = hash.bits(shift, C::LEVEL_MASK);
+                    shift += C::LEVEL_BITS;
+                    node = match inner.slot(bits) {
+                        Some(slot) => load_ptr::<C, _>(slot, pin),
+                        None => Shared::null(),
+                    };
+                }
+            };
+        }
+        result
+    }
+
+    /// Inserts multiple values at once, replacing any previously held ones.
+    ///
+    /// Returns the replaced values, in the same order as the new ones. If there are multiple
+    /// values with the same key in the batch, the later one wins (and replaces the earlier one).
+    ///
+    /// Like with [`get_many`][Raw::get_many], the values are inserted in the order of their paths
+    /// through the trie, but each insertion walks from the root. The batch is not atomic.
+    pub fn insert_many<'s, 'p, 'r, I>(
+        &'s self,
+        payloads: I,
+        pin: &'p Guard,
+    ) -> Vec<Option<&'r C::Payload>>
+    where
+        's: 'r,
+        'p: 'r,
+        I: IntoIterator<Item = C::Payload>,
+    {
+        let mut payloads = payloads.into_iter().map(Some).collect::<Vec<_>>();
+        let hashes = payloads
+            .iter()
+            .map(|payload| self.hash(payload.as_ref().unwrap().borrow()))
+            .collect::<Vec<_>>();
+        let mut result = Vec::new();
+        result.resize_with(payloads.len(), || None);
+        for idx in by_path::<C>(&hashes) {
+            let payload = payloads[idx].take().expect("Each payload is inserted once");
+            let outcome = self.traverse_hashed(
+                hashes[idx],
+                &mut TraverseState::<C, fn(C::Key) -> C::Payload>::Created(payload),
+                TraverseMode::<fn(&C::Payload) -> bool>::Overwrite,
+                pin,
+            );
+            result[idx] = match outcome {
+                Outcome::Replaced(previous, _) => Some(previous),
+                Outcome::Inserted(_) => None,
+                Outcome::Missing | Outcome::Kept(_) | Outcome::Placed | Outcome::InFlight(_) => {
+                    unreachable!("Overwrite always writes")
+                }
+            };
+        }
+        result
+    }
+
+    /// Removes multiple values at once.
+    ///
+    /// Returns the removed values, in the same order as the keys. Like
+    /// [`insert_many`][Raw::insert_many], each removal walks from the root and the batch is not
+    /// atomic.
+    pub fn remove_many<'r, 's, 'p, Q>(
+        &'s self,
+        keys: &[&Q],
+        pin: &'p Guard,
+    ) -> Vec<Option<&'r C::Payload>>
+    where
+        's: 'r,
+        'p: 'r,
+        Q: ?Sized + Eq + Hash,
+        C::Key: Borrow<Q>,
+    {
+        let hashes = keys.iter().map(|key| self.hash(*key)).collect::<Vec<_>>();
+        let mut result = vec![None; keys.len()];
+        for idx in by_path::<C>(&hashes) {
+            result[idx] = self.remove_hashed(hashes[idx], keys[idx], pin);
+        }
+        result
+    }
+
+    /// Inserts all the values, in batches.
+    ///
+    /// Each batch is inserted by [`insert_many`][Raw::insert_many], under its own pin (so the
+    /// memory can get reclaimed during a long insertion).
+    pub(crate) fn extend<I>(&self, payloads: I)
+    where
+        I: IntoIterator<Item = C::Payload>,
+    {
+        let mut payloads = payloads.into_iter().peekable();
+        while payloads.peek().is_some() {
+            let pin = self.guard();
+            self.insert_many(payloads.by_ref().take(EXTEND_BATCH), &pin);
+        }
+    }
+
+    /// Inserts all the values from a parallel iterator, in batches.
+    ///
+    /// Each thread collects the values into its own batch and inserts it once it is full.
+    #[cfg(feature = "rayon")]
+    pub(crate) fn par_extend<I>(&self, payloads: I)
+    where
+        I: IntoParallelIterator<Item = C::Payload>,
+        C::Payload: Send,
+        Self: Sync,
+    {
+        payloads
+            .into_par_iter()
+            .fold(Vec::new, |mut batch, payload| {
+                batch.push(payload);
+                if batch.len() == EXTEND_BATCH {
+                    self.extend(batch.drain(..));
+                }
+                batch
+            })
+            .for_each(|rest| self.extend(rest));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::RandomState;
+
+    use super::super::config::{Snapshots, Trivial};
+    use super::super::tests::NoHasher;
+    use super::*;
+
+    #[test]
+    fn order_by_path() {
+        type C = Trivial<usize>;
+        let hashes = [0b1_00000u64, 0b10, 0b0_00001, 0b1_00001, 0b10];
+        let order = by_path::<C>(&hashes);
+        assert_eq!(vec![0, 2, 3, 1, 4], order);
+        assert_eq!(0, shared_levels::<C>(0b10, 0b01));
+        assert_eq!(1, shared_levels::<C>(0b0_00001, 0b1_00001));
+        assert_eq!(C::MAX_LEVELS, shared_levels::<C>(42, 42));
+    }
+
+    fn check_many<C, S>(map: Raw<C, S>)
+    where
+        C: Config<Key = usize, Payload = usize>,
+        S: BuildHasher,
+    {
+        let pin = crossbeam_epoch::pin();
+        let input = (0..1000).rev().chain(vec![3, 3]).collect::<Vec<_>>();
+        let replaced = map.insert_many(input, &pin);
+        assert_eq!(1002, replaced.len());
+        assert!(replaced[..1000].iter().all(Option::is_none));
+        assert_eq!(vec![Some(&3), Some(&3)], replaced[1000..].to_vec());
+        assert_eq!(1000, map.len());
+
+        let keys = (0..2000).step_by(3).collect::<Vec<_>>();
+        let key_refs = keys.iter().collect::<Vec<_>>();
+        let found = map.get_many(&key_refs, &pin);
+        for (key, found) in keys.iter().zip(&found) {
+            assert_eq!(*key < 1000, found.is_some());
+            if let Some(found) = found {
+                assert_eq!(key, *found);
+            }
+        }
+
+        let removed = map.remove_many(&key_refs, &pin);
+        assert_eq!(found, removed);
+        for key in 0..1000 {
+            assert_eq!(key % 3 != 0, map.get(&key, &pin).is_some());
+        }
+        let removed = map.remove_many(&key_refs, &pin);
+        assert!(removed.iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn many() {
+        check_many(Raw::<Trivial<usize>, _>::with_hasher(RandomState::new()));
+    }
+
+    #[test]
+    fn many_collisions() {
+        check_many(Raw::<Trivial<usize>, _>::with_hasher(NoHasher));
+    }
+
+    #[test]
+    fn many_cow() {
+        check_many(Raw::<Snapshots<Trivial<usize>>, _>::with_hasher(
+            RandomState::new(),
+        ));
+    }
+}
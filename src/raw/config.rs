@@ -1,24 +1,220 @@
-//! The [`Config`][crate::raw::config::Config] trait for specifying behaviour of
+//! The [`Config`] trait for specifying behaviour of
 //! [`Raw`][crate::raw::Raw].
 use std::borrow::Borrow;
-use std::hash::Hash;
+use std::hash::{BuildHasher, Hash, Hasher};
 use std::marker::PhantomData;
+use std::ptr;
+use std::str;
+
+use smallvec::SmallVec;
 
-// TODO: Allow our own hash, returning something else than just u64. Then the constants go here
-// too.
 // TODO: Should Hasher go here too?
-// TODO: Can we get rid of that Clone here? It is currently needed in the collision handling.
 /// Customization of the [`Raw`][crate::raw::Raw].
 ///
 /// This specifies how the trie should act. Maybe some more customization will be possible in the
 /// future, but for now this allows tweaking what in how is stored.
 pub trait Config {
     /// The payload (eg. values) stored inside the trie.
-    type Payload: Clone + Borrow<Self::Key> + 'static;
+    ///
+    /// The trie never makes copies of the payload, so it doesn't have to be `Clone`.
+    type Payload: Borrow<Self::Key>;
 
     /// Each payload must contain a key as its part. This is the type for the key, which is used
     /// for hashing and identification of values in the tree.
+    ///
+    /// By default, the key is hashed and compared by its [`Hash`] and [`Eq`] implementations, but
+    /// this can be changed by the [`hash`][Config::hash] and [`eq`][Config::eq] methods.
     type Key: Hash + Eq;
+
+    /// The hash of the keys, which determines their place in the trie.
+    ///
+    /// The usual choice is `u64`. With `u128`, full-hash collisions (which are handled by a slow
+    /// linear search) become practically impossible, while `u32` makes the trie shallower and the
+    /// traversal cheaper for small maps.
+    type Hash: HashValue;
+
+    /// Keep the trie ready for taking [snapshots][crate::raw::Raw::snapshot].
+    ///
+    /// If turned on, the nodes are never modified once they are published. Each modification
+    /// creates copies of all the nodes on the path from the root to the changed value and swaps
+    /// the whole path in by a single compare-and-swap of the root. A snapshot is then just the
+    /// pointer to the root at the given moment, so taking one is cheap.
+    ///
+    /// This makes the modifications considerably slower (each one allocates the whole path and
+    /// all of them compete for the single root pointer), therefore it is off by default. Lookups
+    /// and iteration stay the same.
+    const SNAPSHOTS: bool = false;
+
+    /// Use compressed inner nodes.
+    ///
+    /// By default, each inner node of the trie has a slot for each possible child, even if most of
+    /// them are empty. With this turned on, the node contains a bitmap of the occupied slots and
+    /// only these. This saves a lot of memory in large tries, where most of the nodes near the
+    /// leaves are sparse.
+    ///
+    /// The present slots are still updated in place. Only when a node needs a slot it doesn't
+    /// have yet, it is replaced by a bigger copy, which makes inserting new keys somewhat slower.
+    const COMPRESSED_NODES: bool = false;
+
+    /// How many bits of the hash are used on each level of the trie.
+    ///
+    /// Each inner node has `2^LEVEL_BITS` slots. More bits make the trie shallower and the lookups
+    /// faster, but each node takes more memory (which matters mostly for small maps and for the
+    /// sparse nodes near the leaves). Must be between 3 and 6 (inclusive), the default is 4.
+    ///
+    /// # Panics
+    ///
+    /// Creating a [`Raw`][crate::raw::Raw] with a value outside of the allowed range panics.
+    const LEVEL_BITS: usize = 4;
+
+    /// Make all the operations on the trie sequentially consistent with each other.
+    ///
+    /// By default, each key is on its own. All threads agree on the order of modifications of the
+    /// same key and a thread that finds a value also sees everything the inserting thread did
+    /// before inserting it. But there's no order across different keys ‒ if one thread inserts
+    /// `a` and then `b`, another thread may find `b` and then not find `a`. Similarly, if one
+    /// thread inserts `a` and then looks up `b` while another inserts `b` and then looks up `a`,
+    /// both lookups may fail.
+    ///
+    /// With this turned on, all the pointers of the trie are read and updated with the `SeqCst`
+    /// ordering. All the lookups and modifications (including the conditional ones, the
+    /// [`clear`][crate::raw::Raw::clear] and the lookups and modifications that find nothing to
+    /// do) then happen in a single total order, consistent with the order in which each thread
+    /// performs them. In the examples above, if `b` is found, so is `a` (unless it got removed
+    /// in the meantime), and at least one of the two lookups succeeds. Publishing a value and
+    /// then a flag under another key becomes safe.
+    ///
+    /// Some things are not covered even then:
+    ///
+    /// * Iteration doesn't see a snapshot. It sees each part of the trie as it is at the moment
+    ///   it gets there, so it observes the order of the modifications only in the part it is yet
+    ///   to walk through (use the [snapshots][Config::SNAPSHOTS] for a consistent view).
+    /// * The [`len`][crate::raw::Raw::len] and [`approx_len`][crate::raw::Raw::approx_len] are
+    ///   counted separately from the trie.
+    ///
+    /// This makes the operations slower, more so on platforms with weaker memory models, and it is
+    /// off by default.
+    const SEQUENTIALLY_CONSISTENT: bool = false;
+
+    /// Feeds a key (or its borrowed form used for lookups) into the hasher.
+    ///
+    /// This, together with [`eq`][Config::eq], allows a different notion of equality of the keys
+    /// than their [`Eq`] implementation, without wrapping them in newtypes. Keys equal according
+    /// to [`eq`][Config::eq] must produce the same hash.
+    fn hash<Q, H>(key: &Q, state: &mut H)
+    where
+        Q: ?Sized + Hash,
+        H: Hasher,
+    {
+        key.hash(state)
+    }
+
+    /// Compares a key of a stored payload with a looked up one.
+    fn eq<Q>(key: &Self::Key, other: &Q) -> bool
+    where
+        Q: ?Sized + Eq + Hash,
+        Self::Key: Borrow<Q>,
+    {
+        key.borrow() == other
+    }
+
+    /// Checks if two payloads are the very same value, not just equal ones.
+    ///
+    /// This is used by the operations that first look at a value and then modify the trie only if
+    /// the value is still there (like [entries][crate::raw::entry::Entry] or
+    /// [`compute`][crate::raw::Raw::compute]).
+    ///
+    /// The default compares the addresses of the payloads. Each payload lives in its own leaf
+    /// which is never moved or modified, not even when the collision node holding it gets rebuilt
+    /// because of another key, and the leaf can't be freed (and its address reused) while the
+    /// caller holds a pin. A payload holding the value behind a pointer (like an
+    /// [`Arc`][std::sync::Arc]) may compare these pointers instead, to recognize the value even
+    /// when it gets removed and inserted again.
+    fn same(a: &Self::Payload, b: &Self::Payload) -> bool {
+        ptr::eq(a, b)
+    }
+}
+
+/// A hash of a key, as used to find its place in the trie.
+///
+/// This is implemented for `u32`, `u64` and `u128`. The default value is the hash with all bits
+/// zero, which comes first in the order the trie is walked (see
+/// [`Cursor`][crate::raw::cursor::Cursor]).
+pub trait HashValue: Copy + Default + Eq + 'static {
+    /// The number of bits in the hash.
+    ///
+    /// At most 128 are supported.
+    const BITS: usize;
+
+    /// Computes a hash of whatever the `feed` writes into the hasher.
+    fn compute<S, F>(hash_builder: &S, feed: F) -> Self
+    where
+        S: BuildHasher,
+        F: Fn(&mut S::Hasher);
+
+    /// Extracts the bits selected by the mask, after shifting the hash to the right.
+    ///
+    /// The shift is always smaller than [`BITS`][HashValue::BITS].
+    fn bits(self, shift: usize, mask: usize) -> usize;
+}
+
+impl HashValue for u32 {
+    const BITS: usize = 32;
+
+    fn compute<S, F>(hash_builder: &S, feed: F) -> Self
+    where
+        S: BuildHasher,
+        F: Fn(&mut S::Hasher),
+    {
+        // Fold it, so we don't throw away half of the hash.
+        let hash = u64::compute(hash_builder, feed);
+        (hash ^ (hash >> 32)) as u32
+    }
+
+    fn bits(self, shift: usize, mask: usize) -> usize {
+        (self >> shift) as usize & mask
+    }
+}
+
+impl HashValue for u64 {
+    const BITS: usize = 64;
+
+    fn compute<S, F>(hash_builder: &S, feed: F) -> Self
+    where
+        S: BuildHasher,
+        F: Fn(&mut S::Hasher),
+    {
+        let mut hasher = hash_builder.build_hasher();
+        feed(&mut hasher);
+        hasher.finish()
+    }
+
+    fn bits(self, shift: usize, mask: usize) -> usize {
+        (self >> shift) as usize & mask
+    }
+}
+
+impl HashValue for u128 {
+    const BITS: usize = 128;
+
+    fn compute<S, F>(hash_builder: &S, feed: F) -> Self
+    where
+        S: BuildHasher,
+        F: Fn(&mut S::Hasher),
+    {
+        // The hashers produce only 64 bits, so we take two different hashes. The second one is of
+        // the key prefixed by a marker, so it differs from the first one.
+        let low = u64::compute(hash_builder, &feed);
+        let high = u64::compute(hash_builder, |hasher| {
+            hasher.write_u8(0xff);
+            feed(hasher);
+        });
+        (u128::from(high) << 64) | u128::from(low)
+    }
+
+    fn bits(self, shift: usize, mask: usize) -> usize {
+        (self >> shift) as usize & mask
+    }
 }
 
 /// A trivial config, where the payload and the key are the same thing.
@@ -26,8 +222,340 @@ pub struct Trivial<T>(PhantomData<T>);
 
 impl<T> Config for Trivial<T>
 where
-    T: Clone + Hash + Eq + 'static,
+    T: Hash + Eq,
 {
     type Payload = T;
     type Key = T;
+    type Hash = u64;
+}
+
+// The config adaptors wrap another config and change only a few of its items. This writes the
+// rest of the impl, forwarding each item not listed to the wrapped config `C`. The listed hooks
+// are functions with the same signature as the hook itself.
+macro_rules! forward_config {
+    (@or [$value: expr] $default: expr) => {
+        $value
+    };
+    (@or [] $default: expr) => {
+        $default
+    };
+    (@or_ty [$value: ty] $default: ty) => {
+        $value
+    };
+    (@or_ty [] $default: ty) => {
+        $default
+    };
+    (
+        impl<$($param: ident: $bound: path),*> for $name: ty {
+            $(type Hash = $hash: ty;)?
+            $(const SNAPSHOTS = $snapshots: expr;)?
+            $(const COMPRESSED_NODES = $compressed: expr;)?
+            $(const LEVEL_BITS = $level_bits: expr;)?
+            $(const SEQUENTIALLY_CONSISTENT = $seq_cst: expr;)?
+            $(fn hash = $hash_fn: expr;)?
+            $(fn eq = $eq_fn: expr;)?
+        }
+    ) => {
+        impl<$($param: $bound),*> Config for $name {
+            type Payload = C::Payload;
+            type Key = C::Key;
+            type Hash = forward_config!(@or_ty [$($hash)?] C::Hash);
+            const SNAPSHOTS: bool = forward_config!(@or [$($snapshots)?] C::SNAPSHOTS);
+            const COMPRESSED_NODES: bool =
+                forward_config!(@or [$($compressed)?] C::COMPRESSED_NODES);
+            const LEVEL_BITS: usize = forward_config!(@or [$($level_bits)?] C::LEVEL_BITS);
+            const SEQUENTIALLY_CONSISTENT: bool =
+                forward_config!(@or [$($seq_cst)?] C::SEQUENTIALLY_CONSISTENT);
+
+            fn hash<Q, H>(key: &Q, state: &mut H)
+            where
+                Q: ?Sized + Hash,
+                H: Hasher,
+            {
+                forward_config!(@or [$($hash_fn)?] C::hash)(key, state)
+            }
+
+            fn eq<Q>(key: &Self::Key, other: &Q) -> bool
+            where
+                Q: ?Sized + Eq + Hash,
+                Self::Key: Borrow<Q>,
+            {
+                forward_config!(@or [$($eq_fn)?] C::eq)(key, other)
+            }
+
+            fn same(a: &Self::Payload, b: &Self::Payload) -> bool {
+                C::same(a, b)
+            }
+        }
+    };
+}
+
+/// A config adaptor, turning on [`SNAPSHOTS`][Config::SNAPSHOTS] for another config.
+pub struct Snapshots<C>(PhantomData<C>);
+
+forward_config! {
+    impl<C: Config> for Snapshots<C> {
+        const SNAPSHOTS = true;
+    }
+}
+
+/// A config adaptor, turning on [`COMPRESSED_NODES`][Config::COMPRESSED_NODES] for another config.
+pub struct CompressedNodes<C>(PhantomData<C>);
+
+forward_config! {
+    impl<C: Config> for CompressedNodes<C> {
+        const COMPRESSED_NODES = true;
+    }
+}
+
+/// A config adaptor, turning on [`SEQUENTIALLY_CONSISTENT`][Config::SEQUENTIALLY_CONSISTENT] for
+/// another config.
+pub struct SequentiallyConsistent<C>(PhantomData<C>);
+
+forward_config! {
+    impl<C: Config> for SequentiallyConsistent<C> {
+        const SEQUENTIALLY_CONSISTENT = true;
+    }
+}
+
+/// A config adaptor, using a different [`Hash`][Config::Hash] type than another config.
+pub struct WithHash<C, V>(PhantomData<(C, V)>);
+
+forward_config! {
+    impl<C: Config, V: HashValue> for WithHash<C, V> {
+        type Hash = V;
+    }
+}
+
+// The ready-made case-insensitive configs can't know the type of the key (the hooks are generic),
+// but they can see what it writes into the hasher. So they normalize that byte stream ‒ both when
+// hashing and when comparing (the streams of the two keys are compared). This works for anything
+// that hashes its content as bytes, like strings, byte slices and their owned variants.
+
+/// A way to normalize the bytes written into a hasher.
+trait Fold {
+    /// Passes the normalized form of the bytes to the sink.
+    fn fold(bytes: &[u8], sink: &mut dyn FnMut(&[u8]));
+}
+
+/// Lowercases the ASCII letters, leaves all the other bytes alone.
+struct AsciiFold;
+
+impl Fold for AsciiFold {
+    fn fold(bytes: &[u8], sink: &mut dyn FnMut(&[u8])) {
+        let mut buffer = [0; 64];
+        for chunk in bytes.chunks(buffer.len()) {
+            let buffer = &mut buffer[..chunk.len()];
+            buffer.copy_from_slice(chunk);
+            buffer.make_ascii_lowercase();
+            sink(buffer);
+        }
+    }
+}
+
+/// Lowercases whole strings (if the bytes are valid UTF-8), falls back to [`AsciiFold`].
+struct UnicodeFold;
+
+impl Fold for UnicodeFold {
+    fn fold(bytes: &[u8], sink: &mut dyn FnMut(&[u8])) {
+        match str::from_utf8(bytes) {
+            Ok(s) if !s.is_ascii() => {
+                let mut buffer = [0; 4];
+                for c in s.chars().flat_map(char::to_lowercase) {
+                    sink(c.encode_utf8(&mut buffer).as_bytes());
+                }
+            }
+            _ => AsciiFold::fold(bytes, sink),
+        }
+    }
+}
+
+/// Normalizes the bytes before passing them to another hasher.
+struct FoldHasher<'a, F, H: 'a> {
+    inner: &'a mut H,
+    _fold: PhantomData<F>,
+}
+
+impl<F: Fold, H: Hasher> Hasher for FoldHasher<'_, F, H> {
+    fn write(&mut self, bytes: &[u8]) {
+        let inner = &mut self.inner;
+        F::fold(bytes, &mut |folded| inner.write(folded));
+    }
+
+    fn finish(&self) -> u64 {
+        self.inner.finish()
+    }
+}
+
+/// Records the normalized bytes.
+struct Recorder<F>(SmallVec<[u8; 64]>, PhantomData<F>);
+
+impl<F: Fold> Hasher for Recorder<F> {
+    fn write(&mut self, bytes: &[u8]) {
+        let recorded = &mut self.0;
+        F::fold(bytes, &mut |folded| recorded.extend_from_slice(folded));
+    }
+
+    fn finish(&self) -> u64 {
+        unreachable!("Used only to record the bytes");
+    }
+}
+
+/// Compares the normalized bytes with recorded ones.
+struct Comparer<'a, F> {
+    expected: &'a [u8],
+    equal: bool,
+    _fold: PhantomData<F>,
+}
+
+impl<F: Fold> Hasher for Comparer<'_, F> {
+    fn write(&mut self, bytes: &[u8]) {
+        let expected = &mut self.expected;
+        let equal = &mut self.equal;
+        F::fold(bytes, &mut |folded| {
+            if *equal && expected.starts_with(folded) {
+                *expected = &expected[folded.len()..];
+            } else {
+                *equal = false;
+            }
+        });
+    }
+
+    fn finish(&self) -> u64 {
+        unreachable!("Used only to compare the bytes");
+    }
+}
+
+fn fold_hash<F, Q, H>(key: &Q, state: &mut H)
+where
+    F: Fold,
+    Q: ?Sized + Hash,
+    H: Hasher,
+{
+    key.hash(&mut FoldHasher::<F, H> {
+        inner: state,
+        _fold: PhantomData,
+    });
+}
+
+fn fold_eq<F, K, Q>(key: &K, other: &Q) -> bool
+where
+    F: Fold,
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+{
+    let (a, b) = (key.borrow(), other);
+    if a == b {
+        return true;
+    }
+    let mut recorder = Recorder::<F>(SmallVec::new(), PhantomData);
+    a.hash(&mut recorder);
+    let mut comparer = Comparer::<F> {
+        expected: &recorder.0,
+        equal: true,
+        _fold: PhantomData,
+    };
+    b.hash(&mut comparer);
+    comparer.equal && comparer.expected.is_empty()
+}
+
+macro_rules! fold_config {
+    ($(#[$attr: meta])* $name: ident, $fold: ty) => {
+        $(#[$attr])*
+        pub struct $name<C>(PhantomData<C>);
+
+        forward_config! {
+            impl<C: Config> for $name<C> {
+                fn hash = fold_hash::<$fold, _, _>;
+                fn eq = fold_eq::<$fold, _, _>;
+            }
+        }
+    };
+}
+
+fold_config! {
+    /// A config adaptor, making the string keys of another config case-insensitive.
+    ///
+    /// The keys are compared by their lowercase forms. Meant for keys hashing as strings (`str`,
+    /// `String`, `Box<str>`, `Arc<str>`...). Byte keys that are not valid UTF-8 are handled as by
+    /// [`AsciiCaseInsensitive`]. It doesn't make sense for other kinds of keys.
+    ///
+    /// The lookups don't allocate unless the key is longer than few dozens of bytes.
+    CaseInsensitive,
+    UnicodeFold
+}
+
+fold_config! {
+    /// A config adaptor, making the keys of another config ASCII case-insensitive.
+    ///
+    /// The keys are compared with the ASCII letters normalized to lowercase, all other bytes are
+    /// compared exactly. Meant for keys hashing as strings or bytes (`str`, `[u8]`, `Vec<u8>`...).
+    /// It doesn't make sense for other kinds of keys (eg. the bytes of integers would get
+    /// normalized too).
+    AsciiCaseInsensitive,
+    AsciiFold
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::RandomState;
+
+    use super::super::Raw;
+    use super::*;
+
+    #[test]
+    fn case_insensitive() {
+        let map = Raw::<CaseInsensitive<Trivial<String>>, _>::with_hasher(RandomState::new());
+        let pin = crossbeam_epoch::pin();
+        assert!(map.insert("Hello".to_owned(), &pin).is_none());
+        assert_eq!("Hello", map.get("hello", &pin).unwrap());
+        assert_eq!("Hello", map.get("HELLO", &pin).unwrap());
+        assert!(map.get("hello!", &pin).is_none());
+        assert!(map.get("hell", &pin).is_none());
+        assert_eq!("Hello", map.insert("HeLLo".to_owned(), &pin).unwrap());
+        assert_eq!(1, map.len());
+
+        assert!(map.insert("Žluťoučký kůň".to_owned(), &pin).is_none());
+        assert!(map.get("ŽLUŤOUČKÝ KŮŇ", &pin).is_some());
+        assert!(map.get("ZLUTOUCKY KUN", &pin).is_none());
+
+        // Longer than the buffer used for comparison
+        let long = "Long Key ".repeat(20);
+        assert!(map.insert(long.clone(), &pin).is_none());
+        assert!(map.get(&long.to_uppercase()[..], &pin).is_some());
+        assert!(map.get(&long[1..], &pin).is_none());
+
+        assert_eq!("HeLLo", map.remove("hELLO", &pin).unwrap());
+        assert_eq!(2, map.len());
+    }
+
+    #[test]
+    fn ascii_case_insensitive_bytes() {
+        type Map = Raw<AsciiCaseInsensitive<Trivial<Vec<u8>>>, RandomState>;
+        let map = Map::with_hasher(RandomState::new());
+        let pin = crossbeam_epoch::pin();
+        assert!(map.insert(b"Key\xC4".to_vec(), &pin).is_none());
+        assert!(map.get(&b"kEY\xC4"[..], &pin).is_some());
+        // Only the ASCII letters are normalized
+        assert!(map.get(&b"key\xE4"[..], &pin).is_none());
+        assert!(map.get(&b"key"[..], &pin).is_none());
+    }
+
+    /// The hooks survive the other adaptors.
+    #[test]
+    fn case_insensitive_adapted() {
+        type Cfg = Snapshots<WithHash<CaseInsensitive<Trivial<String>>, u128>>;
+        let mut map = Raw::<Cfg, _>::with_hasher(RandomState::new());
+        let pin = crossbeam_epoch::pin();
+        for i in 0..100 {
+            assert!(map.insert(format!("Value {}", i), &pin).is_none());
+        }
+        for i in 0..100 {
+            let key = format!("VALUE {}", i);
+            assert!(map.get(&key[..], &pin).is_some());
+            assert!(map.snapshot().get(&key[..]).is_some());
+        }
+        drop(pin);
+        map.assert_pruned();
+    }
 }
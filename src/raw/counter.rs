@@ -0,0 +1,116 @@
+//! A striped counter, to keep track of the number of elements in the map.
+//!
+//! A single atomic counter updated by every insert and remove would be a contention point and
+//! would make all the threads fight over the same cache line. Therefore, the counter is split into
+//! several stripes (each on its own cache line) and each thread updates only one of them. Reading
+//! the value then needs to sum all the stripes.
+
+use std::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
+
+use crossbeam_utils::CachePadded;
+
+const STRIPES: usize = 8;
+
+/// How many times [`Counter::settled`] reads the stripes before it accepts the last value.
+const SETTLE_ATTEMPTS: usize = 4;
+
+static NEXT_STRIPE: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static STRIPE: usize = NEXT_STRIPE.fetch_add(1, Ordering::Relaxed) % STRIPES;
+}
+
+#[derive(Default)]
+pub(crate) struct Counter([CachePadded<AtomicIsize>; STRIPES]);
+
+impl Counter {
+    /// Adds (or subtracts) the difference.
+    pub(crate) fn add(&self, diff: isize) {
+        // Relaxed: we don't synchronize anything through the counter, it's just the number.
+        let stripe = STRIPE.try_with(|s| *s).unwrap_or(0);
+        self.0[stripe].fetch_add(diff, Ordering::Relaxed);
+    }
+
+    fn collect(&self, stripes: &mut [isize; STRIPES]) {
+        for (dst, src) in stripes.iter_mut().zip(&self.0) {
+            *dst = src.load(Ordering::Relaxed);
+        }
+    }
+
+    /// Sums the stripes in a single pass.
+    ///
+    /// The stripes are read at slightly different times, so under concurrent modification this
+    /// might not match any real state of the map (it can even be temporarily negative, which is
+    /// clamped to 0).
+    pub(crate) fn approx(&self) -> usize {
+        let mut stripes = [0; STRIPES];
+        self.collect(&mut stripes);
+        clamp(&stripes)
+    }
+
+    /// Reads the counter more carefully than [`approx`][Counter::approx].
+    ///
+    /// This repeats reading all the stripes until it gets the same values twice in a row (but at
+    /// most few times, to not spin forever under heavy contention). Without concurrent
+    /// modifications, this is the exact value. With them, matching reads make a torn value less
+    /// likely, but they don't guarantee the sum corresponds to any actual moment ‒ a stripe may
+    /// have changed and changed back in between.
+    pub(crate) fn settled(&self) -> usize {
+        let mut previous = [0; STRIPES];
+        let mut current = [0; STRIPES];
+        self.collect(&mut previous);
+        for _ in 0..SETTLE_ATTEMPTS {
+            self.collect(&mut current);
+            if current == previous {
+                break;
+            }
+            previous = current;
+        }
+        clamp(&current)
+    }
+}
+
+fn clamp(stripes: &[isize; STRIPES]) -> usize {
+    let sum = stripes.iter().sum::<isize>();
+    if sum < 0 {
+        0
+    } else {
+        sum as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossbeam_utils::thread;
+
+    use super::*;
+
+    #[test]
+    fn single_thread() {
+        let counter = Counter::default();
+        assert_eq!(0, counter.settled());
+        counter.add(2);
+        counter.add(-1);
+        assert_eq!(1, counter.settled());
+        assert_eq!(1, counter.approx());
+        counter.add(-2);
+        assert_eq!(0, counter.approx());
+    }
+
+    #[test]
+    fn multi_thread() {
+        let counter = Counter::default();
+        thread::scope(|s| {
+            for _ in 0..STRIPES * 2 {
+                s.spawn(|_| {
+                    for _ in 0..1000 {
+                        counter.add(1);
+                    }
+                    counter.add(-500);
+                });
+            }
+        })
+        .unwrap();
+        assert_eq!(STRIPES * 2 * 500, counter.settled());
+    }
+}
@@ -0,0 +1,707 @@
+//! Resumable iteration of the [`Raw`] map.
+//!
+//! The iterators walk the trie in a fixed order given by the hashes of the keys. A [`Cursor`]
+//! remembers a position in that order, so the iteration can be stopped (releasing its epoch pin)
+//! and resumed later, even after the map was modified in between. The same order allows splitting
+//! the map into disjoint ranges of hashes, to be walked independently.
+
+use std::cmp::Ordering as CmpOrdering;
+use std::hash::BuildHasher;
+use std::ops::{Bound, RangeBounds};
+#[cfg(feature = "stream")]
+use std::pin::Pin;
+#[cfg(feature = "stream")]
+use std::task::{Context, Poll};
+
+#[cfg(feature = "stream")]
+use futures_core::Stream;
+
+use super::config::Config;
+use super::iterator::Iter;
+use super::{path_order, Raw};
+
+/// A position in the walk through the trie.
+///
+/// The trie holds its values ordered by their hashes, but the order is not the numerical one. The
+/// hashes are compared by their lowest [`LEVEL_BITS`][Config::LEVEL_BITS] bits first (these pick
+/// the slot at the top of the trie), then by the next [`LEVEL_BITS`][Config::LEVEL_BITS] bits,
+/// and so on. Values with the same hash are ordered by their index in the collision node.
+///
+/// A cursor consists of the hash of the last visited value, the index after it and the end of the
+/// iterated range (plus the identity of the last visited value, see below). It is not bound to
+/// the map, so it can be taken apart, stored (or sent elsewhere) and put together again with
+/// [`new`][Cursor::new] and [`with_end`][Cursor::with_end]. The [`default`][Default::default]
+/// cursor points to the very beginning.
+///
+/// # Quirks
+///
+/// Values inserted or removed while the iteration is paused may or may not be visited, the same
+/// as with a running iterator. The values present the whole time are visited exactly once.
+///
+/// Values sharing the whole hash need some care, as their indices inside the collision node shift
+/// when one of them is removed. Therefore, the cursors returned by the iterators also remember the
+/// last returned value itself (only its address, it doesn't keep the value alive) and resume right
+/// after it. If that one got removed too, they resume at its former index, which is exact unless
+/// other values before it were removed as well. In such case, some values of the collision may be
+/// skipped or visited twice. The same goes for a cursor put together by [`new`][Cursor::new] or
+/// deserialized, which knows only the index.
+///
+/// The collisions are rare with the default 64-bit hashes, but common in large maps with shorter
+/// ones (like [`WithHash<_, u32>`][crate::raw::config::WithHash]) or with a custom hasher or
+/// [`hash`][Config::hash] throwing some of the information away.
+///
+/// # Serialization
+///
+/// With the `serde` feature, the cursor can be serialized, to be stored or passed outside of the
+/// process. It is encoded as a structure with the `hash`, the `index` and the `end` fields, the
+/// last one being the standard encoding of [`Bound`]. The hash is meaningful only to a map using
+/// the same hasher (with the same keys), so a map with a randomly seeded hasher (like the default
+/// one) won't understand the cursors of another instance. The last returned value is not part
+/// of the encoding.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cursor<H> {
+    hash: H,
+    index: usize,
+    end: Bound<H>,
+    /// The address of the last returned value, to find it inside a collision (0 if unknown).
+    #[cfg_attr(feature = "serde", serde(skip))]
+    last: usize,
+}
+
+impl<H: Copy> Cursor<H> {
+    /// Creates a cursor before the value at the given index among the ones with the hash.
+    ///
+    /// The values with hashes ordered before this one are skipped. The cursor iterates to the end
+    /// of the map.
+    pub fn new(hash: H, index: usize) -> Self {
+        Cursor {
+            hash,
+            index,
+            end: Bound::Unbounded,
+            last: 0,
+        }
+    }
+
+    /// Limits the iteration to the hashes before (or up to) the given end.
+    pub fn with_end(self, end: Bound<H>) -> Self {
+        Cursor { end, ..self }
+    }
+
+    /// The hash of the position.
+    pub fn hash(&self) -> H {
+        self.hash
+    }
+
+    /// The index of the position among the values with the same hash.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The end of the iterated range.
+    pub fn end(&self) -> Bound<H> {
+        self.end
+    }
+}
+
+impl<H: Copy + Default> Default for Cursor<H> {
+    fn default() -> Self {
+        Self::new(H::default(), 0)
+    }
+}
+
+/// An iterator through a range of the [`Raw`] map, able to report its position.
+///
+/// Similar to [`Iter`], this borrows the values out of the iterator itself. It is created by the
+/// [`iter_from`][Raw::iter_from] and [`iter_hash_range`][Raw::iter_hash_range] methods.
+///
+/// By default, it holds a single epoch pin for its whole lifetime. A long walk can release it from
+/// time to time, see [`repin_every`][CursorIter::repin_every].
+pub struct CursorIter<'a, C, S>
+where
+    C: Config,
+{
+    map: &'a Raw<C, S>,
+    iter: Iter<'a, C, S>,
+    /// Where to resume. `None` once the range is exhausted.
+    position: Option<Cursor<C::Hash>>,
+    /// After how many values to take a fresh pin, if at all.
+    repin_every: Option<usize>,
+    /// The number of values returned under the current pin.
+    since_pin: usize,
+}
+
+impl<C, S> CursorIter<'_, C, S>
+where
+    C: Config,
+    S: BuildHasher,
+{
+    /// Produces another value, just like `Iterator::next`, except the value is bound to the
+    /// lifetime of the iterator structure.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<&C::Payload> {
+        let position = self.position.as_mut()?;
+        if self.repin_every == Some(self.since_pin) {
+            // Nothing returned under the old pin is borrowed any more, so it can go.
+            self.iter = Iter::starting_from(
+                self.map,
+                self.map.guard(),
+                position.hash,
+                position.index,
+                position.last,
+            );
+            self.since_pin = 0;
+        }
+        if let Some((payload, hash, index)) = self.iter.next_indexed() {
+            let before_end = match position.end {
+                Bound::Included(end) => path_order::<C>(hash, end) != CmpOrdering::Greater,
+                Bound::Excluded(end) => path_order::<C>(hash, end) == CmpOrdering::Less,
+                Bound::Unbounded => true,
+            };
+            if before_end {
+                position.hash = hash;
+                position.index = index + 1;
+                position.last = payload as *const C::Payload as usize;
+                self.since_pin += 1;
+                return Some(payload);
+            }
+        }
+        self.position = None;
+        None
+    }
+
+    /// The position right after the last returned value.
+    ///
+    /// Passing it to [`iter_from`][Raw::iter_from] continues where this iterator stopped. Returns
+    /// `None` if there's nothing more in the range (as far as this iterator knows).
+    pub fn cursor(&self) -> Option<Cursor<C::Hash>> {
+        self.position
+    }
+
+    /// Makes the iterator release its epoch pin and take a new one after each `n` values.
+    ///
+    /// The iterator then holds back the reclamation of memory only for the time it takes to walk
+    /// `n` values, not for the whole iteration. After taking the new pin, it walks the trie from
+    /// the root again to the [`cursor`][CursorIter::cursor]. The values are still returned at
+    /// most once (up to the [quirks][Cursor#quirks] of collisions) and concurrent changes may or
+    /// may not be seen.
+    ///
+    /// In a trie with [`SNAPSHOTS`][crate::raw::config::Config::SNAPSHOTS] turned on, each new
+    /// pin continues in the current version of the trie, so the values no longer come from a
+    /// single snapshot.
+    ///
+    /// # Panics
+    ///
+    /// If `n` is 0.
+    pub fn repin_every(self, n: usize) -> Self {
+        assert!(n > 0, "Can't repin after every 0 values");
+        CursorIter {
+            repin_every: Some(n),
+            ..self
+        }
+    }
+}
+
+/// An iterator through the [`Raw`] map that holds no epoch pin between the values.
+///
+/// Unlike the other iterators, this one remembers only a [`Cursor`]. Each call to `next` pins,
+/// walks from the root to the cursor, extracts the value and unpins again. Therefore it is `Send`
+/// (if the map is `Sync` and the extraction function is `Send`) and can be held across `.await`
+/// points or handed to another thread. The price is a walk from the root for each value.
+///
+/// The values are turned into owned ones by the extraction function, as nothing can be borrowed
+/// once the pin is gone.
+///
+/// With the `stream` feature, it can be turned into a futures `Stream` by `into_stream`.
+pub struct SendIter<'a, C, S, F>
+where
+    C: Config,
+{
+    map: &'a Raw<C, S>,
+    extract: F,
+    position: Option<Cursor<C::Hash>>,
+}
+
+impl<'a, C, S, F> SendIter<'a, C, S, F>
+where
+    C: Config,
+{
+    /// Creates an iterator starting at the cursor.
+    pub fn new(map: &'a Raw<C, S>, cursor: Cursor<C::Hash>, extract: F) -> Self {
+        SendIter {
+            map,
+            extract,
+            position: Some(cursor),
+        }
+    }
+
+    /// The position right after the last returned value.
+    ///
+    /// See [`CursorIter::cursor`].
+    pub fn cursor(&self) -> Option<Cursor<C::Hash>> {
+        self.position
+    }
+
+    /// Turns the iterator into a futures `Stream`.
+    ///
+    /// Available with the `stream` feature.
+    #[cfg(feature = "stream")]
+    pub fn into_stream(self) -> SendStream<'a, C, S, F> {
+        SendStream {
+            iter: self,
+            since_yield: 0,
+        }
+    }
+}
+
+impl<C, S, F, R> Iterator for SendIter<'_, C, S, F>
+where
+    C: Config,
+    S: BuildHasher,
+    F: FnMut(&C::Payload) -> R,
+{
+    type Item = R;
+
+    fn next(&mut self) -> Option<R> {
+        let mut iter = self.map.iter_from(self.position?);
+        let result = iter.next().map(&mut self.extract);
+        self.position = iter.cursor();
+        result
+    }
+}
+
+// Nothing inside is ever pinned.
+impl<C: Config, S, F> Unpin for SendIter<'_, C, S, F> {}
+
+/// After how many values the [`SendStream`] yields to the executor.
+#[cfg(feature = "stream")]
+const YIELD_EVERY: usize = 32;
+
+/// The [`SendIter`] as a futures `Stream`.
+///
+/// The values are readily available, so a poll never waits for anything. Still, a task draining
+/// a large map in one go would keep its executor thread busy for the whole walk. Therefore, after
+/// every few values, the stream wakes its task right away and returns `Pending`, to let other
+/// tasks run in between. Created by [`SendIter::into_stream`], available with the `stream`
+/// feature.
+#[cfg(feature = "stream")]
+pub struct SendStream<'a, C, S, F>
+where
+    C: Config,
+{
+    iter: SendIter<'a, C, S, F>,
+    /// The number of values returned since the last yield.
+    since_yield: usize,
+}
+
+#[cfg(feature = "stream")]
+impl<C, S, F> SendStream<'_, C, S, F>
+where
+    C: Config,
+{
+    /// The position right after the last returned value.
+    ///
+    /// See [`CursorIter::cursor`].
+    pub fn cursor(&self) -> Option<Cursor<C::Hash>> {
+        self.iter.cursor()
+    }
+}
+
+#[cfg(feature = "stream")]
+impl<C, S, F, R> Stream for SendStream<'_, C, S, F>
+where
+    C: Config,
+    S: BuildHasher,
+    F: FnMut(&C::Payload) -> R,
+{
+    type Item = R;
+
+    fn poll_next(mut self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Option<R>> {
+        if self.since_yield == YIELD_EVERY {
+            self.since_yield = 0;
+            ctx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        self.since_yield += 1;
+        Poll::Ready(self.iter.next())
+    }
+}
+
+impl<C, S> Raw<C, S>
+where
+    C: Config,
+    S: BuildHasher,
+{
+    /// Iterates through the values, starting at the cursor.
+    ///
+    /// The trie is walked from the root to the position of the cursor, skipping everything
+    /// before it. See [`Cursor`] for the order of the values.
+    pub fn iter_from(&self, cursor: Cursor<C::Hash>) -> CursorIter<'_, C, S> {
+        CursorIter {
+            map: self,
+            iter: Iter::starting_from(self, self.guard(), cursor.hash, cursor.index, cursor.last),
+            position: Some(cursor),
+            repin_every: None,
+            since_pin: 0,
+        }
+    }
+
+    /// Iterates through the values with hashes in the given range.
+    ///
+    /// Note that the range is in the order of the walk through the trie, not the numerical one
+    /// (see [`Cursor`]). Ranges starting and ending at the values below `1 <<`
+    /// [`LEVEL_BITS`][Config::LEVEL_BITS] split the map along the slots of the top inner node ‒
+    /// `k..k + 1` holds the hashes with the lowest bits equal to `k`.
+    pub fn iter_hash_range<R>(&self, range: R) -> CursorIter<'_, C, S>
+    where
+        R: RangeBounds<C::Hash>,
+    {
+        let start = match range.start_bound() {
+            Bound::Included(start) => Cursor::new(*start, 0),
+            // No index is larger, so all values with this hash get skipped.
+            Bound::Excluded(start) => Cursor::new(*start, usize::MAX),
+            Bound::Unbounded => Cursor::default(),
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => Bound::Included(*end),
+            Bound::Excluded(end) => Bound::Excluded(*end),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        self.iter_from(start.with_end(end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::RandomState;
+
+    use crossbeam_utils::thread;
+
+    use super::super::config::{CompressedNodes, Snapshots, Trivial};
+    use super::super::tests::{MakeSplatHasher, NoHasher};
+    use super::*;
+
+    const CNT: usize = 1000;
+
+    fn filled<C, S>(map: &Raw<C, S>)
+    where
+        C: Config<Key = usize, Payload = usize>,
+        S: BuildHasher,
+    {
+        map.with_pin(|pin| {
+            for i in 0..CNT {
+                map.insert(i, pin);
+            }
+        });
+    }
+
+    /// Takes everything from the iterator, checking it goes in the path order.
+    fn drain<C, S>(map: &Raw<C, S>, iter: &mut CursorIter<C, S>, limit: usize) -> Vec<usize>
+    where
+        C: Config<Key = usize, Payload = usize>,
+        S: BuildHasher,
+    {
+        let mut result = Vec::new();
+        while result.len() < limit {
+            match iter.next() {
+                Some(value) => result.push(*value),
+                None => break,
+            }
+        }
+        for pair in result.windows(2) {
+            let order = path_order::<C>(map.hash(&pair[0]), map.hash(&pair[1]));
+            assert_ne!(CmpOrdering::Greater, order);
+        }
+        result
+    }
+
+    fn check_pages<C, S>(map: Raw<C, S>)
+    where
+        C: Config<Key = usize, Payload = usize>,
+        S: BuildHasher,
+    {
+        filled(&map);
+        let mut found = Vec::new();
+        let mut cursor = Some(Cursor::default());
+        while let Some(start) = cursor {
+            let mut iter = map.iter_from(start);
+            let page = drain(&map, &mut iter, 37);
+            cursor = iter.cursor();
+            // The cursor remembers the hash stored with the last value.
+            if let (Some(cursor), Some(last)) = (cursor, page.last()) {
+                assert!(map.hash(last) == cursor.hash());
+            }
+            found.extend(page);
+        }
+        found.sort();
+        assert_eq!((0..CNT).collect::<Vec<_>>(), found);
+    }
+
+    #[test]
+    fn pages() {
+        check_pages(Raw::<Trivial<usize>, _>::with_hasher(RandomState::new()));
+    }
+
+    #[test]
+    fn pages_collisions() {
+        check_pages(Raw::<Trivial<usize>, _>::with_hasher(NoHasher));
+    }
+
+    /// Removing already visited values of a collision between the pages doesn't shift the cursor.
+    #[test]
+    fn pages_collisions_removed() {
+        let map = Raw::<Trivial<usize>, _>::with_hasher(NoHasher);
+        filled(&map);
+        let mut found = Vec::new();
+        let mut cursor = Some(Cursor::default());
+        while let Some(start) = cursor {
+            let mut iter = map.iter_from(start);
+            let page = drain(&map, &mut iter, 37);
+            cursor = iter.cursor();
+            drop(iter);
+            map.remove(&page[0], &crossbeam_epoch::pin());
+            found.extend(page);
+        }
+        found.sort();
+        assert_eq!((0..CNT).collect::<Vec<_>>(), found);
+    }
+
+    #[test]
+    fn pages_deep() {
+        check_pages(Raw::<Trivial<usize>, _>::with_hasher(MakeSplatHasher));
+    }
+
+    #[test]
+    fn pages_cow() {
+        check_pages(Raw::<Snapshots<Trivial<usize>>, _>::with_hasher(
+            RandomState::new(),
+        ));
+    }
+
+    #[test]
+    fn pages_compressed() {
+        check_pages(Raw::<CompressedNodes<Trivial<usize>>, _>::with_hasher(
+            RandomState::new(),
+        ));
+    }
+
+    /// The ranges along the top slots and between hashes of some keys cover everything once.
+    #[test]
+    fn ranges() {
+        type C = Trivial<usize>;
+        let map = Raw::<C, _>::with_hasher(RandomState::new());
+        filled(&map);
+        let cells = 1u64 << C::LEVEL_BITS;
+        let mut found = Vec::new();
+        for shard in 0..cells {
+            let mut iter = if shard + 1 == cells {
+                map.iter_hash_range(shard..)
+            } else {
+                map.iter_hash_range(shard..shard + 1)
+            };
+            let values = drain(&map, &mut iter, CNT);
+            assert!(values.iter().all(|v| map.hash(v) % cells == shard));
+            found.extend(values);
+        }
+        found.sort();
+        assert_eq!((0..CNT).collect::<Vec<_>>(), found);
+
+        let mut splits = [10usize, 500, 700]
+            .iter()
+            .map(|k| map.hash(k))
+            .collect::<Vec<_>>();
+        splits.sort_by(|&a, &b| path_order::<C>(a, b));
+        let mut found = drain(&map, &mut map.iter_hash_range(..splits[0]), CNT);
+        found.extend(drain(
+            &map,
+            &mut map.iter_hash_range(splits[0]..=splits[1]),
+            CNT,
+        ));
+        let after = (Bound::Excluded(splits[1]), Bound::Excluded(splits[2]));
+        found.extend(drain(&map, &mut map.iter_hash_range(after), CNT));
+        found.extend(drain(&map, &mut map.iter_hash_range(splits[2]..), CNT));
+        found.sort();
+        assert_eq!((0..CNT).collect::<Vec<_>>(), found);
+
+        let hash = map.hash(&10usize);
+        let mut iter = map.iter_hash_range(hash..=hash);
+        assert_eq!(Some(&10), iter.next());
+        assert!(iter.next().is_none());
+        assert!(iter.cursor().is_none());
+    }
+
+    /// Walking in small steps under separate pins still gets everything once, even with removals
+    /// between the steps.
+    ///
+    /// Some of the removals are of already visited values, which shifts the rest of a collision.
+    fn check_repin<C, S>(map: Raw<C, S>)
+    where
+        C: Config<Key = usize, Payload = usize>,
+        S: BuildHasher,
+    {
+        filled(&map);
+        let mut iter = map.iter_hash_range(..).repin_every(10);
+        let mut found = Vec::new();
+        let mut removed = Vec::new();
+        while let Some(&value) = iter.next() {
+            found.push(value);
+            if found.len() % 10 == 0 {
+                let pin = crossbeam_epoch::pin();
+                // Something we haven't seen yet.
+                let victim = CNT - found.len();
+                if !found.contains(&victim) && map.remove(&victim, &pin).is_some() {
+                    removed.push(victim);
+                }
+                // Something we have, sometimes the very last one. The cursor finds its place
+                // after a single removal like this even in a collision.
+                let seen = if found.len() % 20 == 0 {
+                    value
+                } else {
+                    found[found.len() / 2]
+                };
+                map.remove(&seen, &pin);
+            }
+        }
+        found.sort();
+        let len = found.len();
+        found.dedup();
+        assert_eq!(len, found.len());
+        found.extend(removed);
+        found.sort();
+        found.dedup();
+        assert_eq!((0..CNT).collect::<Vec<_>>(), found);
+    }
+
+    #[test]
+    fn repin() {
+        check_repin(Raw::<Trivial<usize>, _>::with_hasher(RandomState::new()));
+    }
+
+    #[test]
+    fn repin_collisions() {
+        check_repin(Raw::<Trivial<usize>, _>::with_hasher(NoHasher));
+    }
+
+    #[test]
+    fn repin_cow() {
+        check_repin(Raw::<Snapshots<Trivial<usize>>, _>::with_hasher(
+            RandomState::new(),
+        ));
+    }
+
+    #[test]
+    fn send_iter() {
+        fn assert_send<T: Send>(t: T) -> T {
+            t
+        }
+
+        let map = Raw::<Trivial<usize>, _>::with_hasher(RandomState::new());
+        filled(&map);
+        let iter = assert_send(SendIter::new(&map, Cursor::default(), |v: &usize| *v));
+        let mut found = thread::scope(|s| s.spawn(move |_| iter.collect::<Vec<_>>()).join())
+            .unwrap()
+            .unwrap();
+        found.sort();
+        assert_eq!((0..CNT).collect::<Vec<_>>(), found);
+
+        let mut iter = SendIter::new(&map, Cursor::default(), |v: &usize| *v);
+        let first = iter.next().unwrap();
+        let cursor = iter.cursor().unwrap();
+        assert!(map.iter_from(cursor).next().is_some());
+        assert_eq!(CNT - 1, iter.filter(|v| *v != first).count());
+    }
+
+    #[cfg(feature = "stream")]
+    #[test]
+    fn stream() {
+        use futures::executor;
+        use futures::stream::StreamExt;
+
+        let map = Raw::<Trivial<usize>, _>::with_hasher(NoHasher);
+        filled(&map);
+        let stream = SendIter::new(&map, Cursor::default(), |v: &usize| *v).into_stream();
+        let mut found = executor::block_on(stream.collect::<Vec<_>>());
+        found.sort();
+        assert_eq!((0..CNT).collect::<Vec<_>>(), found);
+    }
+
+    /// The serialized form is the one documented.
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialized() {
+        use serde_test::{assert_tokens, Token};
+
+        let cursor = Cursor::new(42u64, 3).with_end(Bound::Excluded(100));
+        assert_tokens(
+            &cursor,
+            &[
+                Token::Struct {
+                    name: "Cursor",
+                    len: 3,
+                },
+                Token::Str("hash"),
+                Token::U64(42),
+                Token::Str("index"),
+                Token::U64(3),
+                Token::Str("end"),
+                Token::NewtypeVariant {
+                    name: "Bound",
+                    variant: "Excluded",
+                },
+                Token::U64(100),
+                Token::StructEnd,
+            ],
+        );
+    }
+
+    /// The stream lets other tasks run now and then, but still produces everything.
+    #[cfg(feature = "stream")]
+    #[test]
+    fn stream_yields() {
+        use futures::task::noop_waker;
+
+        let map = Raw::<Trivial<usize>, _>::with_hasher(RandomState::new());
+        filled(&map);
+        let mut stream = SendIter::new(&map, Cursor::default(), |v: &usize| *v).into_stream();
+        let waker = noop_waker();
+        let mut ctx = Context::from_waker(&waker);
+        let mut found = Vec::new();
+        let mut yields = 0;
+        loop {
+            match Pin::new(&mut stream).poll_next(&mut ctx) {
+                Poll::Ready(Some(value)) => found.push(value),
+                Poll::Ready(None) => break,
+                Poll::Pending => yields += 1,
+            }
+        }
+        assert_eq!(CNT / YIELD_EVERY, yields);
+        found.sort();
+        assert_eq!((0..CNT).collect::<Vec<_>>(), found);
+    }
+
+    /// Modifications while the iteration is paused don't disturb the rest of it.
+    #[test]
+    fn resume_after_changes() {
+        let map = Raw::<Trivial<usize>, _>::with_hasher(RandomState::new());
+        filled(&map);
+        let mut iter = map.iter_from(Cursor::default());
+        let first = drain(&map, &mut iter, CNT / 2);
+        let cursor = iter.cursor().unwrap();
+        drop(iter);
+
+        map.with_pin(|pin| {
+            for i in 0..CNT {
+                if i % 2 == 0 {
+                    map.remove(&i, pin);
+                }
+            }
+        });
+        let mut rest = drain(&map, &mut map.iter_from(cursor), CNT);
+        assert!(rest.iter().all(|v| v % 2 == 1 && !first.contains(v)));
+        rest.extend(first);
+        rest.sort();
+        rest.dedup();
+        let expected = (0..CNT).filter(|v| v % 2 == 1).count();
+        assert_eq!(expected, rest.iter().filter(|v| *v % 2 == 1).count());
+    }
+}
@@ -9,7 +9,7 @@ use std::sync::atomic::Ordering;
 use crossbeam_epoch::{self, Atomic, Guard};
 
 use super::config::Config;
-use super::{load_data, nf, Inner, NodeFlags, Raw};
+use super::{load_data, load_inner, nf, Inner, Levels, NodeFlags, Raw};
 
 impl<C, S> Raw<C, S>
 where
@@ -23,6 +23,7 @@ where
     /// though during modifications there might be temporary states which are not pruned. Due to
     /// unique access to it, other threads might not be modifying it at the moment.
     #[cfg(test)]
+    #[allow(clippy::needless_borrow)]
     pub(crate) fn assert_pruned(&mut self) {
         fn handle_ptr<C: Config>(ptr: &Atomic<Inner>, data_cnt: &mut usize, seen_inner: &mut bool) {
             // Unprotected is fine, we are &mut so nobody else is allowed to do stuff to us at the
@@ -38,19 +39,26 @@ where
                 // Do nothing here
             } else if flags.contains(NodeFlags::DATA) {
                 let data = unsafe { load_data::<C>(sub) };
-                assert!(!data.is_empty(), "Empty data nodes should not exist");
+                if let super::Data::Collision(leaves) = data {
+                    assert!(
+                        leaves.len() > 1,
+                        "Collisions of a single value should not exist"
+                    );
+                }
                 *data_cnt += data.len();
             } else {
-                let sub = unsafe { sub.deref() };
+                let sub = unsafe { load_inner::<C>(sub) };
                 *seen_inner = true;
                 check_node::<C>(sub);
             }
         }
-        fn check_node<C: Config>(node: &Inner) {
+        fn check_node<C: Config>(node: super::NodeRef) {
             let mut data_cnt = 0;
             let mut seen_inner = false;
-            for ptr in &node.0 {
-                handle_ptr::<C>(ptr, &mut data_cnt, &mut seen_inner);
+            for idx in 0..C::LEVEL_CELLS {
+                if let Some(ptr) = node.slot(idx) {
+                    handle_ptr::<C>(ptr, &mut data_cnt, &mut seen_inner);
+                }
             }
 
             assert!(
@@ -59,7 +67,16 @@ where
             );
         }
 
-        handle_ptr::<C>(&self.root, &mut 0, &mut false);
+        let pin = unsafe { crossbeam_epoch::unprotected() };
+        handle_ptr::<C>(&self.root(pin).trie, &mut 0, &mut false);
+
+        // While at it, check the counter is right too.
+        let mut iter = super::iterator::Iter::new(self);
+        let mut cnt = 0;
+        while iter.next().is_some() {
+            cnt += 1;
+        }
+        assert_eq!(cnt, self.len(), "Counter doesn't match the content");
     }
 
     fn print_shape_ptr(ptr: &Atomic<Inner>, fmt: &mut Formatter, pin: &Guard) -> FmtResult
@@ -74,13 +91,16 @@ where
             // Nothing
         } else if flags.contains(NodeFlags::DATA) {
             let data = unsafe { load_data::<C>(ptr) };
-            write!(fmt, "{:?}", data)?;
+            fmt.debug_list().entries(data.iter()).finish()?;
         } else {
-            let inner = unsafe { ptr.deref() };
+            let inner = unsafe { load_inner::<C>(ptr) };
             write!(fmt, "(")?;
-            for (idx, sub) in inner.0.iter().enumerate() {
-                write!(fmt, " {:X}:", idx)?;
-                Self::print_shape_ptr(sub, fmt, pin)?;
+            for idx in 0..C::LEVEL_CELLS {
+                // The compressed nodes don't have all the slots.
+                if let Some(sub) = inner.slot(idx) {
+                    write!(fmt, " {:X}:", idx)?;
+                    Self::print_shape_ptr(sub, fmt, pin)?;
+                }
             }
             write!(fmt, " )")?;
         }
@@ -91,8 +111,8 @@ where
     where
         C::Payload: Debug,
     {
-        let pin = crossbeam_epoch::pin();
-        Self::print_shape_ptr(&self.root, fmt, &pin)
+        let pin = self.guard();
+        Self::print_shape_ptr(&self.root(&pin).trie, fmt, &pin)
     }
 }
 
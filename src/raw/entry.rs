@@ -0,0 +1,128 @@
+//! Entries of the [`Raw`] trie, pinned to the value found at a given key.
+
+use std::borrow::Borrow;
+use std::hash::{BuildHasher, Hash};
+
+use super::config::Config;
+use super::{MapGuard, Outcome, Raw, TraverseMode, TraverseState};
+
+// Notes about the safety:
+// We keep a pointer to the payload we've found (or inserted). That one can't go away as long as
+// both the map is alive (guaranteed by the 'a lifetime) and we hold the pin (which we own, so it
+// lives as long as we do). We never hand out the reference with a longer lifetime than borrow of
+// us.
+//
+// Furthermore, as the data nodes are never modified once published and they can't be freed (and
+// their address reused) while we hold the pin, the payload we point to stays valid even after it
+// gets replaced in the trie. Checking if nobody has changed the value in the meantime is left to
+// Config::same.
+
+/// A place in the trie, remembering what value (if any) was found there.
+///
+/// This allows inspecting the current value and then deciding what to do with it. The decision is
+/// then committed by [`replace`][Entry::replace], which succeeds only if the place still holds
+/// the very same value as the one the entry has seen.
+///
+/// The entry holds an epoch pin for its whole lifetime, so it should not be kept around for
+/// extended periods of time.
+pub struct Entry<'a, C: Config, S> {
+    map: &'a Raw<C, S>,
+    pin: MapGuard<'a>,
+    current: Option<*const C::Payload>,
+}
+
+impl<'a, C, S> Entry<'a, C, S>
+where
+    C: Config,
+    S: BuildHasher,
+{
+    /// Looks up the key and creates an entry pinned to the found value.
+    pub fn new<'m: 'a, Q>(map: &'m Raw<C, S>, key: &Q) -> Self
+    where
+        Q: ?Sized + Eq + Hash,
+        C::Key: Borrow<Q>,
+    {
+        let pin = map.guard();
+        let current = map.get(key, &pin).map(|p| p as *const _);
+        Entry { map, pin, current }
+    }
+
+    /// Tries to replace the value the entry has seen with a new one.
+    ///
+    /// The `new` value replaces the current one (or is inserted, if the entry is vacant). If it is
+    /// `None`, the current value is removed. The `key` is the one identifying the entry and it
+    /// must match the key of the new payload.
+    ///
+    /// This succeeds only if the place still holds the same value the entry is pinned to (as
+    /// decided by [`Config::same`]). In such case, the entry is re-pinned to the new value and
+    /// `true` is returned. Otherwise, nothing is modified, the entry is re-pinned to whatever value
+    /// is present now and `false` is returned.
+    pub fn replace<Q>(&mut self, key: &Q, new: Option<C::Payload>) -> bool
+    where
+        Q: ?Sized + Eq + Hash,
+        C::Key: Borrow<Q>,
+    {
+        if let Some(new) = new.as_ref() {
+            assert!(
+                C::eq(new.borrow(), key),
+                "Entry updated with a different key"
+            );
+        }
+        // See the notes about safety above.
+        let current = self.current.map(|p| unsafe { &*p });
+        let (success, now) = match (current, new) {
+            (None, None) => match self.map.get(key, &self.pin) {
+                None => (true, None),
+                Some(found) => (false, Some(found)),
+            },
+            (None, Some(new)) => {
+                let outcome = self.map.traverse(
+                    &mut TraverseState::<C, fn(C::Key) -> C::Payload>::Created(new),
+                    TraverseMode::<fn(&C::Payload) -> bool>::IfMissing,
+                    &self.pin,
+                );
+                match outcome {
+                    Outcome::Inserted(new) => (true, Some(new)),
+                    Outcome::Kept(found) => (false, Some(found)),
+                    Outcome::Missing
+                    | Outcome::Replaced(..)
+                    | Outcome::Placed
+                    | Outcome::InFlight(_) => {
+                        unreachable!("IfMissing misbehaves")
+                    }
+                }
+            }
+            (Some(current), Some(new)) => {
+                let outcome = self.map.traverse(
+                    &mut TraverseState::<C, fn(C::Key) -> C::Payload>::Created(new),
+                    TraverseMode::IfPresent(|p: &C::Payload| C::same(p, current)),
+                    &self.pin,
+                );
+                match outcome {
+                    Outcome::Replaced(_, new) => (true, Some(new)),
+                    Outcome::Kept(found) => (false, Some(found)),
+                    Outcome::Missing => (false, None),
+                    Outcome::Inserted(_) | Outcome::Placed | Outcome::InFlight(_) => {
+                        unreachable!("IfPresent misbehaves")
+                    }
+                }
+            }
+            (Some(current), None) => {
+                let removed = self.map.remove_if(key, |p| C::same(p, current), &self.pin);
+                match removed {
+                    Some(_) => (true, None),
+                    None => (false, self.map.get(key, &self.pin)),
+                }
+            }
+        };
+        self.current = now.map(|p| p as *const _);
+        success
+    }
+}
+
+impl<C: Config, S> Entry<'_, C, S> {
+    /// The value this entry is pinned to.
+    pub fn get(&self) -> Option<&C::Payload> {
+        self.current.map(|p| unsafe { &*p })
+    }
+}
@@ -0,0 +1,637 @@
+//! Creating missing values only once, even under contention.
+//!
+//! The [`get_or_insert_with`][Raw::get_or_insert_with] may call the constructor in several
+//! threads at once, if they all miss the same key at the same time. Only one of the created values
+//! makes it into the map, the others are thrown away. That is a waste if the constructor is
+//! expensive, or plain wrong if it has side effects.
+//!
+//! The [`get_or_insert_with_once`][Raw::get_or_insert_with_once] avoids that. The first thread to
+//! miss the key puts a placeholder ([`Pending`]) into the trie, at the place where the value would
+//! go. Other threads looking for the same key find the placeholder instead of inserting their own,
+//! wait for its [`Flight`] to land and look again. Once the value is created, it replaces the
+//! placeholder. If the constructor panics, the placeholder is removed and one of the waiting
+//! threads takes over.
+//!
+//! The placeholder has no key (it is not created yet), only the hash of it. Lookups, iteration,
+//! the length and removals of values ignore the placeholders.
+//!
+//! Nobody holds a pin while waiting or while running the constructor, so a slow constructor
+//! doesn't prevent reclamation of memory.
+//!
+//! With the `async` feature, there's also the
+//! [`get_or_insert_with_async`][Raw::get_or_insert_with_async]. It works the same, except that the
+//! constructor returns a future and the waiting ones await the landing instead of blocking. If the
+//! future creating the value is dropped, the landing cleans up the same way as after a panic.
+
+use std::borrow::Borrow;
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::hash::BuildHasher;
+use std::mem;
+#[cfg(feature = "async")]
+use std::pin::Pin;
+use std::ptr;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::task::Waker;
+#[cfg(feature = "async")]
+use std::task::{Context, Poll};
+
+use crossbeam_epoch::Shared;
+
+use super::config::Config;
+use super::reference::Ref;
+use super::{load_data, Data, Inner, NewLeaf, Outcome, Raw, TraverseMode, TraverseState};
+use crate::existing_or_new::ExistingOrNew;
+
+/// Creation of a value in progress, for the others to wait on.
+#[derive(Default)]
+pub(super) struct Flight {
+    state: Mutex<FlightState>,
+    cond: Condvar,
+}
+
+#[derive(Default)]
+struct FlightState {
+    landed: bool,
+    /// The async tasks waiting for the landing (the threads wait on the condvar).
+    wakers: Vec<Waker>,
+}
+
+impl Flight {
+    fn lock(&self) -> MutexGuard<'_, FlightState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Marks the creation as finished (one way or another) and wakes up everyone waiting.
+    fn land(&self) {
+        let wakers = {
+            let mut state = self.lock();
+            state.landed = true;
+            mem::take(&mut state.wakers)
+        };
+        self.cond.notify_all();
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Has the flight landed already?
+    pub(super) fn landed(&self) -> bool {
+        self.lock().landed
+    }
+
+    /// Blocks until the flight lands.
+    fn wait(&self) {
+        let mut state = self.lock();
+        while !state.landed {
+            state = self
+                .cond
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// Checks if the flight has landed, registering the task for a wake up if not.
+    #[cfg(feature = "async")]
+    fn poll_landed(&self, ctx: &mut Context) -> Poll<()> {
+        let mut state = self.lock();
+        if state.landed {
+            Poll::Ready(())
+        } else {
+            if !state.wakers.iter().any(|w| w.will_wake(ctx.waker())) {
+                state.wakers.push(ctx.waker().clone());
+            }
+            Poll::Pending
+        }
+    }
+}
+
+/// A future resolving once the flight lands.
+#[cfg(feature = "async")]
+struct Landed<'a>(&'a Flight);
+
+#[cfg(feature = "async")]
+impl Future for Landed<'_> {
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<()> {
+        self.0.poll_landed(ctx)
+    }
+}
+
+/// A placeholder leaf, holding the place of a value being created.
+pub(super) struct Pending<H> {
+    /// The hash of the key (the placeholder doesn't have the key itself).
+    pub(super) hash: H,
+    /// Lands once the value is in place or the creation failed.
+    pub(super) flight: Arc<Flight>,
+}
+
+/// Is the single leaf the placeholder of this flight?
+pub(super) unsafe fn holds_flight<C: Config>(leaf: Shared<Inner>, flight: *const Flight) -> bool {
+    match load_data::<C>(leaf) {
+        Data::Pending(pending) => ptr::eq(&*pending.flight, flight),
+        _ => false,
+    }
+}
+
+/// Is the single leaf a placeholder left behind by an already landed flight?
+///
+/// The landing removes the placeholder before landing the flight, but only from the trie the map
+/// has at that time. If the trie got moved elsewhere in the meantime (by
+/// [`clear_and_take`][Raw::clear_and_take]), the placeholder goes with it and nobody is going to
+/// remove it any more. Such a placeholder counts as missing and is dropped by the next write to
+/// its place.
+pub(super) unsafe fn stale<C: Config>(leaf: Shared<Inner>) -> bool {
+    match load_data::<C>(leaf) {
+        Data::Pending(pending) => pending.flight.landed(),
+        _ => false,
+    }
+}
+
+/// Cleans up after the creation of a value, lands the flight.
+///
+/// The placeholder is removed if it is still in the trie (because the constructor panicked or a
+/// value appeared in the meantime). This is done on drop, to cover the panics.
+struct Landing<'a, C: Config, S: BuildHasher> {
+    map: &'a Raw<C, S>,
+    hash: C::Hash,
+    flight: Arc<Flight>,
+}
+
+impl<C: Config, S: BuildHasher> Drop for Landing<'_, C, S> {
+    fn drop(&mut self) {
+        let pin = self.map.guard();
+        let flight = &*self.flight as *const Flight;
+        let pick = |leaves: &super::Leaves| {
+            leaves
+                .iter()
+                .position(|l| unsafe { holds_flight::<C>(*l, flight) })
+        };
+        self.map.remove_leaf(self.hash, pick, &pin);
+        self.flight.land();
+    }
+}
+
+/// Where [`claim`][Raw::claim] has left us.
+enum Claim<'a, C: Config, S: BuildHasher> {
+    /// The value is already there.
+    Found(Ref<'a, C::Payload>),
+    /// Someone else is creating the value. Wait for the flight and try again with the key.
+    Wait(C::Key, Arc<Flight>),
+    /// Our placeholder is in, we are the ones to create the value for the key.
+    Create(C::Key, Landing<'a, C, S>),
+}
+
+type NoConstructor<C> = fn(<C as Config>::Key) -> <C as Config>::Payload;
+type NoPredicate<C> = fn(&<C as Config>::Payload) -> bool;
+
+/// The found or inserted value, or the key with the error of the constructor.
+type TryInsertResult<'a, C, E> =
+    Result<ExistingOrNew<Ref<'a, <C as Config>::Payload>>, (<C as Config>::Key, E)>;
+
+impl<C, S> Raw<C, S>
+where
+    C: Config,
+    S: BuildHasher,
+{
+    /// Looks for the value and puts a placeholder in its place if it's missing.
+    fn claim(&self, key: C::Key) -> Claim<'_, C, S> {
+        let hash = self.hash(key.borrow());
+        let pin = self.guard();
+        if let Some(existing) = self.get(key.borrow(), &pin) {
+            let existing = existing as *const _;
+            return Claim::Found(unsafe { Ref::new(pin, existing) });
+        }
+
+        let flight = Arc::new(Flight::default());
+        let pending = Pending {
+            hash,
+            flight: Arc::clone(&flight),
+        };
+        let mut state = TraverseState::<C, NoConstructor<C>>::Placeholder {
+            key,
+            hash,
+            leaf: Some(NewLeaf::placeholder(pending)),
+        };
+        let outcome = self.traverse(&mut state, TraverseMode::<NoPredicate<C>>::IfMissing, &pin);
+        let other = match outcome {
+            Outcome::Kept(existing) => {
+                let existing = existing as *const _;
+                return Claim::Found(unsafe { Ref::new(pin, existing) });
+            }
+            Outcome::Placed => None,
+            Outcome::InFlight(other) => Some(Arc::clone(other)),
+            Outcome::Missing | Outcome::Replaced(..) | Outcome::Inserted(_) => {
+                unreachable!("Placeholder misbehaves")
+            }
+        };
+        let key = state.into_key();
+        match other {
+            None => {
+                let landing = Landing {
+                    map: self,
+                    hash,
+                    flight,
+                };
+                Claim::Create(key, landing)
+            }
+            Some(other) => Claim::Wait(key, other),
+        }
+    }
+
+    /// Puts the created value in place of our placeholder and lands the flight.
+    ///
+    /// # Panics
+    ///
+    /// If the payload has a different key than the placeholder (as far as the hash can tell).
+    fn settle(
+        &self,
+        landing: Landing<'_, C, S>,
+        payload: C::Payload,
+    ) -> ExistingOrNew<Ref<'_, C::Payload>> {
+        assert!(
+            self.hash(payload.borrow()) == landing.hash,
+            "Created value has a different key"
+        );
+
+        let pin = self.guard();
+        let settle = TraverseMode::<NoPredicate<C>>::Settle(&*landing.flight as *const Flight);
+        let mut state = TraverseState::<C, NoConstructor<C>>::Created(payload);
+        let result: ExistingOrNew<*const C::Payload> = match self.traverse(&mut state, settle, &pin)
+        {
+            Outcome::Inserted(new) => ExistingOrNew::New(new as *const _),
+            // Someone else has inserted the value the ordinary way in the meantime.
+            Outcome::Kept(existing) => ExistingOrNew::Existing(existing as *const _),
+            Outcome::Missing | Outcome::Replaced(..) | Outcome::Placed | Outcome::InFlight(_) => {
+                unreachable!("Settle misbehaves")
+            }
+        };
+        // Whatever happened, the placeholder must go away now.
+        drop(landing);
+        result.map(|value| unsafe { Ref::new(pin, value) })
+    }
+
+    /// Looks up a value or creates (and inserts) a new one, running at most one constructor for
+    /// the key at a time.
+    ///
+    /// This is like [`get_or_insert_with`][Raw::get_or_insert_with], but if several threads miss
+    /// the same key concurrently, only one of them calls the constructor. The others block until
+    /// the value is in place and return it as existing. If the constructor panics, one of the
+    /// waiting threads calls its own.
+    ///
+    /// No pin is held while constructing or waiting, therefore this one takes no pin and returns
+    /// a [`Ref`] holding its own.
+    ///
+    /// # Panics
+    ///
+    /// If the constructor returns a payload with a different key (as far as the hash can tell).
+    pub fn get_or_insert_with_once<F>(
+        &self,
+        mut key: C::Key,
+        create: F,
+    ) -> ExistingOrNew<Ref<'_, C::Payload>>
+    where
+        F: FnOnce(C::Key) -> C::Payload,
+    {
+        let (key, landing) = loop {
+            let flight = match self.claim(key) {
+                Claim::Found(existing) => return ExistingOrNew::Existing(existing),
+                Claim::Wait(k, flight) => {
+                    key = k;
+                    flight
+                }
+                Claim::Create(k, landing) => break (k, landing),
+            };
+            flight.wait();
+        };
+        // Whatever happens (including a panic), the landing cleans up at the end.
+        self.settle(landing, create(key))
+    }
+
+    /// Looks up a value or tries to create (and insert) a new one.
+    ///
+    /// The constructor may fail, in which case it returns the key back together with the error.
+    /// The error is then propagated and the trie is left untouched.
+    ///
+    /// Unlike [`get_or_insert_with`][Raw::get_or_insert_with], the constructor is called before
+    /// trying to insert the value. If another thread inserts one with the same key in the meantime,
+    /// the created one is thrown away and the existing one is returned. Unlike
+    /// [`get_or_insert_with_once`][Raw::get_or_insert_with_once], concurrent callers don't wait
+    /// for each other, each one may call its constructor.
+    ///
+    /// As with the latter, no pin is held while the constructor runs, so this one takes no pin
+    /// and returns a [`Ref`] holding its own.
+    pub fn get_or_try_insert_with<F, E>(&self, key: C::Key, create: F) -> TryInsertResult<'_, C, E>
+    where
+        F: FnOnce(C::Key) -> Result<C::Payload, (C::Key, E)>,
+    {
+        if let Some(existing) = self.get_ref(key.borrow()) {
+            return Ok(ExistingOrNew::Existing(existing));
+        }
+        let payload = create(key)?;
+
+        let pin = self.guard();
+        let mut state = TraverseState::<C, NoConstructor<C>>::Created(payload);
+        let outcome = self.traverse(&mut state, TraverseMode::<NoPredicate<C>>::IfMissing, &pin);
+        let result: ExistingOrNew<*const C::Payload> = match outcome {
+            Outcome::Kept(existing) => ExistingOrNew::Existing(existing as *const _),
+            Outcome::Inserted(new) => ExistingOrNew::New(new as *const _),
+            // A placeholder of get_or_insert_with_once for the same key doesn't count as a value
+            // (only another placeholder can be in flight with it), so we insert next to it. Its
+            // flight then finds our value and returns it as existing.
+            Outcome::Missing | Outcome::Replaced(..) | Outcome::Placed | Outcome::InFlight(_) => {
+                unreachable!("IfMissing misbehaves")
+            }
+        };
+        Ok(result.map(|value| unsafe { Ref::new(pin, value) }))
+    }
+
+    /// Looks up a value or creates (and inserts) a new one asynchronously, driving at most one
+    /// constructor future for the key at a time.
+    ///
+    /// This is the async counterpart of [`get_or_insert_with_once`][Raw::get_or_insert_with_once].
+    /// The first caller to miss the key drives the future created by the constructor, the
+    /// concurrent callers for the same key await its result instead of creating their own. If the
+    /// driving future panics or is dropped before finishing, one of the waiting ones takes over.
+    ///
+    /// No pin is held across the `.await` points. The returned [`Ref`] holds one, though.
+    ///
+    /// Available with the `async` feature.
+    ///
+    /// # Panics
+    ///
+    /// If the future resolves to a payload with a different key (as far as the hash can tell).
+    #[cfg(feature = "async")]
+    pub async fn get_or_insert_with_async<F, R>(
+        &self,
+        mut key: C::Key,
+        create: F,
+    ) -> ExistingOrNew<Ref<'_, C::Payload>>
+    where
+        F: FnOnce(C::Key) -> R,
+        R: Future<Output = C::Payload>,
+    {
+        // The claim may hold a pin, so it must be gone before any await.
+        let (key, landing) = loop {
+            let flight = match self.claim(key) {
+                Claim::Found(existing) => return ExistingOrNew::Existing(existing),
+                Claim::Wait(k, flight) => {
+                    key = k;
+                    flight
+                }
+                Claim::Create(k, landing) => break (k, landing),
+            };
+            Landed(&flight).await;
+        };
+        // If we get dropped during the await, the landing cleans up.
+        let payload = create(key).await;
+        self.settle(landing, payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::RandomState;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use crossbeam_utils::thread as scoped;
+
+    use super::super::config::{Snapshots, Trivial};
+    use super::super::iterator::Iter;
+    use super::*;
+
+    const THREADS: usize = 8;
+
+    fn content<C: Config<Payload = usize>, S>(map: &Raw<C, S>) -> Vec<usize> {
+        let mut iter = Iter::new(map);
+        let mut result = Vec::new();
+        while let Some(val) = iter.next() {
+            result.push(*val);
+        }
+        result.sort();
+        result
+    }
+
+    fn check_once<C: Config<Key = usize, Payload = usize>>() {
+        let map = Raw::<C, _>::with_hasher(RandomState::new());
+        let calls = AtomicUsize::new(0);
+        scoped::scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|_| {
+                    for key in 0..100 {
+                        let value = map.get_or_insert_with_once(key, |key| {
+                            calls.fetch_add(1, Ordering::Relaxed);
+                            // Give the others the chance to pile up on the placeholder.
+                            thread::sleep(Duration::from_micros(100));
+                            key
+                        });
+                        assert_eq!(key, **value);
+                    }
+                });
+            }
+        })
+        .unwrap();
+        assert_eq!(100, calls.load(Ordering::Relaxed));
+        assert_eq!(100, map.len());
+        assert_eq!((0..100).collect::<Vec<_>>(), content(&map));
+    }
+
+    #[test]
+    fn once() {
+        check_once::<Trivial<usize>>();
+    }
+
+    #[test]
+    fn once_cow() {
+        check_once::<Snapshots<Trivial<usize>>>();
+    }
+
+    /// A panicking constructor leaves nothing behind and the next one takes over.
+    #[test]
+    fn panic_retry() {
+        let mut map = Raw::<Trivial<usize>, _>::with_hasher(RandomState::new());
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            map.get_or_insert_with_once(42, |_| panic!("Constructor failed"));
+        }));
+        assert!(result.is_err());
+        assert!(map.is_empty());
+        assert!(map.get(&42, &crossbeam_epoch::pin()).is_none());
+        assert!(content(&map).is_empty());
+        map.assert_pruned();
+
+        let value = map.get_or_insert_with_once(42, |key| key);
+        assert!(value.is_new());
+        assert_eq!(42, **value);
+        assert_eq!(1, map.len());
+    }
+
+    /// The threads waiting for a panicking constructor don't get stuck.
+    #[test]
+    fn panic_while_waiting() {
+        let map = Raw::<Trivial<usize>, _>::with_hasher(RandomState::new());
+        let calls = AtomicUsize::new(0);
+        scoped::scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|_| {
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                        map.get_or_insert_with_once(1, |key| {
+                            thread::sleep(Duration::from_millis(1));
+                            // The first one fails, the rest succeed.
+                            if calls.fetch_add(1, Ordering::Relaxed) == 0 {
+                                panic!("Constructor failed");
+                            }
+                            key
+                        });
+                    }));
+                    result.is_err()
+                });
+            }
+        })
+        .unwrap();
+        assert_eq!(2, calls.load(Ordering::Relaxed));
+        assert_eq!(Some(&1), map.get(&1, &crossbeam_epoch::pin()));
+    }
+
+    /// An ordinary insert while a value is being created wins, the constructed one is dropped.
+    #[test]
+    fn insert_meanwhile() {
+        let map = Raw::<Trivial<usize>, _>::with_hasher(RandomState::new());
+        let value = map.get_or_insert_with_once(7, |key| {
+            assert!(map.get(&7, &crossbeam_epoch::pin()).is_none());
+            map.insert(key, &crossbeam_epoch::pin());
+            key
+        });
+        assert!(!value.is_new());
+        assert_eq!(1, map.len());
+        assert_eq!(vec![7], content(&map));
+    }
+
+    /// A fallible insert doesn't wait for the placeholder, it puts its value next to it and the
+    /// flight then takes that one.
+    #[test]
+    fn try_insert_meanwhile() {
+        let map = Raw::<Trivial<usize>, _>::with_hasher(RandomState::new());
+        let value = map.get_or_insert_with_once(7, |key| {
+            let inserted = map
+                .get_or_try_insert_with(key, Ok::<_, (usize, ())>)
+                .unwrap();
+            assert!(inserted.is_new());
+            assert_eq!(7, **inserted);
+            key
+        });
+        assert!(!value.is_new());
+        assert_eq!(1, map.len());
+        assert_eq!(vec![7], content(&map));
+    }
+
+    /// Taking the trie away while a value is being created leaves the placeholder there, but it is
+    /// not in the way of anyone after the flight lands.
+    #[test]
+    fn clear_and_take_meanwhile() {
+        let map = Raw::<Trivial<usize>, _>::with_hasher(RandomState::new());
+        let mut taken = None;
+        let value = map.get_or_insert_with_once(1, |key| {
+            assert!(map.is_empty());
+            assert_eq!(0, map.len());
+            taken = Some(map.clear_and_take(&crossbeam_epoch::pin()));
+            key
+        });
+        assert!(value.is_new());
+        assert_eq!(vec![1], content(&map));
+
+        let mut taken = taken.unwrap();
+        assert!(taken.is_empty());
+        assert_eq!(0, taken.len());
+        let value = taken.get_or_insert_with_once(1, |key| key);
+        assert!(value.is_new());
+        drop(value);
+        assert_eq!(1, taken.len());
+        assert_eq!(vec![1], content(&taken));
+        taken.assert_pruned();
+    }
+
+    /// The placeholder is not a value, so it is not seen (or removed) by the rest of the API.
+    #[test]
+    fn placeholder_invisible() {
+        let map = Raw::<Trivial<usize>, _>::with_hasher(RandomState::new());
+        map.insert(1, &crossbeam_epoch::pin());
+        let value = map.get_or_insert_with_once(2, |key| {
+            let pin = crossbeam_epoch::pin();
+            assert!(map.get(&2, &pin).is_none());
+            assert!(map.remove(&2, &pin).is_none());
+            assert_eq!(1, map.len());
+            assert_eq!(vec![1], content(&map));
+            map.retain(|_| false, &pin);
+            assert_eq!(0, map.len());
+            assert!(content(&map).is_empty());
+            key
+        });
+        assert!(value.is_new());
+        assert_eq!(vec![2], content(&map));
+    }
+
+    /// The callers of the async version wait for the first one, even in a single thread.
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_once() {
+        use futures::channel::oneshot;
+        use futures::executor::block_on;
+        use futures::future;
+
+        let map = Raw::<Trivial<usize>, _>::with_hasher(RandomState::new());
+        let calls = AtomicUsize::new(0);
+        let (sender, receiver) = oneshot::channel();
+        let mut receiver = Some(receiver);
+        let getters = (0..THREADS).map(|_| {
+            let receiver = receiver.take();
+            let calls = &calls;
+            map.get_or_insert_with_async(42, move |_| async move {
+                calls.fetch_add(1, Ordering::Relaxed);
+                receiver.expect("Called more than once").await.unwrap()
+            })
+        });
+        let getters = future::join_all(getters);
+        let send = async { sender.send(42).unwrap() };
+        let (values, ()) = block_on(future::join(getters, send));
+        assert_eq!(1, calls.load(Ordering::Relaxed));
+        assert_eq!(1, values.iter().filter(|v| v.is_new()).count());
+        assert!(values.iter().all(|v| ***v == 42));
+    }
+
+    /// Dropping the future creating the value lets someone else create it.
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_dropped() {
+        use futures::executor::block_on;
+        use futures::future::{self, FutureExt};
+
+        let mut map = Raw::<Trivial<usize>, _>::with_hasher(RandomState::new());
+        assert!(map
+            .get_or_insert_with_async(42, |_| future::pending())
+            .now_or_never()
+            .is_none());
+        assert!(content(&map).is_empty());
+        map.assert_pruned();
+
+        let value = block_on(map.get_or_insert_with_async(42, |key| async move { key }));
+        assert!(value.is_new());
+        assert_eq!(vec![42], content(&map));
+    }
+
+    /// Clearing the map while creating a value doesn't lose the value.
+    #[test]
+    fn clear_meanwhile() {
+        let map = Raw::<Trivial<usize>, _>::with_hasher(RandomState::new());
+        map.insert(1, &crossbeam_epoch::pin());
+        let value = map.get_or_insert_with_once(2, |key| {
+            map.clear(&crossbeam_epoch::pin());
+            key
+        });
+        assert!(value.is_new());
+        assert_eq!(1, map.len());
+        assert_eq!(Some(&2), map.get(&2, &crossbeam_epoch::pin()));
+    }
+}
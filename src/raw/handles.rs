@@ -0,0 +1,198 @@
+//! Handles to a dedicated collector, reused between the operations.
+//!
+//! Pinning a collector other than the global default one needs a registered
+//! [`LocalHandle`][crossbeam_epoch::LocalHandle]. Registering one is expensive (it allocates and
+//! puts itself into a list shared by all the threads) and so is getting rid of it. Doing that on
+//! every operation would make the maps with their own collector considerably slower.
+//!
+//! The handles can't be cached in thread locals, though. A handle keeps the collector alive and
+//! also holds some of the garbage in its own bag, so a handle left in some other thread after the
+//! map is dropped would postpone destroying the values (and for the scoped maps, that garbage may
+//! borrow data that doesn't exist any more). Therefore, the map owns a few handles itself and
+//! lends them to the threads. Each thread starts looking at its own slot, so usually it ends up
+//! with the same handle every time. A handle is lent to one thread at a time and only as a whole
+//! with the guard pinned through it, so it doesn't matter it moves between threads now and then.
+//! If all of them are taken, a fresh handle is registered, the same as without the caching.
+
+use std::cell::UnsafeCell;
+use std::mem::ManuallyDrop;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crossbeam_epoch::{Collector, Guard, LocalHandle};
+
+/// How many handles a map keeps around.
+const SLOTS: usize = 16;
+
+static NEXT_SLOT: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static SLOT: usize = NEXT_SLOT.fetch_add(1, Ordering::Relaxed) % SLOTS;
+}
+
+struct Slot {
+    lent: AtomicBool,
+    handle: UnsafeCell<Option<LocalHandle>>,
+}
+
+// The handle is accessed only by whoever has it lent. It is never lent while a guard pinned
+// through it still exists, so the handle (which isn't thread safe) is never used from two threads
+// at once.
+unsafe impl Send for Slot {}
+unsafe impl Sync for Slot {}
+
+/// The handles owned by a map.
+///
+/// Dropping this drops the handles, which moves their garbage to the collector. This must happen
+/// before the map lets go of the collector, so the collector can destroy everything.
+#[derive(Default)]
+pub(crate) struct Handles(Box<[Slot]>);
+
+impl Handles {
+    /// Creates the empty slots (the handles are registered on first use).
+    pub(crate) fn new() -> Self {
+        let slots = (0..SLOTS)
+            .map(|_| Slot {
+                lent: AtomicBool::new(false),
+                handle: UnsafeCell::new(None),
+            })
+            .collect();
+        Handles(slots)
+    }
+
+    /// Pins the collector, through a lent handle if one is free.
+    ///
+    /// The collector must be the same one every time.
+    pub(crate) fn pin<'a>(&'a self, collector: &Collector) -> MapGuard<'a> {
+        let start = SLOT.try_with(|s| *s).unwrap_or(0);
+        for i in 0..self.0.len() {
+            let slot = &self.0[(start + i) % self.0.len()];
+            // Acquire: whatever the previous borrower did with the handle happens before us.
+            if slot
+                .lent
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                let handle = unsafe { &mut *slot.handle.get() };
+                let guard = handle.get_or_insert_with(|| collector.register()).pin();
+                return MapGuard {
+                    guard: ManuallyDrop::new(guard),
+                    slot: Some(slot),
+                };
+            }
+        }
+        // Everything is lent out (many threads or some long-lived guards). The guard keeps the
+        // registration alive even after the handle is gone.
+        MapGuard::from(collector.register().pin())
+    }
+}
+
+/// A [`Guard`] pinned for the use inside a map, possibly through a handle lent by the map.
+///
+/// Dropping it returns the handle.
+pub(crate) struct MapGuard<'a> {
+    guard: ManuallyDrop<Guard>,
+    slot: Option<&'a Slot>,
+}
+
+impl From<Guard> for MapGuard<'_> {
+    fn from(guard: Guard) -> Self {
+        MapGuard {
+            guard: ManuallyDrop::new(guard),
+            slot: None,
+        }
+    }
+}
+
+impl Deref for MapGuard<'_> {
+    type Target = Guard;
+    fn deref(&self) -> &Guard {
+        &self.guard
+    }
+}
+
+impl Drop for MapGuard<'_> {
+    fn drop(&mut self) {
+        // The guard needs to go first, the handle can be lent to someone else after that.
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        if let Some(slot) = self.slot {
+            // Release: pass our use of the handle to the next borrower.
+            slot.lent.store(false, Ordering::Release);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use crossbeam_utils::thread;
+
+    use super::*;
+
+    fn registered(handles: &Handles) -> usize {
+        handles
+            .0
+            .iter()
+            .filter(|s| unsafe { &*s.handle.get() }.is_some())
+            .count()
+    }
+
+    /// Nested pins take different handles and they get reused afterwards.
+    #[test]
+    fn reuse() {
+        let collector = Collector::new();
+        let handles = Handles::new();
+        {
+            let outer = handles.pin(&collector);
+            let inner = handles.pin(&collector);
+            assert!(!ptr::eq(outer.slot.unwrap(), inner.slot.unwrap()));
+        }
+        assert_eq!(2, registered(&handles));
+        for _ in 0..10 {
+            drop(handles.pin(&collector));
+        }
+        assert_eq!(2, registered(&handles));
+    }
+
+    /// Once all the handles are lent, a fresh one is registered.
+    #[test]
+    fn exhausted() {
+        let collector = Collector::new();
+        let handles = Handles::new();
+        let guards = (0..SLOTS)
+            .map(|_| handles.pin(&collector))
+            .collect::<Vec<_>>();
+        let extra = handles.pin(&collector);
+        assert!(extra.slot.is_none());
+        assert_eq!(Some(&collector), extra.collector());
+        drop(guards);
+        assert!(handles.pin(&collector).slot.is_some());
+    }
+
+    /// The garbage deferred through the lent handles is destroyed once the handles and the
+    /// collector go away.
+    #[test]
+    fn garbage_destroyed() {
+        let collector = Collector::new();
+        let handles = Handles::new();
+        let counter = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|_| {
+                    for _ in 0..100 {
+                        let guard = handles.pin(&collector);
+                        let counter = &counter;
+                        unsafe {
+                            guard.defer_unchecked(move || counter.fetch_add(1, Ordering::Relaxed))
+                        };
+                    }
+                });
+            }
+        })
+        .unwrap();
+        drop(handles);
+        drop(collector);
+        assert_eq!(400, counter.load(Ordering::Relaxed));
+    }
+}
@@ -1,16 +1,20 @@
-//! Iteration of the [`Raw`][crate::raw::Raw] map.
+//! Iteration of the [`Raw`] map.
 
+use std::cmp::Ordering as CmpOrdering;
+use std::hash::BuildHasher;
 use std::marker::PhantomData;
 use std::mem;
-use std::sync::atomic::Ordering;
 
 use arrayvec::ArrayVec;
-use crossbeam_epoch::{Guard, Shared};
+use crossbeam_epoch::Shared;
 
-use super::config::Config;
-use super::{load_data, nf, Inner, NodeFlags, Raw, LEVEL_CELLS, MAX_LEVELS};
+use super::config::{Config, HashValue};
+use super::{
+    load_data, load_inner, nf, path_order, Inner, Levels, MapGuard, NodeFlags, Orderings, Raw,
+    PATH_CAPACITY,
+};
 
-unsafe fn extend_lifetime<'a, 'b, T: 'a + 'b>(s: Shared<'a, T>) -> Shared<'b, T> {
+pub(super) unsafe fn extend_lifetime<'a, 'b, T: 'a + 'b>(s: Shared<'a, T>) -> Shared<'b, T> {
     mem::transmute(s)
 }
 
@@ -48,13 +52,15 @@ struct Level<'a> {
 ///
 /// As noted in the crate-level documentation, changes to the content of the map done during the
 /// lifetime of the iterator (both in the current thread and other threads) may or may not be
-/// reflected in the returned values.
+/// reflected in the returned values. The exception is a trie with
+/// [`SNAPSHOTS`][crate::raw::config::Config::SNAPSHOTS] turned on, where the iterator walks the
+/// content as it was at the moment the iterator was created.
 pub struct Iter<'a, C, S>
 where
     C: Config,
 {
-    pin: Guard,
-    levels: ArrayVec<[Level<'a>; MAX_LEVELS + 1]>,
+    pin: MapGuard<'a>,
+    levels: ArrayVec<[Level<'a>; PATH_CAPACITY]>,
     _map: PhantomData<&'a Raw<C, S>>,
 }
 
@@ -64,10 +70,24 @@ where
 {
     /// Creates a new iterator, borrowing from the map.
     pub fn new<'m: 'a>(map: &'m Raw<C, S>) -> Self {
+        let pin = map.guard();
+        let root = map.root(&pin);
+        unsafe {
+            let ptr = extend_lifetime(root.trie.load(C::ACQUIRE, &pin));
+            Self::starting_at(map, pin, ptr)
+        }
+    }
+
+    /// Creates an iterator through the (sub)trie starting at the given node.
+    ///
+    /// The caller must make sure the node stays alive for the whole lifetime of the iterator
+    /// (either because it is protected by the passed pin or by another pin that outlives us).
+    pub(super) unsafe fn starting_at<'m: 'a>(
+        _map: &'m Raw<C, S>,
+        pin: MapGuard<'a>,
+        ptr: Shared<'a, Inner>,
+    ) -> Self {
         let mut levels = ArrayVec::new();
-        let pin = crossbeam_epoch::pin();
-        let ptr = map.root.load(Ordering::Acquire, &pin);
-        let ptr = unsafe { extend_lifetime(ptr) };
         levels.push(Level { ptr, idx: 0 });
         Iter {
             pin,
@@ -81,6 +101,12 @@ where
     /// lifetime of the iterator structure.
     #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Option<&C::Payload> {
+        self.next_indexed().map(|(payload, _, _)| payload)
+    }
+
+    /// Like [`next`][Iter::next], but also returns the hash of the value and its index inside its
+    /// data node.
+    pub(super) fn next_indexed(&mut self) -> Option<(&C::Payload, C::Hash, usize)> {
         loop {
             let top = self.levels.last_mut()?;
 
@@ -90,15 +116,18 @@ where
             } else if flags.contains(NodeFlags::DATA) {
                 let data = unsafe { load_data::<C>(top.ptr) };
                 if top.idx < data.len() {
-                    let result = &data[top.idx];
+                    let result = data.get(top.idx);
                     top.idx += 1;
-                    return Some(result);
+                    // Placeholders have no value to return, skip over them.
+                    if let Some(result) = result {
+                        return Some((result, data.hash(), top.idx - 1));
+                    }
                 } else {
                     self.levels.pop();
                 }
-            } else if top.idx < LEVEL_CELLS {
-                let node = unsafe { top.ptr.deref() };
-                let ptr = node.0[top.idx].load(Ordering::Acquire, &self.pin);
+            } else if top.idx < C::LEVEL_CELLS {
+                let node = unsafe { load_inner::<C>(top.ptr) };
+                let ptr = node.load(top.idx, C::ACQUIRE, &self.pin);
                 let ptr = unsafe { extend_lifetime(ptr) };
                 top.idx += 1;
                 self.levels.push(Level { ptr, idx: 0 });
@@ -108,3 +137,62 @@ where
         }
     }
 }
+
+impl<'a, C, S> Iter<'a, C, S>
+where
+    C: Config,
+    S: BuildHasher,
+{
+    /// Creates an iterator starting at the given position of the walk through the trie.
+    ///
+    /// The iterator goes from the root along the path of the hash, skipping the slots before the
+    /// path on each level. If the path ends in a data node with the very same hash, the values
+    /// before the index are skipped too. If the address of the `last` value is known (non-zero),
+    /// the values up to it are skipped instead.
+    pub(super) fn starting_from<'m: 'a>(
+        map: &'m Raw<C, S>,
+        pin: MapGuard<'a>,
+        hash: C::Hash,
+        index: usize,
+        last: usize,
+    ) -> Self {
+        let mut levels = ArrayVec::new();
+        let mut ptr = unsafe { extend_lifetime(map.root(&pin).trie.load(C::ACQUIRE, &pin)) };
+        let mut shift = 0;
+        while !ptr.is_null() {
+            if nf(ptr).contains(NodeFlags::DATA) {
+                // All the leaves in there have the same hash.
+                let leaf_hash = unsafe { load_data::<C>(ptr) }.hash();
+                let idx = match path_order::<C>(leaf_hash, hash) {
+                    CmpOrdering::Less => unsafe { load_data::<C>(ptr) }.len(),
+                    // The indices in a collision shift when some of its values get removed.
+                    // If the last one is gone, the values after it moved one place back.
+                    CmpOrdering::Equal if last != 0 => {
+                        let data = unsafe { load_data::<C>(ptr) };
+                        (0..data.len())
+                            .find(|&i| {
+                                data.get(i)
+                                    .map_or(false, |v| v as *const C::Payload as usize == last)
+                            })
+                            .map_or(index.saturating_sub(1), |i| i + 1)
+                    }
+                    CmpOrdering::Equal => index,
+                    CmpOrdering::Greater => 0,
+                };
+                levels.push(Level { ptr, idx });
+                break;
+            }
+            let bits = hash.bits(shift, C::LEVEL_MASK);
+            // Once we are done with the path, continue with the slot after it.
+            levels.push(Level { ptr, idx: bits + 1 });
+            let node = unsafe { load_inner::<C>(ptr) };
+            ptr = unsafe { extend_lifetime(node.load(bits, C::ACQUIRE, &pin)) };
+            shift += C::LEVEL_BITS;
+        }
+        Iter {
+            pin,
+            levels,
+            _map: PhantomData,
+        }
+    }
+}
@@ -1,7 +1,7 @@
 #![allow(unsafe_code)]
 //! The core implementation of the concurrent trie data structure.
 //!
-//! This module contains the [`Raw`][crate::raw::Raw] type, which is the engine of all the data
+//! This module contains the [`Raw`] type, which is the engine of all the data
 //! structures in this crate. This is exposed to allow wrapping it into further APIs, but is
 //! probably not the best thing for general use.
 
@@ -11,10 +11,12 @@
 // simplified. Compared to the article, what we don't do (if you don't want to read the article,
 // that's fine, explanation is below):
 //
-// * We don't have variable-sized inner nodes. This wastes some more space, but also allows us to
-//   keep the same node around instead of creating a new one every time we want to add or remove a
-//   pointer.
-// * We don't do snapshots for iterations.
+// * We don't have variable-sized inner nodes (unless asked for in the config, in which case a
+//   node gets replaced by a bigger copy when it needs a slot it doesn't have yet). This wastes
+//   some more space, but also allows us to keep the same node around instead of creating a new
+//   one every time we want to add or remove a pointer.
+// * We don't do snapshots for iterations (unless asked for in the config, see the [`snapshot`]
+//   module for how that works).
 // * We got rid of the I-nodes. This gets rid of half of the pointer loads on the way to the
 //   element, so in theory it should make the data structure about twice faster.
 //
@@ -25,26 +27,30 @@
 //
 // ## How it works
 //
-// The heart of the data structure is a trie where keys are prefixes of the 64bit hash of the key.
-// Each inner node has 16 pointer slots, indexed by the next 4 bits of the hash. When we reach a
-// level where the prefix is unique, we stop (we don't have all 16 levels of inner nodes if we
-// don't have to) and place a data node.
+// The heart of the data structure is a trie where keys are prefixes of the 64bit hash of the key
+// (or whatever hash type the config asks for).
+// Each inner node has 16 pointer slots, indexed by the next 4 bits of the hash (the number of bits
+// per level can be changed in the config). When we reach a level where the prefix is unique, we
+// stop (we don't have all 16 levels of inner nodes if we don't have to) and place a data node.
 //
-// A data nodes contain one or more elements (more in case we get a hash collision on the whole
-// hash ‒ in that case, we store all the colliding elements in an array and distinguish by equality
-// of the keys in a linear search through the array).
+// A data node contains a single element. In case we get a hash collision on the whole hash, we
+// place a collision node instead ‒ an array of pointers to the data nodes of all the colliding
+// elements; we distinguish them by equality of the keys in a linear search through the array.
+// The data nodes are never copied. Modifying a collision creates a new array, pointing to the
+// same data nodes for the elements that stay, so the payloads don't need to be cloned.
 //
 // On lookup, we either find the correct element or stop at the first null pointer encountered.
 //
 // On insertion, if we find a null pointer, we atomically replace that pointer to a new data node
-// containing (only) the new element, using the CaS operation. In case we reach a collision or
-// replace an existing element, we create a new data node and replace the pointer, again using the
-// CaS operation. If we find a non-matching data node in our way, we need to insert another level ‒
+// containing the new element, using the CaS operation. In case we reach a collision or replace an
+// existing element, we create a new data (or collision) node and replace the pointer, again using
+// the CaS operation. If the CaS fails, we keep the data node with the new element around for the
+// next attempt. If we find a non-matching data node in our way, we need to insert another level ‒
 // we create a brand new inner node, link the old node there and again, replace the pointer (then
 // retry with our insertion on the next level).
 //
 // Deletion looks up the element and either replaces the pointer to the data node with null (if it
-// was the last one), or creates a new data node without the element.
+// was the last one), or creates a new collision node without the element.
 //
 // ## Pruning
 //
@@ -111,7 +117,8 @@
 // point of time, though not necessarily optimally small.
 //
 // Therefore, the iterator simply keeps a stack of nodes it is in, with indices into either the
-// pointer array or the array of elements in a data node and does a DFS through the data structure.
+// pointer array or the array of elements in a collision node and does a DFS through the data
+// structure.
 //
 // # Safety
 //
@@ -121,8 +128,10 @@
 // ## Lifetimes & invalid pointers
 //
 // First, we simply never insert pointers that would be invalid at that time into the data
-// structure ‒ whatever gets inserted is just brand new allocated thing. This boils down to just
-// being careful and, as this is relatively short code, this is possible to accomplish.
+// structure ‒ whatever gets inserted is just brand new allocated thing (or a data node that is
+// already in the trie and is being moved into a new collision node, which keeps it alive). This
+// boils down to just being careful and, as this is relatively short code, this is possible to
+// accomplish.
 //
 // So, we must make sure nothing gets destroyed too soon. To accomplish this, we use the mechanism
 // of crossbeam_epoch. When we remove something from the data structure, the destruction is
@@ -163,32 +172,116 @@
 //   the pointer (with the flag), therefore anyone reading it after us only synchronizes against
 //   us, so we also need to re-release it right now onto that pointer.
 //
+// If the config asks for sequential consistency, the release, consume, acquire and AcqRel
+// orderings (and the Relaxed one in is_empty) are upgraded to SeqCst. The rest doesn't decide the
+// outcome of any operation. That puts all the reads and updates of the pointers deciding where an
+// element is (or that it isn't anywhere) into a single total order, which orders the operations
+// on different keys too.
+//
 // [article]: https://www.researchgate.net/publication/221643801_Concurrent_Tries_with_Efficient_Non-Blocking_Snapshots
 // [Wikipedia entry]: https://en.wikipedia.org/wiki/Ctrie
 
+use std::alloc::{self, Layout};
 use std::borrow::Borrow;
-use std::hash::{BuildHasher, Hash, Hasher};
+use std::cmp::Ordering as CmpOrdering;
+use std::hash::{BuildHasher, Hash};
+use std::iter;
 use std::marker::PhantomData;
 use std::mem;
+use std::ptr;
+use std::slice;
 use std::sync::atomic::Ordering;
+use std::sync::Arc;
 
 use arrayvec::ArrayVec;
 use bitflags::bitflags;
-use crossbeam_epoch::{Atomic, Guard, Owned, Shared};
+use crossbeam_epoch::{Atomic, Collector, Guard, Owned, Shared};
 use smallvec::SmallVec;
 
+mod batch;
 pub mod config;
+mod counter;
+pub mod cursor;
 pub mod debug;
+pub mod entry;
+mod flight;
+mod handles;
 pub mod iterator;
-
-use self::config::Config;
+#[cfg(feature = "rayon")]
+pub mod parallel;
+pub mod reference;
+pub mod snapshot;
+
+use self::config::{Config, HashValue};
+use self::counter::Counter;
+use self::flight::{Flight, Pending};
+use self::handles::Handles;
+pub(crate) use self::handles::MapGuard;
 use crate::existing_or_new::ExistingOrNew;
 
-// All directly written, some things are not const fn yet :-(. But tested below.
-pub(crate) const LEVEL_BITS: usize = 4;
-pub(crate) const LEVEL_MASK: u64 = 0b1111;
-pub(crate) const LEVEL_CELLS: usize = 16;
-pub(crate) const MAX_LEVELS: usize = mem::size_of::<u64>() * 8 / LEVEL_BITS;
+// The bounds of Config::LEVEL_BITS. The upper one comes from the bitmap in the compressed nodes,
+// the lower one (together with the largest supported hash) bounds the depth of the trie. The
+// arrays holding a whole path through the trie need one more slot than the depth, rounded up to a
+// size arrayvec supports. All directly written, some things are not const fn yet :-(. But tested
+// below.
+pub(crate) const MIN_LEVEL_BITS: usize = 3;
+pub(crate) const MAX_LEVEL_BITS: usize = 6;
+pub(crate) const MAX_LEVEL_CELLS: usize = 64;
+pub(crate) const MAX_LEVELS: usize = 43;
+pub(crate) const PATH_CAPACITY: usize = 48;
+
+/// The shape of the trie levels, derived from [`Config::LEVEL_BITS`] and [`Config::Hash`].
+trait Levels: Config {
+    const LEVEL_CELLS: usize = 1 << Self::LEVEL_BITS;
+    const LEVEL_MASK: usize = (1 << Self::LEVEL_BITS) - 1;
+    /// The number of levels needed to use up the whole hash (the last one may be partial).
+    const MAX_LEVELS: usize =
+        (<Self::Hash as HashValue>::BITS + Self::LEVEL_BITS - 1) / Self::LEVEL_BITS;
+}
+
+impl<C: Config> Levels for C {}
+
+/// The memory orderings used on the pointers of the trie.
+///
+/// These are the weakest ones that are enough for each key on its own, unless the config asks for
+/// [`SEQUENTIALLY_CONSISTENT`][Config::SEQUENTIALLY_CONSISTENT] behaviour.
+trait Orderings: Config {
+    /// Reading a pointer to look behind it and possibly re-publish it.
+    const ACQUIRE: Ordering = if Self::SEQUENTIALLY_CONSISTENT {
+        Ordering::SeqCst
+    } else {
+        Ordering::Acquire
+    };
+    /// Publishing a pointer (the successful CaS).
+    const RELEASE: Ordering = if Self::SEQUENTIALLY_CONSISTENT {
+        Ordering::SeqCst
+    } else {
+        Ordering::Release
+    };
+    /// Both at once.
+    const ACQ_REL: Ordering = if Self::SEQUENTIALLY_CONSISTENT {
+        Ordering::SeqCst
+    } else {
+        Ordering::AcqRel
+    };
+}
+
+impl<C: Config> Orderings for C {}
+
+/// Loads a pointer of the trie, to follow it.
+///
+/// This uses the consume ordering (see the notes about the synchronization above), unless the
+/// config asks for `SeqCst`.
+fn load_ptr<'p, C: Config, T>(ptr: &Atomic<T>, pin: &'p Guard) -> Shared<'p, T> {
+    if C::SEQUENTIALLY_CONSISTENT {
+        ptr.load(Ordering::SeqCst, pin)
+    } else {
+        ptr.load_consume(pin)
+    }
+}
+
+/// How many times [`flush_garbage`][Raw::flush_garbage] pokes the collector.
+const FLUSH_ROUNDS: usize = 4;
 
 bitflags! {
     /// Flags that can be put onto a pointer pointing to a node, specifying some interesting
@@ -223,56 +316,489 @@ fn nf(node: Shared<Inner>) -> NodeFlags {
 }
 
 /// Type-casts the pointer to a [`Data`] node.
-unsafe fn load_data<'a, C: Config>(node: Shared<'a, Inner>) -> &'a Data<C> {
+unsafe fn load_data<'a, C: Config>(node: Shared<'a, Inner>) -> &'a Data<C::Payload, C::Hash> {
     assert!(
         nf(node).contains(NodeFlags::DATA),
         "Tried to load data from inner node pointer"
     );
-    (node.as_raw() as usize as *const Data<C>)
+    (node.as_raw() as usize as *const Data<C::Payload, C::Hash>)
         .as_ref()
         .expect("A null pointer with data flag found")
 }
 
-/// Moves a data node behind an [`Owned`] pointer, casts it and provides the correct flags.
-fn owned_data<C: Config>(data: Data<C>) -> Owned<Inner> {
-    unsafe {
-        Owned::<Inner>::from_raw(Box::into_raw(Box::new(data)) as usize as *mut _)
-            .with_tag(NodeFlags::DATA.bits())
+/// Type-casts the pointer to a single leaf and returns its payload.
+unsafe fn load_leaf<'a, C: Config>(leaf: Shared<'a, Inner>) -> &'a C::Payload {
+    leaf_payload::<C>(leaf).expect("Expected a single leaf with a value")
+}
+
+/// Type-casts the pointer to a single leaf and returns its payload, unless it is a placeholder.
+unsafe fn leaf_payload<'a, C: Config>(leaf: Shared<'a, Inner>) -> Option<&'a C::Payload> {
+    match load_data::<C>(leaf) {
+        Data::Single(payload, _) => Some(payload),
+        Data::Pending(_) => None,
+        Data::Collision(_) => unreachable!("Expected a single leaf, found a collision"),
     }
 }
 
+/// Moves a data node behind a shared pointer, casts it and provides the correct flags.
+///
+/// The pin only ties the lifetime of the result to something. The caller is responsible for
+/// either publishing the node or freeing it.
+fn share_data<'p, C: Config>(
+    data: Data<C::Payload, C::Hash>,
+    _pin: &'p Guard,
+) -> Shared<'p, Inner> {
+    Shared::from(Box::into_raw(Box::new(data)) as usize as *const Inner)
+        .with_tag(NodeFlags::DATA.bits())
+}
+
 /// Type-casts and drops the node as data.
+///
+/// Dropping a collision frees only the list, not the leaves it points to.
 unsafe fn drop_data<C: Config>(ptr: Shared<Inner>) {
     assert!(
         nf(ptr).contains(NodeFlags::DATA),
         "Tried to drop data from inner node pointer"
     );
-    drop(Owned::from_raw(ptr.as_raw() as usize as *mut Data<C>));
+    drop(Box::from_raw(
+        ptr.as_raw() as usize as *mut Data<C::Payload, C::Hash>
+    ));
+}
+
+/// Drops a data node we've created but failed to put in place.
+///
+/// Only the collisions are ours to free. A single leaf is either shared with the trie or still
+/// owned by the [`TraverseState`] for the next attempt.
+unsafe fn drop_unused_data<C: Config>(ptr: Shared<Inner>) {
+    if !ptr.is_null() {
+        if let Data::Collision(_) = load_data::<C>(ptr) {
+            drop_data::<C>(ptr);
+        }
+    }
+}
+
+/// Schedules destruction of a data node replaced in the trie, together with the leaves removed
+/// from it.
+///
+/// The leaves that made it into the new node are left alone.
+unsafe fn defer_retire<'p, C, I>(node: Shared<Inner>, removed: I, pin: &Guard)
+where
+    C: Config,
+    I: IntoIterator<Item = Shared<'p, Inner>>,
+{
+    if let Data::Collision(_) = load_data::<C>(node) {
+        let node = Shared::from(node.as_raw() as usize as *const Data<C::Payload, C::Hash>);
+        pin.defer_destroy(node);
+    }
+    for leaf in removed {
+        let leaf = Shared::from(leaf.as_raw() as usize as *const Data<C::Payload, C::Hash>);
+        pin.defer_destroy(leaf);
+    }
+}
+
+/// Destroys a whole (sub)trie, including the node passed.
+///
+/// Nobody else may be accessing any of the nodes at the time (either because we have unique
+/// access to the map or because the trie has been unlinked and the epoch has passed).
+unsafe fn drop_recursive<C: Config>(node: Shared<Inner>) {
+    // Unprotected and Relaxed are fine, as nobody else can be modifying the trie any more and all
+    // the modifications got synchronized into our thread by now.
+    let pin = crossbeam_epoch::unprotected();
+    let flags = nf(node);
+    if node.is_null() {
+        // Skip
+    } else if flags.contains(NodeFlags::DATA) {
+        if let Data::Collision(leaves) = load_data::<C>(node) {
+            for leaf in leaves.iter() {
+                drop(Box::from_raw(*leaf as *mut Data<C::Payload, C::Hash>));
+            }
+        }
+        drop_data::<C>(node);
+    } else {
+        let inner = load_inner::<C>(node);
+        for idx in 0..C::LEVEL_CELLS {
+            if let Some(sub) = inner.slot(idx) {
+                drop_recursive::<C>(sub.load(Ordering::Relaxed, pin));
+            }
+        }
+        drop_inner::<C>(node);
+    }
 }
 
 /// An inner branching node of the trie.
 ///
-/// This is just a bunch of pointers to lower levels.
-#[derive(Default)]
-struct Inner([Atomic<Inner>; LEVEL_CELLS]);
+/// This is just a bunch of pointers to lower levels. As their number depends on the config, this
+/// only marks the beginning of the array and the pointers are type-cast (similar to [`Data`]).
+/// The whole array is allocated as a boxed slice of [`LEVEL_CELLS`][Levels::LEVEL_CELLS] pointers.
+#[repr(C)]
+struct Inner([Atomic<Inner>; 0]);
+
+/// A freshly created inner node, not yet published into the trie.
+struct NewInner(Box<[Atomic<Inner>]>);
+
+impl NewInner {
+    fn new<C: Config>() -> Self {
+        NewInner((0..C::LEVEL_CELLS).map(|_| Atomic::null()).collect())
+    }
+}
 
-// Instead of distinguishing the very common case of single leaf and collision list in our code, we
-// just handle everything as a list, possibly with 1 element.
-//
-// However, as the case with 1 element is much more probable, we don't want the Vec indirection
-// there, so we let SmallVec to handle it by not spilling in that case. As the spilled Vec needs 2
-// words in addition to the length (pointer and capacity), we have room for 2 Arcs in the not
-// spilled case too, so we as well might take advantage of it.
-// TODO: We want the union feature.
+/// A compressed inner node, used instead of [`Inner`] if the
+/// [`COMPRESSED_NODES`][Config::COMPRESSED_NODES] are turned on.
+///
+/// The bitmap marks which of the slots are occupied. Only these are stored, in order, in the array
+/// following the header (which is allocated together with the header). The slots are updated in
+/// place the same way as in the full nodes, but the bitmap never changes once the node is
+/// published. A node that needs another slot is condemned and replaced by a bigger copy (see
+/// [`grow`][Raw::grow]). The slots that become null stay in place until the node gets pruned.
+///
+/// The pointers to these nodes are type-cast to [`Inner`], the same way as with [`Data`]. Unlike
+/// data, they don't have their own flag ‒ either all the inner nodes of the trie are compressed or
+/// none of them, depending on the config.
+#[repr(C)]
+struct Packed {
+    // Only LEVEL_CELLS bits are used, but the header takes a whole word anyway due to the
+    // alignment of the pointers that follow. It is also enough for MAX_LEVEL_CELLS.
+    bitmap: u64,
+    slots: [Atomic<Inner>; 0],
+}
+
+impl Packed {
+    fn layout(cnt: usize) -> Layout {
+        let size = mem::size_of::<Packed>() + cnt * mem::size_of::<Atomic<Inner>>();
+        Layout::from_size_align(size, mem::align_of::<Packed>()).expect("Invalid node layout")
+    }
+
+    /// Allocates a new node, holding the non-null pointers of the given one.
+    fn pack(node: NewInner) -> *mut Packed {
+        // Relaxed and unprotected are fine, the node is still ours and not published.
+        let pin = unsafe { crossbeam_epoch::unprotected() };
+        let mut bitmap = 0;
+        let mut children = ArrayVec::<[Shared<Inner>; MAX_LEVEL_CELLS]>::new();
+        for (idx, sub) in node.0.iter().enumerate() {
+            let sub = sub.load(Ordering::Relaxed, pin);
+            if !sub.is_null() {
+                bitmap |= 1 << idx;
+                children.push(sub);
+            }
+        }
+        unsafe {
+            let packed = alloc::alloc(Self::layout(children.len())) as *mut Packed;
+            if packed.is_null() {
+                alloc::handle_alloc_error(Self::layout(children.len()));
+            }
+            ptr::write(&mut (*packed).bitmap, bitmap);
+            let slots = (*packed).slots.as_mut_ptr();
+            for (i, child) in children.into_iter().enumerate() {
+                ptr::write(slots.add(i), Atomic::from(child));
+            }
+            packed
+        }
+    }
+
+    fn slots(&self) -> &[Atomic<Inner>] {
+        let cnt = self.bitmap.count_ones() as usize;
+        unsafe { slice::from_raw_parts(self.slots.as_ptr(), cnt) }
+    }
+
+    fn slot(&self, idx: usize) -> Option<&Atomic<Inner>> {
+        let bit = 1 << idx;
+        if self.bitmap & bit == 0 {
+            None
+        } else {
+            let pos = (self.bitmap & (bit - 1)).count_ones() as usize;
+            Some(unsafe { &*self.slots.as_ptr().add(pos) })
+        }
+    }
+
+    /// Frees the node (not the nodes it points to).
+    unsafe fn free(packed: *mut Packed) {
+        let cnt = (*packed).bitmap.count_ones() as usize;
+        // The atomics don't need dropping, they are just pointers.
+        alloc::dealloc(packed as *mut u8, Self::layout(cnt));
+    }
+}
+
+/// A borrowed inner node, in whatever layout the config uses.
+#[derive(Copy, Clone)]
+enum NodeRef<'a> {
+    Full(&'a [Atomic<Inner>]),
+    Packed(&'a Packed),
+}
+
+impl<'a> NodeRef<'a> {
+    /// The slot for the given index, if the node has one.
+    ///
+    /// A missing slot is the same as a slot holding a null pointer.
+    fn slot(self, idx: usize) -> Option<&'a Atomic<Inner>> {
+        match self {
+            NodeRef::Full(inner) => Some(&inner[idx]),
+            NodeRef::Packed(packed) => packed.slot(idx),
+        }
+    }
+
+    /// All the slots the node has, in order.
+    ///
+    /// Note that with a compressed node the position in the result is not the index of the slot.
+    fn slots(self) -> &'a [Atomic<Inner>] {
+        match self {
+            NodeRef::Full(inner) => inner,
+            NodeRef::Packed(packed) => packed.slots(),
+        }
+    }
+
+    /// Loads the pointer in the given slot.
+    fn load<'p>(self, idx: usize, ordering: Ordering, pin: &'p Guard) -> Shared<'p, Inner> {
+        self.slot(idx)
+            .map(|slot| slot.load(ordering, pin))
+            .unwrap_or_else(Shared::null)
+    }
+}
+
+/// Type-casts the pointer to an inner node of the layout used by the config.
+unsafe fn load_inner<'a, C: Config>(node: Shared<'a, Inner>) -> NodeRef<'a> {
+    assert!(
+        !nf(node).contains(NodeFlags::DATA),
+        "Tried to load inner node from data pointer"
+    );
+    let raw = node.as_raw();
+    assert!(!raw.is_null(), "Tried to load inner node from null pointer");
+    if C::COMPRESSED_NODES {
+        NodeRef::Packed(&*(raw as usize as *const Packed))
+    } else {
+        NodeRef::Full(slice::from_raw_parts(
+            raw as usize as *const Atomic<Inner>,
+            C::LEVEL_CELLS,
+        ))
+    }
+}
+
+/// Turns a freshly created inner node into the layout used by the config and shares it.
 //
-// Alternatively, we probably could use the raw allocator API and structure with len + [Arc<..>; 0].
-// TODO: Compute the stack length based on the Payload size.
-type Data<C> = SmallVec<[<C as Config>::Payload; 2]>;
+// The pin only ties the lifetime of the result to something.
+fn publish_inner<'p, C: Config>(node: NewInner, _pin: &'p Guard) -> Shared<'p, Inner> {
+    let raw = if C::COMPRESSED_NODES {
+        Packed::pack(node) as usize
+    } else {
+        Box::into_raw(node.0) as *mut Atomic<Inner> as usize
+    };
+    Shared::from(raw as *const Inner)
+}
+
+/// Type-casts and drops the inner node (but not the nodes it points to).
+unsafe fn drop_inner<C: Config>(node: Shared<Inner>) {
+    let raw = node.as_raw() as usize;
+    if C::COMPRESSED_NODES {
+        Packed::free(raw as *mut Packed);
+    } else {
+        let slots = slice::from_raw_parts_mut(raw as *mut Atomic<Inner>, C::LEVEL_CELLS);
+        drop(Box::from_raw(slots));
+    }
+}
+
+/// Schedules dropping of the inner node (but not the nodes it points to) for when the epoch ends.
+unsafe fn defer_drop_inner<C: Config>(node: Shared<Inner>, pin: &Guard) {
+    let raw = node.as_raw() as usize;
+    pin.defer_unchecked(move || drop_inner::<C>(Shared::from(raw as *const Inner)));
+}
+
+/// The top of the trie, together with the bookkeeping about its content.
+///
+/// This lives in its own heap node, so it can be swapped as a whole (see [`clear`][Raw::clear]).
+/// Operations that started before the swap finish inside the old trie and account for their
+/// changes in the old counter, which keeps the counters of both exact.
+#[derive(Default)]
+struct Root {
+    trie: Atomic<Inner>,
+    len: Counter,
+}
+
+/// Destroys the root, together with the whole trie.
+///
+/// The same rules as for [`drop_recursive`] apply.
+unsafe fn drop_root<C: Config>(root: Shared<Root>) {
+    let root = root.into_owned();
+    drop_recursive::<C>(
+        root.trie
+            .load(Ordering::Relaxed, crossbeam_epoch::unprotected()),
+    );
+}
+
+/// A leaf of the trie, holding the values.
+///
+/// Usually, a leaf holds a single value. If the whole hashes of several keys collide, the leaf is a
+/// collision list pointing to a single leaf for each of the values. The single leaves are never
+/// copied ‒ a modification of the collision creates a new list, pointing to the same leaves for
+/// the values that stay. Thanks to that, the payload doesn't have to be `Clone`.
+///
+/// A single leaf may also be a placeholder for a value that is still being created (see the
+/// [`flight`] module). It takes a place in the trie like a value would, but the lookups don't see
+/// it.
+///
+/// The pointers to the single leaves inside a collision are kept untagged.
+enum Data<T, H> {
+    /// A value with the hash of its key, so the walks and splits don't need to compute it again.
+    ///
+    /// With the usual pointer-sized payloads, this takes no more space than the other variants.
+    Single(T, H),
+    Pending(Pending<H>),
+    Collision(Box<[*const Data<T, H>]>),
+}
+
+impl<T, H> Data<T, H> {
+    /// The number of single leaves (including the placeholders).
+    fn len(&self) -> usize {
+        match self {
+            Data::Single(..) | Data::Pending(_) => 1,
+            Data::Collision(leaves) => leaves.len(),
+        }
+    }
+
+    /// The value in the single leaf with the given index, if it is a value.
+    fn get(&self, idx: usize) -> Option<&T> {
+        match self {
+            Data::Single(payload, _) if idx == 0 => Some(payload),
+            Data::Single(..) | Data::Pending(_) => None,
+            Data::Collision(leaves) => leaves.get(idx).and_then(|leaf| match unsafe { &**leaf } {
+                Data::Single(payload, _) => Some(payload),
+                Data::Pending(_) => None,
+                Data::Collision(_) => unreachable!("Collision inside a collision"),
+            }),
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &T> {
+        (0..self.len()).filter_map(move |idx| self.get(idx))
+    }
+
+    /// The hash of the keys in the node.
+    ///
+    /// All the leaves of a collision share the same hash, so this is the one of the first.
+    fn hash(&self) -> H
+    where
+        H: Copy,
+    {
+        match self {
+            Data::Single(_, hash) => *hash,
+            Data::Pending(pending) => pending.hash,
+            Data::Collision(leaves) => unsafe { &*leaves[0] }.hash(),
+        }
+    }
+}
+
+/// The number of levels (from the top) on which the two hashes go the same way.
+fn shared_levels<C: Config>(a: C::Hash, b: C::Hash) -> usize {
+    (0..C::MAX_LEVELS)
+        .take_while(|level| {
+            let shift = level * C::LEVEL_BITS;
+            a.bits(shift, C::LEVEL_MASK) == b.bits(shift, C::LEVEL_MASK)
+        })
+        .count()
+}
+
+/// Orders the hashes by their paths through the trie.
+///
+/// This is the order in which the trie is walked by the iterators.
+fn path_order<C: Config>(a: C::Hash, b: C::Hash) -> CmpOrdering {
+    let level = shared_levels::<C>(a, b);
+    if level == C::MAX_LEVELS {
+        CmpOrdering::Equal
+    } else {
+        let shift = level * C::LEVEL_BITS;
+        a.bits(shift, C::LEVEL_MASK)
+            .cmp(&b.bits(shift, C::LEVEL_MASK))
+    }
+}
+
+/// The single leaves of a data node, found by [`leaves`].
+type Leaves<'a> = SmallVec<[Shared<'a, Inner>; 2]>;
+
+/// Turns a pointer from inside a collision back into a pointer to the leaf.
+fn leaf_ptr<'a, T, H>(leaf: *const Data<T, H>) -> Shared<'a, Inner> {
+    Shared::from(leaf as usize as *const Inner).with_tag(NodeFlags::DATA.bits())
+}
+
+/// Lists the single leaves of a data node ‒ either the node itself or the leaves of the collision.
+unsafe fn leaves<'a, C: Config>(node: Shared<'a, Inner>) -> Leaves<'a> {
+    match load_data::<C>(node) {
+        Data::Single(..) | Data::Pending(_) => iter::once(node).collect(),
+        Data::Collision(leaves) => leaves.iter().map(|leaf| leaf_ptr(*leaf)).collect(),
+    }
+}
+
+/// Finds the single leaf holding a value with the key.
+fn key_position<C, Q>(leaves: &Leaves, key: &Q) -> Option<usize>
+where
+    C: Config,
+    Q: ?Sized + Eq + Hash,
+    C::Key: Borrow<Q>,
+{
+    leaves
+        .iter()
+        .position(|l| unsafe { leaf_payload::<C>(*l) }.map_or(false, |p| C::eq(p.borrow(), key)))
+}
+
+/// Creates a data node for the given leaves.
+///
+/// That is a new collision, the lone leaf itself or null if there's none.
+fn join_leaves<'p, C: Config>(leaves: Leaves<'p>, pin: &'p Guard) -> Shared<'p, Inner> {
+    match leaves.len() {
+        0 => Shared::null(),
+        1 => leaves[0],
+        _ => {
+            let leaves = leaves
+                .iter()
+                .map(|leaf| leaf.as_raw() as usize as *const Data<C::Payload, C::Hash>)
+                .collect();
+            share_data::<C>(Data::Collision(leaves), pin)
+        }
+    }
+}
+
+/// A freshly created single leaf, not yet published into the trie.
+struct NewLeaf<C: Config>(*mut Data<C::Payload, C::Hash>);
+
+impl<C: Config> NewLeaf<C> {
+    fn new(payload: C::Payload, hash: C::Hash) -> Self {
+        NewLeaf(Box::into_raw(Box::new(Data::Single(payload, hash))))
+    }
+
+    fn placeholder(pending: Pending<C::Hash>) -> Self {
+        NewLeaf(Box::into_raw(Box::new(Data::Pending(pending))))
+    }
+
+    fn payload(&self) -> &C::Payload {
+        unsafe { load_leaf::<C>(self.shared()) }
+    }
+
+    fn shared<'a>(&self) -> Shared<'a, Inner> {
+        leaf_ptr(self.0)
+    }
+}
+
+impl<C: Config> Drop for NewLeaf<C> {
+    fn drop(&mut self) {
+        unsafe { drop(Box::from_raw(self.0)) };
+    }
+}
 
 enum TraverseState<C: Config, F> {
     Empty, // Invalid temporary state.
     Created(C::Payload),
-    Future { key: C::Key, constructor: F },
+    Future {
+        key: C::Key,
+        constructor: F,
+    },
+    /// Already placed in a leaf by a previous attempt that failed to put it into the trie.
+    Leaf(NewLeaf<C>),
+    /// Placing a placeholder for the key, instead of a value (see the [`flight`] module).
+    ///
+    /// The key is kept here for the caller to take back once done. The leaf is gone once
+    /// published.
+    Placeholder {
+        key: C::Key,
+        hash: C::Hash,
+        leaf: Option<NewLeaf<C>>,
+    },
 }
 
 impl<C: Config, F: FnOnce(C::Key) -> C::Payload> TraverseState<C, F> {
@@ -280,33 +806,186 @@ impl<C: Config, F: FnOnce(C::Key) -> C::Payload> TraverseState<C, F> {
         match self {
             TraverseState::Empty => unreachable!("Not supposed to live in the empty state"),
             TraverseState::Created(payload) => payload.borrow(),
-            TraverseState::Future { key, .. } => key,
+            TraverseState::Future { key, .. } | TraverseState::Placeholder { key, .. } => key,
+            TraverseState::Leaf(leaf) => leaf.payload().borrow(),
         }
     }
-    fn payload(&mut self) -> C::Payload {
-        let (new_val, result) = match mem::replace(self, TraverseState::Empty) {
-            TraverseState::Empty => unreachable!("Not supposed to live in the empty state"),
-            TraverseState::Created(payload) => (TraverseState::Created(payload.clone()), payload),
-            TraverseState::Future { key, constructor } => {
-                let payload = constructor(key);
-                let created = TraverseState::Created(payload.clone());
-                (created, payload)
+
+    /// Is the single leaf the one with our key?
+    ///
+    /// A placeholder has no key, only the hash. Therefore it counts only when placing another
+    /// placeholder with the same hash. A collision on the whole hash may then make someone wait
+    /// for an unrelated key, but that is rare and harmless.
+    unsafe fn matches(&self, leaf: Shared<Inner>) -> bool {
+        match (load_data::<C>(leaf), self) {
+            (Data::Single(payload, _), _) => C::eq(payload.borrow(), self.key()),
+            (Data::Pending(pending), TraverseState::Placeholder { hash, .. }) => {
+                pending.hash == *hash
             }
+            (Data::Pending(_), _) => false,
+            (Data::Collision(_), _) => unreachable!("Expected a single leaf, found a collision"),
+        }
+    }
+
+    /// Provides the single leaf with the new payload, creating it on the first call.
+    ///
+    /// The leaf stays owned by the state until [`published`][TraverseState::published] is called,
+    /// so it can be reused if putting it in place fails. If it never gets published, it is dropped
+    /// together with the state.
+    fn leaf<'p>(&mut self, hash: C::Hash, _pin: &'p Guard) -> Shared<'p, Inner> {
+        if let TraverseState::Placeholder { leaf, .. } = self {
+            return leaf
+                .as_ref()
+                .expect("Placeholder already published")
+                .shared();
+        }
+        let leaf = match mem::replace(self, TraverseState::Empty) {
+            TraverseState::Empty => unreachable!("Not supposed to live in the empty state"),
+            TraverseState::Created(payload) => NewLeaf::new(payload, hash),
+            TraverseState::Future { key, constructor } => NewLeaf::new(constructor(key), hash),
+            TraverseState::Leaf(leaf) => leaf,
+            TraverseState::Placeholder { .. } => unreachable!("Handled above"),
         };
-        *self = new_val;
-        result
+        let shared = leaf.shared();
+        *self = TraverseState::Leaf(leaf);
+        shared
     }
-    fn data_owned(&mut self) -> Owned<Inner> {
-        let mut data = Data::<C>::new();
-        data.push(self.payload());
-        owned_data::<C>(data)
+
+    /// Takes the key back from a placeholder state.
+    fn into_key(self) -> C::Key {
+        match self {
+            TraverseState::Placeholder { key, .. } => key,
+            _ => unreachable!("Only the placeholder state keeps the key"),
+        }
+    }
+
+    /// Marks the leaf as put into the trie, which now owns it.
+    fn published(&mut self) {
+        if let TraverseState::Placeholder { leaf, .. } = self {
+            mem::forget(leaf.take().expect("Placeholder already published"));
+            return;
+        }
+        match mem::replace(self, TraverseState::Empty) {
+            TraverseState::Leaf(leaf) => mem::forget(leaf),
+            _ => unreachable!("Published a leaf that was never created"),
+        }
     }
 }
 
-#[derive(Copy, Clone, Eq, PartialEq)]
-enum TraverseMode {
+/// What [`traverse`][Raw::traverse] should do once it reaches the place for the key.
+enum TraverseMode<P> {
+    /// Put the new value in, no matter if there's a previous one.
     Overwrite,
+    /// Put the new value in only if there's no value with the same key.
     IfMissing,
+    /// Replace a value with the same key, but only if there's one and the predicate accepts it.
+    IfPresent(P),
+    /// Put the new value in place of the placeholder waiting for it (recognized by its flight),
+    /// unless there's already a value with the same key.
+    ///
+    /// If the placeholder is no longer there, this is the same as `IfMissing`.
+    Settle(*const Flight),
+}
+
+impl<P> TraverseMode<P> {
+    /// Is the leaf the placeholder this traversal replaces?
+    unsafe fn settles<C: Config>(&self, leaf: Shared<Inner>) -> bool {
+        match self {
+            TraverseMode::Settle(flight) => flight::holds_flight::<C>(leaf, *flight),
+            _ => false,
+        }
+    }
+}
+
+/// What [`traverse`][Raw::traverse] has found at the place for the key and what it did there.
+enum Outcome<'r, T> {
+    /// There's no value with the key and nothing was inserted.
+    Missing,
+    /// There's a value with the key and it was left in place.
+    Kept(&'r T),
+    /// There was a value with the key and it got replaced by the new one (previous, new).
+    Replaced(&'r T, &'r T),
+    /// There was no value with the key, the new one got inserted.
+    Inserted(&'r T),
+    /// A placeholder for the key got inserted.
+    Placed,
+    /// Placing a placeholder, but there's already another one for the key.
+    InFlight(&'r Arc<Flight>),
+}
+
+/// Turns the leaf just published by [`traverse`][Raw::traverse] into its outcome.
+///
+/// A new value (not replacing a previous one) is counted into the length, a placeholder isn't.
+unsafe fn written<'r, C: Config>(
+    leaf: Shared<'r, Inner>,
+    found: Option<&'r C::Payload>,
+    len: &Counter,
+) -> Outcome<'r, C::Payload> {
+    match (leaf_payload::<C>(leaf), found) {
+        (None, _) => Outcome::Placed,
+        (Some(new), Some(previous)) => Outcome::Replaced(previous, new),
+        (Some(new), None) => {
+            len.add(1);
+            Outcome::Inserted(new)
+        }
+    }
+}
+
+/// The value to be replaced (if any) and the leaves to be removed, as found by [`resolve`].
+type Resolved<'r, 'l, T> = (Option<&'r T>, Leaves<'l>);
+
+/// Decides what [`traverse`][Raw::traverse] should do with the leaves at the place of the key.
+///
+/// Either returns the final outcome right away, or takes the leaves to be replaced out of the
+/// list and returns them, together with the value being replaced (if any). The new leaf is to be
+/// added to the rest.
+unsafe fn resolve<'r, 'l, C, F, P>(
+    leaves: &mut Leaves<'l>,
+    state: &TraverseState<C, F>,
+    mode: &mut TraverseMode<P>,
+) -> Result<Resolved<'r, 'l, C::Payload>, Outcome<'r, C::Payload>>
+where
+    'l: 'r,
+    C: Config,
+    F: FnOnce(C::Key) -> C::Payload,
+    P: FnMut(&C::Payload) -> bool,
+{
+    let pos = leaves.iter().position(|l| state.matches(*l));
+    let leaf: Option<Shared<'r, Inner>> = pos.map(|pos| leaves[pos]);
+    let found = match leaf.map(|leaf| load_data::<C>(leaf)) {
+        None => None,
+        Some(Data::Single(payload, _)) => Some(payload),
+        Some(Data::Pending(pending)) if !pending.flight.landed() => {
+            return Err(Outcome::InFlight(&pending.flight))
+        }
+        // A leftover of a landed flight is replaced as if it wasn't there.
+        Some(Data::Pending(_)) => None,
+        Some(Data::Collision(_)) => unreachable!("Collision inside a collision"),
+    };
+
+    let write = match (&mut *mode, found) {
+        (TraverseMode::Overwrite, _) => true,
+        (TraverseMode::IfMissing, found) | (TraverseMode::Settle(_), found) => found.is_none(),
+        (TraverseMode::IfPresent(_), None) => return Err(Outcome::Missing),
+        (TraverseMode::IfPresent(predicate), Some(found)) => predicate(found),
+    };
+    if !write {
+        return Err(Outcome::Kept(
+            found.expect("Refused to write a missing value"),
+        ));
+    }
+
+    let mut removed = Leaves::new();
+    if let Some(pos) = pos {
+        removed.push(leaves.remove(pos));
+    }
+    if let Some(pos) = leaves.iter().position(|l| mode.settles::<C>(*l)) {
+        removed.push(leaves.remove(pos));
+    }
+    while let Some(pos) = leaves.iter().position(|l| flight::stale::<C>(*l)) {
+        removed.push(leaves.remove(pos));
+    }
+    Ok((found, removed))
 }
 
 /// How well pruning went.
@@ -345,7 +1024,22 @@ enum PruneResult {
 /// source code (they probably don't belong into API documentation).
 pub struct Raw<C: Config, S> {
     hash_builder: S,
-    root: Atomic<Inner>,
+    root: Atomic<Root>,
+    /// The trie was taken out of another map (see [`clear_and_take`][Raw::clear_and_take]).
+    ///
+    /// Threads operating on the original map might still be finishing their work inside the trie,
+    /// so it can't be destroyed right away in our destructor.
+    detached: bool,
+    /// Handles to the dedicated collector, lent to the threads for pinning it.
+    ///
+    /// They need to go before the collector, see the [`handles`] module.
+    handles: Handles,
+    /// A dedicated collector for the garbage of this map, if any.
+    ///
+    /// If not present, the global default collector of [`crossbeam_epoch`] is used.
+    collector: Option<Collector>,
+    /// The snapshots were turned on by [`with_snapshots`][Raw::with_snapshots].
+    snapshots: bool,
     _data: PhantomData<C::Payload>,
 }
 
@@ -355,33 +1049,113 @@ where
     S: BuildHasher,
 {
     /// Constructs an empty instance from the given hasher.
-    pub fn with_hasher(hash_builder: S) -> Self {
+    ///
+    /// The removed values are handed to the global default collector of [`crossbeam_epoch`] and
+    /// may be destroyed long after the map itself. Therefore, the payload must be `'static`.
+    pub fn with_hasher(hash_builder: S) -> Self
+    where
+        C::Payload: 'static,
+    {
+        Self::new_with(hash_builder, None)
+    }
+
+    /// Constructs an empty instance from the given hasher, using a dedicated epoch collector.
+    ///
+    /// The removed values are handed to the collector instead of the global default one. When
+    /// the map is dropped, its reference to the collector goes away too. If it is the last one
+    /// (the collector isn't shared with anything else and there are no outstanding
+    /// [`Guard`]s from it), the collector runs all the pending destructors before the drop of the
+    /// map returns.
+    ///
+    /// The price is that pinning such collector is slower than pinning the default one. The map
+    /// keeps some registered handles for its operations, but each [`pin`][Raw::pin] needs to
+    /// register a new one.
+    ///
+    /// As the collector may be shared with something else, the payload must be `'static`.
+    pub fn with_hasher_and_collector(hash_builder: S, collector: Collector) -> Self
+    where
+        C::Payload: 'static,
+    {
+        Self::new_with(hash_builder, Some(collector))
+    }
+
+    /// Constructs an empty instance from the given hasher, with its own private collector.
+    ///
+    /// The collector is never handed out, so all the removed values are destroyed before the map
+    /// (and any map created from it by [`clear_and_take`][Raw::clear_and_take] or
+    /// [`empty_like`][Raw::empty_like]) goes away. Thanks to that, the payload doesn't have to be
+    /// `'static` ‒ it can borrow data that outlives the map.
+    ///
+    /// If the payload is not `'static`, the guards are available only through
+    /// [`with_pin`][Raw::with_pin] (not [`pin`][Raw::pin]).
+    pub fn with_hasher_scoped(hash_builder: S) -> Self {
+        Self::new_with(hash_builder, Some(Collector::new()))
+    }
+
+    /// Creates a new empty map with the same hasher and collector as this one.
+    pub fn empty_like(&self) -> Self
+    where
+        S: Clone,
+    {
+        let mut map = Self::new_with(self.hash_builder.clone(), self.collector.clone());
+        map.snapshots = self.snapshots;
+        map
+    }
+
+    /// Returns the hasher used by this map.
+    pub fn hasher(&self) -> &S {
+        &self.hash_builder
+    }
+
+    fn new_with(hash_builder: S, collector: Option<Collector>) -> Self {
         // Note: on any sane system, these assertions should actually never ever trigger no matter
         // what the user of the crate does. This is *internal* sanity check. If you ever find a
         // case where it *does* fail, open a bug report.
         assert!(
-            mem::align_of::<Data<C>>().trailing_zeros() >= NodeFlags::all().bits().count_ones(),
+            mem::align_of::<Data<C::Payload, C::Hash>>().trailing_zeros()
+                >= NodeFlags::all().bits().count_ones(),
             "BUG: Alignment of Data<Payload> is not large enough to store the internal flags",
         );
         assert!(
             mem::align_of::<Inner>().trailing_zeros() >= NodeFlags::all().bits().count_ones(),
             "BUG: Alignment of Inner not large enough to store internal flags",
         );
+        assert!(
+            mem::align_of::<Packed>().trailing_zeros() >= NodeFlags::all().bits().count_ones(),
+            "BUG: Alignment of Packed not large enough to store internal flags",
+        );
+        // This one, on the other hand, is the user's fault.
+        assert!(
+            C::LEVEL_BITS >= MIN_LEVEL_BITS && C::LEVEL_BITS <= MAX_LEVEL_BITS,
+            "Config::LEVEL_BITS must be between {} and {}",
+            MIN_LEVEL_BITS,
+            MAX_LEVEL_BITS,
+        );
+        assert!(
+            C::MAX_LEVELS <= MAX_LEVELS,
+            "Config::Hash is too large, at most 128 bits are supported",
+        );
         Self {
             hash_builder,
-            root: Atomic::null(),
+            root: Atomic::new(Root::default()),
+            detached: false,
+            handles: if collector.is_some() {
+                Handles::new()
+            } else {
+                Handles::default()
+            },
+            collector,
+            snapshots: false,
             _data: PhantomData,
         }
     }
 
     /// Computes a hash (using the stored hasher) of a key.
-    fn hash<Q>(&self, key: &Q) -> u64
+    fn hash<Q>(&self, key: &Q) -> C::Hash
     where
         Q: ?Sized + Hash,
     {
-        let mut hasher = self.hash_builder.build_hasher();
-        key.hash(&mut hasher);
-        hasher.finish()
+        C::Hash::compute(&self.hash_builder, |hasher| C::hash(key, hasher))
     }
 
     /// Inserts a new value, replacing and returning any previously held value.
@@ -394,14 +1168,70 @@ where
         's: 'r,
         'p: 'r,
     {
-        self.traverse(
+        let outcome = self.traverse(
             // Any way to do it without the type parameters here? Older rustc doesn't like them.
-            TraverseState::<C, fn(C::Key) -> C::Payload>::Created(payload),
-            TraverseMode::Overwrite,
+            &mut TraverseState::<C, fn(C::Key) -> C::Payload>::Created(payload),
+            TraverseMode::<fn(&C::Payload) -> bool>::Overwrite,
+            pin,
+        );
+        match outcome {
+            Outcome::Replaced(previous, _) => Some(previous),
+            Outcome::Inserted(_) => None,
+            Outcome::Missing | Outcome::Kept(_) | Outcome::Placed | Outcome::InFlight(_) => {
+                unreachable!("Overwrite always writes")
+            }
+        }
+    }
+
+    /// Replaces a value, but only if there's already one with the same key.
+    ///
+    /// Returns the replaced value. If there's no value with the same key, nothing is inserted and
+    /// `None` is returned.
+    pub fn replace<'s, 'p, 'r>(
+        &'s self,
+        payload: C::Payload,
+        pin: &'p Guard,
+    ) -> Option<&'r C::Payload>
+    where
+        's: 'r,
+        'p: 'r,
+    {
+        self.replace_if(payload, |_| true, pin).ok()
+    }
+
+    /// Replaces a value with the same key, but only if the predicate accepts the current one.
+    ///
+    /// The replacement is atomic with the check ‒ if the value changes between the predicate is
+    /// consulted and the new value is put in place, the predicate is asked again about the new
+    /// value. Therefore, the predicate may be called multiple times.
+    ///
+    /// On success, the replaced value is returned. If there's no value with the key or if the
+    /// predicate rejects it, the value currently present (if any) is returned as an error and the
+    /// new payload is thrown away.
+    pub fn replace_if<'s, 'p, 'r, P>(
+        &'s self,
+        payload: C::Payload,
+        predicate: P,
+        pin: &'p Guard,
+    ) -> Result<&'r C::Payload, Option<&'r C::Payload>>
+    where
+        's: 'r,
+        'p: 'r,
+        P: FnMut(&C::Payload) -> bool,
+    {
+        let outcome = self.traverse(
+            &mut TraverseState::<C, fn(C::Key) -> C::Payload>::Created(payload),
+            TraverseMode::IfPresent(predicate),
             pin,
-        )
-        // TODO: Should we sanity-check this is Existing because it returns the previous value?
-        .map(ExistingOrNew::into_inner)
+        );
+        match outcome {
+            Outcome::Replaced(previous, _) => Ok(previous),
+            Outcome::Kept(current) => Err(Some(current)),
+            Outcome::Missing => Err(None),
+            Outcome::Inserted(_) | Outcome::Placed | Outcome::InFlight(_) => {
+                unreachable!("IfPresent never inserts")
+            }
+        }
     }
 
     /// Prunes the given node.
@@ -420,32 +1250,21 @@ where
             !nf(child).contains(NodeFlags::DATA),
             "Child passed to prune must not be data"
         );
-        let inner = child.as_ref().expect("Null child node passed to prune");
+        assert!(!child.is_null(), "Null child node passed to prune");
         let mut allow_contract = true;
         let mut child_cnt = 0;
         let mut last_leaf = None;
-        let mut new_child = Inner::default();
 
-        // 1. Mark all the cells in this one as condemned.
+        // 1. Mark all the cells in this one as condemned and construct a copy of the child
+        //    *without* the tags on the way.
         // 2. Look how many non-null branches are leading from there.
-        // 3. Construct a copy of the child *without* the tags on the way.
-        for (new, grandchild) in new_child.0.iter_mut().zip(&inner.0) {
-            // Acquire ‒ Besides potentially looking at the child, we'll need to republish the
-            // child in our swap of the pointer (this one and also the one below, in the CAS). To
-            // do that we'll have to have acquired it first.
-            //
-            // Note that we don't need SeqCst here nor in the CaS below. We don't care about the
-            // order ‒ the tagging is just making sure this particular slot never ever changes the
-            // pointer. The CaS changes the trie in content-equivalent way, so observing either the
-            // old or the new way is fine.
-            let gc = grandchild.fetch_or(NodeFlags::CONDEMNED.bits(), Ordering::AcqRel, pin);
-            // The flags we insert into the new one should not contain condemned flag even if it
-            // was already present here.
-            let flags = nf(gc) & !NodeFlags::CONDEMNED;
-            let gc = gc.with_tag(flags.bits());
+        let new_child = Self::condemn(pin, child);
+        for gc in new_child.0.iter() {
+            // Relaxed: the copy is still ours.
+            let gc = gc.load(Ordering::Relaxed, pin);
             if gc.is_null() {
                 // Do nothing, just skip
-            } else if flags.contains(NodeFlags::DATA) {
+            } else if nf(gc).contains(NodeFlags::DATA) {
                 last_leaf.replace(gc);
                 let gc = load_data::<C>(gc);
                 child_cnt += gc.len();
@@ -455,8 +1274,6 @@ where
                 allow_contract = false;
                 child_cnt += 1;
             }
-
-            *new = Atomic::from(gc);
         }
 
         // Now, decide what we want to put into the parent.
@@ -471,7 +1288,7 @@ where
             // Many nodes (maybe somewhere below) ‒ someone must have inserted in between. But
             // we've already condemned this node, so create a new one and do the replacement.
             _ => {
-                let new = Owned::new(new_child).into_shared(pin);
+                let new = publish_inner::<C>(new_child, pin);
                 // Note: we don't store Owned, because we may link it in. If we panicked before
                 // disarming it, it would delete something linked in, which is bad. Instead, we
                 // prefer deleting manually after the fact.
@@ -489,66 +1306,144 @@ where
         // to destroy, because we already have it in case of success and we don't care about it on
         // failure.
         let result = parent
-            .compare_and_set(child, insert, (Ordering::Release, Ordering::Relaxed), pin)
+            .compare_and_set(child, insert, (C::RELEASE, Ordering::Relaxed), pin)
             .is_ok();
         if result {
             // We successfully unlinked the old child, so it's time to destroy it (as soon as
             // nobody is looking at it).
-            pin.defer_destroy(child);
+            defer_drop_inner::<C>(child, pin);
             prune_result
         } else {
             // We have failed to insert, so we need to clean up after ourselves.
-            drop(cleanup.map(|c| Shared::into_owned(c)));
+            if let Some(cleanup) = cleanup {
+                drop_inner::<C>(cleanup);
+            }
             PruneResult::CasFail
         }
     }
 
+    /// Condemns all the slots of the given inner node and creates a copy of it without the tags.
+    ///
+    /// Once condemned, the slots can never change, so the copy has exactly the content the node
+    /// ends up with. The caller then tries to replace the node by something made of the copy.
+    unsafe fn condemn(pin: &Guard, node: Shared<Inner>) -> NewInner {
+        let inner = load_inner::<C>(node);
+        let mut copy = NewInner::new::<C>();
+        for (idx, new) in copy.0.iter_mut().enumerate() {
+            let sub = match inner.slot(idx) {
+                Some(sub) => sub,
+                // A compressed node doesn't have this slot, so it is null in the copy.
+                None => continue,
+            };
+            // Acquire ‒ Besides potentially looking at the child, we'll need to republish the
+            // child in our swap of the pointer (this one and also the one below, in the CAS). To
+            // do that we'll have to have acquired it first.
+            //
+            // Note that we don't need SeqCst here nor in the CaS below. We don't care about the
+            // order ‒ the tagging is just making sure this particular slot never ever changes the
+            // pointer. The CaS changes the trie in content-equivalent way, so observing either the
+            // old or the new way is fine.
+            let sub = sub.fetch_or(NodeFlags::CONDEMNED.bits(), C::ACQ_REL, pin);
+            // The flags we insert into the new one should not contain condemned flag even if it
+            // was already present here.
+            let flags = nf(sub) & !NodeFlags::CONDEMNED;
+            *new = Atomic::from(sub.with_tag(flags.bits()));
+        }
+        copy
+    }
+
+    /// Replaces a compressed node by a copy with one more slot, holding the given leaf.
+    ///
+    /// The node must not have the slot yet. It is condemned first, the same way as when pruning,
+    /// so nothing changes in it while it is being copied. Returns if the copy got in place of the
+    /// node. If not, the leaf is not published and the caller has to start over.
+    unsafe fn grow(
+        pin: &Guard,
+        parent: &Atomic<Inner>,
+        child: Shared<Inner>,
+        idx: usize,
+        leaf: Shared<Inner>,
+    ) -> bool {
+        let mut new_child = Self::condemn(pin, child);
+        new_child.0[idx] = Atomic::from(leaf);
+        let new = publish_inner::<C>(new_child, pin);
+        // Same as in prune.
+        let result = parent
+            .compare_and_set(child, new, (C::RELEASE, Ordering::Relaxed), pin)
+            .is_ok();
+        if result {
+            defer_drop_inner::<C>(child, pin);
+        } else {
+            // Only the node, the leaf stays with the caller.
+            drop_inner::<C>(new);
+        }
+        result
+    }
+
     /// Inner implementation of traversing the tree, creating missing branches and doing
     /// *something* at the leaf.
-    fn traverse<'s, 'p, 'r, F>(
+    ///
+    /// The state is left in place for the caller, so it can take the key back if nothing got
+    /// published.
+    fn traverse<'s, 'p, 'r, F, P>(
         &'s self,
-        mut state: TraverseState<C, F>,
-        mode: TraverseMode,
+        state: &mut TraverseState<C, F>,
+        mode: TraverseMode<P>,
         pin: &'p Guard,
-    ) -> Option<ExistingOrNew<&'r C::Payload>>
+    ) -> Outcome<'r, C::Payload>
     where
         's: 'r,
         'p: 'r,
         F: FnOnce(C::Key) -> C::Payload,
+        P: FnMut(&C::Payload) -> bool,
     {
         let hash = self.hash(state.key());
+        self.traverse_hashed(hash, state, mode, pin)
+    }
+
+    /// The same as [`traverse`][Raw::traverse], with the hash of the key already computed.
+    fn traverse_hashed<'s, 'p, 'r, F, P>(
+        &'s self,
+        hash: C::Hash,
+        state: &mut TraverseState<C, F>,
+        mut mode: TraverseMode<P>,
+        pin: &'p Guard,
+    ) -> Outcome<'r, C::Payload>
+    where
+        's: 'r,
+        'p: 'r,
+        F: FnOnce(C::Key) -> C::Payload,
+        P: FnMut(&C::Payload) -> bool,
+    {
+        if self.copy_on_write() {
+            return self.traverse_cow(hash, state, mode, pin);
+        }
         let mut shift = 0;
-        let mut current = &self.root;
+        let mut root = self.root(pin);
+        let mut current = &root.trie;
         let mut parent = None;
         loop {
-            let node = current.load_consume(&pin);
+            let node = load_ptr::<C, _>(current, pin);
             let flags = nf(node);
 
-            let replace = |with: Owned<Inner>, delete_previous| {
-                // If we fail to set it, the `with` is dropped together with the Err case, freeing
-                // whatever was inside it.
-                let result = current.compare_and_set_weak(
-                    node,
-                    with,
-                    (Ordering::Release, Ordering::Relaxed),
-                    pin,
-                );
+            let replace = |with: Shared<'p, Inner>, retire, removed: Leaves<'p>| {
+                // If we fail to set it, we free the `with` (only the node itself, anything it
+                // points to stays in the trie; our own leaf stays in the state for the next try).
+                let result =
+                    current.compare_and_set_weak(node, with, (C::RELEASE, Ordering::Relaxed), pin);
                 match result {
-                    Ok(new) if !node.is_null() && delete_previous => {
+                    Ok(new) if !node.is_null() && retire => {
                         assert!(flags.contains(NodeFlags::DATA));
-                        let node = Shared::from(node.as_raw() as usize as *const Data<C>);
-                        unsafe { pin.defer_destroy(node) };
+                        unsafe { defer_retire::<C, _>(node, removed, pin) };
                         Some(new)
                     }
                     Ok(new) => Some(new),
                     Err(e) => {
-                        if NodeFlags::from_bits(e.new.tag())
-                            .expect("Invalid flags")
-                            .contains(NodeFlags::DATA)
-                        {
-                            unsafe { drop_data::<C>(e.new.into_shared(&pin)) };
+                        if nf(e.new).contains(NodeFlags::DATA) {
+                            unsafe { drop_unused_data::<C>(e.new) };
+                        } else {
+                            unsafe { drop_inner::<C>(e.new) };
                         }
-                        // Else → just let e drop and destroy the owned in there
                         None
                     }
                 }
@@ -562,31 +1457,38 @@ where
                 // just want to walk through and not modify it here at all, it's OK).
                 unsafe {
                     let (parent, child) = parent.expect("Condemned the root!");
-                    Self::prune(&pin, parent, child);
+                    Self::prune(pin, parent, child);
                 }
                 // Either us or someone else modified the tree on our path. In many cases we
                 // could just continue here, but some cases are complex. For now, we just restart
                 // the whole traversal and try from the start, for simplicity. This should be rare
                 // anyway, so complicating the code further probably is not worth it.
                 shift = 0;
-                current = &self.root;
+                root = self.root(pin);
+                current = &root.trie;
                 parent = None;
             } else if node.is_null() {
+                if let TraverseMode::IfPresent(_) = mode {
+                    // Nothing to replace here.
+                    return Outcome::Missing;
+                }
                 // Not found, create it.
-                if let Some(new) = replace(state.data_owned(), true) {
-                    if mode == TraverseMode::Overwrite {
-                        return None;
-                    } else {
-                        let new = unsafe { load_data::<C>(new) };
-                        return Some(ExistingOrNew::New(&new[0]));
-                    }
+                let leaf = state.leaf(hash, pin);
+                if replace(leaf, true, Leaves::new()).is_some() {
+                    state.published();
+                    return unsafe { written::<C>(leaf, None, &root.len) };
                 }
             // else -> retry
             } else if flags.contains(NodeFlags::DATA) {
-                let data = unsafe { load_data::<C>(node) };
-                assert!(!data.is_empty(), "Empty data nodes must not be kept around");
-                if data[0].borrow() != state.key() && shift < mem::size_of_val(&hash) * 8 {
-                    assert!(data.len() == 1, "Collision node not deep enough");
+                let mut leaves = unsafe { leaves::<C>(node) };
+                assert!(
+                    !leaves.is_empty(),
+                    "Empty data nodes must not be kept around"
+                );
+                let first = leaves[0];
+                let ours = unsafe { state.matches(first) || mode.settles::<C>(first) };
+                if !ours && shift < <C::Hash as HashValue>::BITS {
+                    assert!(leaves.len() == 1, "Collision node not deep enough");
                     // There's one data node at this pointer, but we want to place a different one
                     // here too. So we create a new level, push the old one down. Note that we
                     // check both that we are adding something else & that we still have some more
@@ -594,55 +1496,62 @@ where
 
                     // We need to add another level. Note: there *still* might be a collision.
                     // Therefore, we just add the level and try again.
-                    let other_hash = self.hash(data[0].borrow());
-                    let other_bits = (other_hash >> shift) & LEVEL_MASK;
-                    let mut inner = Inner::default();
-                    inner.0[other_bits as usize] = Atomic::from(node);
-                    let split = Owned::new(inner);
+                    let other_hash = unsafe { load_data::<C>(first) }.hash();
+                    let other_bits = other_hash.bits(shift, C::LEVEL_MASK);
+                    let mut inner = NewInner::new::<C>();
+                    inner.0[other_bits] = Atomic::from(node);
+                    let split = publish_inner::<C>(inner, pin);
                     // No matter if it succeeds or fails, we try again. We'll either find the newly
                     // inserted value here and continue with another level down, or it gets
                     // destroyed and we try splitting again.
-                    replace(split, false);
+                    replace(split, false, Leaves::new());
                 } else {
                     // All the other cases:
                     // * It has the same key
                     // * There's already a collision on this level (because we've already run out of
                     //   bits previously).
                     // * We've run out of the hash bits so there's nothing to split by any more.
-                    let mut result = data
-                        .iter()
-                        .find(|l| (*l).borrow().borrow() == state.key())
-                        .map(ExistingOrNew::Existing);
-
-                    if result.is_none() || mode == TraverseMode::Overwrite {
-                        let mut new = Data::<C>::with_capacity(data.len() + 1);
-                        new.extend(
-                            data.iter()
-                                .filter(|l| (*l).borrow() != state.key())
-                                .cloned(),
-                        );
-                        new.push(state.payload());
-                        new.shrink_to_fit();
-                        let new = owned_data::<C>(new);
-                        if let Some(new) = replace(new, true) {
-                            if result.is_none() && mode == TraverseMode::IfMissing {
-                                let new = unsafe { load_data::<C>(new) };
-                                result = Some(ExistingOrNew::New(new.last().unwrap()));
-                            }
-                        } else {
-                            continue;
-                        }
-                    }
+                    let (found, removed) = match unsafe { resolve(&mut leaves, &*state, &mut mode) }
+                    {
+                        Ok(resolved) => resolved,
+                        Err(outcome) => return outcome,
+                    };
 
-                    return result;
+                    // The other values stay in their leaves, only the list of them is new.
+                    let leaf = state.leaf(hash, pin);
+                    leaves.push(leaf);
+                    let new = join_leaves::<C>(leaves, pin);
+                    if replace(new, true, removed).is_some() {
+                        state.published();
+                        return unsafe { written::<C>(leaf, found, &root.len) };
+                    }
+                    // else -> retry
                 }
             } else {
                 // An inner node, go one level deeper.
-                let inner = unsafe { node.as_ref().expect("We just checked this is not NULL") };
-                let bits = (hash >> shift) & LEVEL_MASK;
-                shift += LEVEL_BITS;
-                parent = Some((current, node));
-                current = &inner.0[bits as usize];
+                let inner = unsafe { load_inner::<C>(node) };
+                let bits = hash.bits(shift, C::LEVEL_MASK);
+                if let Some(slot) = inner.slot(bits) {
+                    shift += C::LEVEL_BITS;
+                    parent = Some((current, node));
+                    current = slot;
+                    continue;
+                }
+                // A compressed node without the slot, which is the same as a null pointer in it.
+                if let TraverseMode::IfPresent(_) = mode {
+                    return Outcome::Missing;
+                }
+                let leaf = state.leaf(hash, pin);
+                if unsafe { Self::grow(pin, current, node, bits, leaf) } {
+                    state.published();
+                    return unsafe { written::<C>(leaf, None, &root.len) };
+                }
+                // The node got replaced by someone else in the meantime (or condemned, in which
+                // case it will get pruned on the next walk). Start over, as above.
+                shift = 0;
+                root = self.root(pin);
+                current = &root.trie;
+                parent = None;
             }
         }
     }
@@ -655,22 +1564,39 @@ where
         Q: ?Sized + Eq + Hash,
         C::Key: Borrow<Q>,
     {
-        let mut current = &self.root;
-        let mut hash = self.hash(key);
+        let top = load_ptr::<C, _>(&self.root(pin).trie, pin);
+        self.lookup(top, key, pin)
+    }
+
+    /// Looks up a value in the (sub)trie starting at the given node.
+    fn lookup<'r, Q>(
+        &self,
+        mut node: Shared<'r, Inner>,
+        key: &Q,
+        pin: &'r Guard,
+    ) -> Option<&'r C::Payload>
+    where
+        Q: ?Sized + Eq + Hash,
+        C::Key: Borrow<Q>,
+    {
+        let hash = self.hash(key);
+        let mut shift = 0;
         loop {
-            let node = current.load_consume(pin);
             let flags = nf(node);
             if node.is_null() {
                 return None;
             } else if flags.contains(NodeFlags::DATA) {
                 return unsafe { load_data::<C>(node) }
                     .iter()
-                    .find(|l| (*l).borrow().borrow() == key);
+                    .find(|l| C::eq((*l).borrow(), key));
             } else {
-                let inner = unsafe { node.as_ref().expect("We just checked this is not NULL") };
-                let bits = hash & LEVEL_MASK;
-                hash >>= LEVEL_BITS;
-                current = &inner.0[bits as usize];
+                let inner = unsafe { load_inner::<C>(node) };
+                let bits = hash.bits(shift, C::LEVEL_MASK);
+                shift += C::LEVEL_BITS;
+                node = match inner.slot(bits) {
+                    Some(slot) => load_ptr::<C, _>(slot, pin),
+                    None => return None,
+                };
             }
         }
     }
@@ -689,12 +1615,92 @@ where
         'p: 'r,
         F: FnOnce(C::Key) -> C::Payload,
     {
-        let state = TraverseState::Future {
+        let mut state = TraverseState::Future {
             key,
             constructor: create,
         };
-        self.traverse(state, TraverseMode::IfMissing, pin)
-            .expect("Should have created one for me")
+        let outcome = self.traverse(
+            &mut state,
+            TraverseMode::<fn(&C::Payload) -> bool>::IfMissing,
+            pin,
+        );
+        match outcome {
+            Outcome::Kept(existing) => ExistingOrNew::Existing(existing),
+            Outcome::Inserted(new) => ExistingOrNew::New(new),
+            Outcome::Missing | Outcome::Replaced(..) | Outcome::Placed | Outcome::InFlight(_) => {
+                unreachable!("Should have created one for me")
+            }
+        }
+    }
+
+    /// Atomically computes a new value from the current one.
+    ///
+    /// The closure gets the value currently stored under the key (if any) and decides what should
+    /// be stored there instead ‒ returning `None` removes the value (or leaves the slot empty).
+    /// The result is put in place only if the value is still the one the closure has seen. If it
+    /// was changed by another thread in the meantime, the closure is called again with the fresh
+    /// value. Therefore, the closure may be called multiple times and it should be free of side
+    /// effects.
+    ///
+    /// Returns the newly stored value, if any.
+    ///
+    /// # Panics
+    ///
+    /// If the closure returns a payload with a different key.
+    pub fn compute<'r, 's, 'p, Q, F>(
+        &'s self,
+        key: &Q,
+        mut f: F,
+        pin: &'p Guard,
+    ) -> Option<&'r C::Payload>
+    where
+        's: 'r,
+        'p: 'r,
+        Q: ?Sized + Eq + Hash,
+        C::Key: Borrow<Q>,
+        F: FnMut(Option<&C::Payload>) -> Option<C::Payload>,
+    {
+        // The payloads can't be freed while we hold the pin, so the current one can be compared
+        // (by Config::same) with whatever is in the trie after the closure has run.
+        loop {
+            let current = self.get(key, pin);
+            let new = f(current);
+            if let Some(new) = new.as_ref() {
+                assert!(
+                    C::eq((*new).borrow(), key),
+                    "Computed value has a different key"
+                );
+            }
+            match (current, new) {
+                (None, None) => return None,
+                (None, Some(new)) => {
+                    let outcome = self.traverse(
+                        &mut TraverseState::<C, fn(C::Key) -> C::Payload>::Created(new),
+                        TraverseMode::<fn(&C::Payload) -> bool>::IfMissing,
+                        pin,
+                    );
+                    if let Outcome::Inserted(new) = outcome {
+                        return Some(new);
+                    }
+                }
+                (Some(current), Some(new)) => {
+                    let outcome = self.traverse(
+                        &mut TraverseState::<C, fn(C::Key) -> C::Payload>::Created(new),
+                        TraverseMode::IfPresent(|p: &C::Payload| C::same(p, current)),
+                        pin,
+                    );
+                    if let Outcome::Replaced(_, new) = outcome {
+                        return Some(new);
+                    }
+                }
+                (Some(current), None) => {
+                    if self.remove_if(key, |p| C::same(p, current), pin).is_some() {
+                        return None;
+                    }
+                }
+            }
+            // Someone changed the value under our hands, try again.
+        }
     }
 
     /// Removes a value identified by the key from the trie, returning it if it was found.
@@ -705,35 +1711,100 @@ where
         Q: ?Sized + Eq + Hash,
         C::Key: Borrow<Q>,
     {
-        let mut current = &self.root;
-        let hash = self.hash(key);
+        self.remove_if(key, |_| true, pin)
+    }
+
+    /// Removes a value identified by the key, but only if the predicate accepts it.
+    ///
+    /// The check and the removal happen atomically ‒ if the value is replaced by another one in
+    /// the meantime, the predicate is asked again about the new value. Therefore, the predicate
+    /// may be called multiple times.
+    ///
+    /// Returns the removed value, if any.
+    pub fn remove_if<'r, 's, 'p, Q, P>(
+        &'s self,
+        key: &Q,
+        mut predicate: P,
+        pin: &'p Guard,
+    ) -> Option<&'r C::Payload>
+    where
+        's: 'r,
+        'p: 'r,
+        Q: ?Sized + Eq + Hash,
+        C::Key: Borrow<Q>,
+        P: FnMut(&C::Payload) -> bool,
+    {
+        let pick = |leaves: &Leaves| {
+            let pos = key_position::<C, _>(leaves, key)?;
+            // Either not here at all, or we are not allowed to remove it.
+            if predicate(unsafe { load_leaf::<C>(leaves[pos]) }) {
+                Some(pos)
+            } else {
+                None
+            }
+        };
+        let removed = self.remove_leaf(self.hash(key), pick, pin)?;
+        Some(unsafe { load_leaf::<C>(removed) })
+    }
+
+    /// Removes a value with the key, the hash of which is already computed.
+    fn remove_hashed<'r, 's, 'p, Q>(
+        &'s self,
+        hash: C::Hash,
+        key: &Q,
+        pin: &'p Guard,
+    ) -> Option<&'r C::Payload>
+    where
+        's: 'r,
+        'p: 'r,
+        Q: ?Sized + Eq + Hash,
+        C::Key: Borrow<Q>,
+    {
+        let removed = self.remove_leaf(hash, |leaves| key_position::<C, _>(leaves, key), pin)?;
+        Some(unsafe { load_leaf::<C>(removed) })
+    }
+
+    /// Removes one single leaf from the place of the hash.
+    ///
+    /// The `pick` closure chooses which of the leaves found there (if any) to remove. It may be
+    /// called multiple times, if the leaves change in the meantime.
+    ///
+    /// Returns the removed leaf. It is already scheduled for destruction, but it is kept alive by
+    /// the pin.
+    fn remove_leaf<'r, 's, 'p, P>(
+        &'s self,
+        hash: C::Hash,
+        mut pick: P,
+        pin: &'p Guard,
+    ) -> Option<Shared<'r, Inner>>
+    where
+        's: 'r,
+        'p: 'r,
+        P: FnMut(&Leaves) -> Option<usize>,
+    {
+        if self.copy_on_write() {
+            return self.remove_cow(hash, pick, pin);
+        }
+        let mut root = self.root(pin);
+        let mut current = &root.trie;
         let mut shift = 0;
-        let mut levels: ArrayVec<[_; MAX_LEVELS]> = ArrayVec::new();
+        let mut levels: ArrayVec<[_; PATH_CAPACITY]> = ArrayVec::new();
         let deleted = loop {
-            let node = current.load_consume(&pin);
+            let node = load_ptr::<C, _>(current, pin);
             let flags = nf(node);
-            let replace = |with: Shared<_>| {
-                let result = current.compare_and_set_weak(
-                    node,
-                    with,
-                    (Ordering::Release, Ordering::Relaxed),
-                    &pin,
-                );
+            let replace = |with: Shared<_>, removed| {
+                let result =
+                    current.compare_and_set_weak(node, with, (C::RELEASE, Ordering::Relaxed), pin);
                 match result {
                     Ok(_) => {
                         assert!(flags.contains(NodeFlags::DATA));
-                        unsafe {
-                            let node = Shared::from(node.as_raw() as usize as *const Data<C>);
-                            pin.defer_destroy(node);
-                        }
+                        unsafe { defer_retire::<C, _>(node, Some(removed), pin) };
                         true
                     }
-                    Err(ref e) if !e.new.is_null() => {
-                        assert!(nf(e.new).contains(NodeFlags::DATA));
-                        unsafe { drop_data::<C>(e.new) };
+                    Err(e) => {
+                        unsafe { drop_unused_data::<C>(e.new) };
                         false
                     }
-                    Err(_) => false,
                 }
             };
 
@@ -743,62 +1814,54 @@ where
             } else if flags.contains(NodeFlags::CONDEMNED) {
                 unsafe {
                     let (current, node) = levels.pop().expect("Condemned the root");
-                    Self::prune(&pin, current, node);
+                    Self::prune(pin, current, node);
                 }
                 // Retry by starting over from the top, for similar reasons to the one in
                 // insert.
                 levels.clear();
                 shift = 0;
-                current = &self.root;
+                root = self.root(pin);
+                current = &root.trie;
             } else if flags.contains(NodeFlags::DATA) {
-                let data = unsafe { load_data::<C>(node) };
+                let mut leaves = unsafe { leaves::<C>(node) };
+                let pos = match pick(&leaves) {
+                    Some(pos) => pos,
+                    None => break None,
+                };
+
                 // Try deleting the thing.
-                let mut deleted = None;
-                let new = data
-                    .iter()
-                    .filter(|l| {
-                        if (*l).borrow().borrow() == key {
-                            deleted = Some(*l);
-                            false
-                        } else {
-                            true
-                        }
-                    })
-                    .cloned()
-                    .collect::<Data<C>>();
-
-                if deleted.is_some() {
-                    let new = if new.is_empty() {
-                        Shared::null()
-                    } else {
-                        owned_data::<C>(new).into_shared(&pin)
-                    };
-                    if !replace(new) {
-                        continue;
-                    }
+                let removed = leaves.remove(pos);
+                let new = join_leaves::<C>(leaves, pin);
+                if !replace(new, removed) {
+                    continue;
                 }
 
-                break deleted;
+                // A placeholder doesn't count into the length.
+                if unsafe { leaf_payload::<C>(removed) }.is_some() {
+                    root.len.add(-1);
+                }
+                break Some(removed);
             } else {
-                let inner = unsafe { node.as_ref().expect("We just checked for NULL") };
+                let inner = unsafe { load_inner::<C>(node) };
+                let bits = hash.bits(shift, C::LEVEL_MASK);
+                // A compressed node without the slot, the same as null.
+                let slot = inner.slot(bits)?;
                 levels.push((current, node));
-                let bits = (hash >> shift) & LEVEL_MASK;
-                shift += LEVEL_BITS;
-                current = &inner.0[bits as usize];
+                shift += C::LEVEL_BITS;
+                current = slot;
             }
         };
 
         // Go from the top and try to clean up.
         if deleted.is_some() {
             for (parent, child) in levels.into_iter().rev() {
-                let inner = unsafe { child.as_ref().expect("We just checked for NULL") };
+                let inner = unsafe { load_inner::<C>(child) }.slots();
 
                 // This is an optimisation ‒ replacing the thing is expensive, so we want to check
                 // first (which is cheaper).
                 let non_null = inner
-                    .0
                     .iter()
-                    .filter(|ptr| !ptr.load(Ordering::Relaxed, &pin).is_null())
+                    .filter(|ptr| !ptr.load(Ordering::Relaxed, pin).is_null())
                     .count();
                 if non_null > 1 {
                     // No reason to go into the upper levels.
@@ -806,33 +1869,319 @@ where
                 }
 
                 // OK, we think we could remove this node. Try doing so.
-                if let PruneResult::Copy = unsafe { Self::prune(&pin, parent, child) } {
+                if let PruneResult::Copy = unsafe { Self::prune(pin, parent, child) } {
                     // Even though we tried to count how many pointers there are, someone must have
                     // added some since. So there's no way we can prone anything higher up and we
                     // give up.
                     break;
                 }
-                // Else:
-                // Just continue with higher levels. Even if someone made the contraction for
-                // us, it should be safe to do so.
+                // Else:
+                // Just continue with higher levels. Even if someone made the contraction for
+                // us, it should be safe to do so.
+            }
+        }
+
+        deleted
+    }
+
+    /// Removes all the values the predicate rejects.
+    ///
+    /// This walks the whole trie, similar to the [`Iter`][iterator::Iter], and removes the
+    /// rejected values right away, pruning the emptied branches on the way back up.
+    ///
+    /// The predicate may be called multiple times for the same value (if something else modifies
+    /// the part of the trie holding it concurrently). Values inserted concurrently with the walk
+    /// may or may not be visited.
+    pub fn retain<P>(&self, mut predicate: P, pin: &Guard)
+    where
+        P: FnMut(&C::Payload) -> bool,
+    {
+        if self.copy_on_write() {
+            return self.retain_cow(predicate, pin);
+        }
+        let root = self.root(pin);
+        let done = unsafe { self.retain_rec(&root.trie, &root.len, &mut predicate, pin) };
+        assert!(done, "Condemned the root");
+    }
+
+    /// Recursive part of [`retain`][Raw::retain], handling one pointer of the trie.
+    ///
+    /// Returns `false` if the pointer turned out to be condemned. In such case the node holding it
+    /// needs to be pruned and the caller has to retry with whatever replaced it.
+    unsafe fn retain_rec<P>(
+        &self,
+        current: &Atomic<Inner>,
+        len: &Counter,
+        predicate: &mut P,
+        pin: &Guard,
+    ) -> bool
+    where
+        P: FnMut(&C::Payload) -> bool,
+    {
+        'retry: loop {
+            let node = load_ptr::<C, _>(current, pin);
+            let flags = nf(node);
+            if flags.contains(NodeFlags::CONDEMNED) {
+                return false;
+            } else if node.is_null() {
+                return true;
+            } else if flags.contains(NodeFlags::DATA) {
+                let (kept, removed): (Leaves, Leaves) =
+                    leaves::<C>(node)
+                        .into_iter()
+                        .partition(|l| match leaf_payload::<C>(*l) {
+                            Some(payload) => predicate(payload),
+                            // Not a value, it's not ours to remove.
+                            None => true,
+                        });
+                if removed.is_empty() {
+                    // Nothing to remove here.
+                    return true;
+                }
+                let new = join_leaves::<C>(kept, pin);
+                // Same as in remove_if.
+                let result =
+                    current.compare_and_set_weak(node, new, (C::RELEASE, Ordering::Relaxed), pin);
+                match result {
+                    Ok(_) => {
+                        len.add(-(removed.len() as isize));
+                        defer_retire::<C, _>(node, removed, pin);
+                        return true;
+                    }
+                    Err(e) => drop_unused_data::<C>(e.new),
+                }
+                // Someone changed it in the meantime, so retry with the new one.
+            } else {
+                for sub in load_inner::<C>(node).slots() {
+                    if !self.retain_rec(sub, len, predicate, pin) {
+                        // The node is condemned, so we need to get rid of it first and then walk
+                        // through whatever got put into its place (similar to what traverse does).
+                        Self::prune(pin, current, node);
+                        continue 'retry;
+                    }
+                }
+
+                if Self::retain_prune(current, node, pin) {
+                    return true;
+                }
+                // We failed to prune because something changed the pointer in the meantime. It
+                // might have been condemned or replaced by something that needs another look.
             }
         }
+    }
 
-        deleted
+    /// Prunes an inner node after [`retain_rec`][Raw::retain_rec] went through all its children.
+    ///
+    /// Returns `false` if the pruning was needed, but the pointer changed in the meantime.
+    unsafe fn retain_prune(current: &Atomic<Inner>, node: Shared<Inner>, pin: &Guard) -> bool {
+        // Check if this node should be pruned now. Unlike remove_if, we want to prune only if
+        // there's at most one value directly below, not to create copies of nodes with a lone
+        // inner child.
+        let mut values = 0;
+        for sub in load_inner::<C>(node).slots() {
+            let sub = sub.load(Ordering::Relaxed, pin);
+            if sub.is_null() {
+                // Nothing here
+            } else if nf(sub).contains(NodeFlags::DATA) {
+                values += load_data::<C>(sub).len();
+            } else {
+                // There's a whole subtree below, can't prune.
+                values += 2;
+            }
+        }
+        values > 1 || Self::prune(pin, current, node) != PruneResult::CasFail
     }
 }
 
 impl<C: Config, S> Raw<C, S> {
+    /// Turns on the [snapshots][Raw::snapshot] for this map.
+    ///
+    /// This has the same effect as the [`SNAPSHOTS`][Config::SNAPSHOTS] in the config (including
+    /// the slower modifications), but it is decided when the map is created instead of by its
+    /// type.
+    pub fn with_snapshots(mut self) -> Self {
+        self.snapshots = true;
+        self
+    }
+
+    /// Are the published nodes immutable and modifications done by copying the path?
+    ///
+    /// See the [`snapshot`] module.
+    fn copy_on_write(&self) -> bool {
+        C::SNAPSHOTS || self.snapshots
+    }
+
+    /// Removes all the values.
+    ///
+    /// This detaches the whole trie at once. The values are destroyed once no other thread can be
+    /// looking at them, like with the other removals. Operations running concurrently with the
+    /// clear may end up either in the old trie (and get destroyed with it) or in the new one.
+    pub fn clear(&self, pin: &Guard) {
+        self.check_pin(pin);
+        // AcqRel: We need to acquire the old trie to destroy it, while we publish the empty one.
+        let old = self.root.swap(Owned::new(Root::default()), C::ACQ_REL, pin);
+        unsafe { pin.defer_unchecked(move || drop_root::<C>(old)) };
+    }
+
+    /// Removes all the values and returns them in a new map.
+    ///
+    /// This is like [`clear`][Raw::clear], but instead of destroying the old trie, it is moved into
+    /// the returned map. The hasher is cloned, because the hashes of the values need to stay the
+    /// same.
+    pub fn clear_and_take(&self, pin: &Guard) -> Self
+    where
+        S: Clone,
+    {
+        self.check_pin(pin);
+        let old = self.root.swap(Owned::new(Root::default()), C::ACQ_REL, pin);
+        Raw {
+            hash_builder: self.hash_builder.clone(),
+            root: Atomic::from(old),
+            detached: true,
+            handles: if self.collector.is_some() {
+                Handles::new()
+            } else {
+                Handles::default()
+            },
+            // The stragglers are protected by the pins of our collector, so the trie must stay
+            // with it.
+            collector: self.collector.clone(),
+            snapshots: self.snapshots,
+            _data: PhantomData,
+        }
+    }
+
+    /// Pins the epoch of the collector used by this map.
+    ///
+    /// All the [`Guard`]s passed to the methods of this map must come from here (or, if the map
+    /// uses the default collector, from [`crossbeam_epoch::pin`]).
+    ///
+    /// The guard keeps the collector alive, so it is available only for `'static` payloads. See
+    /// [`with_pin`][Raw::with_pin] for the others.
+    pub fn pin(&self) -> Guard
+    where
+        C::Payload: 'static,
+    {
+        // This one may outlive the map, so it can't use the handles owned by the map.
+        match &self.collector {
+            // The guard keeps the registration alive even after the handle is gone.
+            Some(collector) => collector.register().pin(),
+            None => crossbeam_epoch::pin(),
+        }
+    }
+
+    /// Runs the closure with a pinned epoch of the collector used by this map.
+    ///
+    /// This is like [`pin`][Raw::pin], but the guard can't escape the closure. Therefore, it is
+    /// available for all maps, including [scoped ones][Raw::with_hasher_scoped].
+    pub fn with_pin<R, F: FnOnce(&Guard) -> R>(&self, f: F) -> R {
+        f(&self.guard())
+    }
+
+    /// Pins our collector, for internal use.
+    ///
+    /// Unlike [`pin`][Raw::pin], this one is not restricted to `'static` payloads. The guard is
+    /// bound to the borrow of the map, which also allows it to use one of the handles the map
+    /// keeps for its dedicated collector instead of registering a new one.
+    pub(crate) fn guard(&self) -> MapGuard<'_> {
+        match &self.collector {
+            Some(collector) => self.handles.pin(collector),
+            None => MapGuard::from(crossbeam_epoch::pin()),
+        }
+    }
+
+    /// Returns the collector managing the garbage of this map.
+    ///
+    /// Similar to [`pin`][Raw::pin], this is available only for `'static` payloads.
+    pub fn collector(&self) -> &Collector
+    where
+        C::Payload: 'static,
+    {
+        self.collector
+            .as_ref()
+            .unwrap_or_else(|| crossbeam_epoch::default_collector())
+    }
+
+    /// Tries to destroy the already removed values right away.
+    ///
+    /// This advances the epoch of the collector used by this map and runs the destructors of
+    /// whatever is safe to destroy. Values that are still protected by a [`Guard`] held anywhere
+    /// else (eg. an iterator in another thread) can't be destroyed yet.
+    pub fn flush_garbage(&self) {
+        // The garbage needs two advances of the epoch to expire and each collection only handles
+        // a limited amount of it, so give it few rounds.
+        for _ in 0..FLUSH_ROUNDS {
+            self.guard().flush();
+        }
+    }
+
+    /// Makes sure the guard belongs to the right collector.
+    ///
+    /// Pins of another collector don't protect our garbage, so this would lead to use after free.
+    /// The unprotected guard has no collector, it is used internally when we have unique access.
+    fn check_pin(&self, pin: &Guard) {
+        if let Some(collector) = pin.collector() {
+            let own = self
+                .collector
+                .as_ref()
+                .unwrap_or_else(|| crossbeam_epoch::default_collector());
+            assert!(
+                collector == own,
+                "The guard belongs to a different collector than the map"
+            );
+        }
+    }
+
+    /// Loads the current root.
+    fn root<'r>(&'r self, pin: &'r Guard) -> &'r Root {
+        self.check_pin(pin);
+        let root = load_ptr::<C, _>(&self.root, pin);
+        unsafe { root.as_ref() }.expect("The root is never NULL")
+    }
+
     /// Checks for emptiness.
+    ///
+    /// The placeholders of the values still being created by
+    /// [`get_or_insert_with_once`][Raw::get_or_insert_with_once] are not values, so they don't
+    /// count (the same as with [`len`][Raw::len] and the iteration).
     pub fn is_empty(&self) -> bool {
         // This relies on proper branch pruning.
-        // We can use the unprotected here, because we are not actually interested in where the
-        // pointer points to. Therefore we can also use the Relaxed ordering.
-        unsafe {
-            self.root
-                .load(Ordering::Relaxed, &crossbeam_epoch::unprotected())
-                .is_null()
+        //
+        // The root may get swapped and freed by a concurrent clear, so we need a pin to look
+        // inside it. But usually we are not actually interested in where the pointer to the trie
+        // points to. Therefore we can use the Relaxed ordering for it (unless we need to take part
+        // in the global order).
+        let pin = self.guard();
+        let ordering = if C::SEQUENTIALLY_CONSISTENT {
+            Ordering::SeqCst
+        } else {
+            Ordering::Relaxed
+        };
+        if self.root(&pin).trie.load(ordering, &pin).is_null() {
+            return true;
         }
+        // Something is there, but it may be just placeholders. Look for an actual value (when
+        // there are any, it's usually the very first leaf).
+        iterator::Iter::new(self).next().is_none()
+    }
+
+    /// Returns the number of values.
+    ///
+    /// The number is exact if there are no concurrent modifications. Otherwise, it is only an
+    /// estimate ‒ the modifications in progress may or may not be counted.
+    pub fn len(&self) -> usize {
+        let pin = self.guard();
+        self.root(&pin).len.settled()
+    }
+
+    /// Returns an approximate number of values.
+    ///
+    /// This is cheaper than [`len`][Raw::len], but under concurrent modifications the result
+    /// doesn't have to correspond to any actual state of the map. It is exact if there are no
+    /// concurrent modifications.
+    pub fn approx_len(&self) -> usize {
+        let pin = self.guard();
+        self.root(&pin).len.approx()
     }
 
     /// Access to the hash builder.
@@ -852,32 +2201,35 @@ impl<C: Config, S> Drop for Raw<C, S> {
          * * Similarly, the Relaxed ordering here is fine too, as the whole data structure must
          *   have been synchronized into our thread already by this time.
          * * The pointer inside this data structure is never dangling.
+         * * The exception is a detached trie ‒ there might be some stragglers from the original
+         *   map, so we let the epoch GC destroy it once they are done.
          */
-        unsafe fn drop_recursive<C: Config>(node: &Atomic<Inner>) {
-            let pin = crossbeam_epoch::unprotected();
-            let extract = node.load(Ordering::Relaxed, &pin);
-            let flags = nf(extract);
-            if extract.is_null() {
-                // Skip
-            } else if flags.contains(NodeFlags::DATA) {
-                drop_data::<C>(extract);
-            } else {
-                let owned = extract.into_owned();
-                for sub in &owned.0 {
-                    drop_recursive::<C>(sub);
-                }
-                drop(owned);
-            }
+        let root = unsafe {
+            self.root
+                .load(Ordering::Relaxed, crossbeam_epoch::unprotected())
+        };
+        if self.detached {
+            let pin = self.guard();
+            unsafe { pin.defer_unchecked(move || drop_root::<C>(root)) };
+        } else {
+            unsafe { drop_root::<C>(root) };
         }
-        unsafe { drop_recursive::<C>(&self.root) };
+        // A dedicated collector gets dropped right after this, together with the other fields. If
+        // we hold the last reference to it, it runs all the garbage that's still pending, so the
+        // removed values are gone by the time we return.
     }
 }
 
 #[cfg(test)]
 pub(crate) mod tests {
-    use std::ptr;
-
-    use super::config::Trivial as TrivialConfig;
+    use std::collections::hash_map::RandomState;
+    use std::hash::Hasher;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+
+    use super::config::{
+        CompressedNodes, SequentiallyConsistent, Snapshots, Trivial as TrivialConfig, WithHash,
+    };
     use super::*;
 
     // A hasher to create collisions on purpose. Let's make the hash trie into a glorified array.
@@ -938,12 +2290,134 @@ pub(crate) mod tests {
         assert_eq!(0x0808_0808_0808_0808, hasher.finish());
     }
 
+    /// The number of slots in the inner nodes of the default config.
+    pub(crate) const LEVEL_CELLS: usize = <TrivialConfig<usize> as Levels>::LEVEL_CELLS;
+
+    /// Generates a config adaptor with the given number of bits per level.
+    macro_rules! level_bits {
+        ($name: ident, $bits: expr) => {
+            struct $name<C>(PhantomData<C>);
+
+            impl<C: Config> Config for $name<C> {
+                type Payload = C::Payload;
+                type Key = C::Key;
+                type Hash = C::Hash;
+                const SNAPSHOTS: bool = C::SNAPSHOTS;
+                const COMPRESSED_NODES: bool = C::COMPRESSED_NODES;
+                const LEVEL_BITS: usize = $bits;
+                const SEQUENTIALLY_CONSISTENT: bool = C::SEQUENTIALLY_CONSISTENT;
+            }
+        };
+    }
+
+    level_bits!(Bits3, 3);
+    level_bits!(Bits5, 5);
+    level_bits!(Bits6, 6);
+    level_bits!(Bits7, 7);
+
+    fn check_consts<C: Config>() {
+        assert!(C::LEVEL_CELLS.is_power_of_two());
+        assert!(C::LEVEL_CELLS <= MAX_LEVEL_CELLS);
+        assert_eq!(C::LEVEL_BITS, C::LEVEL_MASK.count_ones() as usize);
+        assert_eq!(C::LEVEL_BITS, (!C::LEVEL_MASK).trailing_zeros() as usize);
+        assert_eq!(C::LEVEL_CELLS, 2usize.pow(C::LEVEL_BITS as u32));
+        // The levels cover the whole hash, but not more than necessary.
+        let hash_bits = <C::Hash as HashValue>::BITS;
+        assert!(C::MAX_LEVELS * C::LEVEL_BITS >= hash_bits);
+        assert!((C::MAX_LEVELS - 1) * C::LEVEL_BITS < hash_bits);
+        assert!(C::MAX_LEVELS <= MAX_LEVELS);
+    }
+
     #[test]
     fn consts_consistent() {
-        assert!(LEVEL_CELLS.is_power_of_two());
-        assert_eq!(LEVEL_BITS, LEVEL_MASK.count_ones() as usize);
-        assert_eq!(LEVEL_BITS, (!LEVEL_MASK).trailing_zeros() as usize);
-        assert_eq!(LEVEL_CELLS, 2usize.pow(LEVEL_BITS as u32));
+        assert_eq!(MAX_LEVEL_CELLS, 1 << MAX_LEVEL_BITS);
+        assert_eq!(MAX_LEVEL_CELLS, mem::size_of::<Packed>() * 8);
+        assert_eq!(
+            MAX_LEVELS,
+            <Bits3<WithHash<TrivialConfig<usize>, u128>> as Levels>::MAX_LEVELS
+        );
+        // One for each level, plus one for the data node at the end.
+        let path = ArrayVec::<[(); PATH_CAPACITY]>::new();
+        assert!(path.capacity() > MAX_LEVELS);
+        check_consts::<TrivialConfig<usize>>();
+        check_consts::<Bits3<TrivialConfig<usize>>>();
+        check_consts::<Bits5<TrivialConfig<usize>>>();
+        check_consts::<Bits6<TrivialConfig<usize>>>();
+        check_consts::<Bits3<WithHash<TrivialConfig<usize>, u32>>>();
+        check_consts::<Bits5<WithHash<TrivialConfig<usize>, u128>>>();
+    }
+
+    fn check_level_bits<C: Config<Payload = usize, Key = usize>, S: BuildHasher>(hasher: S) {
+        let mut map = Raw::<C, _>::with_hasher(hasher);
+        let pin = crossbeam_epoch::pin();
+        for i in 0..2000 {
+            assert!(map.insert(i, &pin).is_none());
+        }
+        for i in (0..2000).step_by(2) {
+            assert_eq!(Some(&i), map.remove(&i, &pin));
+        }
+        map.retain(|v| v % 3 != 0, &pin);
+        for i in 0..2000 {
+            assert_eq!(i % 2 == 1 && i % 3 != 0, map.get(&i, &pin).is_some());
+        }
+        drop(pin);
+        map.assert_pruned();
+    }
+
+    #[test]
+    fn level_bits() {
+        check_level_bits::<Bits3<TrivialConfig<usize>>, _>(RandomState::new());
+        check_level_bits::<Bits5<TrivialConfig<usize>>, _>(RandomState::new());
+        check_level_bits::<Bits6<TrivialConfig<usize>>, _>(RandomState::new());
+        check_level_bits::<Bits5<Compressed>, _>(RandomState::new());
+        check_level_bits::<Bits6<Compressed>, _>(RandomState::new());
+        check_level_bits::<Bits5<super::config::Snapshots<TrivialConfig<usize>>>, _>(
+            RandomState::new(),
+        );
+    }
+
+    /// The last level uses only part of the bits, make sure the collisions work there.
+    #[test]
+    fn level_bits_collisions() {
+        check_level_bits::<Bits5<TrivialConfig<usize>>, _>(NoHasher);
+        check_level_bits::<Bits5<Compressed>, _>(NoHasher);
+        check_level_bits::<Bits6<TrivialConfig<usize>>, _>(MakeSplatHasher);
+        check_level_bits::<Bits3<Compressed>, _>(MakeSplatHasher);
+    }
+
+    type Hash32 = WithHash<TrivialConfig<usize>, u32>;
+    type Hash128 = WithHash<TrivialConfig<usize>, u128>;
+
+    #[test]
+    fn hash_widths() {
+        check_level_bits::<Hash32, _>(RandomState::new());
+        check_level_bits::<Hash128, _>(RandomState::new());
+        check_level_bits::<Bits5<Hash32>, _>(RandomState::new());
+        check_level_bits::<Bits3<Hash128>, _>(RandomState::new());
+        check_level_bits::<CompressedNodes<Hash128>, _>(RandomState::new());
+        check_level_bits::<CompressedNodes<Bits6<Hash32>>, _>(RandomState::new());
+    }
+
+    /// The deepest tries possible, with the collisions at the very bottom.
+    #[test]
+    fn hash_widths_collisions() {
+        check_level_bits::<Bits3<Hash128>, _>(NoHasher);
+        check_level_bits::<CompressedNodes<Bits3<Hash128>>, _>(NoHasher);
+        check_level_bits::<Bits5<Hash32>, _>(MakeSplatHasher);
+        check_level_bits::<CompressedNodes<Bits5<Hash128>>, _>(MakeSplatHasher);
+    }
+
+    /// The two halves of the large hash are not the same.
+    #[test]
+    fn hash_128_halves() {
+        let hash = <u128 as HashValue>::compute(&RandomState::new(), |h| "hello".hash(h));
+        assert_ne!(hash as u64, (hash >> 64) as u64);
+    }
+
+    #[test]
+    #[should_panic(expected = "LEVEL_BITS must be between")]
+    fn level_bits_too_large() {
+        Raw::<Bits7<TrivialConfig<usize>>, _>::with_hasher(RandomState::new());
     }
 
     /// Pretend something left a condemned marker on one of the nodes when we insert. This will get
@@ -963,7 +2437,7 @@ pub(crate) mod tests {
 
         // By now, we should have exactly one data node under each pointer under root. Sanity
         // check that (Relaxed is fine, we are in a single threaded test).
-        let root = map.root.load(Ordering::Relaxed, &pin);
+        let root = map.root(&pin).trie.load(Ordering::Relaxed, &pin);
         let flags = nf(root);
         assert_eq!(
             NodeFlags::empty(),
@@ -972,9 +2446,9 @@ pub(crate) mod tests {
         );
         assert!(!root.is_null());
         let old_root = root.as_raw();
-        let root = unsafe { root.deref() };
+        let root = unsafe { load_inner::<TrivialConfig<u8>>(root) }.slots();
 
-        for ptr in &root.0 {
+        for ptr in root {
             let ptr = ptr.load(Ordering::Relaxed, &pin);
             assert!(!ptr.is_null());
             let flags = nf(ptr);
@@ -987,7 +2461,7 @@ pub(crate) mod tests {
         }
 
         // Now, *start* condemning the node. Mark the first slot, the one we'll eventually use.
-        root.0[0].fetch_or(NodeFlags::CONDEMNED.bits(), Ordering::Relaxed, &pin);
+        root[0].fetch_or(NodeFlags::CONDEMNED.bits(), Ordering::Relaxed, &pin);
 
         // This touches the condemned slot, so it should trigger fixing stuff.
         let old = map.insert(0, &pin);
@@ -997,7 +2471,7 @@ pub(crate) mod tests {
         map.assert_pruned();
 
         // And the root should have changed for a brand new one.
-        let new_root = map.root.load(Ordering::Relaxed, &pin).as_raw();
+        let new_root = map.root(&pin).trie.load(Ordering::Relaxed, &pin).as_raw();
         assert!(!ptr::eq(old_root, new_root), "Condemned node not replaced");
 
         // But all the content is preserved
@@ -1013,14 +2487,16 @@ pub(crate) mod tests {
         let map = Raw::<TrivialConfig<u8>, _>::with_hasher(MakeSplatHasher);
         let pin = crossbeam_epoch::pin();
 
-        let i = Inner::default();
+        let i = NewInner::new::<TrivialConfig<u8>>();
         i.0[0].fetch_or(NodeFlags::CONDEMNED.bits(), Ordering::Relaxed, &pin);
-        map.root.store(Owned::new(i), Ordering::Relaxed);
+        let i = publish_inner::<TrivialConfig<u8>>(i, &pin);
+        map.root(&pin).trie.store(i, Ordering::Relaxed);
 
-        // There's nothing in this map effectively, but it doesn't claim to be empty due to the
-        // non-null pointer.
+        // There's nothing in this map effectively, but the pointer is not null. It still claims
+        // to be empty, as it looks for values if there's anything.
         assert!(iterator::Iter::new(&map).next().is_none());
-        assert!(!map.is_empty());
+        assert!(!map.root(&pin).trie.load(Ordering::Relaxed, &pin).is_null());
+        assert!(map.is_empty());
 
         map
     }
@@ -1033,13 +2509,13 @@ pub(crate) mod tests {
     fn prune_on_insert_empty() {
         let mut map = with_leftover();
         let pin = crossbeam_epoch::pin();
-        let old_root = map.root.load(Ordering::Relaxed, &pin).as_raw();
+        let old_root = map.root(&pin).trie.load(Ordering::Relaxed, &pin).as_raw();
 
         // Now, let's insert something so it meets the condemned mark
         assert!(map.insert(0, &pin).is_none());
 
         map.assert_pruned();
-        let new_root = map.root.load(Ordering::Relaxed, &pin);
+        let new_root = map.root(&pin).trie.load(Ordering::Relaxed, &pin);
         // It got replaced and the root is directly the data node
         let new_flags = nf(new_root);
         assert_eq!(NodeFlags::DATA, new_flags);
@@ -1056,25 +2532,430 @@ pub(crate) mod tests {
         let map = Raw::<TrivialConfig<u8>, _>::with_hasher(MakeSplatHasher);
         let pin = crossbeam_epoch::pin();
 
-        let i_inner = Inner::default();
-        let i_outer = Inner::default();
+        let i_inner =
+            publish_inner::<TrivialConfig<u8>>(NewInner::new::<TrivialConfig<u8>>(), &pin);
+        let i_outer = NewInner::new::<TrivialConfig<u8>>();
         i_outer.0[0].store(
-            Owned::new(i_inner).with_tag(NodeFlags::CONDEMNED.bits()),
+            i_inner.with_tag(NodeFlags::CONDEMNED.bits()),
             Ordering::Relaxed,
         );
-        map.root.store(Owned::new(i_outer), Ordering::Relaxed);
+        let i_outer = publish_inner::<TrivialConfig<u8>>(i_outer, &pin);
+        map.root(&pin).trie.store(i_outer, Ordering::Relaxed);
 
-        // There's nothing in this map effectively, but it doesn't claim to be empty due to the
-        // non-null pointer.
+        // There's nothing in this map effectively, but the pointer is not null. It still claims
+        // to be empty, as it looks for values if there's anything.
         assert!(iterator::Iter::new(&map).next().is_none());
-        assert!(!map.is_empty());
+        assert!(!map.root(&pin).trie.load(Ordering::Relaxed, &pin).is_null());
+        assert!(map.is_empty());
 
         assert!(map.remove(&0, &pin).is_none());
 
         eprintln!("{}", debug::PrintShape(&map));
 
-        assert_eq!(0, map.root.load(Ordering::Relaxed, &pin).tag());
+        assert_eq!(0, map.root(&pin).trie.load(Ordering::Relaxed, &pin).tag());
         // Note: it is still *not* properly pruned. The inner node should have a thread it'll clean
         // up later on. And we can't contract it as the one below is inner node, not data node.
     }
+
+    /// Retain removes the rejected values and leaves the trie pruned.
+    #[test]
+    fn retain_prunes() {
+        let mut map = Raw::<TrivialConfig<usize>, _>::with_hasher(RandomState::new());
+        let pin = crossbeam_epoch::pin();
+        for i in 0..1000 {
+            assert!(map.insert(i, &pin).is_none());
+        }
+
+        map.retain(|i| i % 3 == 0, &pin);
+        map.assert_pruned();
+        for i in 0..1000 {
+            assert_eq!(i % 3 == 0, map.get(&i, &pin).is_some());
+        }
+
+        map.retain(|_| false, &pin);
+        map.assert_pruned();
+        assert!(map.is_empty());
+    }
+
+    /// Retain copes with a condemned slot left behind by someone else.
+    #[test]
+    fn retain_condemned() {
+        let mut map = Raw::<TrivialConfig<u8>, _>::with_hasher(MakeSplatHasher);
+        let pin = crossbeam_epoch::pin();
+        for i in 0..LEVEL_CELLS as u8 {
+            assert!(map.insert(i, &pin).is_none());
+        }
+        let root = map.root(&pin).trie.load(Ordering::Relaxed, &pin);
+        let root = unsafe { load_inner::<TrivialConfig<u8>>(root) }.slots();
+        root[0].fetch_or(NodeFlags::CONDEMNED.bits(), Ordering::Relaxed, &pin);
+
+        map.retain(|i| i % 2 == 1, &pin);
+        map.assert_pruned();
+        for i in 0..LEVEL_CELLS as u8 {
+            assert_eq!(i % 2 == 1, map.get(&i, &pin).is_some());
+        }
+    }
+
+    #[test]
+    fn clear() {
+        let map = Raw::<TrivialConfig<usize>, _>::with_hasher(RandomState::new());
+        let pin = crossbeam_epoch::pin();
+        for i in 0..1000 {
+            assert!(map.insert(i, &pin).is_none());
+        }
+        map.clear(&pin);
+        assert!(map.is_empty());
+        assert!(map.get(&0, &pin).is_none());
+        assert!(map.insert(0, &pin).is_none());
+        assert_eq!(0, *map.get(&0, &pin).unwrap());
+    }
+
+    #[test]
+    fn clear_and_take() {
+        let map = Raw::<TrivialConfig<usize>, _>::with_hasher(RandomState::new());
+        let pin = crossbeam_epoch::pin();
+        for i in 0..1000 {
+            assert!(map.insert(i, &pin).is_none());
+        }
+        let mut taken = map.clear_and_take(&pin);
+        assert!(map.is_empty());
+        taken.assert_pruned();
+        for i in 0..1000 {
+            assert_eq!(i, *taken.get(&i, &pin).unwrap());
+        }
+        // The taken one works as a usual map
+        assert_eq!(0, *taken.remove(&0, &pin).unwrap());
+        assert!(taken.insert(1000, &pin).is_none());
+    }
+
+    type Tracked = TrivialConfig<(usize, Arc<()>)>;
+
+    /// With a dedicated collector, everything is destroyed by the time the drop returns.
+    #[test]
+    fn collector_destroys_on_drop() {
+        let tracker = Arc::new(());
+        let map =
+            Raw::<Tracked, _>::with_hasher_and_collector(RandomState::new(), Collector::new());
+        for i in 0..1000 {
+            let pin = map.pin();
+            map.insert((i, Arc::clone(&tracker)), &pin);
+            if i % 3 == 0 {
+                map.remove(&(i, Arc::clone(&tracker)), &pin);
+            }
+        }
+        let taken = map.clear_and_take(&map.pin());
+        for i in 0..10 {
+            taken.insert((i, Arc::clone(&tracker)), &taken.pin());
+        }
+        assert!(Arc::strong_count(&tracker) > 1);
+        drop(map);
+        drop(taken);
+        assert_eq!(1, Arc::strong_count(&tracker));
+    }
+
+    /// The garbage left in the handles the map lends to the threads is destroyed on drop too.
+    #[test]
+    fn collector_handles_destroy_on_drop() {
+        let tracker = Arc::new(());
+        let map =
+            Raw::<Tracked, _>::with_hasher_and_collector(RandomState::new(), Collector::new());
+        crossbeam_utils::thread::scope(|s| {
+            for t in 0..4 {
+                let map = &map;
+                let tracker = &tracker;
+                s.spawn(move |_| {
+                    for i in 0..100 {
+                        let value = (t * 100 + i, Arc::clone(tracker));
+                        map.with_pin(|pin| {
+                            map.insert(value.clone(), pin);
+                            map.remove(&value, pin);
+                        });
+                    }
+                });
+            }
+        })
+        .unwrap();
+        drop(map);
+        assert_eq!(1, Arc::strong_count(&tracker));
+    }
+
+    #[test]
+    fn collector_flush() {
+        let tracker = Arc::new(());
+        let map =
+            Raw::<Tracked, _>::with_hasher_and_collector(RandomState::new(), Collector::new());
+        for i in 0..100 {
+            map.insert((i, Arc::clone(&tracker)), &map.pin());
+        }
+        map.clear(&map.pin());
+        map.flush_garbage();
+        assert_eq!(1, Arc::strong_count(&tracker));
+    }
+
+    /// A value borrowing a counter of its living instances.
+    struct Borrowing<'a>(usize, &'a AtomicUsize);
+
+    impl<'a> Borrowing<'a> {
+        fn new(key: usize, alive: &'a AtomicUsize) -> Self {
+            alive.fetch_add(1, Ordering::Relaxed);
+            Borrowing(key, alive)
+        }
+    }
+
+    impl PartialEq for Borrowing<'_> {
+        fn eq(&self, other: &Self) -> bool {
+            self.0 == other.0
+        }
+    }
+
+    impl Eq for Borrowing<'_> {}
+
+    impl Hash for Borrowing<'_> {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.0.hash(state)
+        }
+    }
+
+    impl Drop for Borrowing<'_> {
+        fn drop(&mut self) {
+            self.1.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    /// A scoped map destroys all its borrowing values before it goes away.
+    #[test]
+    fn scoped_borrowed() {
+        let alive = AtomicUsize::new(0);
+        let map = Raw::<TrivialConfig<Borrowing>, _>::with_hasher_scoped(RandomState::new());
+        map.with_pin(|pin| {
+            for i in 0..100 {
+                assert!(map.insert(Borrowing::new(i, &alive), pin).is_none());
+            }
+            for i in 0..50 {
+                assert!(map.remove(&Borrowing::new(i, &alive), pin).is_some());
+            }
+        });
+        let copy = map.empty_like();
+        map.with_pin(|pin| copy.insert(Borrowing::new(1000, &alive), pin).is_none());
+        assert!(alive.load(Ordering::Relaxed) >= 51);
+        drop(map);
+        drop(copy);
+        assert_eq!(0, alive.load(Ordering::Relaxed));
+    }
+
+    fn check_not_clone<'a, C>(alive: &'a AtomicUsize)
+    where
+        C: Config<Payload = Borrowing<'a>, Key = Borrowing<'a>>,
+    {
+        // All in one collision, so the values get moved between the collision nodes a lot.
+        let map = Raw::<C, _>::with_hasher_scoped(NoHasher);
+        map.with_pin(|pin| {
+            for i in 0..10 {
+                assert!(map.insert(Borrowing::new(i, alive), pin).is_none());
+            }
+            assert_eq!(3, map.insert(Borrowing::new(3, alive), pin).unwrap().0);
+            assert!(map.replace(Borrowing::new(20, alive), pin).is_none());
+            assert_eq!(5, map.remove(&Borrowing::new(5, alive), pin).unwrap().0);
+            map.retain(|b| b.0 % 3 != 0, pin);
+            let new = map.get_or_insert_with(Borrowing::new(11, alive), |k| k, pin);
+            assert!(new.is_new());
+            let existing = map.get_or_insert_with(Borrowing::new(11, alive), |k| k, pin);
+            assert!(!existing.is_new());
+        });
+        map.with_pin(|pin| {
+            for i in 0..12 {
+                let present = i != 5 && i != 10 && i % 3 != 0;
+                assert_eq!(present, map.get(&Borrowing::new(i, alive), pin).is_some());
+            }
+        });
+        assert_eq!(6, map.len());
+        drop(map);
+        assert_eq!(0, alive.load(Ordering::Relaxed));
+    }
+
+    /// Payloads that can't be cloned are neither leaked nor dropped twice, even in collisions.
+    #[test]
+    fn not_clone() {
+        check_not_clone::<TrivialConfig<Borrowing>>(&AtomicUsize::new(0));
+    }
+
+    #[test]
+    fn not_clone_cow() {
+        check_not_clone::<Snapshots<TrivialConfig<Borrowing>>>(&AtomicUsize::new(0));
+    }
+
+    type SeqCstConfig = SequentiallyConsistent<TrivialConfig<usize>>;
+
+    /// Two threads, each inserting a value and then looking for the other one, can't both miss.
+    #[test]
+    fn seq_cst_store_buffering() {
+        for _ in 0..1000 {
+            let map = Raw::<SeqCstConfig, _>::with_hasher(RandomState::new());
+            let found = crossbeam_utils::thread::scope(|s| {
+                let threads = [(1, 2), (2, 1)]
+                    .iter()
+                    .map(|&(mine, other)| {
+                        let map = &map;
+                        s.spawn(move |_| {
+                            let pin = crossbeam_epoch::pin();
+                            map.insert(mine, &pin);
+                            map.get(&other, &pin).is_some()
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                threads
+                    .into_iter()
+                    .map(|t| t.join().unwrap())
+                    .filter(|&f| f)
+                    .count()
+            })
+            .unwrap();
+            assert!(found > 0);
+        }
+    }
+
+    /// Values inserted before a flag (under another key) are visible to whoever sees the flag.
+    fn check_seq_cst_flag<C: Config<Payload = usize, Key = usize>>() {
+        const FLAG: usize = 1_000_000;
+        let map = Raw::<C, _>::with_hasher(RandomState::new());
+        crossbeam_utils::thread::scope(|s| {
+            let map = &map;
+            s.spawn(move |_| {
+                for i in 0..1000 {
+                    map.insert(i, &crossbeam_epoch::pin());
+                }
+                map.insert(FLAG, &crossbeam_epoch::pin());
+            });
+            s.spawn(move |_| {
+                let pin = crossbeam_epoch::pin();
+                while map.get(&FLAG, &pin).is_none() {}
+                for i in 0..1000 {
+                    assert!(map.get(&i, &pin).is_some());
+                }
+            });
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn seq_cst_flag() {
+        check_seq_cst_flag::<SeqCstConfig>();
+    }
+
+    #[test]
+    fn seq_cst_flag_cow() {
+        check_seq_cst_flag::<Snapshots<SeqCstConfig>>();
+    }
+
+    type Compressed = CompressedNodes<TrivialConfig<usize>>;
+
+    fn check_compressed<S: BuildHasher>(hasher: S) {
+        let mut map = Raw::<Compressed, _>::with_hasher(hasher);
+        let pin = crossbeam_epoch::pin();
+        for i in 0..1000 {
+            assert!(map.insert(i, &pin).is_none());
+        }
+        assert_eq!(Some(&5), map.insert(5, &pin));
+        for i in (0..1000).step_by(2) {
+            assert_eq!(Some(&i), map.remove(&i, &pin));
+        }
+        map.retain(|v| v % 3 != 0, &pin);
+        for i in 0..1000 {
+            assert_eq!(i % 2 == 1 && i % 3 != 0, map.get(&i, &pin).is_some());
+        }
+        drop(pin);
+        map.assert_pruned();
+    }
+
+    #[test]
+    fn compressed() {
+        check_compressed(RandomState::new());
+    }
+
+    #[test]
+    fn compressed_collisions() {
+        check_compressed(NoHasher);
+    }
+
+    #[test]
+    fn compressed_parallel() {
+        let mut map = Raw::<Compressed, _>::with_hasher(RandomState::new());
+        crossbeam_utils::thread::scope(|s| {
+            for t in 0..4 {
+                let map = &map;
+                s.spawn(move |_| {
+                    for i in 0..1000 {
+                        let pin = crossbeam_epoch::pin();
+                        assert!(map.insert(t * 1000 + i, &pin).is_none());
+                        if i % 2 == 0 {
+                            assert!(map.remove(&(t * 1000 + i), &pin).is_some());
+                        }
+                    }
+                });
+            }
+        })
+        .unwrap();
+        assert_eq!(2000, map.len());
+        let pin = crossbeam_epoch::pin();
+        for i in 0..4000 {
+            assert_eq!(i % 2 == 1, map.get(&i, &pin).is_some());
+        }
+        drop(pin);
+        map.assert_pruned();
+    }
+
+    #[test]
+    #[should_panic(expected = "Snapshots are not turned on")]
+    fn compressed_no_snapshots() {
+        Raw::<Compressed, _>::with_hasher(RandomState::new()).snapshot();
+    }
+
+    #[test]
+    fn compressed_snapshots() {
+        let map = Raw::<Snapshots<Compressed>, _>::with_hasher(RandomState::new());
+        map.extend(0..1000);
+        let snapshot = map.snapshot();
+        map.retain(|v| v % 2 == 0, &map.pin());
+        let mut iter = snapshot.iter();
+        let mut cnt = 0;
+        while iter.next().is_some() {
+            cnt += 1;
+        }
+        assert_eq!(1000, cnt);
+        assert_eq!(500, map.len());
+    }
+
+    #[test]
+    fn compressed_destroys() {
+        let tracker = Arc::new(());
+        let map = Raw::<CompressedNodes<Tracked>, _>::with_hasher_and_collector(
+            RandomState::new(),
+            Collector::new(),
+        );
+        for i in 0..1000 {
+            map.insert((i, Arc::clone(&tracker)), &map.pin());
+        }
+        for i in 0..500 {
+            map.remove(&(i, Arc::clone(&tracker)), &map.pin());
+        }
+        drop(map);
+        assert_eq!(1, Arc::strong_count(&tracker));
+    }
+
+    #[test]
+    fn packed_smaller() {
+        let full = LEVEL_CELLS * mem::size_of::<Atomic<Inner>>();
+        assert!(Packed::layout(LEVEL_CELLS).size() <= full + 8);
+        assert!(Packed::layout(2).size() * 4 <= full);
+    }
+
+    #[test]
+    #[should_panic(expected = "different collector")]
+    fn collector_foreign_pin() {
+        let map = Raw::<TrivialConfig<usize>, _>::with_hasher_and_collector(
+            RandomState::new(),
+            Collector::new(),
+        );
+        map.insert(42, &crossbeam_epoch::pin());
+    }
 }
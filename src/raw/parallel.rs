@@ -0,0 +1,436 @@
+//! Parallel iteration and bulk operations of the [`Raw`] map, through
+//! [`rayon`].
+//!
+//! Available with the `rayon` feature.
+
+use std::borrow::Borrow;
+use std::hash::BuildHasher;
+use std::ops::Range;
+
+use crossbeam_epoch::{Atomic, Guard, Shared};
+use rayon::iter::plumbing::{bridge_unindexed, Folder, UnindexedConsumer, UnindexedProducer};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use super::config::Config;
+use super::counter::Counter;
+use super::iterator::{extend_lifetime, Iter};
+use super::{load_inner, load_ptr, nf, Inner, Levels, MapGuard, NodeFlags, Orderings, Raw};
+
+/// How many levels from the top [`par_retain`][Raw::par_retain] splits between the workers.
+///
+/// Below that, each worker handles its whole subtree alone.
+const PAR_LEVELS: usize = 2;
+
+// Notes about the safety:
+// The epoch pins are not Send, so the splits can't carry one around between the threads. Instead,
+// the thread driving the iterator holds a pin for the whole time the splits exist (the driving
+// blocks until all of them are done). Anything a split reaches was reachable after that pin was
+// taken, so it can't be reclaimed before the pin goes away. The splits still take their own pins
+// when loading further pointers and walking their parts, as the API requires them.
+//
+// The lifetimes of the pointers are lies the same way as in the sequential iterator.
+
+/// A parallel iterator through the raw trie.
+///
+/// Unlike the [sequential iterator][Iter], this produces owned items. Each payload is turned into
+/// one by the provided closure (usually by cloning some part of it), while it is still protected
+/// by an epoch pin.
+///
+/// The work is split along the slots of the inner nodes. The top-level node is split in halves
+/// until a single slot remains, then the split descends into that slot and continues there. Each
+/// split walks its part of the trie under its own pin.
+///
+/// # Quirks
+///
+/// The same as with the sequential iterator, concurrent modifications may or may not be
+/// reflected in the returned values, unless [`SNAPSHOTS`][crate::raw::config::Config::SNAPSHOTS]
+/// are turned on.
+///
+/// The thread driving the iterator holds an epoch pin until all the work is done. Therefore, no
+/// memory is reclaimed for the whole duration.
+pub struct ParIter<'a, C, S, F>
+where
+    C: Config,
+{
+    map: &'a Raw<C, S>,
+    extract: F,
+}
+
+impl<'a, C, S, F> ParIter<'a, C, S, F>
+where
+    C: Config,
+{
+    /// Creates a new parallel iterator, borrowing from the map.
+    ///
+    /// The `extract` closure turns the payloads into the produced items.
+    pub fn new<'m: 'a>(map: &'m Raw<C, S>, extract: F) -> Self {
+        ParIter { map, extract }
+    }
+}
+
+impl<'a, C, S, F, R> ParallelIterator for ParIter<'a, C, S, F>
+where
+    C: Config,
+    Raw<C, S>: Sync,
+    F: Fn(&C::Payload) -> R + Sync + Send,
+    R: Send,
+{
+    type Item = R;
+
+    fn drive_unindexed<Co>(self, consumer: Co) -> Co::Result
+    where
+        Co: UnindexedConsumer<R>,
+    {
+        // Keeps everything the splits can reach alive until they are all done.
+        let pin = self.map.guard();
+        let root = load_ptr::<C, _>(&self.map.root(&pin).trie, &pin);
+        let split = Split {
+            map: self.map,
+            extract: &self.extract,
+            node: unsafe { extend_lifetime(root) },
+            slots: 0..C::LEVEL_CELLS,
+        };
+        let result = bridge_unindexed(split, consumer);
+        drop(pin);
+        result
+    }
+}
+
+/// A part of the trie to walk.
+///
+/// This is either a whole subtree or some of the slots of an inner node.
+struct Split<'a, C, S, F>
+where
+    C: Config,
+{
+    map: &'a Raw<C, S>,
+    extract: &'a F,
+    node: Shared<'a, Inner>,
+    /// The slots of the node to walk, if it is an inner node.
+    slots: Range<usize>,
+}
+
+// See the notes above, the node is kept alive by the pin of the driving thread.
+unsafe impl<C, S, F> Send for Split<'_, C, S, F>
+where
+    C: Config,
+    Raw<C, S>: Sync,
+    F: Sync,
+{
+}
+
+impl<'a, C, S, F> Split<'a, C, S, F>
+where
+    C: Config,
+{
+    /// Is the node an inner one, therefore possible to split further?
+    fn is_inner(&self) -> bool {
+        !self.node.is_null() && !nf(self.node).contains(NodeFlags::DATA)
+    }
+
+    /// Walks the whole subtree starting at the node, feeding the folder.
+    fn walk<Fo, R>(&self, node: Shared<'a, Inner>, pin: MapGuard<'a>, mut folder: Fo) -> Fo
+    where
+        F: Fn(&C::Payload) -> R,
+        Fo: Folder<R>,
+    {
+        let mut iter = unsafe { Iter::starting_at(self.map, pin, node) };
+        while let Some(payload) = iter.next() {
+            folder = folder.consume((self.extract)(payload));
+            if folder.full() {
+                break;
+            }
+        }
+        folder
+    }
+}
+
+impl<'a, C, S, F, R> UnindexedProducer for Split<'a, C, S, F>
+where
+    C: Config,
+    Raw<C, S>: Sync,
+    F: Fn(&C::Payload) -> R + Sync,
+{
+    type Item = R;
+
+    fn split(mut self) -> (Self, Option<Self>) {
+        let pin = self.map.guard();
+        while self.is_inner() {
+            let len = self.slots.len();
+            if len > 1 {
+                let mid = self.slots.start + len / 2;
+                let other = Split {
+                    map: self.map,
+                    extract: self.extract,
+                    node: self.node,
+                    slots: mid..self.slots.end,
+                };
+                self.slots.end = mid;
+                return (self, Some(other));
+            } else if len == 1 {
+                let inner = unsafe { load_inner::<C>(self.node) };
+                let child = inner.load(self.slots.start, C::ACQUIRE, &pin);
+                self.node = unsafe { extend_lifetime(child) };
+                self.slots = 0..C::LEVEL_CELLS;
+            } else {
+                break;
+            }
+        }
+        (self, None)
+    }
+
+    fn fold_with<Fo>(self, mut folder: Fo) -> Fo
+    where
+        Fo: Folder<R>,
+    {
+        if !self.is_inner() {
+            return self.walk(self.node, self.map.guard(), folder);
+        }
+        let inner = unsafe { load_inner::<C>(self.node) };
+        for idx in self.slots.clone() {
+            let pin = self.map.guard();
+            let child = unsafe { extend_lifetime(inner.load(idx, C::ACQUIRE, &pin)) };
+            folder = self.walk(child, pin, folder);
+            if folder.full() {
+                break;
+            }
+        }
+        folder
+    }
+}
+
+impl<C, S> Raw<C, S>
+where
+    C: Config,
+    S: BuildHasher,
+    Self: Sync,
+{
+    /// Calls the closure on each value, in parallel.
+    ///
+    /// This walks the trie the same way as the [`ParIter`], but hands the values to the closure
+    /// by reference instead of producing owned items.
+    pub fn par_for_each<F>(&self, f: F)
+    where
+        F: Fn(&C::Payload) + Sync + Send,
+    {
+        ParIter::new(self, f).for_each(|()| ());
+    }
+
+    /// Removes all the values the predicate rejects, in parallel.
+    ///
+    /// This is the parallel version of [`retain`][Raw::retain], with the same quirks. The top
+    /// levels of the trie are split between the workers and each of them then owns a whole
+    /// subtree, so they don't compete for the same nodes. Each worker pins the epoch on its own.
+    ///
+    /// With the snapshots turned on (see the [`snapshot`][super::snapshot] module), every
+    /// modification replaces the whole path up to the root, so the workers do compete there.
+    /// Only the walk is parallel in such case.
+    pub fn par_retain<P>(&self, predicate: P)
+    where
+        P: Fn(&C::Payload) -> bool + Sync + Send,
+    {
+        if self.copy_on_write() {
+            self.par_for_each(|payload| {
+                if !predicate(payload) {
+                    let key: &C::Key = payload.borrow();
+                    self.remove_if(key, |p| C::same(p, payload), &self.guard());
+                }
+            });
+            return;
+        }
+        let pin = self.guard();
+        let root = self.root(&pin);
+        let done =
+            unsafe { self.par_retain_rec(&root.trie, &root.len, &predicate, PAR_LEVELS, &pin) };
+        assert!(done, "Condemned the root");
+    }
+
+    /// Parallel part of [`par_retain`][Raw::par_retain], handling one pointer of the trie.
+    ///
+    /// The children of inner nodes on the top `levels` levels are handled by separate workers,
+    /// anything else is left to the sequential [`retain_rec`][Raw::retain_rec]. Returns `false` if
+    /// the pointer turned out to be condemned.
+    unsafe fn par_retain_rec<P>(
+        &self,
+        current: &Atomic<Inner>,
+        len: &Counter,
+        predicate: &P,
+        levels: usize,
+        pin: &Guard,
+    ) -> bool
+    where
+        P: Fn(&C::Payload) -> bool + Sync,
+    {
+        loop {
+            let node = load_ptr::<C, _>(current, pin);
+            let flags = nf(node);
+            if levels == 0
+                || node.is_null()
+                || flags.intersects(NodeFlags::DATA | NodeFlags::CONDEMNED)
+            {
+                return self.retain_rec(current, len, &mut &*predicate, pin);
+            }
+            // The node is kept alive by our pin until all the workers are done.
+            let inner = load_inner::<C>(node).slots();
+            // Not short-circuiting, the other children can be finished before the pruning.
+            let done = inner
+                .into_par_iter()
+                .map(|sub| {
+                    let pin = self.guard();
+                    self.par_retain_rec(sub, len, predicate, levels - 1, &pin)
+                })
+                .reduce(|| true, |a, b| a && b);
+            if !done {
+                // Same as in retain_rec, get rid of the condemned node and walk the replacement.
+                Self::prune(pin, current, node);
+            } else if Self::retain_prune(current, node, pin) {
+                return true;
+            }
+        }
+    }
+
+    /// Creates a copy of the map, in parallel.
+    ///
+    /// The copy uses the same hasher and collector (see [`empty_like`][Raw::empty_like]). As the
+    /// values therefore land in the same places of the new trie, each worker fills its own
+    /// subtree.
+    pub fn par_clone(&self) -> Self
+    where
+        C::Payload: Clone + Send,
+        S: Clone,
+    {
+        let new = self.empty_like();
+        new.par_extend(ParIter::new(self, C::Payload::clone));
+        new
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::RandomState;
+    use std::collections::HashSet;
+    use std::hash::BuildHasher;
+
+    use super::super::config::{CompressedNodes, Snapshots, Trivial};
+    use super::super::tests::{MakeSplatHasher, NoHasher};
+    use super::*;
+
+    fn check_par_iter<C, S>(map: Raw<C, S>)
+    where
+        C: Config<Key = usize, Payload = usize>,
+        S: BuildHasher + Sync,
+    {
+        const CNT: usize = 10_000;
+        map.with_pin(|pin| {
+            for i in 0..CNT {
+                map.insert(i, pin);
+            }
+        });
+        let found = ParIter::new(&map, |p: &usize| *p).collect::<Vec<_>>();
+        assert_eq!(CNT, found.len());
+        assert_eq!(
+            (0..CNT).collect::<HashSet<_>>(),
+            found.into_iter().collect()
+        );
+
+        let first = ParIter::new(&map, |p: &usize| *p).find_any(|p| *p == 42);
+        assert_eq!(Some(42), first);
+    }
+
+    #[test]
+    fn par_iter() {
+        check_par_iter(Raw::<Trivial<usize>, _>::with_hasher(RandomState::new()));
+    }
+
+    #[test]
+    fn par_iter_collisions() {
+        check_par_iter(Raw::<Trivial<usize>, _>::with_hasher(NoHasher));
+    }
+
+    #[test]
+    fn par_iter_deep() {
+        check_par_iter(Raw::<Trivial<usize>, _>::with_hasher(MakeSplatHasher));
+    }
+
+    #[test]
+    fn par_iter_compressed() {
+        check_par_iter(Raw::<CompressedNodes<Trivial<usize>>, _>::with_hasher(
+            RandomState::new(),
+        ));
+    }
+
+    #[test]
+    fn par_iter_snapshots() {
+        check_par_iter(Raw::<Snapshots<Trivial<usize>>, _>::with_hasher(
+            RandomState::new(),
+        ));
+    }
+
+    #[test]
+    fn par_iter_empty() {
+        let map = Raw::<Trivial<usize>, _>::with_hasher(RandomState::new());
+        assert_eq!(0, ParIter::new(&map, |p: &usize| *p).count());
+    }
+
+    fn check_par_retain<C, S>(mut map: Raw<C, S>)
+    where
+        C: Config<Key = usize, Payload = usize>,
+        S: BuildHasher + Sync,
+    {
+        const CNT: usize = 10_000;
+        map.with_pin(|pin| {
+            for i in 0..CNT {
+                map.insert(i, pin);
+            }
+        });
+        map.par_retain(|v| v % 3 == 0);
+        map.assert_pruned();
+        assert_eq!((0..CNT).filter(|i| i % 3 == 0).count(), map.len());
+        map.with_pin(|pin| {
+            for i in 0..CNT {
+                assert_eq!(i % 3 == 0, map.get(&i, pin).is_some());
+            }
+        });
+
+        map.par_retain(|_| false);
+        map.assert_pruned();
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn par_retain() {
+        check_par_retain(Raw::<Trivial<usize>, _>::with_hasher(RandomState::new()));
+    }
+
+    #[test]
+    fn par_retain_collisions() {
+        check_par_retain(Raw::<Trivial<usize>, _>::with_hasher(NoHasher));
+    }
+
+    #[test]
+    fn par_retain_deep() {
+        check_par_retain(Raw::<Trivial<usize>, _>::with_hasher(MakeSplatHasher));
+    }
+
+    #[test]
+    fn par_retain_cow() {
+        check_par_retain(Raw::<Snapshots<Trivial<usize>>, _>::with_hasher(
+            RandomState::new(),
+        ));
+    }
+
+    #[test]
+    fn par_clone() {
+        let map = Raw::<CompressedNodes<Trivial<usize>>, _>::with_hasher(RandomState::new());
+        map.extend(0..1000);
+        let copy = map.par_clone();
+        map.par_retain(|_| false);
+        assert_eq!(1000, copy.len());
+        copy.par_for_each(|v| assert!(map.get(v, &map.guard()).is_none()));
+        copy.with_pin(|pin| {
+            for i in 0..1000 {
+                assert_eq!(i, *copy.get(&i, pin).unwrap());
+            }
+        });
+    }
+}
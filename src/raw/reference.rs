@@ -0,0 +1,115 @@
+//! References into the [`Raw`] trie, keeping the value alive.
+
+use std::borrow::Borrow;
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+use super::config::Config;
+use super::{MapGuard, Raw};
+
+// Notes about the safety:
+// The same as with the entry. The value can't go away as long as the map is alive (guaranteed by
+// the 'a lifetime) and we hold the pin (which we own). We never hand out the reference with a
+// longer lifetime than borrow of us.
+
+/// A reference to a value inside the trie.
+///
+/// This holds an epoch pin, which keeps the value alive even if it gets removed from the map in
+/// the meantime. Therefore, it provides access to the value without cloning it, but it should not
+/// be kept around for extended periods of time.
+///
+/// Created by [`Raw::get_ref`].
+pub struct Ref<'a, T: ?Sized> {
+    _pin: MapGuard<'a>,
+    value: *const T,
+    _map: PhantomData<&'a T>,
+}
+
+impl<'a, T: ?Sized> Ref<'a, T> {
+    /// Wraps a value kept alive by the pin.
+    ///
+    /// The value must stay valid as long as the pin is held and the map (`'a`) is alive.
+    pub(crate) unsafe fn new(pin: MapGuard<'a>, value: *const T) -> Self {
+        Ref {
+            _pin: pin,
+            value,
+            _map: PhantomData,
+        }
+    }
+
+    /// Turns the reference into a reference to a part of the value.
+    ///
+    /// This is an associated function (used as `Ref::map(r, ...)`), so it doesn't shadow
+    /// methods of the value.
+    pub fn map<U, F>(orig: Self, f: F) -> Ref<'a, U>
+    where
+        U: ?Sized,
+        F: FnOnce(&T) -> &U,
+    {
+        let value = f(&*orig) as *const U;
+        Ref {
+            _pin: orig._pin,
+            value,
+            _map: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for Ref<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.value }
+    }
+}
+
+impl<T: Debug + ?Sized> Debug for Ref<'_, T> {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        (**self).fmt(fmt)
+    }
+}
+
+impl<C, S> Raw<C, S>
+where
+    C: Config,
+    S: BuildHasher,
+{
+    /// Looks up a value and returns a reference to it, holding its own pin.
+    ///
+    /// This is like [`get`][Raw::get], but the caller doesn't have to provide (and keep around)
+    /// the guard.
+    pub fn get_ref<Q>(&self, key: &Q) -> Option<Ref<'_, C::Payload>>
+    where
+        Q: ?Sized + Eq + Hash,
+        C::Key: Borrow<Q>,
+    {
+        let pin = self.guard();
+        let value = self.get(key, &pin)? as *const _;
+        Some(unsafe { Ref::new(pin, value) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::RandomState;
+
+    use super::super::config::Trivial;
+    use super::*;
+
+    #[test]
+    fn survives_removal() {
+        let map = Raw::<Trivial<(usize, String)>, _>::with_hasher(RandomState::new());
+        let pin = crossbeam_epoch::pin();
+        map.insert((1, "hello".to_owned()), &pin);
+        drop(pin);
+        let found = map.get_ref(&(1, "hello".to_owned())).unwrap();
+        let text = map.get_ref(&(1, "hello".to_owned())).unwrap();
+        let text = Ref::map(text, |v| &v.1[..]);
+        map.remove(&(1, "hello".to_owned()), &crossbeam_epoch::pin());
+        map.flush_garbage();
+        assert_eq!(1, found.0);
+        assert_eq!("hello", &*text);
+        assert!(map.get_ref(&(1, "hello".to_owned())).is_none());
+    }
+}
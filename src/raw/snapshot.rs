@@ -0,0 +1,615 @@
+//! Consistent snapshots of the [`Raw`] trie.
+//!
+//! This is available only if the [`SNAPSHOTS`][Config::SNAPSHOTS] are turned on in the config or
+//! the map was created with them by [`with_snapshots`][crate::raw::Raw::with_snapshots].
+
+// # How it works
+//
+// The Ctrie article uses generation-tagged I-nodes and the GCAS operation to make sure nobody
+// modifies a node shared with a snapshot. We don't have I-nodes and modify the pointers inside the
+// inner nodes in place, so we would have to bring all that machinery back. Instead, in the
+// snapshot mode we simply never modify anything once it is published:
+//
+// * A modification walks down to the place of the key, remembering the path.
+// * It creates the new leaf and then copies of all the inner nodes on the path, each pointing to
+//   the copy below (and to the same untouched subtrees as the original).
+// * The new path is put in place by a single CaS of the pointer to the top of the trie. If it
+//   fails, someone else has modified the trie in the meantime, so we throw our copies away and
+//   start over.
+// * On success, the old inner nodes on the path and the replaced data node are no longer
+//   reachable from the new version, so they are scheduled for destruction through the epochs.
+//   The rest is shared with the new version and it is owned by it.
+//
+// As there's only one pointer that ever changes, the pointer to the top at any given moment is a
+// consistent snapshot. To keep it alive, it is enough to hold a pin ‒ all the nodes of the older
+// version that got replaced since wait for our pin to go away before being destroyed.
+//
+// Pruning is done right while copying the path, so there are no condemned pointers in this mode.
+// The nodes that would be left with at most one value and without any inner node below are
+// replaced by that value (or by null) in the copy of their parent.
+//
+// The price is that each modification allocates the whole path and all of them compete for the
+// single pointer. Lookups stay exactly the same.
+
+use std::borrow::Borrow;
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::Ordering;
+
+use arrayvec::ArrayVec;
+use crossbeam_epoch::{Atomic, Guard, Shared};
+
+use super::config::{Config, HashValue};
+use super::iterator::{extend_lifetime, Iter};
+use super::{
+    defer_drop_inner, defer_retire, drop_inner, drop_unused_data, join_leaves, leaf_payload,
+    leaves, load_data, load_inner, load_ptr, nf, publish_inner, resolve, written, Inner, Leaves,
+    Levels, MapGuard, NewInner, NodeFlags, Orderings, Outcome, Raw, TraverseMode, TraverseState,
+    PATH_CAPACITY,
+};
+
+/// The inner nodes on the way from the top, together with the index of the slot we went through.
+type Path<'p> = ArrayVec<[(Shared<'p, Inner>, usize); PATH_CAPACITY]>;
+
+/// Newly allocated nodes, to be freed if we fail to put them in place.
+///
+/// There can't be more inner nodes than levels, plus there's one data node. The leaf with the new
+/// value is not in here, it stays with the [`TraverseState`] for the next attempt.
+type Fresh<'p> = ArrayVec<[Shared<'p, Inner>; PATH_CAPACITY]>;
+
+fn bits<C: Config>(hash: C::Hash, shift: usize) -> usize {
+    hash.bits(shift, C::LEVEL_MASK)
+}
+
+/// Walks down from the top, to the place where the hash belongs.
+///
+/// Returns the path and the node found at the end (either NULL or data), together with the shift
+/// of its level.
+fn descend<'p, C: Config>(
+    top: Shared<'p, Inner>,
+    hash: C::Hash,
+    pin: &'p Guard,
+) -> (Path<'p>, Shared<'p, Inner>, usize) {
+    let mut path = Path::new();
+    let mut node = top;
+    let mut shift = 0;
+    loop {
+        let flags = nf(node);
+        assert!(
+            !flags.contains(NodeFlags::CONDEMNED),
+            "Condemned pointer in snapshot mode"
+        );
+        if node.is_null() || flags.contains(NodeFlags::DATA) {
+            return (path, node, shift);
+        }
+        let inner = unsafe { load_inner::<C>(node) };
+        let idx = bits::<C>(hash, shift);
+        path.push((node, idx));
+        shift += C::LEVEL_BITS;
+        node = match inner.slot(idx) {
+            Some(slot) => load_ptr::<C, _>(slot, pin),
+            None => Shared::null(),
+        };
+    }
+}
+
+/// Wraps the leaf into new inner nodes for the levels between the two shifts.
+fn push_down<'p, C: Config>(
+    mut leaf: Shared<'p, Inner>,
+    hash: C::Hash,
+    from: usize,
+    mut to: usize,
+    fresh: &mut Fresh<'p>,
+    pin: &'p Guard,
+) -> Shared<'p, Inner> {
+    while to > from {
+        to -= C::LEVEL_BITS;
+        let mut inner = NewInner::new::<C>();
+        inner.0[bits::<C>(hash, to)] = Atomic::from(leaf);
+        leaf = publish_inner::<C>(inner, pin);
+        fresh.push(leaf);
+    }
+    leaf
+}
+
+/// Creates copies of the inner nodes on the path, with the leaf put at the end of it.
+///
+/// The nodes that would end up with at most one value and without any inner node below are
+/// contracted, the same way [`prune`][Raw::prune] would do it. Returns the new top of the trie.
+unsafe fn rebuild<'p, C: Config>(
+    path: &Path<'p>,
+    mut leaf: Shared<'p, Inner>,
+    fresh: &mut Fresh<'p>,
+    pin: &'p Guard,
+) -> Shared<'p, Inner> {
+    for &(node, idx) in path.iter().rev() {
+        let mut copy = NewInner::new::<C>();
+        let mut values = 0;
+        let mut last_leaf = Shared::null();
+        let node = load_inner::<C>(node);
+        for (i, dst) in copy.0.iter_mut().enumerate() {
+            // Acquire: we are going to re-publish the pointer in the copy.
+            let sub = if i == idx {
+                leaf
+            } else {
+                node.load(i, C::ACQUIRE, pin)
+            };
+            if sub.is_null() {
+                // Nothing here
+            } else if nf(sub).contains(NodeFlags::DATA) {
+                values += load_data::<C>(sub).len();
+                last_leaf = sub;
+            } else {
+                // A whole subtree, this one can't be contracted.
+                values += 2;
+            }
+            *dst = Atomic::from(sub);
+        }
+        leaf = match values {
+            0 => Shared::null(),
+            1 => last_leaf,
+            _ => {
+                let copy = publish_inner::<C>(copy, pin);
+                fresh.push(copy);
+                copy
+            }
+        };
+    }
+    leaf
+}
+
+/// Frees the nodes we've created but failed to put in place.
+unsafe fn drop_fresh<C: Config>(fresh: Fresh) {
+    for node in fresh {
+        if nf(node).contains(NodeFlags::DATA) {
+            drop_unused_data::<C>(node);
+        } else {
+            // Only the node itself, the pointers inside are shared with the trie.
+            drop_inner::<C>(node);
+        }
+    }
+}
+
+/// Puts the new version of the trie in place, if nobody changed the top in the meantime.
+///
+/// On success, the nodes of the old path and the replaced data node (if any), together with the
+/// removed leaves, are scheduled for destruction. On failure, the fresh nodes are freed.
+unsafe fn commit<'p, C, I>(
+    top: &Atomic<Inner>,
+    (old, new): (Shared<'p, Inner>, Shared<'p, Inner>),
+    path: Path<'p>,
+    (replaced, removed): (Option<Shared<'p, Inner>>, I),
+    fresh: Fresh<'p>,
+    pin: &'p Guard,
+) -> bool
+where
+    C: Config,
+    I: IntoIterator<Item = Shared<'p, Inner>>,
+{
+    // Release: we publish the whole new path. Relaxed on failure, as we throw our stuff away.
+    if top
+        .compare_and_set(old, new, (C::RELEASE, Ordering::Relaxed), pin)
+        .is_ok()
+    {
+        for (node, _) in path {
+            defer_drop_inner::<C>(node, pin);
+        }
+        if let Some(replaced) = replaced {
+            defer_retire::<C, _>(replaced, removed, pin);
+        }
+        true
+    } else {
+        drop_fresh::<C>(fresh);
+        false
+    }
+}
+
+impl<C, S> Raw<C, S>
+where
+    C: Config,
+    S: BuildHasher,
+{
+    /// The snapshot mode counterpart of [`traverse`][Raw::traverse].
+    pub(super) fn traverse_cow<'s, 'p, 'r, F, P>(
+        &'s self,
+        hash: C::Hash,
+        state: &mut TraverseState<C, F>,
+        mut mode: TraverseMode<P>,
+        pin: &'p Guard,
+    ) -> Outcome<'r, C::Payload>
+    where
+        's: 'r,
+        'p: 'r,
+        F: FnOnce(C::Key) -> C::Payload,
+        P: FnMut(&C::Payload) -> bool,
+    {
+        // The shift of the level below the last one, where the collisions live.
+        let bottom = C::MAX_LEVELS * C::LEVEL_BITS;
+        loop {
+            let root = self.root(pin);
+            let top = load_ptr::<C, _>(&root.trie, pin);
+            let (path, node, shift) = descend::<C>(top, hash, pin);
+            let mut fresh = Fresh::new();
+            let (ours, leaf, found, retired) = if node.is_null() {
+                if let TraverseMode::IfPresent(_) = mode {
+                    return Outcome::Missing;
+                }
+                let ours = state.leaf(hash, pin);
+                (ours, ours, None, (None, Leaves::new()))
+            } else {
+                let mut leaves = unsafe { leaves::<C>(node) };
+                assert!(
+                    !leaves.is_empty(),
+                    "Empty data nodes must not be kept around"
+                );
+                let first = leaves[0];
+                let split = unsafe { !(state.matches(first) || mode.settles::<C>(first)) }
+                    && shift < bottom;
+                let (found, removed) = match unsafe { resolve(&mut leaves, &*state, &mut mode) } {
+                    Ok(resolved) => resolved,
+                    Err(outcome) => return outcome,
+                };
+                let ours = state.leaf(hash, pin);
+
+                let other_hash = if split {
+                    assert!(leaves.len() == 1, "Collision node not deep enough");
+                    unsafe { load_data::<C>(first) }.hash()
+                } else {
+                    hash
+                };
+                if other_hash != hash {
+                    // There's another value in our place. Push it down to the level where the
+                    // hashes differ, together with ours.
+                    let mut level = shift;
+                    while bits::<C>(hash, level) == bits::<C>(other_hash, level) {
+                        level += C::LEVEL_BITS;
+                    }
+                    let mut inner = NewInner::new::<C>();
+                    inner.0[bits::<C>(hash, level)] = Atomic::from(ours);
+                    inner.0[bits::<C>(other_hash, level)] = Atomic::from(node);
+                    let inner = publish_inner::<C>(inner, pin);
+                    fresh.push(inner);
+                    let leaf = push_down::<C>(inner, hash, shift, level, &mut fresh, pin);
+                    (ours, leaf, None, (None, Leaves::new()))
+                } else {
+                    // Replacing a value or adding to a collision (if the hashes are the same, we
+                    // push the collision node down to the very bottom).
+                    leaves.push(ours);
+                    let collision = join_leaves::<C>(leaves, pin);
+                    // If it is just our leaf, drop_fresh knows to leave it alone.
+                    fresh.push(collision);
+                    let to = if split { bottom } else { shift };
+                    let leaf = push_down::<C>(collision, hash, shift, to, &mut fresh, pin);
+                    (ours, leaf, found, (Some(node), removed))
+                }
+            };
+
+            unsafe {
+                let new_top = rebuild::<C>(&path, leaf, &mut fresh, pin);
+                if commit::<C, _>(&root.trie, (top, new_top), path, retired, fresh, pin) {
+                    state.published();
+                    return written::<C>(ours, found, &root.len);
+                }
+            }
+            // Someone else got there first, retry.
+        }
+    }
+
+    /// The snapshot mode counterpart of [`remove_leaf`][Raw::remove_leaf].
+    pub(super) fn remove_cow<'r, 's, 'p, P>(
+        &'s self,
+        hash: C::Hash,
+        mut pick: P,
+        pin: &'p Guard,
+    ) -> Option<Shared<'r, Inner>>
+    where
+        's: 'r,
+        'p: 'r,
+        P: FnMut(&Leaves) -> Option<usize>,
+    {
+        loop {
+            let root = self.root(pin);
+            let top = load_ptr::<C, _>(&root.trie, pin);
+            let (path, node, _) = descend::<C>(top, hash, pin);
+            if node.is_null() {
+                return None;
+            }
+            let mut leaves = unsafe { leaves::<C>(node) };
+            let pos = pick(&leaves)?;
+
+            let mut fresh = Fresh::new();
+            let removed = leaves.remove(pos);
+            let leaf = join_leaves::<C>(leaves, pin);
+            if !leaf.is_null() {
+                fresh.push(leaf);
+            }
+
+            unsafe {
+                let new_top = rebuild::<C>(&path, leaf, &mut fresh, pin);
+                let retired = (Some(node), Some(removed));
+                if commit::<C, _>(&root.trie, (top, new_top), path, retired, fresh, pin) {
+                    // A placeholder doesn't count into the length.
+                    if leaf_payload::<C>(removed).is_some() {
+                        root.len.add(-1);
+                    }
+                    return Some(removed);
+                }
+            }
+        }
+    }
+
+    /// The snapshot mode counterpart of [`retain`][Raw::retain].
+    ///
+    /// This walks one version of the trie and removes the rejected values one by one (unless they
+    /// got replaced in the meantime).
+    pub(super) fn retain_cow<P>(&self, mut predicate: P, pin: &Guard)
+    where
+        P: FnMut(&C::Payload) -> bool,
+    {
+        let mut iter = Iter::new(self);
+        while let Some(payload) = iter.next() {
+            if !predicate(payload) {
+                let key: &C::Key = payload.borrow();
+                self.remove_if(key, |p| C::same(p, payload), pin);
+            }
+        }
+    }
+}
+
+impl<C: Config, S> Raw<C, S> {
+    /// Takes a consistent snapshot of the current content.
+    ///
+    /// This is cheap, it only remembers the current top of the trie. The snapshot is not affected
+    /// by any later modifications of the map.
+    ///
+    /// The snapshot holds an epoch pin for its whole lifetime (the same as the iterators do), so
+    /// no memory is reclaimed while it exists. It should not be kept around for extended periods
+    /// of time.
+    ///
+    /// # Panics
+    ///
+    /// If the snapshots are turned on neither in the config (see
+    /// [`SNAPSHOTS`][Config::SNAPSHOTS]) nor by [`with_snapshots`][Raw::with_snapshots].
+    pub fn snapshot(&self) -> Snapshot<'_, C, S> {
+        assert!(self.copy_on_write(), "Snapshots are not turned on");
+        let pin = self.guard();
+        let root = self.root(&pin);
+        // Acquire: we are going to read the content.
+        let top = root.trie.load(C::ACQUIRE, &pin);
+        let top = unsafe { extend_lifetime(top) };
+        Snapshot {
+            map: self,
+            pin,
+            top,
+        }
+    }
+}
+
+// Notes about the lifetimes: the same as with the iterator. The 'a of the top is a lie, it is
+// actually bound to the pin we hold. Therefore we never hand out anything with the 'a lifetime,
+// only bound to borrows of us.
+
+/// A consistent read-only view of the content of a [`Raw`] at a given moment.
+///
+/// Created by [`Raw::snapshot`].
+pub struct Snapshot<'a, C: Config, S> {
+    map: &'a Raw<C, S>,
+    pin: MapGuard<'a>,
+    top: Shared<'a, Inner>,
+}
+
+impl<C: Config, S> Snapshot<'_, C, S> {
+    /// Looks up a value.
+    pub fn get<Q>(&self, key: &Q) -> Option<&C::Payload>
+    where
+        S: BuildHasher,
+        Q: ?Sized + Eq + Hash,
+        C::Key: Borrow<Q>,
+    {
+        self.map.lookup(self.top, key, &self.pin)
+    }
+
+    /// Checks for emptiness.
+    ///
+    /// As with [`Raw::is_empty`], placeholders of values still being created don't count.
+    pub fn is_empty(&self) -> bool {
+        self.top.is_null() || self.iter().next().is_none()
+    }
+
+    /// Iterates through the content of the snapshot.
+    pub fn iter(&self) -> Iter<'_, C, S> {
+        // The top is protected by our pin and the iterator can't outlive us.
+        unsafe { Iter::starting_at(self.map, self.map.guard(), self.top) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::RandomState;
+    use std::collections::HashSet;
+    use std::sync::atomic::AtomicBool;
+
+    use crossbeam_utils::thread;
+
+    use super::super::config::{Snapshots, Trivial};
+    use super::super::tests::NoHasher;
+    use super::*;
+
+    type Map<S = RandomState> = Raw<Snapshots<Trivial<usize>>, S>;
+
+    fn content<C: Config<Payload = usize>, S>(mut iter: Iter<C, S>) -> Vec<usize> {
+        let mut result = Vec::new();
+        while let Some(val) = iter.next() {
+            result.push(*val);
+        }
+        result.sort();
+        result
+    }
+
+    fn check_isolated<S: BuildHasher>(hasher: S) {
+        let mut map = Raw::<Snapshots<Trivial<usize>>, _>::with_hasher(hasher);
+        let pin = crossbeam_epoch::pin();
+        for i in 0..100 {
+            map.insert(i, &pin);
+        }
+        {
+            let snapshot = map.snapshot();
+            for i in (0..100).step_by(2) {
+                assert_eq!(Some(&i), map.remove(&i, &pin));
+            }
+            for i in 100..150 {
+                map.insert(i, &pin);
+            }
+            for i in 0..150 {
+                assert_eq!(i < 100, snapshot.get(&i).is_some());
+                assert_eq!(i >= 100 || i % 2 == 1, map.get(&i, &pin).is_some());
+            }
+            assert_eq!((0..100).collect::<Vec<_>>(), content(snapshot.iter()));
+        }
+        drop(pin);
+        map.assert_pruned();
+        for i in 0..150 {
+            map.remove(&i, &crossbeam_epoch::pin());
+        }
+        assert!(map.is_empty());
+        map.assert_pruned();
+    }
+
+    #[test]
+    fn isolated() {
+        check_isolated(RandomState::new());
+    }
+
+    #[test]
+    fn isolated_collisions() {
+        check_isolated(NoHasher);
+    }
+
+    #[test]
+    fn survives_clear() {
+        let map = Map::with_hasher(RandomState::new());
+        let pin = crossbeam_epoch::pin();
+        for i in 0..10 {
+            map.insert(i, &pin);
+        }
+        let snapshot = map.snapshot();
+        map.clear(&pin);
+        map.insert(42, &pin);
+        assert_eq!((0..10).collect::<Vec<_>>(), content(snapshot.iter()));
+        assert!(snapshot.get(&42).is_none());
+        assert!(!snapshot.is_empty());
+        assert!(map.snapshot().get(&42).is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "Snapshots are not turned on")]
+    fn not_turned_on() {
+        let map = Raw::<Trivial<usize>, _>::with_hasher(RandomState::new());
+        map.snapshot();
+    }
+
+    #[test]
+    fn turned_on_at_runtime() {
+        let mut map = Raw::<Trivial<usize>, _>::with_hasher(RandomState::new()).with_snapshots();
+        let pin = crossbeam_epoch::pin();
+        for i in 0..100 {
+            map.insert(i, &pin);
+        }
+        let snapshot = map.snapshot();
+        map.retain(|v| v % 2 == 0, &pin);
+        assert_eq!((0..100).collect::<Vec<_>>(), content(snapshot.iter()));
+        assert!(map.get(&1, &pin).is_none());
+        drop(snapshot);
+
+        // The maps made out of this one keep the snapshots.
+        let taken = map.clear_and_take(&pin);
+        let taken_snapshot = taken.snapshot();
+        taken.insert(1, &pin);
+        assert!(taken_snapshot.get(&1).is_none());
+        assert!(map.empty_like().snapshot().is_empty());
+        drop(pin);
+        map.assert_pruned();
+    }
+
+    #[test]
+    fn retain() {
+        let mut map = Map::with_hasher(RandomState::new());
+        let pin = crossbeam_epoch::pin();
+        for i in 0..1000 {
+            map.insert(i, &pin);
+        }
+        map.retain(|v| v % 3 == 0, &pin);
+        drop(pin);
+        assert_eq!(
+            (0..1000).filter(|v| v % 3 == 0).collect::<Vec<_>>(),
+            content(Iter::new(&map))
+        );
+        assert_eq!(334, map.len());
+        map.assert_pruned();
+    }
+
+    const WRITES: usize = 2000;
+
+    /// The writer inserts the values in order and then removes them in order. Therefore, every
+    /// consistent view must contain a continuous range of them.
+    #[test]
+    fn consistent_under_writes() {
+        let map = Map::with_hasher(RandomState::new());
+        let done = AtomicBool::new(false);
+        thread::scope(|s| {
+            s.spawn(|_| {
+                for i in 0..WRITES {
+                    map.insert(i, &crossbeam_epoch::pin());
+                }
+                for i in 0..WRITES {
+                    map.remove(&i, &crossbeam_epoch::pin());
+                }
+                done.store(true, Ordering::Relaxed);
+            });
+            for _ in 0..2 {
+                s.spawn(|_| {
+                    while !done.load(Ordering::Relaxed) {
+                        let snapshot = map.snapshot();
+                        let values = content(snapshot.iter());
+                        if let (Some(first), Some(last)) = (values.first(), values.last()) {
+                            assert_eq!(values.len(), last - first + 1, "Gap in the snapshot");
+                            for val in &values {
+                                assert!(snapshot.get(val).is_some());
+                            }
+                        }
+                    }
+                });
+            }
+        })
+        .unwrap();
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn parallel_modifications() {
+        let mut map = Map::with_hasher(RandomState::new());
+        thread::scope(|s| {
+            for t in 0..4 {
+                let map = &map;
+                s.spawn(move |_| {
+                    for i in 0..500 {
+                        let pin = crossbeam_epoch::pin();
+                        map.insert(t * 1000 + i, &pin);
+                        if i % 2 == 0 {
+                            assert!(map.remove(&(t * 1000 + i), &pin).is_some());
+                        }
+                    }
+                });
+            }
+        })
+        .unwrap();
+        let expected = (0..4)
+            .flat_map(|t| (0..500).filter(|i| i % 2 == 1).map(move |i| t * 1000 + i))
+            .collect::<HashSet<_>>();
+        assert_eq!(
+            expected,
+            content(Iter::new(&map)).into_iter().collect::<HashSet<_>>()
+        );
+        assert_eq!(1000, map.len());
+        map.assert_pruned();
+    }
+}
@@ -5,14 +5,24 @@ use std::collections::hash_map::RandomState;
 use std::fmt::{Debug, Formatter, Result as FmtResult};
 use std::hash::{BuildHasher, Hash};
 use std::iter::FromIterator;
-
-use crossbeam_epoch;
-
+use std::ops::RangeBounds;
+#[cfg(feature = "stream")]
+use std::pin::Pin;
+#[cfg(feature = "stream")]
+use std::task::{Context, Poll};
+
+use crossbeam_epoch::Collector;
+#[cfg(feature = "stream")]
+use futures_core::Stream;
+#[cfg(feature = "rayon")]
+use rayon::iter::plumbing::UnindexedConsumer;
 #[cfg(feature = "rayon")]
 use rayon::iter::{FromParallelIterator, IntoParallelIterator, ParallelExtend, ParallelIterator};
 
+use crate::existing_or_new::ExistingOrNew;
 use crate::raw::config::Trivial as TrivialConfig;
-use crate::raw::{self, Raw};
+use crate::raw::cursor::Cursor;
+use crate::raw::{self, MapGuard, Raw};
 
 /// A concurrent lock-free set.
 ///
@@ -62,41 +72,151 @@ use crate::raw::{self, Raw};
 /// ```
 pub struct ConSet<T, S = RandomState>
 where
-    T: Clone + Hash + Eq + 'static,
+    T: Clone + Hash + Eq,
 {
     raw: Raw<TrivialConfig<T>, S>,
 }
 
 impl<T> ConSet<T, RandomState>
 where
-    T: Clone + Hash + Eq + 'static,
+    T: Clone + Hash + Eq,
 {
     /// Creates a new empty set.
-    pub fn new() -> Self {
+    pub fn new() -> Self
+    where
+        T: 'static,
+    {
         Self::with_hasher(RandomState::default())
     }
+
+    /// Creates a new empty set, with its own private epoch collector.
+    ///
+    /// See [`with_hasher_scoped`][ConSet::with_hasher_scoped] for details.
+    pub fn new_scoped() -> Self {
+        Self::with_hasher_scoped(RandomState::default())
+    }
 }
 
 impl<T, S> ConSet<T, S>
 where
-    T: Clone + Hash + Eq + 'static,
+    T: Clone + Hash + Eq,
     S: BuildHasher,
 {
     /// Creates a new empty set with the given hasher.
-    pub fn with_hasher(hasher: S) -> Self {
+    pub fn with_hasher(hasher: S) -> Self
+    where
+        T: 'static,
+    {
         Self {
             raw: Raw::with_hasher(hasher),
         }
     }
 
+    /// Creates a new empty set with the provided hasher and a dedicated epoch collector.
+    ///
+    /// The removed elements are destroyed through the given collector instead of the global
+    /// default one. If the set holds the last reference to the collector, all the elements are
+    /// guaranteed to be destroyed by the time the drop of the set returns.
+    ///
+    /// Note that operations on such set are somewhat slower.
+    pub fn with_hasher_and_collector(hasher: S, collector: Collector) -> Self
+    where
+        T: 'static,
+    {
+        Self {
+            raw: Raw::with_hasher_and_collector(hasher, collector),
+        }
+    }
+
+    /// Creates a new empty set with the provided hasher, that may borrow its elements.
+    ///
+    /// The set uses its own epoch collector, which is never shared. This makes sure all the
+    /// removed elements are destroyed before the set goes away, so the elements don't have to be
+    /// `'static`.
+    ///
+    /// Note that operations on such set are somewhat slower.
+    pub fn with_hasher_scoped(hasher: S) -> Self {
+        Self {
+            raw: Raw::with_hasher_scoped(hasher),
+        }
+    }
+
+    /// Turns on the [snapshots][ConSet::snapshot] for this set.
+    ///
+    /// This is meant to be chained right after one of the constructors. Modifications of such set
+    /// are considerably slower, because each one copies the whole path from the root of the trie
+    /// (see [`Config::SNAPSHOTS`][crate::raw::config::Config::SNAPSHOTS]). Lookups and iteration
+    /// stay the same.
+    pub fn with_snapshots(self) -> Self {
+        Self {
+            raw: self.raw.with_snapshots(),
+        }
+    }
+
     /// Inserts a new value into the set.
     ///
     /// It returns the previous value, if any was present.
     pub fn insert(&self, value: T) -> Option<T> {
-        let pin = crossbeam_epoch::pin();
+        let pin = self.raw.guard();
         self.raw.insert(value, &pin).cloned()
     }
 
+    /// Inserts multiple values at once.
+    ///
+    /// This is like calling [insert][ConSet::insert] for each of them, but it pins the epoch only
+    /// once and walks the trie in an order that lets the neighbouring values share the work. The
+    /// previous values are returned in the same order as the inserted ones.
+    ///
+    /// The batch is not atomic, other threads may observe only some of the values inserted.
+    pub fn insert_many<I>(&self, values: I) -> Vec<Option<T>>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let pin = self.raw.guard();
+        self.raw
+            .insert_many(values, &pin)
+            .into_iter()
+            .map(|v| v.cloned())
+            .collect()
+    }
+
+    /// Replaces a value in the set, but only if an equal one is already present.
+    ///
+    /// This is useful if the equality doesn't cover the whole value. It returns the replaced
+    /// value. If no equal value was present, nothing is inserted and `None` is returned.
+    ///
+    /// The values of the set are their own keys, so this is also the counterpart of
+    /// `compare_and_swap_value` on the maps ‒ the value found by the key always compares equal.
+    pub fn replace(&self, value: T) -> Option<T> {
+        let pin = self.raw.guard();
+        self.raw.replace(value, &pin).cloned()
+    }
+
+    /// Looks up a value equal to the key, or inserts a newly created one if the creation succeeds.
+    ///
+    /// The closure creates the value to insert from the key. This is useful if the equality
+    /// doesn't cover the whole value. If the closure fails, the error is returned together with
+    /// the key and the set is left unmodified.
+    ///
+    /// The closure is called whenever the key is found missing. If another thread inserts an equal
+    /// value before the new one is put in place, the new one is thrown away and the existing one is
+    /// returned.
+    pub fn get_or_try_insert_with<F, E>(
+        &self,
+        key: T,
+        create: F,
+    ) -> Result<ExistingOrNew<T>, (T, E)>
+    where
+        F: FnOnce(&T) -> Result<T, E>,
+    {
+        self.raw
+            .get_or_try_insert_with(key, |key| match create(&key) {
+                Ok(value) => Ok(value),
+                Err(e) => Err((key, e)),
+            })
+            .map(|found| found.map(|value| T::clone(&value)))
+    }
+
     /// Looks up a value in the set.
     ///
     /// This creates a copy of the original value.
@@ -105,10 +225,97 @@ where
         Q: ?Sized + Eq + Hash,
         T: Borrow<Q>,
     {
-        let pin = crossbeam_epoch::pin();
+        let pin = self.raw.guard();
         self.raw.get(key, &pin).cloned()
     }
 
+    /// Looks up multiple values at once.
+    ///
+    /// The results are in the same order as the keys. This creates copies of the original values.
+    pub fn get_many<Q>(&self, keys: &[&Q]) -> Vec<Option<T>>
+    where
+        Q: ?Sized + Eq + Hash,
+        T: Borrow<Q>,
+    {
+        let pin = self.raw.guard();
+        self.raw
+            .get_many(keys, &pin)
+            .into_iter()
+            .map(|v| v.cloned())
+            .collect()
+    }
+
+    /// Looks up a value in the set and returns a reference to it, instead of a copy.
+    ///
+    /// The reference holds an epoch pin, so it should not be kept around for extended periods of
+    /// time.
+    pub fn get_ref<Q>(&self, key: &Q) -> Option<Ref<'_, T>>
+    where
+        Q: ?Sized + Eq + Hash,
+        T: Borrow<Q>,
+    {
+        self.raw.get_ref(key)
+    }
+
+    /// Pins the epoch and returns a read handle to the set.
+    ///
+    /// The lookups through the handle return references instead of copies, bound to the lifetime
+    /// of the handle. It also saves the cost of pinning for each lookup. However, as the handle
+    /// holds the pin, it should not be kept around for extended periods of time.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use contrie::ConSet;
+    ///
+    /// let set = ConSet::new();
+    /// set.insert("hello".to_owned());
+    ///
+    /// let pinned = set.pin();
+    /// assert_eq!("hello", pinned.get("hello").unwrap());
+    /// assert!(!pinned.contains("world"));
+    /// ```
+    pub fn pin(&self) -> Pinned<'_, T, S> {
+        Pinned {
+            set: self,
+            pin: self.raw.guard(),
+        }
+    }
+
+    /// Takes a consistent snapshot of the current content.
+    ///
+    /// Taking the snapshot is cheap and it is not affected by any later modifications of the set.
+    /// Like the [`pin`][ConSet::pin], it holds an epoch pin for its whole lifetime.
+    ///
+    /// # Panics
+    ///
+    /// If the set was not created [with the snapshots][ConSet::with_snapshots].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use contrie::ConSet;
+    ///
+    /// let set = ConSet::new().with_snapshots();
+    /// set.insert(1);
+    /// set.insert(2);
+    ///
+    /// let snapshot = set.snapshot();
+    /// set.remove(&1);
+    /// set.insert(3);
+    ///
+    /// assert!(snapshot.contains(&1));
+    /// assert!(!snapshot.contains(&3));
+    /// let mut content = snapshot.iter().collect::<Vec<_>>();
+    /// content.sort();
+    /// assert_eq!(vec![1, 2], content);
+    /// ```
+    pub fn snapshot(&self) -> Snapshot<'_, T, S> {
+        Snapshot {
+            inner: self.raw.snapshot(),
+        }
+    }
+
     /// Checks if a value identified by the given key is present in the set.
     ///
     /// Note that by the time you can act on it, the presence of the value can change (eg. other
@@ -118,7 +325,7 @@ where
         Q: ?Sized + Eq + Hash,
         T: Borrow<Q>,
     {
-        let pin = crossbeam_epoch::pin();
+        let pin = self.raw.guard();
         self.raw.get(key, &pin).is_some()
     }
 
@@ -128,10 +335,86 @@ where
         Q: ?Sized + Eq + Hash,
         T: Borrow<Q>,
     {
-        let pin = crossbeam_epoch::pin();
+        let pin = self.raw.guard();
         self.raw.remove(key, &pin).cloned()
     }
 
+    /// Removes multiple elements at once, returning them in the same order as the keys.
+    pub fn remove_many<Q>(&self, keys: &[&Q]) -> Vec<Option<T>>
+    where
+        Q: ?Sized + Eq + Hash,
+        T: Borrow<Q>,
+    {
+        let pin = self.raw.guard();
+        self.raw
+            .remove_many(keys, &pin)
+            .into_iter()
+            .map(|v| v.cloned())
+            .collect()
+    }
+
+    /// Removes an element identified by the given key, but only if the predicate accepts it.
+    ///
+    /// The check and the removal are done atomically. If the element gets replaced in between,
+    /// the predicate is consulted again about the new one, so it may be called multiple times.
+    ///
+    /// Returns the removed element, if any.
+    pub fn remove_if<Q, F>(&self, key: &Q, predicate: F) -> Option<T>
+    where
+        Q: ?Sized + Eq + Hash,
+        T: Borrow<Q>,
+        F: FnMut(&T) -> bool,
+    {
+        let pin = self.raw.guard();
+        self.raw.remove_if(key, predicate, &pin).cloned()
+    }
+
+    /// Removes all the elements the predicate rejects.
+    ///
+    /// This walks the whole set and removes the rejected elements in place, which is cheaper than
+    /// collecting the keys and removing them one by one. Emptied branches are pruned on the way.
+    ///
+    /// This is not an atomic operation. Elements inserted or modified concurrently may or may not
+    /// be visited and the predicate may be called multiple times for the same element.
+    pub fn retain<F>(&self, predicate: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let pin = self.raw.guard();
+        self.raw.retain(predicate, &pin);
+    }
+
+    /// Removes all the values the predicate rejects, in parallel.
+    ///
+    /// This is the parallel version of [`retain`][ConSet::retain], with the same quirks. The top
+    /// levels of the trie are split between the workers, each of them owns a whole subtree.
+    ///
+    /// Available with the `rayon` feature.
+    #[cfg(feature = "rayon")]
+    pub fn par_retain<F>(&self, predicate: F)
+    where
+        T: Send + Sync,
+        S: Sync,
+        F: Fn(&T) -> bool + Sync + Send,
+    {
+        self.raw.par_retain(predicate);
+    }
+
+    /// Calls the closure on each value, in parallel.
+    ///
+    /// Unlike going through [`par_iter`][ConSet::par_iter], this doesn't clone the values.
+    ///
+    /// Available with the `rayon` feature.
+    #[cfg(feature = "rayon")]
+    pub fn par_for_each<F>(&self, f: F)
+    where
+        T: Send + Sync,
+        S: Sync,
+        F: Fn(&T) + Sync + Send,
+    {
+        self.raw.par_for_each(f);
+    }
+
     /// Checks if the set is currently empty.
     ///
     /// Note that due to being concurrent, the use-case of this method is mostly for debugging
@@ -139,6 +422,73 @@ where
     pub fn is_empty(&self) -> bool {
         self.raw.is_empty()
     }
+
+    /// Returns the number of elements.
+    ///
+    /// Similar to [is_empty][ConSet::is_empty], this is inherently racy if other threads modify
+    /// the set at the same time. The returned number is exact if there are no concurrent
+    /// modifications. Otherwise it is only an estimate.
+    pub fn len(&self) -> usize {
+        self.raw.len()
+    }
+
+    /// Returns an approximate number of elements.
+    ///
+    /// This is cheaper than [len][ConSet::len] and is meant for things like statistics. If other
+    /// threads modify the set at the same time, the number might not match any actual state of
+    /// the set.
+    pub fn approx_len(&self) -> usize {
+        self.raw.approx_len()
+    }
+
+    /// Removes all the elements.
+    ///
+    /// This detaches the whole content of the set at once, instead of removing the elements one
+    /// by one. Elements inserted concurrently with the clear may or may not survive.
+    pub fn clear(&self) {
+        let pin = self.raw.guard();
+        self.raw.clear(&pin);
+    }
+
+    /// Removes all the elements and returns them as a new set.
+    ///
+    /// This is like [`clear`][ConSet::clear], but the old content is moved into the returned set
+    /// instead of being destroyed. This can be used to atomically rotate the content.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use contrie::ConSet;
+    ///
+    /// let seen = ConSet::new();
+    /// seen.insert("hello");
+    ///
+    /// let previous = seen.clear_and_take();
+    /// assert!(seen.is_empty());
+    /// assert!(previous.contains("hello"));
+    /// ```
+    pub fn clear_and_take(&self) -> Self
+    where
+        S: Clone,
+    {
+        let pin = self.raw.guard();
+        Self {
+            raw: self.raw.clear_and_take(&pin),
+        }
+    }
+
+    /// Tries to destroy the already removed elements right away.
+    ///
+    /// Removed elements are normally destroyed at some later point, once no thread can be looking
+    /// at them. This forces the collection at a known point. Elements that might still be
+    /// accessed by some other thread (eg. through an iterator) are not destroyed.
+    ///
+    /// This is most useful together with a dedicated collector (see
+    /// [`with_hasher_and_collector`][ConSet::with_hasher_and_collector]), as the global one is
+    /// shared with the rest of the program.
+    pub fn flush_garbage(&self) {
+        self.raw.flush_garbage();
+    }
 }
 
 impl<T> Default for ConSet<T, RandomState>
@@ -152,7 +502,7 @@ where
 
 impl<T, S> Debug for ConSet<T, S>
 where
-    T: Debug + Clone + Hash + Eq + 'static,
+    T: Debug + Clone + Hash + Eq,
 {
     fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
         fmt.debug_set().entries(self.iter()).finish()
@@ -161,14 +511,166 @@ where
 
 impl<T, S> ConSet<T, S>
 where
-    T: Clone + Hash + Eq + 'static,
+    T: Clone + Hash + Eq,
 {
     /// Returns an iterator through the elements of the set.
+    #[allow(mismatched_lifetime_syntaxes)]
     pub fn iter(&self) -> Iter<T, S> {
         Iter {
             inner: raw::iterator::Iter::new(&self.raw),
         }
     }
+
+    /// Returns an iterator through the elements of the set, starting at the cursor.
+    ///
+    /// Together with [`CursorIter::cursor`], this allows pausing the iteration (and releasing the
+    /// epoch pin it holds) and resuming it later. See [`Cursor`] for the order of the elements.
+    pub fn iter_from(&self, cursor: Cursor<u64>) -> CursorIter<'_, T, S>
+    where
+        S: BuildHasher,
+    {
+        CursorIter {
+            inner: self.raw.iter_from(cursor),
+        }
+    }
+
+    /// Returns an iterator through the elements with hashes in the given range.
+    ///
+    /// The range is in the order of the walk through the trie, not the numerical one. In
+    /// particular, the ranges `k..k + 1` for `k` below `1 <<`
+    /// [`LEVEL_BITS`][crate::raw::config::Config::LEVEL_BITS] (with the last one left open) split
+    /// the set into disjoint parts that can be walked by different threads.
+    pub fn iter_hash_range<R>(&self, range: R) -> CursorIter<'_, T, S>
+    where
+        S: BuildHasher,
+        R: RangeBounds<u64>,
+    {
+        CursorIter {
+            inner: self.raw.iter_hash_range(range),
+        }
+    }
+
+    /// Returns an iterator through the elements of the set that holds no epoch pin.
+    ///
+    /// The iterator pins only for the duration of each `next` call and remembers just a
+    /// [`Cursor`] in between. Therefore it is `Send` (if the set is `Sync`), so it can be
+    /// held across `.await` points in a multi-threaded runtime or moved to another thread. With
+    /// the `stream` feature, it can be turned into a futures `Stream`. The price is a walk from
+    /// the root of the trie for each element.
+    pub fn send_iter(&self) -> SendIter<'_, T, S> {
+        self.send_iter_from(Cursor::default())
+    }
+
+    /// Like [`send_iter`][ConSet::send_iter], but starting at the cursor.
+    pub fn send_iter_from(&self, cursor: Cursor<u64>) -> SendIter<'_, T, S> {
+        SendIter {
+            inner: raw::cursor::SendIter::new(&self.raw, cursor, T::clone),
+        }
+    }
+
+    /// Returns a parallel iterator through the elements of the set.
+    ///
+    /// The work is split along the branches of the trie, each part being walked under its own
+    /// epoch pin. However, the thread driving the iterator holds a pin until all the work is
+    /// done, so no memory is reclaimed in the meantime.
+    ///
+    /// Available with the `rayon` feature.
+    #[cfg(feature = "rayon")]
+    pub fn par_iter(&self) -> ParIter<'_, T, S>
+    where
+        T: Send + Sync,
+        S: Sync,
+    {
+        ParIter {
+            inner: raw::parallel::ParIter::new(&self.raw, T::clone),
+        }
+    }
+}
+
+/// A reference to a value in the [`ConSet`].
+///
+/// See the [`get_ref`][ConSet::get_ref] method for details.
+pub type Ref<'a, T> = raw::reference::Ref<'a, T>;
+
+/// A read handle to the [`ConSet`], holding an epoch pin.
+///
+/// See the [`pin`][ConSet::pin] method for details.
+pub struct Pinned<'a, T, S>
+where
+    T: Clone + Hash + Eq,
+{
+    set: &'a ConSet<T, S>,
+    pin: MapGuard<'a>,
+}
+
+impl<T, S> Pinned<'_, T, S>
+where
+    T: Clone + Hash + Eq,
+    S: BuildHasher,
+{
+    /// Looks up a value in the set.
+    pub fn get<Q>(&self, key: &Q) -> Option<&T>
+    where
+        Q: ?Sized + Eq + Hash,
+        T: Borrow<Q>,
+    {
+        self.set.raw.get(key, &self.pin)
+    }
+
+    /// Checks if a value identified by the given key is present in the set.
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        Q: ?Sized + Eq + Hash,
+        T: Borrow<Q>,
+    {
+        self.get(key).is_some()
+    }
+}
+
+/// A consistent read-only view of the [`ConSet`] at a given moment.
+///
+/// See the [`snapshot`][ConSet::snapshot] method for details.
+pub struct Snapshot<'a, T, S>
+where
+    T: Clone + Hash + Eq,
+{
+    inner: raw::snapshot::Snapshot<'a, TrivialConfig<T>, S>,
+}
+
+impl<T, S> Snapshot<'_, T, S>
+where
+    T: Clone + Hash + Eq,
+    S: BuildHasher,
+{
+    /// Looks up a value in the snapshot.
+    pub fn get<Q>(&self, key: &Q) -> Option<&T>
+    where
+        Q: ?Sized + Eq + Hash,
+        T: Borrow<Q>,
+    {
+        self.inner.get(key)
+    }
+
+    /// Checks if a value identified by the given key is present in the snapshot.
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        Q: ?Sized + Eq + Hash,
+        T: Borrow<Q>,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Checks if the snapshot is empty.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Returns an iterator through the values in the snapshot.
+    pub fn iter(&self) -> Iter<'_, T, S> {
+        Iter {
+            inner: self.inner.iter(),
+        }
+    }
 }
 
 /// The iterator of the [`ConSet`].
@@ -176,14 +678,14 @@ where
 /// See the [`iter`][ConSet::iter] method for details.
 pub struct Iter<'a, T, S>
 where
-    T: Clone + Hash + Eq + 'static,
+    T: Clone + Hash + Eq,
 {
     inner: raw::iterator::Iter<'a, TrivialConfig<T>, S>,
 }
 
 impl<'a, T, S> Iterator for Iter<'a, T, S>
 where
-    T: Clone + Hash + Eq + 'static,
+    T: Clone + Hash + Eq,
 {
     type Item = T;
 
@@ -192,9 +694,168 @@ where
     }
 }
 
+/// The iterator of the [`ConSet`] starting at a [`Cursor`].
+///
+/// See the [`iter_from`][ConSet::iter_from] and [`iter_hash_range`][ConSet::iter_hash_range]
+/// methods for details.
+pub struct CursorIter<'a, T, S>
+where
+    T: Clone + Hash + Eq,
+{
+    inner: raw::cursor::CursorIter<'a, TrivialConfig<T>, S>,
+}
+
+impl<T, S> CursorIter<'_, T, S>
+where
+    T: Clone + Hash + Eq,
+    S: BuildHasher,
+{
+    /// The position right after the last returned element.
+    ///
+    /// Passing it to [`iter_from`][ConSet::iter_from] resumes the iteration. Returns `None` if
+    /// there are no more elements in the range.
+    pub fn cursor(&self) -> Option<Cursor<u64>> {
+        self.inner.cursor()
+    }
+
+    /// Makes the iterator release its epoch pin and take a new one after each `n` elements.
+    ///
+    /// This bounds the time the iterator holds back releasing memory, which is useful for long
+    /// walks through large maps. See [`raw::cursor::CursorIter::repin_every`] for details.
+    ///
+    /// # Panics
+    ///
+    /// If `n` is 0.
+    pub fn repin_every(self, n: usize) -> Self {
+        CursorIter {
+            inner: self.inner.repin_every(n),
+        }
+    }
+}
+
+impl<T, S> Iterator for CursorIter<'_, T, S>
+where
+    T: Clone + Hash + Eq,
+    S: BuildHasher,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.inner.next().cloned()
+    }
+}
+
+/// The iterator of the [`ConSet`] holding no epoch pin.
+///
+/// See the [`send_iter`][ConSet::send_iter] method for details.
+pub struct SendIter<'a, T, S>
+where
+    T: Clone + Hash + Eq,
+{
+    inner: raw::cursor::SendIter<'a, TrivialConfig<T>, S, fn(&T) -> T>,
+}
+
+impl<'a, T, S> SendIter<'a, T, S>
+where
+    T: Clone + Hash + Eq,
+{
+    /// The position right after the last returned element.
+    ///
+    /// See [`CursorIter::cursor`].
+    pub fn cursor(&self) -> Option<Cursor<u64>> {
+        self.inner.cursor()
+    }
+
+    /// Turns the iterator into a futures `Stream`.
+    ///
+    /// Available with the `stream` feature.
+    #[cfg(feature = "stream")]
+    pub fn into_stream(self) -> SendStream<'a, T, S> {
+        SendStream {
+            inner: self.inner.into_stream(),
+        }
+    }
+}
+
+impl<T, S> Iterator for SendIter<'_, T, S>
+where
+    T: Clone + Hash + Eq,
+    S: BuildHasher,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.inner.next()
+    }
+}
+
+/// The [`SendIter`] as a futures `Stream`.
+///
+/// Created by [`SendIter::into_stream`], available with the `stream` feature.
+#[cfg(feature = "stream")]
+pub struct SendStream<'a, T, S>
+where
+    T: Clone + Hash + Eq,
+{
+    inner: raw::cursor::SendStream<'a, TrivialConfig<T>, S, fn(&T) -> T>,
+}
+
+#[cfg(feature = "stream")]
+impl<T, S> SendStream<'_, T, S>
+where
+    T: Clone + Hash + Eq,
+{
+    /// The position right after the last returned element.
+    ///
+    /// See [`CursorIter::cursor`].
+    pub fn cursor(&self) -> Option<Cursor<u64>> {
+        self.inner.cursor()
+    }
+}
+
+#[cfg(feature = "stream")]
+impl<T, S> Stream for SendStream<'_, T, S>
+where
+    T: Clone + Hash + Eq,
+    S: BuildHasher,
+{
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        Pin::new(&mut self.inner).poll_next(cx)
+    }
+}
+
+/// The parallel iterator of the [`ConSet`].
+///
+/// See the [`par_iter`][ConSet::par_iter] method for details. Available with the `rayon` feature.
+#[cfg(feature = "rayon")]
+pub struct ParIter<'a, T, S>
+where
+    T: Clone + Hash + Eq,
+{
+    inner: raw::parallel::ParIter<'a, TrivialConfig<T>, S, fn(&T) -> T>,
+}
+
+#[cfg(feature = "rayon")]
+impl<T, S> ParallelIterator for ParIter<'_, T, S>
+where
+    T: Clone + Hash + Eq + Send + Sync,
+    S: Sync,
+{
+    type Item = T;
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<T>,
+    {
+        self.inner.drive_unindexed(consumer)
+    }
+}
+
 impl<'a, T, S> IntoIterator for &'a ConSet<T, S>
 where
-    T: Clone + Hash + Eq + 'static,
+    T: Clone + Hash + Eq,
 {
     type Item = T;
     type IntoIter = Iter<'a, T, S>;
@@ -204,24 +865,37 @@ where
     }
 }
 
+#[cfg(feature = "rayon")]
+impl<'a, T, S> IntoParallelIterator for &'a ConSet<T, S>
+where
+    T: Clone + Hash + Eq + Send + Sync,
+    S: Sync,
+{
+    type Item = T;
+    type Iter = ParIter<'a, T, S>;
+
+    fn into_par_iter(self) -> Self::Iter {
+        self.par_iter()
+    }
+}
+
+#[allow(clippy::needless_lifetimes)]
 impl<'a, T, S> Extend<T> for &'a ConSet<T, S>
 where
-    T: Clone + Hash + Eq + 'static,
+    T: Clone + Hash + Eq,
     S: BuildHasher,
 {
     fn extend<I>(&mut self, iter: I)
     where
         I: IntoIterator<Item = T>,
     {
-        for n in iter {
-            self.insert(n);
-        }
+        self.raw.extend(iter);
     }
 }
 
 impl<T, S> Extend<T> for ConSet<T, S>
 where
-    T: Clone + Hash + Eq + 'static,
+    T: Clone + Hash + Eq,
     S: BuildHasher,
 {
     fn extend<I>(&mut self, iter: I)
@@ -248,6 +922,7 @@ where
 }
 
 #[cfg(feature = "rayon")]
+#[allow(clippy::needless_lifetimes)]
 impl<'a, T, S> ParallelExtend<T> for &'a ConSet<T, S>
 where
     T: Clone + Hash + Eq + Send + Sync,
@@ -257,9 +932,7 @@ where
     where
         I: IntoParallelIterator<Item = T>,
     {
-        par_iter.into_par_iter().for_each(|n| {
-            self.insert(n);
-        });
+        self.raw.par_extend(par_iter);
     }
 }
 
@@ -281,7 +954,7 @@ where
 #[cfg(feature = "rayon")]
 impl<T> FromParallelIterator<T> for ConSet<T>
 where
-    T: Clone + Hash + Eq + Send + Sync,
+    T: Clone + Hash + Eq + Send + Sync + 'static,
 {
     fn from_par_iter<I>(iter: I) -> Self
     where
@@ -295,13 +968,16 @@ where
 
 #[cfg(test)]
 mod tests {
+    #[cfg(feature = "rayon")]
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
     use crossbeam_utils::thread;
     #[cfg(feature = "rayon")]
     use rayon::prelude::*;
 
     use super::*;
     use crate::raw::tests::NoHasher;
-    use crate::raw::LEVEL_CELLS;
+    use crate::raw::tests::LEVEL_CELLS;
 
     const TEST_THREADS: usize = 4;
     const TEST_BATCH: usize = 10000;
@@ -314,6 +990,40 @@ mod tests {
         assert_eq!("{}", &format!("{:?}", set));
     }
 
+    #[test]
+    fn batch() {
+        let set = ConSet::new();
+        let replaced = set.insert_many(vec!["hello", "world", "hello"]);
+        assert_eq!(vec![None, None, Some("hello")], replaced);
+        let found = set.get_many(&["world", "universe"]);
+        assert_eq!(vec![Some("world"), None], found);
+        let removed = set.remove_many(&["hello", "universe"]);
+        assert_eq!(vec![Some("hello"), None], removed);
+        assert_eq!(1, set.len());
+    }
+
+    #[test]
+    fn hash_range() {
+        let set: ConSet<usize> = (0..100).collect();
+        let split = 1 << 3;
+        let mut found = set.iter_hash_range(..split).collect::<Vec<_>>();
+        found.extend(set.iter_hash_range(split..));
+        found.sort();
+        assert_eq!((0..100).collect::<Vec<_>>(), found);
+    }
+
+    #[cfg(feature = "stream")]
+    #[test]
+    fn send_stream() {
+        use futures::stream::StreamExt;
+
+        let set: ConSet<usize> = (0..100).collect();
+        let stream = set.send_iter().into_stream();
+        let mut found = futures::executor::block_on(stream.collect::<Vec<_>>());
+        found.sort();
+        assert_eq!((0..100).collect::<Vec<_>>(), found);
+    }
+
     #[test]
     fn debug_when_has_elements() {
         let set: ConSet<&str> = ConSet::new();
@@ -534,6 +1244,145 @@ mod tests {
         assert!(set.is_empty());
     }
 
+    /// A value where only part of it takes part in equality.
+    #[derive(Clone, Debug)]
+    struct Tagged(usize, &'static str);
+
+    impl PartialEq for Tagged {
+        fn eq(&self, other: &Self) -> bool {
+            self.0 == other.0
+        }
+    }
+
+    impl Eq for Tagged {}
+
+    impl Hash for Tagged {
+        fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+            self.0.hash(state);
+        }
+    }
+
+    #[test]
+    fn replace_only_present() {
+        let set = ConSet::new();
+        assert!(set.replace(Tagged(1, "a")).is_none());
+        assert!(set.is_empty());
+        assert!(set.insert(Tagged(1, "a")).is_none());
+        assert_eq!("a", set.replace(Tagged(1, "b")).unwrap().1);
+        assert_eq!("b", set.get(&Tagged(1, "")).unwrap().1);
+    }
+
+    #[test]
+    fn get_or_try_insert() {
+        let set = ConSet::new();
+        let (key, e) = set
+            .get_or_try_insert_with(Tagged(1, ""), |_| Err("failed"))
+            .unwrap_err();
+        assert_eq!(1, key.0);
+        assert_eq!("failed", e);
+        assert!(set.is_empty());
+
+        let new = set
+            .get_or_try_insert_with(Tagged(1, ""), |k| Ok::<_, ()>(Tagged(k.0, "a")))
+            .unwrap();
+        assert!(new.is_new());
+        assert_eq!("a", new.1);
+
+        let existing = set
+            .get_or_try_insert_with(Tagged(1, ""), |_| -> Result<Tagged, ()> {
+                panic!("Must not be called for an existing value")
+            })
+            .unwrap();
+        assert!(!existing.is_new());
+        assert_eq!("a", existing.1);
+        assert_eq!(1, set.len());
+    }
+
+    #[test]
+    fn remove_conditional() {
+        let mut set = ConSet::with_hasher(NoHasher);
+        set.insert(Tagged(1, "a"));
+        set.insert(Tagged(2, "b"));
+        assert!(set.remove_if(&Tagged(1, ""), |t| t.1 == "b").is_none());
+        assert_eq!(
+            "a",
+            set.remove_if(&Tagged(1, ""), |t| t.1 == "a").unwrap().1
+        );
+        set.raw.assert_pruned();
+        assert!(set.contains(&Tagged(2, "")));
+    }
+
+    #[test]
+    fn retain() {
+        let mut set: ConSet<usize> = (0..TEST_BATCH_SMALL).collect();
+        set.retain(|i| i % 2 == 0);
+        set.raw.assert_pruned();
+        for i in 0..TEST_BATCH_SMALL {
+            assert_eq!(i % 2 == 0, set.contains(&i));
+        }
+    }
+
+    #[test]
+    fn pinned_reads() {
+        let set: ConSet<String> = (0..TEST_BATCH_SMALL).map(|i| i.to_string()).collect();
+        let pinned = set.pin();
+        let found = set.get_ref("0").unwrap();
+        set.clear();
+        assert_eq!("0", *found);
+        assert!(pinned.get("0").is_none());
+        set.insert("42".to_owned());
+        assert!(pinned.contains("42"));
+    }
+
+    #[test]
+    fn clear() {
+        let set: ConSet<usize> = (0..TEST_BATCH_SMALL).collect();
+        let taken = set.clear_and_take();
+        assert!(set.is_empty());
+        set.insert(0);
+        set.clear();
+        assert!(set.is_empty());
+        for i in 0..TEST_BATCH_SMALL {
+            assert!(taken.contains(&i));
+        }
+    }
+
+    /// The snapshot keeps seeing the content from the time it was taken, while other threads
+    /// modify the set.
+    #[test]
+    fn snapshot_isolated() {
+        let set = ConSet::with_hasher(NoHasher).with_snapshots();
+        for i in 0..TEST_BATCH_SMALL {
+            set.insert(i);
+        }
+        let snapshot = set.snapshot();
+        thread::scope(|s| {
+            for t in 0..TEST_THREADS {
+                let set = &set;
+                s.spawn(move |_| {
+                    for i in 0..TEST_BATCH_SMALL {
+                        if i % TEST_THREADS == t {
+                            set.remove(&i);
+                        }
+                    }
+                });
+            }
+        })
+        .unwrap();
+        assert!(set.is_empty());
+        assert!(!snapshot.is_empty());
+        let mut content = snapshot.iter().collect::<Vec<_>>();
+        content.sort();
+        assert_eq!((0..TEST_BATCH_SMALL).collect::<Vec<_>>(), content);
+        assert!((0..TEST_BATCH_SMALL).all(|i| snapshot.contains(&i)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Snapshots are not turned on")]
+    fn snapshot_not_turned_on() {
+        ConSet::<usize>::new().snapshot();
+    }
+
     fn iter_test_inner<S: BuildHasher>(set: ConSet<usize, S>) {
         for i in 0..TEST_BATCH_SMALL {
             assert!(set.insert(i).is_none());
@@ -616,4 +1465,38 @@ mod tests {
         let expected = (0..TEST_BATCH_SMALL).collect::<Vec<_>>();
         assert_eq!(expected, extracted);
     }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn rayon_par_iter() {
+        let set = ConSet::new();
+        set.insert_many(0..TEST_BATCH_SMALL);
+
+        let mut extracted = set.par_iter().collect::<Vec<_>>();
+        extracted.par_sort();
+
+        let expected = (0..TEST_BATCH_SMALL).collect::<Vec<_>>();
+        assert_eq!(expected, extracted);
+        assert_eq!(TEST_BATCH_SMALL, (&set).into_par_iter().count());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn rayon_par_bulk() {
+        let set = ConSet::new();
+        set.insert_many(0..TEST_BATCH_SMALL);
+
+        set.par_retain(|v| v % 2 == 0);
+        let sum = AtomicUsize::new(0);
+        set.par_for_each(|v| {
+            sum.fetch_add(*v, Ordering::Relaxed);
+        });
+
+        let expected = (0..TEST_BATCH_SMALL).filter(|i| i % 2 == 0).sum::<usize>();
+        assert_eq!(expected, sum.into_inner());
+        assert_eq!(
+            (0..TEST_BATCH_SMALL).filter(|i| i % 2 == 0).count(),
+            set.len()
+        );
+    }
 }
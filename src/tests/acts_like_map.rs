@@ -11,6 +11,7 @@ use std::collections::hash_map::RandomState;
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::hash::{BuildHasher, Hash};
+use std::mem;
 
 use proptest::collection::vec;
 use proptest::prelude::*;
@@ -23,12 +24,15 @@ enum Instruction<K, V> {
     Lookup(K),
     Remove(K),
     Insert(K, V),
+    Replace(K, V),
+    RemoveIfEq(K, V),
+    RetainBelow(V),
 }
 
 impl<K, V> Instruction<K, V>
 where
     K: Arbitrary + Clone + Debug + Eq + Hash + 'static,
-    V: Arbitrary + Clone + Debug + PartialEq + 'static,
+    V: Arbitrary + Clone + Debug + PartialOrd + 'static,
 {
     fn strategy() -> impl Strategy<Value = Self> {
         use Instruction::*;
@@ -37,6 +41,9 @@ where
             any::<K>().prop_map(Lookup),
             any::<K>().prop_map(Remove),
             any::<(K, V)>().prop_map(|(k, v)| Insert(k, v)),
+            any::<(K, V)>().prop_map(|(k, v)| Replace(k, v)),
+            any::<(K, V)>().prop_map(|(k, v)| RemoveIfEq(k, v)),
+            any::<V>().prop_map(RetainBelow),
         ]
     }
 
@@ -64,7 +71,32 @@ where
                     prop_assert_eq!(expected.as_ref(), found.as_ref().map(|l| l.value()));
                     assert!(!map.is_empty());
                 }
+                Replace(key, value) => {
+                    let expected = match map.get_mut(&key) {
+                        Some(old) => Some(mem::replace(old, value.clone())),
+                        None => None,
+                    };
+                    let found = trie.replace(key, value);
+                    prop_assert_eq!(expected.as_ref(), found.as_ref().map(|l| l.value()));
+                }
+                RemoveIfEq(key, value) => {
+                    let expected = if map.get(&key) == Some(&value) {
+                        map.remove(&key)
+                    } else {
+                        None
+                    };
+                    let found = trie.remove_if(&key, |e| *e.value() == value);
+                    prop_assert_eq!(expected.as_ref(), found.as_ref().map(|l| l.value()));
+                    prop_assert_eq!(map.is_empty(), trie.is_empty());
+                }
+                RetainBelow(bound) => {
+                    map.retain(|_, v| *v < bound);
+                    trie.retain(|_, v| *v < bound);
+                    prop_assert_eq!(map.len(), trie.iter().count());
+                    prop_assert_eq!(map.is_empty(), trie.is_empty());
+                }
             }
+            prop_assert_eq!(map.len(), trie.len());
         }
 
         Ok(())
@@ -21,6 +21,7 @@ fn insert_parallel_test<
     values.into_par_iter().for_each(|v| {
         trie.insert(v);
     });
+    prop_assert_eq!(set.len(), trie.len());
     for v in set {
         prop_assert!(trie.contains(&v));
     }
@@ -147,3 +147,170 @@ struct CantExtendBeyondDestroy;
 /// assert!(element.is_none());
 /// ```
 struct DoesntOutliveIterator;
+
+/// The scoped maps can hold borrowed data, but only as long as the data lives.
+///
+/// ```compile_fail
+/// use contrie::ConMap;
+///
+/// let map = ConMap::new_scoped();
+/// {
+///     let key = String::from("hello");
+///     map.insert(key.as_str(), 1);
+/// }
+/// // The key is gone while the map still holds it
+/// assert!(map.get("hello").is_some());
+/// ```
+///
+/// The map going away first is fine.
+///
+/// ```
+/// use contrie::ConMap;
+///
+/// let key = String::from("hello");
+/// {
+///     let map = ConMap::new_scoped();
+///     map.insert(key.as_str(), 1);
+///     assert!(map.get("hello").is_some());
+/// }
+/// drop(key);
+/// ```
+struct ScopedDoesntOutliveData;
+
+/// The borrowed payload handed out of a scoped map can't outlive the data either, even though the
+/// map itself is already gone.
+///
+/// ```compile_fail
+/// use contrie::ConMap;
+///
+/// let element = {
+///     let value = String::from("world");
+///     let map = ConMap::new_scoped();
+///     map.insert(1, value.as_str());
+///     map.get(&1).unwrap()
+/// };
+/// assert_eq!("world", *element.value());
+/// ```
+///
+/// With the data living long enough, the element may outlive the map.
+///
+/// ```
+/// use contrie::ConMap;
+///
+/// let value = String::from("world");
+/// let element = {
+///     let map = ConMap::new_scoped();
+///     map.insert(1, value.as_str());
+///     map.get(&1).unwrap()
+/// };
+/// assert_eq!("world", *element.value());
+/// ```
+struct ScopedPayloadDoesntOutliveData;
+
+/// A reference into a scoped map holds a pin of the map's private collector, so it must not
+/// outlive the map.
+///
+/// ```compile_fail
+/// use contrie::ConMap;
+///
+/// let value = String::from("world");
+/// let map = ConMap::new_scoped();
+/// map.insert(1, value.as_str());
+/// let reference = map.get_ref(&1).unwrap();
+/// drop(map);
+/// assert_eq!("world", *reference.value());
+/// ```
+///
+/// The same goes for the read handle and references obtained through it.
+///
+/// ```compile_fail
+/// use contrie::ConMap;
+///
+/// let value = String::from("world");
+/// let map = ConMap::new_scoped();
+/// map.insert(1, value.as_str());
+/// let pinned = map.pin();
+/// let found = pinned.get(&1).unwrap();
+/// drop(pinned);
+/// assert_eq!("world", *found);
+/// ```
+///
+/// ```
+/// use contrie::ConMap;
+///
+/// let value = String::from("world");
+/// let map = ConMap::new_scoped();
+/// map.insert(1, value.as_str());
+/// let pinned = map.pin();
+/// assert_eq!("world", *pinned.get(&1).unwrap());
+/// assert_eq!("world", *map.get_ref(&1).unwrap().value());
+/// ```
+struct ScopedRefDoesntOutliveMap;
+
+/// Neither can an iterator.
+///
+/// ```compile_fail
+/// use contrie::ConSet;
+///
+/// let value = String::from("hello");
+/// let set = ConSet::new_scoped();
+/// set.insert(value.as_str());
+/// let mut iter = set.iter();
+/// drop(set);
+/// assert_eq!(Some("hello"), iter.next());
+/// ```
+///
+/// ```
+/// use contrie::ConSet;
+///
+/// let value = String::from("hello");
+/// let set = ConSet::new_scoped();
+/// set.insert(value.as_str());
+/// let mut iter = set.iter();
+/// assert_eq!(Some("hello"), iter.next());
+/// ```
+struct ScopedIterDoesntOutliveMap;
+
+/// A guard of the private collector doesn't escape from a scoped [`Raw`][crate::raw::Raw]
+/// holding borrowed values. The [`pin`][crate::raw::Raw::pin] is not available for it at all.
+///
+/// ```compile_fail
+/// use std::collections::hash_map::RandomState;
+///
+/// use contrie::raw::config::Trivial;
+/// use contrie::raw::Raw;
+///
+/// let value = String::from("hello");
+/// let map = Raw::<Trivial<&str>, _>::with_hasher_scoped(RandomState::new());
+/// let pin = map.pin();
+/// map.insert(value.as_str(), &pin);
+/// ```
+///
+/// And the one from [`with_pin`][crate::raw::Raw::with_pin] can't be smuggled out of the closure.
+///
+/// ```compile_fail
+/// use std::collections::hash_map::RandomState;
+///
+/// use contrie::raw::config::Trivial;
+/// use contrie::raw::Raw;
+///
+/// let value = String::from("hello");
+/// let map = Raw::<Trivial<&str>, _>::with_hasher_scoped(RandomState::new());
+/// let pin = map.with_pin(|pin| pin);
+/// map.insert(value.as_str(), pin);
+/// ```
+///
+/// ```
+/// use std::collections::hash_map::RandomState;
+///
+/// use contrie::raw::config::Trivial;
+/// use contrie::raw::Raw;
+///
+/// let value = String::from("hello");
+/// let map = Raw::<Trivial<&str>, _>::with_hasher_scoped(RandomState::new());
+/// map.with_pin(|pin| {
+///     map.insert(value.as_str(), pin);
+/// });
+/// assert!(map.with_pin(|pin| map.get("hello", pin).is_some()));
+/// ```
+struct ScopedGuardDoesntEscape;
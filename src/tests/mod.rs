@@ -1,3 +1,2 @@
 mod acts_like_map;
 mod acts_like_set;
-mod compile_fail;